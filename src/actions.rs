@@ -0,0 +1,664 @@
+use bevy::input::ButtonInput;
+use bevy::prelude::*;
+
+use crate::cursor::{BoardCursor, FocusOverlays};
+use crate::game_state::SelectedSquare;
+use crate::menu::PlayState;
+use crate::widgets::UiFocus;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    Board,
+    Game,
+    Network,
+    Review,
+    Menus,
+}
+
+impl Category {
+    const ALL: [Category; 5] = [
+        Category::Board,
+        Category::Game,
+        Category::Network,
+        Category::Review,
+        Category::Menus,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            Category::Board => "Board",
+            Category::Game => "Game",
+            Category::Network => "Network",
+            Category::Review => "Review",
+            Category::Menus => "Menus",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    ToggleHelp,
+    ToggleEnPassant,
+    ToggleCastling,
+    MiniMode,
+    MiniModeOnTop,
+    AlwaysOnTop,
+    Borderless,
+    MaterialBalance,
+    AutoRotate,
+    FlipBoard,
+    ExportImage,
+    ExportAnimation,
+    OpenAnalysis,
+    ExplainIllegal,
+    Settings,
+    Undo,
+    Redo,
+    CopyFen,
+    CopyPgn,
+    CopyMoves,
+    Paste,
+    SaveGame,
+    NewTab,
+    NextTab,
+    CloseTab,
+    Hint,
+    CursorUp,
+    CursorDown,
+    CursorLeft,
+    CursorRight,
+    CursorSelect,
+    CursorCancel,
+    Pause,
+    Chat,
+    SendPgn,
+    ShareReview,
+    ReviewBack,
+    ReviewForward,
+    TypeMove,
+    EventLog,
+    FocusNext,
+    FocusPrevious,
+    PressFocused,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Modifier {
+    None,
+    Shift,
+    Ctrl,
+    CtrlShift,
+}
+
+impl Modifier {
+    fn held(self, keys: &ButtonInput<KeyCode>) -> bool {
+        let ctrl = keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+        let shift = keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+        match self {
+            Modifier::None => !ctrl && !shift,
+            Modifier::Shift => !ctrl && shift,
+            Modifier::Ctrl => ctrl && !shift,
+            Modifier::CtrlShift => ctrl && shift,
+        }
+    }
+}
+
+/// When a binding is live. A key can be bound once in each scope, and
+/// when more than one of them is active the one listed last here gets it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Scope {
+    /// Wherever the system reading the binding runs. Its key is bound in
+    /// no other scope.
+    Any,
+    /// A game on the board, outside review.
+    Game,
+    /// A finished game being stepped through.
+    Review,
+    /// The square cursor is up or a piece is picked up, with no overlay
+    /// over the board.
+    Cursor,
+    /// A button has been moved to with Tab.
+    Focus,
+}
+
+/// The scopes active this frame, see `update_scopes`.
+#[derive(Resource, Default)]
+pub struct ActiveScopes {
+    game: bool,
+    review: bool,
+    cursor: bool,
+    focus: bool,
+}
+
+impl ActiveScopes {
+    fn contains(&self, scope: Scope) -> bool {
+        match scope {
+            Scope::Any => true,
+            Scope::Game => self.game,
+            Scope::Review => self.review,
+            Scope::Cursor => self.cursor,
+            Scope::Focus => self.focus,
+        }
+    }
+}
+
+/// Works out which scopes have the keyboard, before anything reads it.
+pub fn update_scopes(
+    play_state: Option<Res<State<PlayState>>>,
+    cursor: Res<BoardCursor>,
+    selected: Res<SelectedSquare>,
+    focus: Res<UiFocus>,
+    overlays: Query<(), FocusOverlays>,
+    mut scopes: ResMut<ActiveScopes>,
+) {
+    let play_state = play_state.map(|state| *state.get());
+    let game = play_state.is_some_and(|state| state != PlayState::Review);
+    *scopes = ActiveScopes {
+        game,
+        review: play_state == Some(PlayState::Review),
+        cursor: game && (cursor.is_active() || selected.0.is_some()) && overlays.is_empty(),
+        focus: focus.focused.is_some(),
+    };
+}
+
+pub struct Binding {
+    pub action: Action,
+    pub category: Category,
+    pub key: KeyCode,
+    pub modifier: Modifier,
+    pub scope: Scope,
+    pub description: &'static str,
+}
+
+impl Binding {
+    fn label(&self) -> String {
+        if self.key == KeyCode::Slash && self.modifier == Modifier::Shift {
+            return "?".to_string();
+        }
+        let key = format!("{:?}", self.key);
+        let key = key
+            .strip_prefix("Key")
+            .or_else(|| key.strip_prefix("Digit"))
+            .unwrap_or(&key);
+        match self.modifier {
+            Modifier::None => key.to_string(),
+            Modifier::Shift => format!("Shift+{key}"),
+            Modifier::Ctrl => format!("Ctrl+{key}"),
+            Modifier::CtrlShift => format!("Ctrl+Shift+{key}"),
+        }
+    }
+}
+
+/// Every keyboard shortcut in the app. Input systems look bindings up here
+/// through `just_pressed`, and the help overlay is generated from it, so a
+/// shortcut only has to be added in one place.
+pub const BINDINGS: &[Binding] = &[
+    Binding {
+        action: Action::ToggleHelp,
+        category: Category::Game,
+        key: KeyCode::Slash,
+        modifier: Modifier::Shift,
+        scope: Scope::Any,
+        description: "Show or hide this help",
+    },
+    Binding {
+        action: Action::ToggleEnPassant,
+        category: Category::Board,
+        key: KeyCode::KeyE,
+        modifier: Modifier::None,
+        scope: Scope::Any,
+        description: "Show or hide en passant markers",
+    },
+    Binding {
+        action: Action::ToggleCastling,
+        category: Category::Board,
+        key: KeyCode::KeyC,
+        modifier: Modifier::None,
+        scope: Scope::Any,
+        description: "Show or hide castling rights",
+    },
+    Binding {
+        action: Action::MiniMode,
+        category: Category::Board,
+        key: KeyCode::KeyM,
+        modifier: Modifier::None,
+        scope: Scope::Any,
+        description: "Toggle compact board-only window",
+    },
+    Binding {
+        action: Action::MiniModeOnTop,
+        category: Category::Board,
+        key: KeyCode::KeyM,
+        modifier: Modifier::Shift,
+        scope: Scope::Any,
+        description: "Toggle compact window, kept on top",
+    },
+    Binding {
+        action: Action::AlwaysOnTop,
+        category: Category::Board,
+        key: KeyCode::KeyT,
+        modifier: Modifier::None,
+        scope: Scope::Any,
+        description: "Toggle always on top",
+    },
+    Binding {
+        action: Action::Borderless,
+        category: Category::Board,
+        key: KeyCode::KeyB,
+        modifier: Modifier::None,
+        scope: Scope::Any,
+        description: "Toggle window borders",
+    },
+    Binding {
+        action: Action::MaterialBalance,
+        category: Category::Board,
+        key: KeyCode::KeyV,
+        modifier: Modifier::None,
+        scope: Scope::Any,
+        description: "Show or hide captured pieces and material balance",
+    },
+    Binding {
+        action: Action::AutoRotate,
+        category: Category::Board,
+        key: KeyCode::KeyR,
+        modifier: Modifier::None,
+        scope: Scope::Any,
+        description: "Rotate the board after every move",
+    },
+    Binding {
+        action: Action::FlipBoard,
+        category: Category::Board,
+        key: KeyCode::KeyF,
+        modifier: Modifier::None,
+        scope: Scope::Any,
+        description: "Flip the board",
+    },
+    Binding {
+        action: Action::ExportImage,
+        category: Category::Board,
+        key: KeyCode::KeyP,
+        modifier: Modifier::Ctrl,
+        scope: Scope::Any,
+        description: "Save the board as a PNG image",
+    },
+    Binding {
+        action: Action::ExportAnimation,
+        category: Category::Board,
+        key: KeyCode::KeyP,
+        modifier: Modifier::CtrlShift,
+        scope: Scope::Any,
+        description: "Save the game as an animated PNG",
+    },
+    Binding {
+        action: Action::OpenAnalysis,
+        category: Category::Board,
+        key: KeyCode::KeyA,
+        modifier: Modifier::Ctrl,
+        scope: Scope::Any,
+        description: "Open an analysis board in a second window",
+    },
+    Binding {
+        action: Action::ExplainIllegal,
+        category: Category::Board,
+        key: KeyCode::KeyL,
+        modifier: Modifier::None,
+        scope: Scope::Any,
+        description: "Explain why a move is illegal",
+    },
+    Binding {
+        action: Action::Settings,
+        category: Category::Board,
+        key: KeyCode::KeyO,
+        modifier: Modifier::None,
+        scope: Scope::Any,
+        description: "Choose board colors and piece set",
+    },
+    Binding {
+        action: Action::Undo,
+        category: Category::Game,
+        key: KeyCode::KeyZ,
+        modifier: Modifier::Ctrl,
+        scope: Scope::Any,
+        description: "Undo the last move (local play)",
+    },
+    Binding {
+        action: Action::Redo,
+        category: Category::Game,
+        key: KeyCode::KeyY,
+        modifier: Modifier::Ctrl,
+        scope: Scope::Any,
+        description: "Redo an undone move (local play)",
+    },
+    Binding {
+        action: Action::CopyFen,
+        category: Category::Game,
+        key: KeyCode::KeyC,
+        modifier: Modifier::Ctrl,
+        scope: Scope::Any,
+        description: "Copy the position as FEN",
+    },
+    Binding {
+        action: Action::CopyPgn,
+        category: Category::Game,
+        key: KeyCode::KeyC,
+        modifier: Modifier::CtrlShift,
+        scope: Scope::Any,
+        description: "Copy the game so far as PGN",
+    },
+    Binding {
+        action: Action::CopyMoves,
+        category: Category::Game,
+        key: KeyCode::KeyM,
+        modifier: Modifier::Ctrl,
+        scope: Scope::Any,
+        description: "Copy just the moves, for pasting into a chat",
+    },
+    Binding {
+        action: Action::Paste,
+        category: Category::Game,
+        key: KeyCode::KeyV,
+        modifier: Modifier::Ctrl,
+        scope: Scope::Any,
+        description: "Load a FEN or PGN from the clipboard (local play)",
+    },
+    Binding {
+        action: Action::SaveGame,
+        category: Category::Game,
+        key: KeyCode::KeyS,
+        modifier: Modifier::Ctrl,
+        scope: Scope::Any,
+        description: "Save the game to resume later (local play)",
+    },
+    Binding {
+        action: Action::NewTab,
+        category: Category::Game,
+        key: KeyCode::KeyT,
+        modifier: Modifier::Ctrl,
+        scope: Scope::Any,
+        description: "Open a local game in a new tab",
+    },
+    Binding {
+        action: Action::NextTab,
+        category: Category::Game,
+        key: KeyCode::Tab,
+        modifier: Modifier::Ctrl,
+        scope: Scope::Any,
+        description: "Show the next tab",
+    },
+    Binding {
+        action: Action::CloseTab,
+        category: Category::Game,
+        key: KeyCode::KeyW,
+        modifier: Modifier::Ctrl,
+        scope: Scope::Any,
+        description: "Close the shown tab",
+    },
+    Binding {
+        action: Action::Hint,
+        category: Category::Game,
+        key: KeyCode::KeyH,
+        modifier: Modifier::None,
+        scope: Scope::Any,
+        description: "Highlight a suggested move (local play)",
+    },
+    Binding {
+        action: Action::EventLog,
+        category: Category::Game,
+        key: KeyCode::KeyG,
+        modifier: Modifier::None,
+        scope: Scope::Any,
+        description: "Show or hide the log of game events",
+    },
+    Binding {
+        action: Action::CursorUp,
+        category: Category::Game,
+        key: KeyCode::ArrowUp,
+        modifier: Modifier::None,
+        scope: Scope::Game,
+        description: "Move the square cursor up",
+    },
+    Binding {
+        action: Action::CursorDown,
+        category: Category::Game,
+        key: KeyCode::ArrowDown,
+        modifier: Modifier::None,
+        scope: Scope::Game,
+        description: "Move the square cursor down",
+    },
+    Binding {
+        action: Action::CursorLeft,
+        category: Category::Game,
+        key: KeyCode::ArrowLeft,
+        modifier: Modifier::None,
+        scope: Scope::Game,
+        description: "Move the square cursor left",
+    },
+    Binding {
+        action: Action::CursorRight,
+        category: Category::Game,
+        key: KeyCode::ArrowRight,
+        modifier: Modifier::None,
+        scope: Scope::Game,
+        description: "Move the square cursor right",
+    },
+    Binding {
+        action: Action::CursorSelect,
+        category: Category::Game,
+        key: KeyCode::Enter,
+        modifier: Modifier::None,
+        scope: Scope::Cursor,
+        description: "Pick up or move to the square under the cursor",
+    },
+    Binding {
+        action: Action::CursorSelect,
+        category: Category::Game,
+        key: KeyCode::Space,
+        modifier: Modifier::None,
+        scope: Scope::Cursor,
+        description: "Pick up or move to the square under the cursor",
+    },
+    Binding {
+        action: Action::CursorCancel,
+        category: Category::Game,
+        key: KeyCode::Escape,
+        modifier: Modifier::None,
+        scope: Scope::Cursor,
+        description: "Drop the selected piece, or put the cursor away",
+    },
+    Binding {
+        action: Action::Pause,
+        category: Category::Game,
+        key: KeyCode::Escape,
+        modifier: Modifier::None,
+        scope: Scope::Game,
+        description: "Open or close the pause menu",
+    },
+    Binding {
+        action: Action::Chat,
+        category: Category::Network,
+        key: KeyCode::Enter,
+        modifier: Modifier::None,
+        scope: Scope::Game,
+        description: "Open chat, or send the typed line",
+    },
+    Binding {
+        action: Action::SendPgn,
+        category: Category::Network,
+        key: KeyCode::KeyS,
+        modifier: Modifier::CtrlShift,
+        scope: Scope::Any,
+        description: "Send the game so far to the opponent as PGN",
+    },
+    Binding {
+        action: Action::ShareReview,
+        category: Category::Network,
+        key: KeyCode::KeyR,
+        modifier: Modifier::CtrlShift,
+        scope: Scope::Review,
+        description: "Send the review's scores to the opponent",
+    },
+    Binding {
+        action: Action::TypeMove,
+        category: Category::Game,
+        key: KeyCode::Slash,
+        modifier: Modifier::None,
+        scope: Scope::Any,
+        description: "Type a move, e.g. Nf3 or g1f3",
+    },
+    Binding {
+        action: Action::ReviewBack,
+        category: Category::Review,
+        key: KeyCode::ArrowLeft,
+        modifier: Modifier::None,
+        scope: Scope::Review,
+        description: "Step back through a finished game",
+    },
+    Binding {
+        action: Action::ReviewForward,
+        category: Category::Review,
+        key: KeyCode::ArrowRight,
+        modifier: Modifier::None,
+        scope: Scope::Review,
+        description: "Step forward through a finished game",
+    },
+    Binding {
+        action: Action::FocusNext,
+        category: Category::Menus,
+        key: KeyCode::Tab,
+        modifier: Modifier::None,
+        scope: Scope::Any,
+        description: "Move to the next button",
+    },
+    Binding {
+        action: Action::FocusPrevious,
+        category: Category::Menus,
+        key: KeyCode::Tab,
+        modifier: Modifier::Shift,
+        scope: Scope::Any,
+        description: "Move to the previous button",
+    },
+    Binding {
+        action: Action::PressFocused,
+        category: Category::Menus,
+        key: KeyCode::Enter,
+        modifier: Modifier::None,
+        scope: Scope::Focus,
+        description: "Press the button moved to",
+    },
+];
+
+pub fn just_pressed(keys: &ButtonInput<KeyCode>, action: Action) -> bool {
+    BINDINGS
+        .iter()
+        .filter(|binding| binding.action == action)
+        .any(|binding| keys.just_pressed(binding.key) && binding.modifier.held(keys))
+}
+
+/// `just_pressed` for the actions whose keys are shared with another
+/// scope: a binding counts only while its scope is active and no later
+/// active scope binds the same key.
+pub fn just_pressed_in(keys: &ButtonInput<KeyCode>, scopes: &ActiveScopes, action: Action) -> bool {
+    BINDINGS
+        .iter()
+        .filter(|binding| binding.action == action && scopes.contains(binding.scope))
+        .filter(|binding| {
+            !BINDINGS.iter().any(|other| {
+                other.key == binding.key
+                    && other.modifier == binding.modifier
+                    && other.scope > binding.scope
+                    && scopes.contains(other.scope)
+            })
+        })
+        .any(|binding| keys.just_pressed(binding.key) && binding.modifier.held(keys))
+}
+
+#[derive(Component)]
+pub struct HelpOverlay;
+
+fn help_text() -> String {
+    let mut ret = String::new();
+    for category in Category::ALL {
+        let bindings: Vec<&Binding> = BINDINGS
+            .iter()
+            .filter(|binding| binding.category == category)
+            .collect();
+        if bindings.is_empty() {
+            continue;
+        }
+        if !ret.is_empty() {
+            ret.push('\n');
+        }
+        ret.push_str(category.label());
+        ret.push('\n');
+        for binding in bindings {
+            ret.push_str(&format!(
+                "  {:<14}{}\n",
+                binding.label(),
+                binding.description
+            ));
+        }
+    }
+    ret
+}
+
+pub fn toggle_help_overlay(
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    overlays: Query<Entity, With<HelpOverlay>>,
+) {
+    if !just_pressed(&keys, Action::ToggleHelp) {
+        return;
+    }
+    if let Some(entity) = overlays.iter().next() {
+        commands.entity(entity).despawn();
+        return;
+    }
+    commands.spawn((
+        HelpOverlay,
+        Node {
+            position_type: PositionType::Absolute,
+            width: Val::Percent(100.0),
+            height: Val::Percent(100.0),
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.75)),
+        GlobalZIndex(10),
+        children![(
+            Text::new(help_text()),
+            TextFont {
+                font_size: 18.0,
+                ..default()
+            },
+        )],
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_key_is_bound_twice_in_a_scope() {
+        for (index, binding) in BINDINGS.iter().enumerate() {
+            for other in &BINDINGS[index + 1..] {
+                if other.key != binding.key
+                    || other.modifier != binding.modifier
+                    || other.action == binding.action
+                {
+                    continue;
+                }
+                assert!(
+                    binding.scope != other.scope
+                        && binding.scope != Scope::Any
+                        && other.scope != Scope::Any,
+                    "{:?} and {:?} are both bound to {}",
+                    binding.action,
+                    other.action,
+                    binding.label()
+                );
+            }
+        }
+    }
+}
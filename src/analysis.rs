@@ -0,0 +1,407 @@
+use bevy::input::ButtonInput;
+use bevy::input::keyboard::KeyboardInput;
+use bevy::prelude::*;
+use bevy::render::camera::{RenderTarget, ScalingMode};
+use bevy::render::view::RenderLayers;
+use bevy::window::{PrimaryWindow, WindowRef, WindowResolution};
+use hermanha_chess::{BOARD_COLS, BOARD_ROWS, Board, Color as HermanhaColor, Position};
+
+use crate::actions::{self, Action};
+use crate::board_render::BoardOrientation;
+use crate::game_state::{BoardState, Castling};
+use crate::input::legal_targets;
+use crate::piece_images::PieceImages;
+use crate::rules::{self, CastlingRights};
+use crate::theme::Theme;
+use crate::{PIECE_SCALE, PIECE_Z, TILE_SIZE, cursor_to_board_position, hint, pos_to_vec3};
+
+/// The analysis board is drawn on its own layer, so it only shows up in
+/// its own window.
+const ANALYSIS_LAYER: usize = 2;
+
+const SELECTED_COLOR: Color = Color::srgba(0.9, 0.8, 0.2, 0.5);
+const TARGET_COLOR: Color = Color::srgba(0.2, 0.3, 0.1, 0.45);
+
+/// A second board in a window of its own. It follows the game until a
+/// move is played on it, and from then on can be explored freely without
+/// touching the game. Only present while the window is open.
+#[derive(Resource)]
+pub struct AnalysisBoard {
+    window: Entity,
+    /// The position after each move played on the analysis board. Empty
+    /// while it follows the game.
+    moves: Vec<(Board, CastlingRights)>,
+    selected: Option<Position>,
+    /// Which side is drawn at the bottom of the analysis window.
+    bottom: HermanhaColor,
+}
+
+impl AnalysisBoard {
+    /// The position shown: the last one reached on the analysis board, or
+    /// the game's while it follows the game.
+    fn position(&self, board: &Board, castling: CastlingRights) -> (Board, CastlingRights) {
+        self.moves
+            .last()
+            .map_or((board.clone(), castling), |(board, castling)| {
+                (board.clone(), *castling)
+            })
+    }
+
+    /// Where `pos` is drawn, in the coordinates of a board seen from
+    /// White's side, and back.
+    fn flip(&self, pos: Position) -> Position {
+        match self.bottom {
+            HermanhaColor::White => pos,
+            HermanhaColor::Black => Position::new(
+                BOARD_ROWS as i8 - 1 - pos.row,
+                BOARD_COLS as i8 - 1 - pos.col,
+            ),
+        }
+    }
+
+    fn title(&self) -> String {
+        match self.moves.len() {
+            0 => "Analysis - following the game".to_string(),
+            1 => "Analysis - 1 move off the game (Esc to return)".to_string(),
+            moves => format!("Analysis - {moves} moves off the game (Esc to return)"),
+        }
+    }
+}
+
+/// Everything drawn in the analysis window, its camera included.
+#[derive(Component)]
+pub struct AnalysisScene;
+
+#[derive(Component)]
+pub struct AnalysisCamera;
+
+/// Ctrl+A opens the analysis window showing the game's position, turned
+/// the way the main board is, or brings it to the front if it is open.
+pub fn open_analysis(
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    analysis: Option<Res<AnalysisBoard>>,
+    orientation: Res<BoardOrientation>,
+    mut windows: Query<&mut Window>,
+) {
+    if !actions::just_pressed(&keys, Action::OpenAnalysis) {
+        return;
+    }
+    if let Some(analysis) = analysis {
+        if let Ok(mut window) = windows.get_mut(analysis.window) {
+            window.focused = true;
+        }
+        return;
+    }
+    let size = BOARD_COLS as f32 * TILE_SIZE;
+    let window = commands
+        .spawn(Window {
+            title: "Analysis".to_string(),
+            resolution: WindowResolution::new(size, size),
+            ..default()
+        })
+        .id();
+    commands.spawn((
+        AnalysisScene,
+        AnalysisCamera,
+        Camera2d,
+        Camera {
+            target: RenderTarget::Window(WindowRef::Entity(window)),
+            ..default()
+        },
+        Projection::Orthographic(OrthographicProjection {
+            scaling_mode: ScalingMode::AutoMin {
+                min_width: size,
+                min_height: size,
+            },
+            ..OrthographicProjection::default_2d()
+        }),
+        RenderLayers::layer(ANALYSIS_LAYER),
+    ));
+    commands.insert_resource(AnalysisBoard {
+        window,
+        moves: Vec::new(),
+        selected: None,
+        bottom: orientation.bottom(),
+    });
+}
+
+/// Keys pressed in the analysis window go to the analysis board:
+/// Backspace takes back its last move, Escape returns it to the game and
+/// F flips it. While the window has focus the game's shortcuts see no
+/// keys at all.
+pub fn route_analysis_keys(
+    mut keyboard: EventReader<KeyboardInput>,
+    mut keys: ResMut<ButtonInput<KeyCode>>,
+    mut analysis: ResMut<AnalysisBoard>,
+    windows: Query<&Window>,
+) {
+    for event in keyboard.read() {
+        if event.window != analysis.window || !event.state.is_pressed() {
+            continue;
+        }
+        match event.key_code {
+            KeyCode::Backspace => {
+                analysis.moves.pop();
+                analysis.selected = None;
+            }
+            KeyCode::Escape => {
+                analysis.moves.clear();
+                analysis.selected = None;
+            }
+            KeyCode::KeyF => analysis.bottom = rules::opponent(analysis.bottom),
+            _ => {}
+        }
+    }
+    if windows
+        .get(analysis.window)
+        .is_ok_and(|window| window.focused)
+    {
+        keys.reset_all();
+    }
+}
+
+/// A move played in the game drops the selection on an analysis board
+/// that follows it.
+pub fn follow_game(
+    mut analysis: ResMut<AnalysisBoard>,
+    board: Res<BoardState>,
+    castling: Res<Castling>,
+) {
+    if analysis.moves.is_empty() && (board.is_changed() || castling.is_changed()) {
+        analysis.selected = None;
+    }
+}
+
+/// Clicks in the analysis window pick a piece of the side to move there
+/// and then its target. The move is only played on the analysis board;
+/// pawns always promote to a queen.
+pub fn handle_analysis_clicks(
+    buttons: Res<ButtonInput<MouseButton>>,
+    mut analysis: ResMut<AnalysisBoard>,
+    board: Res<BoardState>,
+    castling: Res<Castling>,
+    windows: Query<&Window>,
+    camera_q: Query<(&Camera, &GlobalTransform), With<AnalysisCamera>>,
+) {
+    if !buttons.just_pressed(MouseButton::Left) {
+        return;
+    }
+    let Some(cursor_position) = windows
+        .get(analysis.window)
+        .ok()
+        .and_then(Window::cursor_position)
+    else {
+        return;
+    };
+    let Ok((camera, camera_transform)) = camera_q.single() else {
+        return;
+    };
+    let (position, position_castling) = analysis.position(&board.0, castling.0);
+    let targets: Vec<Position> = analysis
+        .selected
+        .map(|from| legal_targets(&position, position_castling, from))
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(to, _)| to)
+        .collect();
+    let snap_targets: Vec<Position> = targets.iter().map(|to| analysis.flip(*to)).collect();
+    let Some(clicked) =
+        cursor_to_board_position(cursor_position, camera, camera_transform, &snap_targets)
+            .map(|pos| analysis.flip(pos))
+    else {
+        return;
+    };
+    if !position.pos_on_board(clicked) {
+        return;
+    }
+    if let Some(from) = analysis.selected
+        && targets.contains(&clicked)
+    {
+        let promotion_piece = hint::promotion_for(&position, from, clicked);
+        if let Some(after) =
+            rules::play_move(&position, position_castling, from, clicked, promotion_piece)
+        {
+            let mut after_castling = position_castling;
+            after_castling.update(from, clicked);
+            analysis.moves.push((after, after_castling));
+        }
+        analysis.selected = None;
+        return;
+    }
+    let own_piece = position
+        .get(clicked)
+        .is_some_and(|piece| piece.color == position.move_turn);
+    analysis.selected = own_piece.then_some(clicked);
+}
+
+/// Redraws the analysis board whenever its position, selection or the
+/// theme changes, and keeps the window title saying whether it still
+/// follows the game.
+#[allow(clippy::too_many_arguments)]
+pub fn render_analysis(
+    mut commands: Commands,
+    analysis: Res<AnalysisBoard>,
+    board: Res<BoardState>,
+    castling: Res<Castling>,
+    theme: Res<Theme>,
+    piece_images: Res<PieceImages>,
+    scene: Query<Entity, (With<AnalysisScene>, Without<AnalysisCamera>)>,
+    mut windows: Query<&mut Window>,
+) {
+    let following = analysis.moves.is_empty() && (board.is_changed() || castling.is_changed());
+    if !analysis.is_changed() && !theme.is_changed() && !piece_images.is_changed() && !following {
+        return;
+    }
+    if let Ok(mut window) = windows.get_mut(analysis.window) {
+        window.title = analysis.title();
+    }
+    for entity in scene.iter() {
+        commands.entity(entity).despawn();
+    }
+    let (position, position_castling) = analysis.position(&board.0, castling.0);
+    let targets: Vec<Position> = analysis
+        .selected
+        .map(|from| legal_targets(&position, position_castling, from))
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(to, _)| to)
+        .collect();
+    for row in 0..BOARD_ROWS as i8 {
+        for col in 0..BOARD_COLS as i8 {
+            let pos = Position::new(row, col);
+            let drawn_at = analysis.flip(pos);
+            commands.spawn((
+                AnalysisScene,
+                RenderLayers::layer(ANALYSIS_LAYER),
+                Sprite {
+                    color: theme.square_color(pos),
+                    custom_size: Some(Vec2::splat(TILE_SIZE)),
+                    ..default()
+                },
+                Transform::from_translation(pos_to_vec3(drawn_at, 0.0)),
+            ));
+            let marker = if analysis.selected == Some(pos) {
+                Some(SELECTED_COLOR)
+            } else if targets.contains(&pos) {
+                Some(TARGET_COLOR)
+            } else {
+                None
+            };
+            if let Some(color) = marker {
+                commands.spawn((
+                    AnalysisScene,
+                    RenderLayers::layer(ANALYSIS_LAYER),
+                    Sprite {
+                        color,
+                        custom_size: Some(Vec2::splat(TILE_SIZE)),
+                        ..default()
+                    },
+                    Transform::from_translation(pos_to_vec3(drawn_at, 0.5)),
+                ));
+            }
+            let Some(piece) = position.get(pos) else {
+                continue;
+            };
+            commands.spawn((
+                AnalysisScene,
+                RenderLayers::layer(ANALYSIS_LAYER),
+                piece_images.sprite(piece.color, piece.piece_type),
+                Transform {
+                    translation: pos_to_vec3(drawn_at, PIECE_Z),
+                    scale: Vec3::splat(PIECE_SCALE),
+                    ..default()
+                },
+            ));
+        }
+    }
+}
+
+fn despawn_analysis(
+    commands: &mut Commands,
+    analysis: &AnalysisBoard,
+    scene: &Query<Entity, With<AnalysisScene>>,
+) {
+    for entity in scene.iter() {
+        commands.entity(entity).despawn();
+    }
+    commands.remove_resource::<AnalysisBoard>();
+    // Gone already if the window was closed by the player.
+    if let Ok(mut window) = commands.get_entity(analysis.window) {
+        window.despawn();
+    }
+}
+
+/// Closing the analysis window takes its board with it, and closing the
+/// main window closes the analysis window too, so the app still exits.
+pub fn close_analysis(
+    mut commands: Commands,
+    analysis: Res<AnalysisBoard>,
+    windows: Query<(), With<Window>>,
+    primary: Query<(), With<PrimaryWindow>>,
+    scene: Query<Entity, With<AnalysisScene>>,
+) {
+    if windows.contains(analysis.window) && !primary.is_empty() {
+        return;
+    }
+    despawn_analysis(&mut commands, &analysis, &scene);
+}
+
+/// Leaving the game for the menu closes the analysis window.
+pub fn close_analysis_on_exit(
+    mut commands: Commands,
+    analysis: Option<Res<AnalysisBoard>>,
+    scene: Query<Entity, With<AnalysisScene>>,
+) {
+    if let Some(analysis) = analysis {
+        despawn_analysis(&mut commands, &analysis, &scene);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fen;
+
+    fn analysis(bottom: HermanhaColor) -> AnalysisBoard {
+        AnalysisBoard {
+            window: Entity::PLACEHOLDER,
+            moves: Vec::new(),
+            selected: None,
+            bottom,
+        }
+    }
+
+    #[test]
+    fn black_at_the_bottom_turns_the_board_around() {
+        let corner = Position::new(0, 0);
+        assert!(analysis(HermanhaColor::White).flip(corner) == corner);
+        let black = analysis(HermanhaColor::Black);
+        assert!(black.flip(corner) == Position::new(7, 7));
+        let square = Position::new(2, 5);
+        assert!(black.flip(black.flip(square)) == square);
+    }
+
+    #[test]
+    fn the_game_shows_until_a_move_is_played_on_the_board() {
+        let mut board = analysis(HermanhaColor::White);
+        let (game, castling) = fen::board_from_fen("4k3/8/8/8/8/8/8/4K3 w - -").unwrap();
+        let (explored, explored_castling) =
+            fen::board_from_fen("4k3/8/8/8/8/8/4K3/8 b - -").unwrap();
+        assert_eq!(
+            board.position(&game, castling).0.move_turn,
+            HermanhaColor::White
+        );
+        assert_eq!(board.title(), "Analysis - following the game");
+
+        board.moves.push((explored, explored_castling));
+        assert_eq!(
+            board.position(&game, castling).0.move_turn,
+            HermanhaColor::Black
+        );
+        assert_eq!(
+            board.title(),
+            "Analysis - 1 move off the game (Esc to return)"
+        );
+    }
+}
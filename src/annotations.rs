@@ -0,0 +1,163 @@
+use bevy::prelude::*;
+use bevy::window::PrimaryWindow;
+use hermanha_chess::Position;
+
+use crate::board_render::BoardCamera;
+use crate::game_state::BoardState;
+use crate::ui::GameUi;
+use crate::{PIECE_Z, TILE_SIZE, cursor_to_board_position, pos_to_vec3};
+
+const ANNOTATION_Z: f32 = PIECE_Z + 1.0;
+const ARROW_WIDTH: f32 = TILE_SIZE * 0.18;
+const ARROW_HEAD_LENGTH: f32 = TILE_SIZE * 0.4;
+const ARROW_HEAD_WIDTH: f32 = TILE_SIZE * 0.45;
+
+/// Analysis arrows and marked squares drawn with the right mouse button.
+/// `drag_from` is the square the right button went down on.
+#[derive(Resource, Default)]
+pub struct Annotations {
+    arrows: Vec<(Position, Position)>,
+    marks: Vec<Position>,
+    drag_from: Option<Position>,
+}
+
+impl Annotations {
+    pub fn clear(&mut self) {
+        *self = Annotations::default();
+    }
+
+    fn toggle_arrow(&mut self, from: Position, to: Position) {
+        if let Some(index) = self.arrows.iter().position(|arrow| *arrow == (from, to)) {
+            self.arrows.remove(index);
+        } else {
+            self.arrows.push((from, to));
+        }
+    }
+
+    fn toggle_mark(&mut self, pos: Position) {
+        if let Some(index) = self.marks.iter().position(|mark| *mark == pos) {
+            self.marks.remove(index);
+        } else {
+            self.marks.push(pos);
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct AnnotationMarker;
+
+/// Dragging with the right button between two squares draws an arrow and
+/// right clicking a square marks it. Doing the same again removes it. A
+/// left click or the next move clears everything.
+pub fn annotate(
+    buttons: Res<ButtonInput<MouseButton>>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    camera_q: Query<(&Camera, &GlobalTransform), With<BoardCamera>>,
+    board: Res<BoardState>,
+    mut annotations: ResMut<Annotations>,
+) {
+    if board.is_changed() || buttons.just_pressed(MouseButton::Left) {
+        if !annotations.arrows.is_empty() || !annotations.marks.is_empty() {
+            annotations.clear();
+        }
+        return;
+    }
+    let pressed = buttons.just_pressed(MouseButton::Right);
+    let released = buttons.just_released(MouseButton::Right);
+    if !pressed && !released {
+        return;
+    }
+    let position = windows
+        .iter()
+        .next()
+        .and_then(Window::cursor_position)
+        .zip(camera_q.iter().next())
+        .and_then(|(cursor_position, (camera, camera_transform))| {
+            cursor_to_board_position(cursor_position, camera, camera_transform, &[])
+        })
+        .filter(|pos| board.0.pos_on_board(*pos));
+    if pressed {
+        annotations.drag_from = position;
+        return;
+    }
+    let (Some(from), Some(to)) = (annotations.drag_from.take(), position) else {
+        return;
+    };
+    if from == to {
+        annotations.toggle_mark(to);
+    } else {
+        annotations.toggle_arrow(from, to);
+    }
+}
+
+/// Arrows are a shaft and a head meeting at the center of the target
+/// square, drawn above the pieces. Marks are a ring around the square.
+pub fn render_annotations(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    annotations: Res<Annotations>,
+    markers: Query<Entity, With<AnnotationMarker>>,
+) {
+    if !annotations.is_changed() {
+        return;
+    }
+    for entity in markers.iter() {
+        commands.entity(entity).despawn();
+    }
+    if annotations.arrows.is_empty() && annotations.marks.is_empty() {
+        return;
+    }
+    let material = materials.add(Color::srgba(0.1, 0.5, 0.15, 0.75));
+    for &pos in &annotations.marks {
+        commands.spawn((
+            AnnotationMarker,
+            GameUi,
+            Mesh2d(meshes.add(Annulus::new(TILE_SIZE * 0.42, TILE_SIZE * 0.48))),
+            MeshMaterial2d(material.clone()),
+            Transform::from_translation(pos_to_vec3(pos, ANNOTATION_Z)),
+        ));
+    }
+    for &(from, to) in &annotations.arrows {
+        for (mesh, transform) in arrow(&mut meshes, from, to, ANNOTATION_Z) {
+            commands.spawn((
+                AnnotationMarker,
+                GameUi,
+                mesh,
+                MeshMaterial2d(material.clone()),
+                transform,
+            ));
+        }
+    }
+}
+
+/// The shaft and the head of an arrow from the center of one square to
+/// the center of another, where the head ends.
+pub fn arrow(
+    meshes: &mut Assets<Mesh>,
+    from: Position,
+    to: Position,
+    z: f32,
+) -> [(Mesh2d, Transform); 2] {
+    let start = pos_to_vec3(from, z).truncate();
+    let end = pos_to_vec3(to, z).truncate();
+    let direction = (end - start).normalize();
+    let rotation = Quat::from_rotation_z(direction.to_angle());
+    let shaft_length = start.distance(end) - ARROW_HEAD_LENGTH;
+    let shaft_center = start + direction * shaft_length * 0.5;
+    let head_base = start + direction * shaft_length;
+    [
+        (
+            Mesh2d(meshes.add(Rectangle::new(shaft_length, ARROW_WIDTH))),
+            Transform::from_translation(shaft_center.extend(z)).with_rotation(rotation),
+        ),
+        (
+            Mesh2d(meshes.add(Triangle2d::new(
+                Vec2::new(ARROW_HEAD_LENGTH, 0.0),
+                Vec2::new(0.0, ARROW_HEAD_WIDTH * 0.5),
+                Vec2::new(0.0, -ARROW_HEAD_WIDTH * 0.5),
+            ))),
+            Transform::from_translation(head_base.extend(z)).with_rotation(rotation),
+        ),
+    ]
+}
@@ -0,0 +1,128 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::io::{self, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use chess_app::lobby::{Challenge, DEFAULT_LOBBY_PORT, Request, Response, read_line};
+
+/// A listed game and where its host accepts connections.
+struct OpenGame {
+    host: SocketAddr,
+    challenge: Challenge,
+}
+
+/// The games waiting for an opponent, by id.
+#[derive(Default)]
+struct Lobby {
+    games: BTreeMap<u32, OpenGame>,
+    next_id: u32,
+}
+
+/// Serves one connection, a line per request, until it closes or sends a
+/// line longer than `lobby::MAX_LINE`. The games a connection opened are
+/// taken off the list when it closes, since their host has given up
+/// waiting.
+fn serve(stream: TcpStream, lobby: Arc<Mutex<Lobby>>) -> io::Result<()> {
+    let peer = stream.peer_addr()?;
+    let mut writer = stream.try_clone()?;
+    let mut opened = Vec::new();
+    let mut result = Ok(());
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        match read_line(&mut reader, &mut line) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(err) => {
+                result = Err(err);
+                break;
+            }
+        }
+        let responses = match Request::parse(&line) {
+            Ok(request) => answer(request, peer, &lobby, &mut opened),
+            Err(err) => vec![Response::Error(err)],
+        };
+        let text: String = responses.iter().map(Response::to_line).collect();
+        if let Err(err) = writer.write_all(text.as_bytes()) {
+            result = Err(err);
+            break;
+        }
+    }
+    let mut lobby = lobby.lock().unwrap();
+    for id in opened {
+        lobby.games.remove(&id);
+    }
+    result
+}
+
+fn answer(
+    request: Request,
+    peer: SocketAddr,
+    lobby: &Mutex<Lobby>,
+    opened: &mut Vec<u32>,
+) -> Vec<Response> {
+    let mut lobby = lobby.lock().unwrap();
+    match request {
+        Request::Open { port, challenge } => {
+            let id = lobby.next_id;
+            lobby.next_id += 1;
+            let host = SocketAddr::new(peer.ip(), port);
+            println!("{} opened game {id} on {host}", challenge.name);
+            lobby.games.insert(id, OpenGame { host, challenge });
+            opened.push(id);
+            vec![Response::Opened(id)]
+        }
+        Request::List => lobby
+            .games
+            .iter()
+            .map(|(id, game)| Response::Game(*id, game.challenge.clone()))
+            .chain([Response::End])
+            .collect(),
+        Request::Accept(id) => match lobby.games.remove(&id) {
+            Some(game) => {
+                println!("Game {id} accepted by {peer}");
+                vec![Response::Match(game.host.to_string(), game.challenge)]
+            }
+            None => vec![Response::Error(format!("Game {id} is no longer open"))],
+        },
+    }
+}
+
+/// A matchmaking lobby. Hosts list their games here and players pick one
+/// to join; the lobby only passes on the host's address, and the game
+/// itself is played over a direct connection.
+///
+/// Usage: `lobby [address]`, listening on `0.0.0.0:8100` by default.
+fn main() {
+    let address = env::args()
+        .nth(1)
+        .unwrap_or_else(|| format!("0.0.0.0:{DEFAULT_LOBBY_PORT}"));
+    let listener = match TcpListener::bind(&address) {
+        Ok(listener) => listener,
+        Err(err) => {
+            eprintln!("Could not listen on {address}: {err}");
+            process::exit(1);
+        }
+    };
+    println!("Lobby listening on {address}");
+    let lobby = Arc::new(Mutex::new(Lobby::default()));
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                eprintln!("Could not accept a connection: {err}");
+                continue;
+            }
+        };
+        let lobby = lobby.clone();
+        thread::spawn(move || {
+            if let Err(err) = serve(stream, lobby) {
+                eprintln!("Connection failed: {err}");
+            }
+        });
+    }
+}
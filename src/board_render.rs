@@ -0,0 +1,699 @@
+use std::f32::consts::PI;
+
+use bevy::input::{ButtonInput, InputSystem};
+use bevy::prelude::*;
+use hermanha_chess::{BOARD_COLS, BOARD_ROWS, Color as HermanhaColor, PieceType, Position};
+
+use crate::actions::{self, Action};
+use crate::analysis::{self, AnalysisBoard};
+use crate::export::{
+    self, AnimationRequested, AnimationWriter, ImageExport, PendingAnimation, PendingExport,
+};
+use crate::game_state::{BoardState, Castling, LocalPlayer, PlayerColor, SelectedSquare};
+use crate::history::MoveHistory;
+use crate::input::{TargetKind, legal_targets};
+use crate::menu::AppState;
+use crate::piece_images::{self, PieceImages};
+use crate::review::ReviewPosition;
+use crate::theme::{Square, Theme};
+use crate::training::Training;
+use crate::{
+    BOARD_OFFSET, GameSet, PIECE_SCALE, PIECE_Z, TILE_SIZE, annotations, cursor, opponent_move,
+    pos_to_vec3, premove, rules,
+};
+
+const CHECK_ALPHA: f32 = 0.6;
+
+/// The board, the pieces and the markers drawn over them, and the camera
+/// that turns with the board's orientation.
+pub struct BoardRenderPlugin;
+
+impl Plugin for BoardRenderPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ShowEnPassant>()
+            .init_resource::<ShowCastling>()
+            .init_resource::<ShowMaterial>()
+            .init_resource::<BoardOrientation>()
+            .init_resource::<Training>()
+            .init_resource::<ImageExport>()
+            .init_resource::<PieceImages>()
+            .add_event::<AnimationRequested>()
+            .add_systems(Startup, (setup_camera, render_board))
+            .add_systems(
+                Update,
+                (
+                    export::finish_export.run_if(resource_exists::<PendingExport>),
+                    export::advance_animation.run_if(resource_exists::<PendingAnimation>),
+                    export::report_animation.run_if(resource_exists::<AnimationWriter>),
+                    analysis::close_analysis.run_if(resource_exists::<AnalysisBoard>),
+                    piece_images::rasterize_pieces.before(GameSet::Render),
+                ),
+            )
+            .add_systems(
+                PreUpdate,
+                analysis::route_analysis_keys
+                    .after(InputSystem)
+                    .run_if(resource_exists::<AnalysisBoard>),
+            )
+            .add_systems(
+                Update,
+                (
+                    (analysis::follow_game, analysis::handle_analysis_clicks)
+                        .in_set(GameSet::Input),
+                    analysis::render_analysis.in_set(GameSet::Render),
+                )
+                    .run_if(resource_exists::<AnalysisBoard>)
+                    .run_if(in_state(AppState::Playing)),
+            )
+            .add_systems(OnExit(AppState::Playing), analysis::close_analysis_on_exit)
+            .add_systems(
+                Update,
+                (render_pieces, apply_training)
+                    .chain()
+                    .in_set(GameSet::Render)
+                    .run_if(in_state(AppState::Playing).or(in_state(AppState::Setup))),
+            )
+            .add_systems(
+                Update,
+                (
+                    (
+                        toggle_en_passant,
+                        toggle_castling,
+                        toggle_material,
+                        toggle_orientation,
+                        export::export_image,
+                        export::export_animation,
+                        analysis::open_analysis,
+                    )
+                        .in_set(GameSet::Input),
+                    (
+                        render_highlights,
+                        (render_check, flash_check).chain(),
+                        render_en_passant,
+                        render_castling,
+                        render_material,
+                        cursor::render_cursor,
+                        cursor::render_hover,
+                        premove::render_premove,
+                        annotations::render_annotations,
+                        (
+                            opponent_move::mark_opponent_move,
+                            opponent_move::fade_opponent_move,
+                            opponent_move::pulse_pieces,
+                        )
+                            .chain()
+                            .after(render_pieces),
+                        (orient_to_player, toggle_auto_rotate, animate_rotation)
+                            .chain()
+                            .after(render_pieces)
+                            .after(render_material),
+                    )
+                        .in_set(GameSet::Render),
+                )
+                    .run_if(in_state(AppState::Playing)),
+            );
+    }
+}
+
+#[derive(Resource)]
+struct ShowEnPassant(bool);
+
+impl Default for ShowEnPassant {
+    fn default() -> Self {
+        ShowEnPassant(true)
+    }
+}
+
+#[derive(Resource, Default)]
+struct ShowCastling(bool);
+
+#[derive(Resource, Default)]
+struct ShowMaterial(bool);
+
+/// Which side is drawn at the bottom of the screen. The board is flipped
+/// by turning the camera, so world coordinates and `pos_to_vec3` stay the
+/// same and `cursor_to_board_position` follows the camera transform.
+#[derive(Resource, Default, Clone, Copy, PartialEq)]
+pub enum BoardOrientation {
+    #[default]
+    White,
+    Black,
+}
+
+impl BoardOrientation {
+    fn angle(self) -> f32 {
+        match self {
+            BoardOrientation::White => 0.0,
+            BoardOrientation::Black => PI,
+        }
+    }
+
+    pub fn bottom(self) -> HermanhaColor {
+        match self {
+            BoardOrientation::White => HermanhaColor::White,
+            BoardOrientation::Black => HermanhaColor::Black,
+        }
+    }
+}
+
+/// Teaching-demo option that turns the board 180° after every move.
+#[derive(Resource, Default)]
+pub struct AutoRotate {
+    enabled: bool,
+    pub angle: f32,
+    target: f32,
+    last_ply: u32,
+}
+
+#[derive(Component, Clone, Copy)]
+#[require(Transform, Sprite)]
+pub struct Piece {
+    pub pos: Position,
+    pub color: HermanhaColor,
+    pub piece_type: PieceType,
+}
+
+impl Piece {
+    fn same_kind(&self, other: &Piece) -> bool {
+        self.color == other.color && rules::same_type(self.piece_type, other.piece_type)
+    }
+}
+
+/// The camera showing the game's board in the main window, as opposed to
+/// the ones drawing exported images or the analysis board.
+#[derive(Component)]
+pub struct BoardCamera;
+
+/// Entities that should stay upright on screen however the camera is
+/// rotated.
+#[derive(Component)]
+pub struct Upright;
+
+#[derive(Component)]
+struct Highlight;
+
+#[derive(Component)]
+struct EnPassantMarker;
+
+/// Red tint on the square of a king in check. The timer drives a short
+/// flash when the check first appears.
+#[derive(Component)]
+struct CheckMarker(Timer);
+
+#[derive(Component)]
+struct CastlingMarker;
+
+#[derive(Component)]
+struct MaterialDisplay;
+
+fn setup_camera(mut commands: Commands) {
+    commands.spawn((BoardCamera, Camera2d));
+}
+
+fn render_board(mut commands: Commands, theme: Res<Theme>) {
+    for row in 0..BOARD_ROWS as usize {
+        for col in 0..BOARD_COLS as usize {
+            let render_pos = Position::new(row as i8, col as i8);
+
+            let color = theme.square_color(render_pos);
+            spawn_square(&mut commands, render_pos, color);
+        }
+    }
+}
+
+/// Brings the piece entities in line with the board whenever it changes,
+/// or with the position being reviewed after the game. Pieces that stayed
+/// put are left alone, pieces that moved are repositioned, and only
+/// captures, promotions and new pieces touch the entity list.
+#[allow(clippy::too_many_arguments)]
+fn render_pieces(
+    mut commands: Commands,
+    piece_images: Res<PieceImages>,
+    board: Res<BoardState>,
+    castling: Res<Castling>,
+    history: Res<MoveHistory>,
+    review: Option<Res<ReviewPosition>>,
+    mut reviewing: Local<bool>,
+    mut pieces: Query<(Entity, &mut Piece, &mut Transform)>,
+) {
+    let review_changed = review.as_ref().is_some_and(|review| review.is_changed());
+    if !board.is_changed() && !review_changed && *reviewing == review.is_some() {
+        return;
+    }
+    *reviewing = review.is_some();
+    let (shown, _) = match &review {
+        Some(review) => review.shown(&history, (&board.0, castling.0)),
+        None => (&board.0, castling.0),
+    };
+    let mut stale: Vec<(Entity, Piece)> = Vec::new();
+    let mut missing: Vec<Piece> = Vec::new();
+    for row in 0..BOARD_ROWS as usize {
+        for col in 0..BOARD_COLS as usize {
+            let pos = Position::new(row as i8, col as i8);
+            if let Some(piece) = shown.get(pos) {
+                missing.push(Piece {
+                    pos,
+                    color: piece.color,
+                    piece_type: piece.piece_type,
+                });
+            }
+        }
+    }
+    for (entity, piece, _) in pieces.iter() {
+        if let Some(index) = missing
+            .iter()
+            .position(|wanted| wanted.pos == piece.pos && wanted.same_kind(piece))
+        {
+            missing.swap_remove(index);
+        } else {
+            stale.push((entity, *piece));
+        }
+    }
+    for wanted in missing {
+        let Some(index) = stale.iter().position(|(_, piece)| piece.same_kind(&wanted)) else {
+            spawn_piece(&mut commands, &piece_images, wanted);
+            continue;
+        };
+        let (entity, _) = stale.swap_remove(index);
+        if let Ok((_, mut piece, mut transform)) = pieces.get_mut(entity) {
+            *piece = wanted;
+            transform.translation = pos_to_vec3(wanted.pos, PIECE_Z);
+        }
+    }
+    for (entity, _) in stale {
+        commands.entity(entity).despawn();
+    }
+}
+
+/// Hides the pieces the training mode leaves out. Our own pieces are
+/// those we play, or in hotseat play those at the bottom. Setting up a
+/// position always shows every piece.
+fn apply_training(
+    training: Res<Training>,
+    local_player: Res<LocalPlayer>,
+    orientation: Res<BoardOrientation>,
+    state: Res<State<AppState>>,
+    mut pieces: Query<(&Piece, &mut Visibility)>,
+) {
+    let own = match *local_player {
+        LocalPlayer::One(color) => color,
+        _ => orientation.bottom(),
+    };
+    let training = match state.get() {
+        AppState::Playing => *training,
+        _ => Training::Off,
+    };
+    for (piece, mut visibility) in pieces.iter_mut() {
+        visibility.set_if_neq(if training.shows(piece.color, piece.piece_type, own) {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        });
+    }
+}
+
+fn render_highlights(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    board: Res<BoardState>,
+    castling: Res<Castling>,
+    selected: Res<SelectedSquare>,
+    theme: Res<Theme>,
+    highlights: Query<Entity, With<Highlight>>,
+) {
+    if !board.is_changed() && !selected.is_changed() && !theme.is_changed() {
+        return;
+    }
+    for entity in highlights.iter() {
+        commands.entity(entity).despawn();
+    }
+
+    let Some(selected_pos) = selected.0 else {
+        return;
+    };
+    let board = &board.0;
+    let legal_targets = legal_targets(board, castling.0, selected_pos);
+
+    for (target, kind) in legal_targets {
+        spawn_highlight(
+            &mut commands,
+            &mut meshes,
+            &mut materials,
+            target,
+            kind,
+            theme.palette.legal_move(),
+        );
+    }
+}
+
+fn render_check(
+    mut commands: Commands,
+    board: Res<BoardState>,
+    theme: Res<Theme>,
+    markers: Query<Entity, With<CheckMarker>>,
+) {
+    if !board.is_changed() && !theme.is_changed() {
+        return;
+    }
+    for entity in markers.iter() {
+        commands.entity(entity).despawn();
+    }
+    let board = &board.0;
+    if !rules::in_check(board, board.move_turn) {
+        return;
+    }
+    if let Some(king_pos) = rules::king_position(board, board.move_turn) {
+        spawn_check_marker(&mut commands, king_pos, &theme);
+    }
+}
+
+fn flash_check(time: Res<Time>, mut markers: Query<(&mut CheckMarker, &mut Sprite)>) {
+    for (mut marker, mut sprite) in markers.iter_mut() {
+        marker.0.tick(time.delta());
+        let alpha = if marker.0.finished() {
+            CHECK_ALPHA
+        } else {
+            let pulse = (marker.0.fraction() * 3.0 * 2.0 * PI).cos() * 0.5 + 0.5;
+            0.2 + pulse * (0.9 - 0.2)
+        };
+        sprite.color.set_alpha(alpha);
+    }
+}
+
+fn toggle_en_passant(keys: Res<ButtonInput<KeyCode>>, mut show: ResMut<ShowEnPassant>) {
+    if actions::just_pressed(&keys, Action::ToggleEnPassant) {
+        show.0 = !show.0;
+    }
+}
+
+fn render_en_passant(
+    mut commands: Commands,
+    board: Res<BoardState>,
+    show: Res<ShowEnPassant>,
+    markers: Query<Entity, With<EnPassantMarker>>,
+) {
+    if !board.is_changed() && !show.is_changed() {
+        return;
+    }
+    for entity in markers.iter() {
+        commands.entity(entity).despawn();
+    }
+    if !show.0 {
+        return;
+    }
+    for en_passant in rules::en_passant_captures(&board.0) {
+        spawn_en_passant_marker(&mut commands, en_passant.captured, TILE_SIZE);
+        spawn_en_passant_marker(&mut commands, en_passant.to, TILE_SIZE * 0.3);
+    }
+}
+
+fn toggle_castling(keys: Res<ButtonInput<KeyCode>>, mut show: ResMut<ShowCastling>) {
+    if actions::just_pressed(&keys, Action::ToggleCastling) {
+        show.0 = !show.0;
+    }
+}
+
+fn render_castling(
+    mut commands: Commands,
+    board: Res<BoardState>,
+    castling: Res<Castling>,
+    show: Res<ShowCastling>,
+    markers: Query<Entity, With<CastlingMarker>>,
+) {
+    if !board.is_changed() && !castling.is_changed() && !show.is_changed() {
+        return;
+    }
+    for entity in markers.iter() {
+        commands.entity(entity).despawn();
+    }
+    if !show.0 {
+        return;
+    }
+    for color in [HermanhaColor::White, HermanhaColor::Black] {
+        let Some(king_pos) = rules::king_position(&board.0, color) else {
+            continue;
+        };
+        if castling.0.kingside(color) {
+            spawn_castling_marker(&mut commands, king_pos, 1.0);
+        }
+        if castling.0.queenside(color) {
+            spawn_castling_marker(&mut commands, king_pos, -1.0);
+        }
+    }
+}
+
+fn toggle_material(keys: Res<ButtonInput<KeyCode>>, mut show: ResMut<ShowMaterial>) {
+    if actions::just_pressed(&keys, Action::MaterialBalance) {
+        show.0 = !show.0;
+    }
+}
+
+/// Captured-piece trays next to the board, the pieces White has taken by
+/// White's edge and Black's by Black's. Pieces of one kind overlap more
+/// tightly than different kinds. Whoever is ahead on material also gets
+/// the point difference.
+fn render_material(
+    mut commands: Commands,
+    piece_images: Res<PieceImages>,
+    board: Res<BoardState>,
+    history: Res<MoveHistory>,
+    show: Res<ShowMaterial>,
+    displays: Query<Entity, With<MaterialDisplay>>,
+) {
+    if !board.is_changed()
+        && !show.is_changed()
+        && !piece_images.is_changed()
+        && !history.is_changed()
+    {
+        return;
+    }
+    for entity in displays.iter() {
+        commands.entity(entity).despawn();
+    }
+    if !show.0 {
+        return;
+    }
+    let x = (BOARD_COLS as f32 * 0.5 + 0.5) * TILE_SIZE;
+    for (color, y) in [
+        (HermanhaColor::White, -BOARD_OFFSET * TILE_SIZE),
+        (HermanhaColor::Black, BOARD_OFFSET * TILE_SIZE),
+    ] {
+        let mut offset = 0.0;
+        let mut previous: Option<PieceType> = None;
+        for piece_type in history.captured_by(color) {
+            if let Some(previous) = previous {
+                offset += if rules::same_type(previous, piece_type) {
+                    TILE_SIZE * 0.2
+                } else {
+                    TILE_SIZE * 0.4
+                };
+            }
+            previous = Some(piece_type);
+            commands.spawn((
+                MaterialDisplay,
+                Upright,
+                piece_images.sprite(rules::opponent(color), piece_type),
+                Transform {
+                    translation: Vec3::new(x + offset, y, PIECE_Z + offset * 0.001),
+                    scale: Vec3::splat(PIECE_SCALE * 0.5),
+                    ..default()
+                },
+            ));
+        }
+        let sign = if color == HermanhaColor::White { 1 } else { -1 };
+        let balance = rules::material_balance(&board.0) * sign;
+        if balance > 0 {
+            commands.spawn((
+                MaterialDisplay,
+                Upright,
+                Text2d::new(format!("+{balance}")),
+                TextFont {
+                    font_size: 18.0,
+                    ..default()
+                },
+                Transform::from_translation(Vec3::new(x + offset + TILE_SIZE * 0.5, y, PIECE_Z)),
+            ));
+        }
+    }
+}
+
+fn toggle_auto_rotate(
+    keys: Res<ButtonInput<KeyCode>>,
+    history: Res<MoveHistory>,
+    mut rotate: ResMut<AutoRotate>,
+) {
+    if actions::just_pressed(&keys, Action::AutoRotate) {
+        rotate.enabled = !rotate.enabled;
+        if !rotate.enabled {
+            rotate.target = 0.0;
+        }
+    }
+    if rotate.last_ply != history.ply_count() {
+        rotate.last_ply = history.ply_count();
+        if rotate.enabled {
+            rotate.target = (rotate.target + PI) % (2.0 * PI);
+        }
+    }
+}
+
+/// In online games the local player's pieces start at the bottom. The
+/// client learns its color from the handshake, so this follows changes.
+fn orient_to_player(
+    player_color: Option<Res<PlayerColor>>,
+    mut orientation: ResMut<BoardOrientation>,
+) {
+    if let Some(player_color) = player_color
+        && player_color.is_changed()
+    {
+        *orientation = match player_color.0 {
+            HermanhaColor::White => BoardOrientation::White,
+            HermanhaColor::Black => BoardOrientation::Black,
+        };
+    }
+}
+
+fn toggle_orientation(keys: Res<ButtonInput<KeyCode>>, mut orientation: ResMut<BoardOrientation>) {
+    if actions::just_pressed(&keys, Action::FlipBoard) {
+        *orientation = match *orientation {
+            BoardOrientation::White => BoardOrientation::Black,
+            BoardOrientation::Black => BoardOrientation::White,
+        };
+    }
+}
+
+/// Eases the camera toward the target angle and counter-rotates every
+/// `Upright` entity by the same amount.
+fn animate_rotation(
+    time: Res<Time>,
+    orientation: Res<BoardOrientation>,
+    mut rotate: ResMut<AutoRotate>,
+    mut cameras: Query<&mut Transform, With<BoardCamera>>,
+    mut uprights: Query<&mut Transform, (With<Upright>, Without<BoardCamera>)>,
+) {
+    let target = (rotate.target + orientation.angle()) % (2.0 * PI);
+    // Always turn the short way round, so going from 180° to 0° after a
+    // wrap doesn't spin a full circle.
+    let mut delta = target - rotate.angle;
+    if delta > PI {
+        delta -= 2.0 * PI;
+    } else if delta < -PI {
+        delta += 2.0 * PI;
+    }
+    if delta.abs() < 0.001 {
+        rotate.angle = target;
+    } else {
+        rotate.angle += delta * (1.0 - (-8.0 * time.delta_secs()).exp());
+    }
+    let rotation = Quat::from_rotation_z(rotate.angle);
+    for mut transform in cameras.iter_mut() {
+        transform.rotation = rotation;
+    }
+    for mut transform in uprights.iter_mut() {
+        transform.rotation = rotation;
+    }
+}
+
+fn spawn_square(commands: &mut Commands, pos: Position, color: Color) {
+    commands.spawn((
+        Square(pos),
+        Sprite {
+            color,
+            custom_size: Some(Vec2::splat(TILE_SIZE)),
+            ..default()
+        },
+        Transform::from_translation(pos_to_vec3(pos, 0.0)),
+    ));
+}
+
+/// Quiet moves get a small dot, captures a ring around the occupied square.
+fn spawn_highlight(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<ColorMaterial>,
+    pos: Position,
+    kind: TargetKind,
+    color: Color,
+) {
+    let mesh = match kind {
+        TargetKind::Quiet => meshes.add(Circle::new(TILE_SIZE * 0.16)),
+        TargetKind::Capture => meshes.add(Annulus::new(TILE_SIZE * 0.42, TILE_SIZE * 0.5)),
+    };
+    commands.spawn((
+        Highlight,
+        Mesh2d(mesh),
+        MeshMaterial2d(materials.add(color.with_alpha(0.45))),
+        Transform::from_translation(pos_to_vec3(pos, PIECE_Z + 0.5)),
+    ));
+}
+
+/// With shape markers on, a cross over the square says "check" without
+/// relying on the tint.
+fn spawn_check_marker(commands: &mut Commands, pos: Position, theme: &Theme) {
+    let mut marker = commands.spawn((
+        CheckMarker(Timer::from_seconds(0.9, TimerMode::Once)),
+        Sprite {
+            color: theme.palette.check().with_alpha(CHECK_ALPHA),
+            custom_size: Some(Vec2::splat(TILE_SIZE)),
+            ..default()
+        },
+        Transform::from_translation(pos_to_vec3(pos, 0.3)),
+    ));
+    if !theme.shape_markers {
+        return;
+    }
+    for angle in [PI / 4.0, -PI / 4.0] {
+        marker.with_child((
+            Sprite {
+                color: Color::srgba(0.05, 0.05, 0.05, 0.8),
+                custom_size: Some(Vec2::new(TILE_SIZE * 1.2, TILE_SIZE * 0.08)),
+                ..default()
+            },
+            Transform {
+                translation: Vec3::new(0.0, 0.0, 0.05),
+                rotation: Quat::from_rotation_z(angle),
+                ..default()
+            },
+        ));
+    }
+}
+
+fn spawn_en_passant_marker(commands: &mut Commands, pos: Position, size: f32) {
+    commands.spawn((
+        EnPassantMarker,
+        Sprite {
+            color: Color::srgba(0.95, 0.62, 0.2, 0.45),
+            custom_size: Some(Vec2::splat(size)),
+            ..default()
+        },
+        Transform::from_translation(pos_to_vec3(pos, 0.4)),
+    ));
+}
+
+/// Draws a thin bar on the kingside (`side = 1.0`) or queenside
+/// (`side = -1.0`) edge of the king's square.
+fn spawn_castling_marker(commands: &mut Commands, king_pos: Position, side: f32) {
+    let offset = Vec3::new(side * TILE_SIZE * 0.45, 0.0, 0.0);
+    commands.spawn((
+        CastlingMarker,
+        Sprite {
+            color: Color::srgba(0.25, 0.55, 0.85, 0.8),
+            custom_size: Some(Vec2::new(TILE_SIZE * 0.08, TILE_SIZE * 0.6)),
+            ..default()
+        },
+        Transform::from_translation(pos_to_vec3(king_pos, 0.4) + offset),
+    ));
+}
+
+fn spawn_piece(commands: &mut Commands, piece_images: &PieceImages, piece: Piece) {
+    commands.spawn((
+        piece,
+        Upright,
+        piece_images.sprite(piece.color, piece.piece_type),
+        Transform {
+            translation: pos_to_vec3(piece.pos, PIECE_Z),
+            scale: Vec3::splat(PIECE_SCALE),
+            ..default()
+        },
+    ));
+}
@@ -0,0 +1,140 @@
+use bevy::input::ButtonInput;
+use bevy::input::keyboard::{Key, KeyboardInput};
+use bevy::prelude::*;
+
+use crate::actions::{self, Action, ActiveScopes};
+use crate::move_input::MoveInput;
+use crate::tcp::ChatMessage;
+use crate::ui::GameUi;
+
+/// How many lines of history the panel shows.
+const VISIBLE_LINES: usize = 6;
+
+/// Sent and received chat lines, oldest first.
+#[derive(Resource, Default)]
+pub struct ChatLog {
+    pub lines: Vec<String>,
+}
+
+/// The line being typed. While `open` is set the chat owns the keyboard.
+#[derive(Resource, Default)]
+pub struct ChatInput {
+    open: bool,
+    text: String,
+}
+
+/// A line the local player wants sent to the opponent.
+#[derive(Event)]
+pub struct OutgoingChat(pub String);
+
+#[derive(Component)]
+pub struct ChatPanel;
+
+pub fn spawn_chat_panel(mut commands: Commands) {
+    commands.spawn((
+        ChatPanel,
+        GameUi,
+        Text::new(""),
+        TextFont {
+            font_size: 14.0,
+            ..default()
+        },
+        Node {
+            position_type: PositionType::Absolute,
+            left: Val::Percent(25.0),
+            width: Val::Percent(40.0),
+            bottom: Val::Px(8.0),
+            padding: UiRect::all(Val::Px(6.0)),
+            ..default()
+        },
+        BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.4)),
+    ));
+}
+
+/// The Chat action opens the input line and sends it; Escape closes it.
+/// While the input is open every key press goes to the chat, and the
+/// keyboard state is cleared so the rest of the app doesn't react to the
+/// typing. Runs in `PreUpdate`, right after input is collected. Keys typed
+/// into the move box are left to it.
+#[allow(clippy::too_many_arguments)]
+pub fn type_chat(
+    mut keyboard: EventReader<KeyboardInput>,
+    mut keys: ResMut<ButtonInput<KeyCode>>,
+    scopes: Res<ActiveScopes>,
+    mut input: ResMut<ChatInput>,
+    mut log: ResMut<ChatLog>,
+    mut outgoing: EventWriter<OutgoingChat>,
+    move_input: Res<MoveInput>,
+) {
+    if move_input.is_open() {
+        keyboard.clear();
+        return;
+    }
+    if !input.open {
+        keyboard.clear();
+        if actions::just_pressed_in(&keys, &scopes, Action::Chat) {
+            input.open = true;
+            keys.reset_all();
+        }
+        return;
+    }
+    for event in keyboard.read() {
+        if !event.state.is_pressed() {
+            continue;
+        }
+        match &event.logical_key {
+            Key::Escape => {
+                input.open = false;
+                input.text.clear();
+            }
+            Key::Backspace => {
+                input.text.pop();
+            }
+            Key::Space if input.text.len() < ChatMessage::MAX_LEN => {
+                input.text.push(' ');
+            }
+            Key::Character(chars) => {
+                for c in chars.chars().filter(|c| ChatMessage::is_valid_char(*c)) {
+                    if input.text.len() < ChatMessage::MAX_LEN {
+                        input.text.push(c);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    // The chat has the keyboard to itself while open, so its key sends
+    // the line whichever scope would have it otherwise.
+    if input.open && actions::just_pressed(&keys, Action::Chat) {
+        if !input.text.is_empty() {
+            let text = std::mem::take(&mut input.text);
+            log.lines.push(format!("You: {text}"));
+            outgoing.write(OutgoingChat(text));
+        }
+        input.open = false;
+    }
+    keys.reset_all();
+}
+
+pub fn render_chat(
+    log: Res<ChatLog>,
+    input: Res<ChatInput>,
+    mut panels: Query<&mut Text, With<ChatPanel>>,
+) {
+    if !log.is_changed() && !input.is_changed() {
+        return;
+    }
+    let start = log.lines.len().saturating_sub(VISIBLE_LINES);
+    let mut text = log.lines[start..].join("\n");
+    if input.open {
+        if !text.is_empty() {
+            text.push('\n');
+        }
+        text.push_str(&format!("> {}_", input.text));
+    } else if text.is_empty() {
+        text.push_str("Press Enter to chat");
+    }
+    for mut panel in panels.iter_mut() {
+        panel.0 = text.clone();
+    }
+}
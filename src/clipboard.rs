@@ -0,0 +1,223 @@
+use arboard::Clipboard;
+use bevy::ecs::system::SystemParam;
+use bevy::input::ButtonInput;
+use bevy::prelude::*;
+use hermanha_chess::{Board, Color as HermanhaColor};
+
+use crate::actions::{self, Action};
+use crate::clock::Clocks;
+use crate::computer::{Computer, Seed};
+use crate::fen::position_to_fen;
+use crate::game_state::{BoardState, Castling, GameOutcome, NewGame, PlayerColor};
+use crate::history::MoveHistory;
+use crate::net::{Connection, Opponent, PlayerName};
+use crate::offers::Concluded;
+use crate::pgn;
+use crate::rules::CastlingRights;
+use crate::toast::Toasts;
+use crate::ui::GameUi;
+use crate::widgets;
+
+/// The system clipboard's text.
+pub fn read_text() -> Result<String, String> {
+    Clipboard::new()
+        .and_then(|mut clipboard| clipboard.get_text())
+        .map_err(|err| err.to_string())
+}
+
+/// Replaces the system clipboard's text.
+pub fn write_text(text: String) -> Result<(), String> {
+    Clipboard::new()
+        .and_then(|mut clipboard| clipboard.set_text(text))
+        .map_err(|err| err.to_string())
+}
+
+/// A game pasted from the clipboard, waiting for the player to confirm
+/// that it should replace the one on the board.
+#[derive(Resource, Default)]
+pub struct PendingPaste(pub Option<(Board, CastlingRights, MoveHistory)>);
+
+#[derive(Component)]
+pub struct PasteDialog;
+
+#[derive(Component, Clone, Copy)]
+pub enum PasteButton {
+    Load,
+    Cancel,
+}
+
+/// The game on the board and who is playing it, for writing it out as PGN.
+#[derive(SystemParam)]
+pub struct GameRecord<'w> {
+    board: Res<'w, BoardState>,
+    castling: Res<'w, Castling>,
+    history: Res<'w, MoveHistory>,
+    outcome: Res<'w, GameOutcome>,
+    concluded: Res<'w, Concluded>,
+    clocks: Option<Res<'w, Clocks>>,
+    player_color: Option<Res<'w, PlayerColor>>,
+    player_name: Res<'w, PlayerName>,
+    opponent: Option<Res<'w, Opponent>>,
+    computer: Option<Res<'w, Computer>>,
+    seed: Option<Res<'w, Seed>>,
+}
+
+impl GameRecord<'_> {
+    /// The game so far as PGN.
+    pub fn pgn(&self) -> String {
+        let opponent_name = self
+            .opponent
+            .as_ref()
+            .map_or("Opponent", |opponent| &opponent.name);
+        let player_name = self.player_name.0.as_str();
+        let (white, black) = match self.player_color.as_deref() {
+            Some(PlayerColor(HermanhaColor::White)) => (player_name, opponent_name),
+            Some(PlayerColor(HermanhaColor::Black)) => (opponent_name, player_name),
+            None => ("White", "Black"),
+        };
+        let result = pgn::result_token(
+            self.outcome.0,
+            self.concluded.0,
+            self.clocks.as_ref().and_then(|clocks| clocks.flagged),
+        );
+        let start = self
+            .history
+            .start()
+            .unwrap_or((&self.board.0, self.castling.0));
+        // Only games against the computer are played from the seed.
+        let seed = self
+            .seed
+            .as_ref()
+            .filter(|_| self.computer.is_some())
+            .map(|seed| seed.0);
+        pgn::to_pgn(&self.history, start, white, black, result, seed)
+    }
+}
+
+/// Ctrl+C copies the position as FEN, Ctrl+Shift+C the game so far as PGN
+/// and Ctrl+M only its numbered moves, on one line.
+pub fn copy_to_clipboard(
+    keys: Res<ButtonInput<KeyCode>>,
+    game: GameRecord,
+    mut toasts: ResMut<Toasts>,
+) {
+    let (text, what) = if actions::just_pressed(&keys, Action::CopyFen) {
+        (
+            format!("{} - 0 1", position_to_fen(&game.board.0, game.castling.0)),
+            "FEN",
+        )
+    } else if actions::just_pressed(&keys, Action::CopyPgn) {
+        (game.pgn(), "PGN")
+    } else if actions::just_pressed(&keys, Action::CopyMoves) {
+        (game.history.movetext().replace('\n', " "), "Moves")
+    } else {
+        return;
+    };
+    match write_text(text) {
+        Ok(()) => toasts.push(format!("{what} copied to the clipboard")),
+        Err(err) => {
+            warn!("Could not write clipboard: {err}");
+            toasts.push(format!("Could not copy {what}: {err}"));
+        }
+    }
+}
+
+/// Ctrl+V reads a FEN or PGN from the clipboard and asks before loading
+/// it. Only available in local play.
+pub fn paste_from_clipboard(
+    keys: Res<ButtonInput<KeyCode>>,
+    connection: Option<Res<Connection>>,
+    mut pending: ResMut<PendingPaste>,
+    mut toasts: ResMut<Toasts>,
+) {
+    if connection.is_some() || !actions::just_pressed(&keys, Action::Paste) {
+        return;
+    }
+    let text = match read_text() {
+        Ok(text) => text,
+        Err(err) => {
+            warn!("Could not read clipboard: {err}");
+            toasts.push(format!("Could not read the clipboard: {err}"));
+            return;
+        }
+    };
+    match pgn::parse_fen_or_pgn(&text) {
+        Ok(game) => pending.0 = Some(game),
+        Err(err) => {
+            warn!("Could not load game from clipboard: {err}");
+            toasts.push(format!("Could not load the clipboard: {err}"));
+        }
+    }
+}
+
+fn button(kind: PasteButton, label: &str) -> impl Bundle {
+    (
+        kind,
+        widgets::button(
+            widgets::button_node(110.0, 32.0),
+            widgets::small_label(label),
+        ),
+    )
+}
+
+/// Asks whether a pasted game should replace the current one.
+pub fn show_paste_dialog(
+    mut commands: Commands,
+    pending: Res<PendingPaste>,
+    dialogs: Query<(), With<PasteDialog>>,
+) {
+    let Some((_, _, history)) = pending.0.as_ref() else {
+        return;
+    };
+    if !pending.is_changed() || !dialogs.is_empty() {
+        return;
+    }
+    let question = match history.moves.len() {
+        0 => "Load the position from the clipboard?".to_string(),
+        moves => format!("Load the game from the clipboard ({moves} plies)?"),
+    };
+    commands.spawn((
+        PasteDialog,
+        GameUi,
+        widgets::modal(10.0, 8),
+        children![
+            widgets::label(question),
+            (
+                widgets::button_row(),
+                children![
+                    button(PasteButton::Load, "Load"),
+                    button(PasteButton::Cancel, "Cancel"),
+                ],
+            ),
+        ],
+    ));
+}
+
+/// Loading starts a new game from the pasted position, with its moves
+/// already played.
+pub fn handle_paste_dialog(
+    mut commands: Commands,
+    interactions: Query<(&Interaction, &PasteButton), Changed<Interaction>>,
+    dialogs: Query<Entity, With<PasteDialog>>,
+    mut pending: ResMut<PendingPaste>,
+    mut new_game: NewGame,
+    mut toasts: ResMut<Toasts>,
+) {
+    for (interaction, button) in interactions.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        if let (PasteButton::Load, Some((board, castling, history))) = (button, pending.0.take()) {
+            new_game.start_from(board, castling, history);
+            toasts.push("Game loaded from the clipboard");
+        }
+        pending.0 = None;
+        for entity in dialogs.iter() {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+pub fn clear_pending_paste(mut pending: ResMut<PendingPaste>) {
+    pending.0 = None;
+}
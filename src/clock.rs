@@ -0,0 +1,269 @@
+use std::fmt;
+use std::time::Duration;
+
+use bevy::prelude::*;
+use hermanha_chess::Color as HermanhaColor;
+
+use crate::game_over::GameEnded;
+use crate::game_state::{BoardState, GameOutcome, Paused};
+use crate::history::MoveHistory;
+use crate::offers::Concluded;
+use crate::rules;
+use crate::ui::GameUi;
+
+/// Base time and per-move increment, written as minutes+seconds, e.g.
+/// "5+3" or "10+0".
+#[derive(Clone, Copy)]
+pub struct TimeControl {
+    pub base: Duration,
+    pub increment: Duration,
+}
+
+impl TimeControl {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let (base, increment) = text.split_once('+').unwrap_or((text, "0"));
+        let base: f32 = base
+            .parse()
+            .map_err(|_| format!("Invalid base time: {base}"))?;
+        let increment: u64 = increment
+            .parse()
+            .map_err(|_| format!("Invalid increment: {increment}"))?;
+        if base.is_nan() || base <= 0.0 {
+            return Err("Base time must be positive".to_string());
+        }
+        let base = Duration::try_from_secs_f32(base * 60.0)
+            .map_err(|_| format!("Base time is too long: {base}"))?;
+        Ok(TimeControl {
+            base,
+            increment: Duration::from_secs(increment),
+        })
+    }
+
+    /// Reads `--time-control=<minutes+seconds>` from the command line.
+    pub fn from_args(args: &[String]) -> Result<Option<Self>, String> {
+        args.iter()
+            .find_map(|arg| arg.strip_prefix("--time-control="))
+            .map(TimeControl::parse)
+            .transpose()
+    }
+}
+
+impl fmt::Display for TimeControl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}+{}",
+            self.base.as_secs_f32() / 60.0,
+            self.increment.as_secs()
+        )
+    }
+}
+
+/// Remaining time for both players. Only present when the game is played
+/// with a time control.
+#[derive(Resource)]
+pub struct Clocks {
+    time_control: TimeControl,
+    white: Duration,
+    black: Duration,
+    increment: Duration,
+    /// The player whose time ran out, which ends the game.
+    pub flagged: Option<HermanhaColor>,
+    last_ply: u32,
+}
+
+impl Clocks {
+    pub fn new(time_control: TimeControl) -> Self {
+        Clocks {
+            time_control,
+            white: time_control.base,
+            black: time_control.base,
+            increment: time_control.increment,
+            flagged: None,
+            last_ply: 0,
+        }
+    }
+
+    /// Clocks at the start of the time control, with White's base time cut
+    /// to one part in `time_odds` for a handicap game.
+    pub fn with_time_odds(time_control: TimeControl, time_odds: u32) -> Self {
+        Clocks {
+            white: time_control.base / time_odds.max(1),
+            ..Clocks::new(time_control)
+        }
+    }
+
+    /// Puts both clocks back to the start of the time control, see
+    /// `with_time_odds`.
+    pub fn reset(&mut self, time_odds: u32) {
+        *self = Clocks::with_time_odds(self.time_control, time_odds);
+    }
+
+    /// Clocks for a game picked up again after `ply_count` moves, with the
+    /// time each side had left.
+    pub fn resumed(
+        time_control: TimeControl,
+        white: Duration,
+        black: Duration,
+        ply_count: u32,
+    ) -> Self {
+        Clocks {
+            white,
+            black,
+            last_ply: ply_count,
+            ..Clocks::new(time_control)
+        }
+    }
+
+    pub fn time_control(&self) -> TimeControl {
+        self.time_control
+    }
+
+    /// Sets both clocks, e.g. to the times a host relayed.
+    pub fn set_remaining(&mut self, white: Duration, black: Duration) {
+        self.white = white;
+        self.black = black;
+    }
+
+    pub fn remaining(&self, color: HermanhaColor) -> Duration {
+        match color {
+            HermanhaColor::White => self.white,
+            HermanhaColor::Black => self.black,
+        }
+    }
+
+    fn remaining_mut(&mut self, color: HermanhaColor) -> &mut Duration {
+        match color {
+            HermanhaColor::White => &mut self.white,
+            HermanhaColor::Black => &mut self.black,
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct ClockDisplay;
+
+pub fn spawn_clock_display(mut commands: Commands) {
+    commands.spawn((
+        ClockDisplay,
+        GameUi,
+        Text::new(""),
+        TextFont {
+            font_size: 22.0,
+            ..default()
+        },
+        Node {
+            position_type: PositionType::Absolute,
+            left: Val::Px(8.0),
+            top: Val::Px(32.0),
+            ..default()
+        },
+    ));
+}
+
+/// Runs the clock of the side to move once the first move has been played,
+/// and adds the increment to whoever just moved.
+pub fn tick_clocks(
+    time: Res<Time>,
+    board: Res<BoardState>,
+    history: Res<MoveHistory>,
+    concluded: Res<Concluded>,
+    outcome: Res<GameOutcome>,
+    paused: Res<Paused>,
+    mut clocks: ResMut<Clocks>,
+    mut ended: EventWriter<GameEnded>,
+) {
+    if clocks.flagged.is_some() || concluded.0.is_some() || outcome.0.is_some() || paused.0 {
+        return;
+    }
+    let ply_count = history.ply_count();
+    if ply_count > clocks.last_ply {
+        let increment = clocks.increment;
+        *clocks.remaining_mut(rules::opponent(board.0.move_turn)) += increment;
+    }
+    clocks.last_ply = ply_count;
+    if ply_count == 0 {
+        return;
+    }
+    let to_move = board.0.move_turn;
+    let remaining = clocks.remaining_mut(to_move);
+    *remaining = remaining.saturating_sub(time.delta());
+    if remaining.is_zero() {
+        clocks.flagged = Some(to_move);
+        ended.write(GameEnded);
+    }
+}
+
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs < 10 {
+        format!("0:{:04.1}", duration.as_secs_f32())
+    } else {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+pub fn render_clocks(clocks: Res<Clocks>, mut displays: Query<&mut Text, With<ClockDisplay>>) {
+    if !clocks.is_changed() {
+        return;
+    }
+    let mut text = format!(
+        "White {}\nBlack {}",
+        format_duration(clocks.white),
+        format_duration(clocks.black)
+    );
+    if let Some(color) = clocks.flagged {
+        let (loser, winner) = match color {
+            HermanhaColor::White => ("White", "Black"),
+            HermanhaColor::Black => ("Black", "White"),
+        };
+        text.push_str(&format!("\n{loser} ran out of time, {winner} wins"));
+    }
+    for mut display in displays.iter_mut() {
+        display.0 = text.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_minutes_and_increment() {
+        let time_control = TimeControl::parse("2.5+3").unwrap();
+        assert_eq!(time_control.base, Duration::from_secs(150));
+        assert_eq!(time_control.increment, Duration::from_secs(3));
+        let time_control = TimeControl::parse("10").unwrap();
+        assert_eq!(time_control.base, Duration::from_secs(600));
+        assert_eq!(time_control.increment, Duration::ZERO);
+    }
+
+    #[test]
+    fn rejects_base_times_that_are_not_a_length_of_time() {
+        for text in [
+            "0+1", "-5+0", "NaN+0", "inf+0", "-inf+0", "1e30+0", "five+0",
+        ] {
+            assert!(TimeControl::parse(text).is_err(), "{text} was accepted");
+        }
+    }
+
+    #[test]
+    fn time_odds_only_cut_whites_time() {
+        let time_control = TimeControl::parse("10+5").unwrap();
+        let clocks = Clocks::with_time_odds(time_control, 3);
+        assert_eq!(
+            clocks.remaining(HermanhaColor::White),
+            Duration::from_secs(200)
+        );
+        assert_eq!(
+            clocks.remaining(HermanhaColor::Black),
+            Duration::from_secs(600)
+        );
+        let mut clocks = Clocks::new(time_control);
+        clocks.reset(1);
+        assert_eq!(
+            clocks.remaining(HermanhaColor::White),
+            Duration::from_secs(600)
+        );
+    }
+}
@@ -0,0 +1,302 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::thread;
+use std::time::Duration;
+
+use bevy::prelude::*;
+use crossbeam_channel::{Receiver, TryRecvError, bounded};
+use hermanha_chess::{Board, Color as HermanhaColor, PieceType, Position};
+
+use crate::clock::{Clocks, TimeControl};
+use crate::config::DefaultTimeControl;
+use crate::engine::builtin::Builtin;
+use crate::engine::{Engine, EngineMove};
+use crate::game_state::{
+    BoardState, Castling, GameOutcome, MoveOrigin, MoveRequested, NewGame, Paused,
+};
+use crate::history::MoveHistory;
+use crate::rules::CastlingRights;
+use crate::tcp::board_to_fen;
+use crate::{hint, san, zobrist};
+
+/// How long the computer waits before answering, so its moves can be
+/// followed.
+const THINKING_TIME: Duration = Duration::from_millis(600);
+
+/// Opening lines the computer knows, in SAN without check marks. It keeps
+/// to them while a game from the standard start position follows one,
+/// picking at random where lines branch.
+///
+/// This is not a Polyglot book. A Polyglot `.bin` file is looked up by a
+/// hash built from the 781 fixed keys of its `Random64` table, and with no
+/// copy of that table to check against, a hash that is off by one key
+/// would only show as the book never being hit. So the lines are compiled
+/// in, and a Polyglot file can't be loaded in their place.
+const BOOK: &[&str] = &[
+    "e4 e5 Nf3 Nc6 Bb5 a6 Ba4 Nf6 O-O Be7",
+    "e4 e5 Nf3 Nc6 Bc4 Bc5 c3 Nf6 d4 exd4",
+    "e4 e5 Nf3 Nf6 Nxe5 d6 Nf3 Nxe4 d4 d5",
+    "e4 c5 Nf3 d6 d4 cxd4 Nxd4 Nf6 Nc3 a6",
+    "e4 c5 Nf3 Nc6 d4 cxd4 Nxd4 Nf6 Nc3 e5",
+    "e4 e6 d4 d5 Nc3 Nf6 Bg5 Be7 e5 Nfd7",
+    "e4 c6 d4 d5 Nc3 dxe4 Nxe4 Bf5 Ng3 Bg6",
+    "d4 d5 c4 e6 Nc3 Nf6 Bg5 Be7 e3 O-O",
+    "d4 d5 c4 c6 Nf3 Nf6 Nc3 dxc4 a4 Bf5",
+    "d4 Nf6 c4 e6 Nc3 Bb4 e3 O-O Bd3 d5",
+    "d4 Nf6 c4 g6 Nc3 Bg7 e4 d6 Nf3 O-O",
+    "c4 e5 Nc3 Nf6 Nf3 Nc6 g3 d5 cxd5 Nxd5",
+    "Nf3 d5 g3 Nf6 Bg2 e6 O-O Be7 d3 O-O",
+];
+
+/// How long the engine gets to pick the computer's move. It searches
+/// while the computer waits out `THINKING_TIME`, so the two match.
+const SEARCH_BUDGET: Duration = THINKING_TIME;
+
+/// How deep the engine searches when the computer plays from a seed.
+const SEEDED_DEPTH: u32 = 3;
+
+/// Clocks for an exhibition game when no default time control is set, so
+/// there is something to watch run down.
+pub const EXHIBITION_TIME_CONTROL: TimeControl = TimeControl {
+    base: Duration::from_secs(180),
+    increment: Duration::from_secs(2),
+};
+
+/// How long a finished exhibition game stays on the board before the next
+/// one starts.
+const EXHIBITION_PAUSE: Duration = Duration::from_secs(5);
+
+/// Present in a game against the built-in computer: the color it plays,
+/// or both in an exhibition game, which the player only watches.
+#[derive(Resource, Clone, Copy)]
+pub enum Computer {
+    Plays(HermanhaColor),
+    Both,
+}
+
+impl Computer {
+    pub fn plays(self, color: HermanhaColor) -> bool {
+        match self {
+            Computer::Plays(own) => own == color,
+            Computer::Both => true,
+        }
+    }
+}
+
+/// Set with `--seed=`: the computer picks among book lines by the seed and
+/// searches to a fixed depth rather than for a fixed time, so a game
+/// against it can be played again move for move. Each game gets a seed of
+/// its own, which goes into its PGN; passed as `--seed=` it replays that
+/// game.
+#[derive(Resource, Clone, Copy)]
+pub struct Seed(pub u64);
+
+impl Seed {
+    /// Moves on to the next game's seed.
+    pub fn next_game(&mut self) {
+        self.0 = zobrist::next_key(&mut self.0);
+    }
+}
+
+fn side(color: HermanhaColor) -> usize {
+    match color {
+        HermanhaColor::White => 0,
+        HermanhaColor::Black => 1,
+    }
+}
+
+type Found = Option<(Position, Position, Option<PieceType>)>;
+
+/// The computer's engines, one for each side, and the pick for a
+/// position, by Zobrist hash. An engine is lent to a thread while it
+/// searches, so the window keeps drawing, and comes back with the move.
+#[derive(Default)]
+pub struct ComputerSearch {
+    engines: [Option<Box<dyn Engine>>; 2],
+    running: Option<(u64, usize, Receiver<(Box<dyn Engine>, Option<EngineMove>)>)>,
+    found: Option<(u64, Found)>,
+}
+
+impl ComputerSearch {
+    fn start(&mut self, position: u64, board: &Board, castling: CastlingRights, seeded: bool) {
+        let side = side(board.move_turn);
+        let mut engine = self.engines[side].take().unwrap_or_else(|| {
+            Box::new(if seeded {
+                Builtin::fixed_depth(SEEDED_DEPTH)
+            } else {
+                Builtin::default()
+            })
+        });
+        let board = board.clone();
+        let (sender, receiver) = bounded(1);
+        thread::spawn(move || {
+            let found = engine.best_move(&board, castling, SEARCH_BUDGET);
+            let _ = sender.send((engine, found));
+        });
+        self.running = Some((position, side, receiver));
+    }
+
+    fn poll(&mut self) {
+        let Some((position, side, receiver)) = &self.running else {
+            return;
+        };
+        match receiver.try_recv() {
+            Ok((engine, found)) => {
+                self.engines[*side] = Some(engine);
+                self.found = Some((
+                    *position,
+                    found.map(|found| (found.from, found.to, found.promotion_piece)),
+                ));
+                self.running = None;
+            }
+            Err(TryRecvError::Empty) => {}
+            Err(TryRecvError::Disconnected) => self.running = None,
+        }
+    }
+}
+
+/// Plays the computer's move once it is its turn and it has thought for
+/// `THINKING_TIME`: from the book while the game is in it, otherwise the
+/// built-in engine's. Its moves count as the opponent's.
+#[allow(clippy::too_many_arguments)]
+pub fn play_computer_move(
+    computer: Res<Computer>,
+    board: Res<BoardState>,
+    castling: Res<Castling>,
+    history: Res<MoveHistory>,
+    outcome: Res<GameOutcome>,
+    paused: Res<Paused>,
+    seed: Option<Res<Seed>>,
+    time: Res<Time>,
+    mut thinking: Local<Duration>,
+    mut search: Local<ComputerSearch>,
+    mut requests: EventWriter<MoveRequested>,
+) {
+    search.poll();
+    if paused.0 {
+        return;
+    }
+    if !computer.plays(board.move_turn) || outcome.0.is_some() {
+        *thinking = Duration::ZERO;
+        return;
+    }
+    *thinking += time.delta();
+    let position = zobrist::hash(&board.0, castling.0);
+    let searched = search
+        .found
+        .is_some_and(|(searched, _)| searched == position);
+    let searching = search
+        .running
+        .as_ref()
+        .is_some_and(|(searching, _, _)| *searching == position);
+    if !searched && !searching {
+        match book_move(&board.0, &history, seed.as_deref().copied()) {
+            Some((from, to)) => {
+                let promotion_piece = hint::promotion_for(&board.0, from, to);
+                search.found = Some((position, Some((from, to, promotion_piece))));
+            }
+            // A search for an earlier position is left to finish on its
+            // own; its engine is lost, and a fresh one takes over.
+            None => search.start(position, &board.0, castling.0, seed.is_some()),
+        }
+    }
+    if *thinking < THINKING_TIME {
+        return;
+    }
+    let Some((searched, found)) = search.found else {
+        return;
+    };
+    if searched != position {
+        return;
+    }
+    *thinking = Duration::ZERO;
+    search.found = None;
+    let Some((from, to, promotion_piece)) = found else {
+        return;
+    };
+    requests.write(MoveRequested {
+        from,
+        to,
+        promotion_piece,
+        origin: MoveOrigin::Opponent,
+    });
+}
+
+/// A move from a book line the game has followed so far, if any. Where
+/// lines branch the pick follows `seed` if there is one.
+fn book_move(
+    board: &Board,
+    history: &MoveHistory,
+    seed: Option<Seed>,
+) -> Option<(Position, Position)> {
+    let start = history.start().map_or(board, |(start, _)| start);
+    if board_to_fen(start) != board_to_fen(&Board::start_pos()) {
+        return None;
+    }
+    let played: Vec<&str> = history
+        .moves
+        .iter()
+        .map(|played| played.san.trim_end_matches(['+', '#']))
+        .collect();
+    let candidates: Vec<&str> = BOOK
+        .iter()
+        .filter_map(|line| {
+            let mut moves = line.split_whitespace();
+            played
+                .iter()
+                .all(|san| moves.next() == Some(*san))
+                .then(|| moves.next())
+                .flatten()
+        })
+        .collect();
+    if candidates.is_empty() {
+        return None;
+    }
+    let hash = match seed {
+        Some(Seed(seed)) => zobrist::next_key(&mut (seed ^ played.len() as u64)),
+        None => RandomState::new().hash_one(played.len()),
+    };
+    let pick = hash as usize % candidates.len();
+    board
+        .legal_moves()
+        .into_iter()
+        .map(|(from, to, _)| (from, to))
+        .find(|&(from, to)| {
+            san::move_to_san(board, from, to, None).trim_end_matches(['+', '#']) == candidates[pick]
+        })
+}
+
+/// In an exhibition the next game starts a little while after one ends,
+/// so it runs on unattended.
+pub fn next_exhibition_game(
+    computer: Res<Computer>,
+    time: Res<Time>,
+    mut waited: Local<Duration>,
+    mut new_game: NewGame,
+) {
+    if !matches!(*computer, Computer::Both) {
+        return;
+    }
+    *waited += time.delta();
+    if *waited < EXHIBITION_PAUSE {
+        return;
+    }
+    *waited = Duration::ZERO;
+    new_game.start();
+}
+
+/// Leaving an exhibition puts the clocks back to the default time control
+/// it may have stood in for.
+pub fn stop_computer(
+    mut commands: Commands,
+    computer: Option<Res<Computer>>,
+    time_control: Res<DefaultTimeControl>,
+) {
+    if computer.is_some_and(|computer| matches!(*computer, Computer::Both)) {
+        match time_control.0 {
+            Some(time_control) => commands.insert_resource(Clocks::new(time_control)),
+            None => commands.remove_resource::<Clocks>(),
+        }
+    }
+    commands.remove_resource::<Computer>();
+}
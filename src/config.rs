@@ -0,0 +1,537 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use bevy::ecs::event::Events;
+use bevy::input::ButtonInput;
+use bevy::input::keyboard::KeyboardInput;
+use bevy::prelude::*;
+
+use crate::actions::{self, Action};
+use crate::clock::{Clocks, TimeControl};
+use crate::export::{ImageExport, ImageLabels};
+use crate::menu::MenuAddress;
+use crate::net::PlayerName;
+use crate::tcp::{ChatMessage, HelloMessage};
+use crate::text_input::{self, TextEdit, TextInput};
+use crate::theme::Theme;
+use crate::training::{self, Training, TrainingPanel};
+use crate::widgets;
+use crate::window::WindowFlags;
+
+const APP_DIR: &str = "chess-app";
+const FILE_NAME: &str = "settings.toml";
+
+/// Time controls the settings panel cycles through, after "none".
+const TIME_CONTROL_PRESETS: [&str; 5] = ["1+0", "3+2", "5+3", "10+0", "15+10"];
+
+/// User preferences as stored on disk. Every field is optional so a
+/// missing or partial file just leaves the defaults in place.
+#[derive(Default, PartialEq)]
+pub struct Config {
+    pub board_theme: Option<String>,
+    pub piece_set: Option<String>,
+    pub palette: Option<String>,
+    pub shape_markers: Option<String>,
+    pub address: Option<String>,
+    pub player_name: Option<String>,
+    pub time_control: Option<String>,
+    pub image_size: Option<String>,
+    pub image_labels: Option<String>,
+    pub frame_delay_ms: Option<String>,
+    pub always_on_top: Option<String>,
+    pub borderless: Option<String>,
+    pub click_through: Option<String>,
+}
+
+/// `file_name` in the app's directory under `$XDG_CONFIG_HOME` or
+/// `~/.config` on Linux, `~/Library/Application Support` on macOS and
+/// `%APPDATA%` on Windows.
+pub fn config_path(file_name: &str) -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        PathBuf::from(env::var_os("APPDATA")?)
+    } else if cfg!(target_os = "macos") {
+        PathBuf::from(env::var_os("HOME")?).join("Library/Application Support")
+    } else {
+        match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        }
+    };
+    Some(base.join(APP_DIR).join(file_name))
+}
+
+pub fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+pub fn unquote(value: &str) -> Result<String, String> {
+    let inner = value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .ok_or_else(|| format!("Expected a quoted string: {value}"))?;
+    let mut ret = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => ret.push(chars.next().ok_or("Dangling escape")?),
+            '"' => return Err(format!("Unescaped quote in {value}")),
+            _ => ret.push(c),
+        }
+    }
+    Ok(ret)
+}
+
+impl Config {
+    /// Reads the settings file, or returns the defaults if there is none
+    /// or it can't be read.
+    pub fn load() -> Self {
+        let Some(path) = config_path(FILE_NAME) else {
+            return Config::default();
+        };
+        let Ok(text) = fs::read_to_string(&path) else {
+            return Config::default();
+        };
+        Config::from_toml(&text).unwrap_or_else(|err| {
+            warn!("Ignoring invalid settings in {}: {err}", path.display());
+            Config::default()
+        })
+    }
+
+    fn save(&self) {
+        let Some(path) = config_path(FILE_NAME) else {
+            warn!("No config directory to save settings to");
+            return;
+        };
+        let result = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|()| fs::write(&path, self.to_toml()));
+        if let Err(err) = result {
+            warn!("Could not save settings to {}: {err}", path.display());
+        }
+    }
+
+    /// Only the flat `key = "string"` subset of TOML is read or written.
+    fn from_toml(text: &str) -> Result<Self, String> {
+        let mut config = Config::default();
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("Expected key = value: {line}"))?;
+            let value = Some(unquote(value.trim())?);
+            match key.trim() {
+                "board_theme" => config.board_theme = value,
+                "piece_set" => config.piece_set = value,
+                "palette" => config.palette = value,
+                "shape_markers" => config.shape_markers = value,
+                "address" => config.address = value,
+                "player_name" => config.player_name = value,
+                "time_control" => config.time_control = value,
+                "image_size" => config.image_size = value,
+                "image_labels" => config.image_labels = value,
+                "frame_delay_ms" => config.frame_delay_ms = value,
+                "always_on_top" => config.always_on_top = value,
+                "borderless" => config.borderless = value,
+                "click_through" => config.click_through = value,
+                other => warn!("Unknown setting: {other}"),
+            }
+        }
+        Ok(config)
+    }
+
+    fn to_toml(&self) -> String {
+        [
+            ("board_theme", &self.board_theme),
+            ("piece_set", &self.piece_set),
+            ("palette", &self.palette),
+            ("shape_markers", &self.shape_markers),
+            ("address", &self.address),
+            ("player_name", &self.player_name),
+            ("time_control", &self.time_control),
+            ("image_size", &self.image_size),
+            ("image_labels", &self.image_labels),
+            ("frame_delay_ms", &self.frame_delay_ms),
+            ("always_on_top", &self.always_on_top),
+            ("borderless", &self.borderless),
+            ("click_through", &self.click_through),
+        ]
+        .iter()
+        .filter_map(|(key, value)| {
+            value
+                .as_ref()
+                .map(|value| format!("{key} = {}\n", quote(value)))
+        })
+        .collect()
+    }
+
+    /// The saved time control, if there is one and it still parses.
+    pub fn time_control(&self) -> Option<TimeControl> {
+        let text = self.time_control.as_ref()?;
+        TimeControl::parse(text)
+            .inspect_err(|err| warn!("Ignoring saved time control: {err}"))
+            .ok()
+    }
+
+    /// The saved image export settings, falling back to the defaults for
+    /// any that are missing or don't parse.
+    pub fn image_export(&self) -> ImageExport {
+        let mut export = ImageExport::default();
+        if let Some(size) = &self.image_size {
+            match ImageExport::parse_size(size) {
+                Ok(size) => export.size = size,
+                Err(err) => warn!("Ignoring saved image size: {err}"),
+            }
+        }
+        if let Some(name) = &self.image_labels {
+            match ImageLabels::from_name(name) {
+                Some(labels) => export.labels = labels,
+                None => warn!("Ignoring saved image labels: {name}"),
+            }
+        }
+        if let Some(delay) = &self.frame_delay_ms {
+            match ImageExport::parse_frame_delay(delay) {
+                Ok(delay) => export.frame_delay = delay,
+                Err(err) => warn!("Ignoring saved frame delay: {err}"),
+            }
+        }
+        export
+    }
+
+    /// The saved window options, each off unless it was saved as "on".
+    pub fn window_flags(&self) -> WindowFlags {
+        let on = |value: &Option<String>| value.as_deref() == Some("on");
+        WindowFlags {
+            always_on_top: on(&self.always_on_top),
+            borderless: on(&self.borderless),
+            click_through: on(&self.click_through),
+        }
+    }
+}
+
+/// The time control new games are played with, as chosen in the settings.
+#[derive(Resource, Default)]
+pub struct DefaultTimeControl(pub Option<TimeControl>);
+
+/// The player name in the settings panel while it's being typed into.
+/// The name changes as it's typed, and goes back to what it was if it's
+/// left blank.
+#[derive(Resource)]
+pub struct NameInput {
+    editing: bool,
+    field: TextInput,
+    before: String,
+    error: String,
+}
+
+impl Default for NameInput {
+    fn default() -> Self {
+        NameInput {
+            editing: false,
+            field: TextInput::new(
+                HelloMessage::MAX_NAME_LEN,
+                ChatMessage::is_valid_char,
+                validate_name,
+            ),
+            before: String::new(),
+            error: String::new(),
+        }
+    }
+}
+
+impl NameInput {
+    fn start(&mut self, name: &str) {
+        self.editing = true;
+        self.field.set_text(name);
+        self.before = name.to_string();
+        self.error.clear();
+    }
+
+    fn stop(&mut self, name: &mut PlayerName) {
+        if self.editing && self.field.validate().is_err() {
+            name.0 = std::mem::take(&mut self.before);
+        }
+        self.editing = false;
+        self.error.clear();
+    }
+}
+
+fn validate_name(name: &str) -> Result<(), String> {
+    if name.trim().is_empty() {
+        Err("can't be blank".to_string())
+    } else {
+        Ok(())
+    }
+}
+
+#[derive(Component)]
+pub struct SettingsPanel;
+
+#[derive(Component, Clone, Copy)]
+pub enum SettingsButton {
+    BoardTheme,
+    PieceSet,
+    Palette,
+    ShapeMarkers,
+    TimeControl,
+    PlayerName,
+    ImageSize,
+    ImageLabels,
+    FrameDelay,
+    Training,
+}
+
+fn settings_label(
+    button: SettingsButton,
+    theme: &Theme,
+    time_control: &DefaultTimeControl,
+    name: &PlayerName,
+    input: &NameInput,
+    image_export: &ImageExport,
+    training: Training,
+) -> String {
+    match button {
+        SettingsButton::BoardTheme => format!("Board: {}", theme.board().name),
+        SettingsButton::PieceSet => format!("Pieces: {}", theme.piece_set().name),
+        SettingsButton::Palette => format!("Highlights: {}", theme.palette.name()),
+        SettingsButton::ShapeMarkers if theme.shape_markers => "Shape markers: on".to_string(),
+        SettingsButton::ShapeMarkers => "Shape markers: off".to_string(),
+        SettingsButton::TimeControl => match time_control.0 {
+            Some(time_control) => format!("Time control: {time_control}"),
+            None => "Time control: none".to_string(),
+        },
+        SettingsButton::PlayerName if !input.error.is_empty() => {
+            format!("Name: {} ({})", input.field.with_cursor(), input.error)
+        }
+        SettingsButton::PlayerName if input.editing => {
+            format!("Name: {}", input.field.with_cursor())
+        }
+        SettingsButton::PlayerName => format!("Name: {}", name.0),
+        SettingsButton::ImageSize => format!("Image size: {} px", image_export.size),
+        SettingsButton::ImageLabels => format!("Image labels: {}", image_export.labels.name()),
+        SettingsButton::FrameDelay => format!(
+            "Animation frame: {:.1} s",
+            image_export.frame_delay.as_secs_f32()
+        ),
+        SettingsButton::Training => format!("Training: {}", training.label()),
+    }
+}
+
+pub fn toggle_settings(
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    panels: Query<Entity, With<SettingsPanel>>,
+    training_panels: Query<Entity, With<TrainingPanel>>,
+    mut input: ResMut<NameInput>,
+    mut name: ResMut<PlayerName>,
+) {
+    if !actions::just_pressed(&keys, Action::Settings) {
+        return;
+    }
+    if let Some(entity) = panels.iter().next() {
+        commands.entity(entity).despawn();
+        training::despawn_training_panel(&mut commands, &training_panels);
+        input.stop(&mut name);
+        return;
+    }
+    spawn_settings_panel(&mut commands);
+}
+
+/// Opens the settings panel, from its key or the pause menu.
+pub fn spawn_settings_panel(commands: &mut Commands) {
+    commands
+        .spawn((SettingsPanel, widgets::modal(10.0, 9)))
+        .with_children(|parent| {
+            parent.spawn(widgets::label("Settings"));
+            for button in [
+                SettingsButton::BoardTheme,
+                SettingsButton::PieceSet,
+                SettingsButton::Palette,
+                SettingsButton::ShapeMarkers,
+                SettingsButton::TimeControl,
+                SettingsButton::PlayerName,
+                SettingsButton::ImageSize,
+                SettingsButton::ImageLabels,
+                SettingsButton::FrameDelay,
+                SettingsButton::Training,
+            ] {
+                parent.spawn((
+                    button,
+                    widgets::button(widgets::button_node(260.0, 36.0), widgets::label("")),
+                ));
+            }
+            parent.spawn(widgets::small_label(
+                "Time control changes apply to the next game",
+            ));
+        });
+}
+
+/// The theme, time control and image buttons step through their choices;
+/// the name button starts typing a new name and the training button opens
+/// the training submenu.
+pub fn handle_settings_buttons(
+    mut commands: Commands,
+    interactions: Query<(&Interaction, &SettingsButton), Changed<Interaction>>,
+    mut theme: ResMut<Theme>,
+    mut time_control: ResMut<DefaultTimeControl>,
+    mut input: ResMut<NameInput>,
+    mut name: ResMut<PlayerName>,
+    mut image_export: ResMut<ImageExport>,
+) {
+    for (interaction, button) in interactions.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        match button {
+            SettingsButton::BoardTheme => theme.next_board(),
+            SettingsButton::PieceSet => theme.next_piece_set(),
+            SettingsButton::Palette => theme.next_palette(),
+            SettingsButton::ShapeMarkers => theme.shape_markers = !theme.shape_markers,
+            SettingsButton::TimeControl => {
+                let current = time_control.0.map(|time_control| time_control.to_string());
+                let next = match current
+                    .and_then(|current| TIME_CONTROL_PRESETS.iter().position(|p| *p == current))
+                {
+                    Some(index) => TIME_CONTROL_PRESETS.get(index + 1),
+                    None if time_control.0.is_none() => TIME_CONTROL_PRESETS.first(),
+                    None => None,
+                };
+                time_control.0 = next.and_then(|preset| TimeControl::parse(preset).ok());
+            }
+            SettingsButton::PlayerName if input.editing => input.stop(&mut name),
+            SettingsButton::PlayerName => input.start(&name.0),
+            SettingsButton::ImageSize => image_export.next_size(),
+            SettingsButton::ImageLabels => image_export.labels = image_export.labels.next(),
+            SettingsButton::FrameDelay => image_export.next_frame_delay(),
+            SettingsButton::Training => training::spawn_training_panel(&mut commands),
+        }
+    }
+}
+
+/// Typing into the name field. Like chat, the key presses are taken away
+/// from the rest of the app while editing. Runs in `PreUpdate`.
+pub fn type_name(
+    mut keyboard: ResMut<Events<KeyboardInput>>,
+    mut keys: ResMut<ButtonInput<KeyCode>>,
+    mut input: ResMut<NameInput>,
+    mut name: ResMut<PlayerName>,
+) {
+    if !input.editing {
+        return;
+    }
+    let ctrl = text_input::ctrl_held(&keys);
+    for event in keyboard.drain() {
+        if !event.state.is_pressed() {
+            continue;
+        }
+        match input.field.edit(&event.logical_key, ctrl) {
+            TextEdit::Unchanged => {}
+            TextEdit::Changed => {
+                name.0 = input.field.text.clone();
+                input.error.clear();
+            }
+            TextEdit::Submitted | TextEdit::Cancelled => input.stop(&mut name),
+            TextEdit::Invalid(err) => input.error = err,
+        }
+    }
+    keys.reset_all();
+}
+
+pub fn render_settings(
+    theme: Res<Theme>,
+    time_control: Res<DefaultTimeControl>,
+    name: Res<PlayerName>,
+    input: Res<NameInput>,
+    image_export: Res<ImageExport>,
+    training: Res<Training>,
+    buttons: Query<(&SettingsButton, &Children)>,
+    added: Query<(), Added<SettingsPanel>>,
+    mut texts: Query<&mut Text>,
+) {
+    if !theme.is_changed()
+        && !time_control.is_changed()
+        && !name.is_changed()
+        && !input.is_changed()
+        && !image_export.is_changed()
+        && !training.is_changed()
+        && added.is_empty()
+    {
+        return;
+    }
+    for (button, children) in buttons.iter() {
+        for child in children.iter() {
+            if let Ok(mut text) = texts.get_mut(child) {
+                text.0 = settings_label(
+                    *button,
+                    &theme,
+                    &time_control,
+                    &name,
+                    &input,
+                    &image_export,
+                    *training,
+                );
+            }
+        }
+    }
+}
+
+/// Until a game is started from the menu, the clocks follow the time
+/// control picked in the settings.
+pub fn apply_default_time_control(mut commands: Commands, time_control: Res<DefaultTimeControl>) {
+    if !time_control.is_changed() || time_control.is_added() {
+        return;
+    }
+    match time_control.0 {
+        Some(time_control) => commands.insert_resource(Clocks::new(time_control)),
+        None => commands.remove_resource::<Clocks>(),
+    }
+}
+
+/// Writes the settings file whenever one of the saved preferences changes.
+pub fn save_config(
+    theme: Res<Theme>,
+    address: Res<MenuAddress>,
+    name: Res<PlayerName>,
+    time_control: Res<DefaultTimeControl>,
+    image_export: Res<ImageExport>,
+    window_flags: Res<WindowFlags>,
+    mut saved: Local<Option<Config>>,
+) {
+    if !theme.is_changed()
+        && !address.is_changed()
+        && !name.is_changed()
+        && !time_control.is_changed()
+        && !image_export.is_changed()
+        && !window_flags.is_changed()
+    {
+        return;
+    }
+    let on_off = |on: bool| Some(if on { "on" } else { "off" }.to_string());
+    let config = Config {
+        board_theme: Some(theme.board().name.clone()),
+        piece_set: Some(theme.piece_set().name.clone()),
+        palette: Some(theme.palette.name().to_string()),
+        shape_markers: on_off(theme.shape_markers),
+        address: Some(address.0.text.clone()),
+        player_name: Some(name.0.clone()),
+        time_control: time_control.0.map(|time_control| time_control.to_string()),
+        image_size: Some(image_export.size.to_string()),
+        image_labels: Some(image_export.labels.name().to_string()),
+        frame_delay_ms: Some(image_export.frame_delay.as_millis().to_string()),
+        always_on_top: on_off(window_flags.always_on_top),
+        borderless: on_off(window_flags.borderless),
+        click_through: on_off(window_flags.click_through),
+    };
+    // The first run only records what was loaded at startup.
+    if saved.is_none() {
+        *saved = Some(config);
+        return;
+    }
+    if saved.as_ref() != Some(&config) {
+        config.save();
+        *saved = Some(config);
+    }
+}
@@ -0,0 +1,105 @@
+use bevy::prelude::*;
+
+use crate::ui::GameUi;
+use crate::widgets;
+
+/// Something that can't be taken back, and so is asked about first.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Confirmable {
+    Resign,
+    /// Leaving an online game, which tells the opponent we quit.
+    Quit,
+    /// Leaving a local game with moves that aren't saved.
+    LeaveUnsaved,
+    /// Saving over the save of a different game.
+    OverwriteSave,
+}
+
+impl Confirmable {
+    fn question(self) -> &'static str {
+        match self {
+            Confirmable::Resign => "Resign this game?",
+            Confirmable::Quit => "Leave the game? Your opponent will be told you quit.",
+            Confirmable::LeaveUnsaved => {
+                "Leave without saving? The moves since the last save are lost."
+            }
+            Confirmable::OverwriteSave => "Replace the saved game with this one?",
+        }
+    }
+
+    fn yes(self) -> &'static str {
+        match self {
+            Confirmable::Resign => "Yes, resign",
+            Confirmable::Quit | Confirmable::LeaveUnsaved => "Leave",
+            Confirmable::OverwriteSave => "Replace",
+        }
+    }
+}
+
+/// The question on screen, above every other dialog. Everything asked
+/// about belongs to a game, so it's taken down with the game's UI.
+#[derive(Component)]
+pub struct ConfirmDialog(pub Confirmable);
+
+#[derive(Component, Clone, Copy)]
+pub enum ConfirmButton {
+    Yes,
+    No,
+}
+
+/// The player said yes. Whoever asked goes ahead when it sees this.
+#[derive(Event, Clone, Copy)]
+pub struct Confirmed(pub Confirmable);
+
+/// Puts up the question for `action`, unless one is already up.
+pub fn ask(commands: &mut Commands, dialogs: &Query<(), With<ConfirmDialog>>, action: Confirmable) {
+    if !dialogs.is_empty() {
+        return;
+    }
+    commands.spawn((
+        ConfirmDialog(action),
+        GameUi,
+        widgets::modal(10.0, 10),
+        children![
+            widgets::label(action.question()),
+            (
+                widgets::button_row(),
+                children![
+                    (
+                        ConfirmButton::Yes,
+                        widgets::button(
+                            widgets::button_node(110.0, 32.0),
+                            widgets::small_label(action.yes()),
+                        ),
+                    ),
+                    (
+                        ConfirmButton::No,
+                        widgets::button(
+                            widgets::button_node(110.0, 32.0),
+                            widgets::small_label("Cancel"),
+                        ),
+                    ),
+                ],
+            ),
+        ],
+    ));
+}
+
+pub fn handle_confirm_buttons(
+    mut commands: Commands,
+    interactions: Query<(&Interaction, &ConfirmButton), Changed<Interaction>>,
+    dialogs: Query<(Entity, &ConfirmDialog)>,
+    mut confirmed: EventWriter<Confirmed>,
+) {
+    for (interaction, button) in interactions.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        for (entity, dialog) in dialogs.iter() {
+            commands.entity(entity).despawn();
+            if let ConfirmButton::Yes = button {
+                confirmed.write(Confirmed(dialog.0));
+            }
+        }
+    }
+}
@@ -0,0 +1,307 @@
+use std::collections::hash_map::RandomState;
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+use bevy::input::ButtonInput;
+use bevy::prelude::*;
+use bevy::window::PrimaryWindow;
+use hermanha_chess::{BOARD_COLS, BOARD_ROWS, Position};
+
+use crate::board_render::{BoardCamera, Piece};
+use crate::config::{self, quote, unquote};
+use crate::cursor_to_board_position;
+use crate::menu::AppState;
+use crate::san::square_name;
+use crate::widgets;
+
+const FILE_NAME: &str = "coordinates.toml";
+
+/// How long a square name stays up before it counts as missed.
+const TIME_LIMIT: Duration = Duration::from_secs(5);
+
+/// How the coordinate drill went: squares asked, squares found, and the
+/// time taken by the clicks that were made in time.
+#[derive(Default, Clone, Copy, PartialEq)]
+pub struct DrillStats {
+    pub attempts: u32,
+    pub hits: u32,
+    pub clicks: u32,
+    pub response: Duration,
+}
+
+impl DrillStats {
+    fn record(&mut self, hit: bool, response: Option<Duration>) {
+        self.attempts += 1;
+        self.hits += hit as u32;
+        if let Some(response) = response {
+            self.clicks += 1;
+            self.response += response;
+        }
+    }
+
+    fn merged(self, other: DrillStats) -> DrillStats {
+        DrillStats {
+            attempts: self.attempts + other.attempts,
+            hits: self.hits + other.hits,
+            clicks: self.clicks + other.clicks,
+            response: self.response + other.response,
+        }
+    }
+
+    fn summary(&self) -> String {
+        if self.attempts == 0 {
+            return "no squares yet".to_string();
+        }
+        let accuracy = self.hits as f32 / self.attempts as f32 * 100.0;
+        let mut summary = format!("{}/{} ({accuracy:.0}%)", self.hits, self.attempts);
+        if self.clicks > 0 {
+            let average = self.response.as_secs_f32() / self.clicks as f32;
+            summary.push_str(&format!(", {average:.2} s per click"));
+        }
+        summary
+    }
+
+    /// The results of earlier sessions, or none if they can't be read.
+    fn load() -> Self {
+        let Some(path) = config::config_path(FILE_NAME) else {
+            return DrillStats::default();
+        };
+        let Ok(text) = fs::read_to_string(&path) else {
+            return DrillStats::default();
+        };
+        DrillStats::from_toml(&text).unwrap_or_else(|err| {
+            warn!(
+                "Ignoring invalid drill results in {}: {err}",
+                path.display()
+            );
+            DrillStats::default()
+        })
+    }
+
+    fn save(&self) {
+        let Some(path) = config::config_path(FILE_NAME) else {
+            warn!("No config directory to save drill results to");
+            return;
+        };
+        let result = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|()| fs::write(&path, self.to_toml()));
+        if let Err(err) = result {
+            warn!("Could not save drill results to {}: {err}", path.display());
+        }
+    }
+
+    fn from_toml(text: &str) -> Result<Self, String> {
+        let mut stats = DrillStats::default();
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("Expected key = value: {line}"))?;
+            let value = unquote(value.trim())?;
+            let number = || {
+                value
+                    .parse::<u32>()
+                    .map_err(|_| format!("Invalid number: {value}"))
+            };
+            match key.trim() {
+                "attempts" => stats.attempts = number()?,
+                "hits" => stats.hits = number()?,
+                "clicks" => stats.clicks = number()?,
+                "response_ms" => stats.response = Duration::from_millis(number()?.into()),
+                other => warn!("Unknown drill result: {other}"),
+            }
+        }
+        Ok(stats)
+    }
+
+    fn to_toml(&self) -> String {
+        [
+            ("attempts", self.attempts.to_string()),
+            ("hits", self.hits.to_string()),
+            ("clicks", self.clicks.to_string()),
+            ("response_ms", self.response.as_millis().to_string()),
+        ]
+        .iter()
+        .map(|(key, value)| format!("{key} = {}\n", quote(value)))
+        .collect()
+    }
+}
+
+/// The coordinate drill under way: the square asked for and how long is
+/// left to click it.
+#[derive(Resource)]
+pub struct CoordinateDrill {
+    target: Position,
+    timer: Timer,
+    session: DrillStats,
+    earlier: DrillStats,
+    /// How the last square went.
+    feedback: String,
+}
+
+impl CoordinateDrill {
+    /// Moves on to a random square other than the current one.
+    fn next_target(&mut self) {
+        loop {
+            let seed = RandomState::new().build_hasher().finish();
+            let square = (seed % (BOARD_ROWS * BOARD_COLS) as u64) as i8;
+            let target = Position::new(square / BOARD_COLS as i8, square % BOARD_COLS as i8);
+            if target != self.target {
+                self.target = target;
+                break;
+            }
+        }
+        self.timer = Timer::new(TIME_LIMIT, TimerMode::Once);
+    }
+}
+
+#[derive(Component)]
+pub struct DrillPanel;
+
+#[derive(Component)]
+pub struct DrillTarget;
+
+#[derive(Component)]
+pub struct DrillStatus;
+
+#[derive(Component)]
+pub struct DrillBackButton;
+
+/// Starts a drill on the empty board; the pieces of the last game are
+/// hidden until the board is used for play again.
+pub fn enter_drill(mut commands: Commands, mut pieces: Query<&mut Visibility, With<Piece>>) {
+    for mut visibility in pieces.iter_mut() {
+        *visibility = Visibility::Hidden;
+    }
+    let mut drill = CoordinateDrill {
+        target: Position::new(0, 0),
+        timer: Timer::new(TIME_LIMIT, TimerMode::Once),
+        session: DrillStats::default(),
+        earlier: DrillStats::load(),
+        feedback: "Click the square named above".to_string(),
+    };
+    drill.next_target();
+    commands.insert_resource(drill);
+    commands.spawn((
+        DrillPanel,
+        Node {
+            position_type: PositionType::Absolute,
+            left: Val::Px(0.0),
+            top: Val::Px(0.0),
+            width: Val::Px(220.0),
+            height: Val::Percent(100.0),
+            padding: UiRect::all(Val::Px(10.0)),
+            flex_direction: FlexDirection::Column,
+            row_gap: Val::Px(8.0),
+            ..default()
+        },
+        BackgroundColor(widgets::PANEL_COLOR),
+        children![
+            widgets::label("Coordinate training"),
+            (DrillTarget, widgets::title("", 48.0)),
+            (DrillStatus, widgets::small_label("")),
+            (
+                DrillBackButton,
+                widgets::button(widgets::button_node(200.0, 28.0), widgets::label("Back")),
+            ),
+        ],
+    ));
+}
+
+/// Adds the session to the saved results.
+pub fn exit_drill(
+    mut commands: Commands,
+    drill: Res<CoordinateDrill>,
+    panels: Query<Entity, With<DrillPanel>>,
+) {
+    if drill.session.attempts > 0 {
+        drill.earlier.merged(drill.session).save();
+    }
+    for entity in panels.iter() {
+        commands.entity(entity).despawn();
+    }
+    commands.remove_resource::<CoordinateDrill>();
+}
+
+/// A left click on the board answers; running out of time counts as a
+/// miss. Either way the next square comes up.
+pub fn answer_drill(
+    buttons: Res<ButtonInput<MouseButton>>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    camera_q: Query<(&Camera, &GlobalTransform), With<BoardCamera>>,
+    time: Res<Time>,
+    mut drill: ResMut<CoordinateDrill>,
+) {
+    let target = drill.target;
+    if drill.timer.tick(time.delta()).finished() {
+        drill.session.record(false, None);
+        drill.feedback = format!("Too slow, {} was here", square_name(target));
+        drill.next_target();
+        return;
+    }
+    if !buttons.just_pressed(MouseButton::Left) {
+        return;
+    }
+    let Some(cursor_position) = windows.iter().next().and_then(Window::cursor_position) else {
+        return;
+    };
+    let Some((camera, camera_transform)) = camera_q.iter().next() else {
+        return;
+    };
+    let Some(clicked) = cursor_to_board_position(cursor_position, camera, camera_transform, &[])
+        .filter(|pos| (0..BOARD_ROWS as i8).contains(&pos.row))
+        .filter(|pos| (0..BOARD_COLS as i8).contains(&pos.col))
+    else {
+        return;
+    };
+    let response = drill.timer.elapsed();
+    let hit = clicked == target;
+    drill.session.record(hit, Some(response));
+    drill.feedback = if hit {
+        format!("Correct in {:.2} s", response.as_secs_f32())
+    } else {
+        format!(
+            "That was {}, {} was here",
+            square_name(clicked),
+            square_name(target)
+        )
+    };
+    drill.next_target();
+}
+
+pub fn render_drill(
+    drill: Res<CoordinateDrill>,
+    mut targets: Query<&mut Text, (With<DrillTarget>, Without<DrillStatus>)>,
+    mut statuses: Query<&mut Text, (With<DrillStatus>, Without<DrillTarget>)>,
+) {
+    for mut text in targets.iter_mut() {
+        text.0 = square_name(drill.target);
+    }
+    let status = format!(
+        "{:.1} s left\n\n{}\n\nThis session: {}\nAll sessions: {}",
+        drill.timer.remaining_secs(),
+        drill.feedback,
+        drill.session.summary(),
+        drill.earlier.merged(drill.session).summary()
+    );
+    for mut text in statuses.iter_mut() {
+        text.0 = status.clone();
+    }
+}
+
+pub fn handle_drill_buttons(
+    interactions: Query<&Interaction, (Changed<Interaction>, With<DrillBackButton>)>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    if interactions
+        .iter()
+        .any(|interaction| *interaction == Interaction::Pressed)
+    {
+        next_state.set(AppState::Menu);
+    }
+}
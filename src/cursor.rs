@@ -0,0 +1,221 @@
+use bevy::prelude::*;
+use bevy::window::PrimaryWindow;
+use hermanha_chess::{BOARD_COLS, BOARD_ROWS, Position};
+
+use crate::actions::{self, Action, ActiveScopes, HelpOverlay};
+use crate::board_render::{AutoRotate, BoardCamera};
+use crate::config::SettingsPanel;
+use crate::confirm::ConfirmDialog;
+use crate::game_over::GameOverOverlay;
+use crate::game_state::{BoardState, Castling, SelectedSquare};
+use crate::input::legal_targets;
+use crate::pause::PauseMenu;
+use crate::{TILE_SIZE, cursor_to_board_position, pos_to_vec3};
+
+/// The square cursor for playing without a mouse. It appears when a
+/// cursor key is pressed and hides again on the next mouse click.
+#[derive(Resource, Default)]
+pub struct BoardCursor {
+    pos: Option<Position>,
+    active: bool,
+}
+
+impl BoardCursor {
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+}
+
+/// The keyboard equivalent of clicking a square.
+#[derive(Event)]
+pub struct SquareChosen {
+    pub at: Position,
+}
+
+#[derive(Component)]
+pub struct CursorMarker;
+
+/// The square under the mouse, and while a piece is selected the legal
+/// target a click would go to, which may be a near miss snapped to it.
+#[derive(Resource, Default, PartialEq)]
+pub struct HoveredSquare {
+    pub square: Option<Position>,
+    pub target: Option<Position>,
+}
+
+impl HoveredSquare {
+    /// The square a click lands on.
+    pub fn clicked(&self) -> Option<Position> {
+        self.target.or(self.square)
+    }
+}
+
+#[derive(Component)]
+pub struct HoverMarker;
+
+/// Overlays that take the keyboard away from the board while open.
+pub type FocusOverlays = Or<(
+    With<HelpOverlay>,
+    With<SettingsPanel>,
+    With<GameOverOverlay>,
+    With<PauseMenu>,
+    With<ConfirmDialog>,
+)>;
+
+/// Arrow keys move the cursor as seen on screen, so they're reversed
+/// while the board is turned around. Select chooses the square under the
+/// cursor and Escape drops the current selection, or with nothing selected
+/// puts the cursor away. Keys are left alone while an overlay has the
+/// focus.
+#[allow(clippy::too_many_arguments)]
+pub fn move_cursor(
+    keys: Res<ButtonInput<KeyCode>>,
+    scopes: Res<ActiveScopes>,
+    buttons: Res<ButtonInput<MouseButton>>,
+    rotate: Res<AutoRotate>,
+    overlays: Query<(), FocusOverlays>,
+    mut cursor: ResMut<BoardCursor>,
+    mut selected: ResMut<SelectedSquare>,
+    mut chosen: EventWriter<SquareChosen>,
+) {
+    if buttons.get_just_pressed().next().is_some() {
+        cursor.active = false;
+        return;
+    }
+    if !overlays.is_empty() {
+        return;
+    }
+    let flipped = rotate.angle.cos() < 0.0;
+    let step = if flipped { -1 } else { 1 };
+    let (row, col) = if actions::just_pressed_in(&keys, &scopes, Action::CursorUp) {
+        (step, 0)
+    } else if actions::just_pressed_in(&keys, &scopes, Action::CursorDown) {
+        (-step, 0)
+    } else if actions::just_pressed_in(&keys, &scopes, Action::CursorLeft) {
+        (0, -step)
+    } else if actions::just_pressed_in(&keys, &scopes, Action::CursorRight) {
+        (0, step)
+    } else {
+        if actions::just_pressed_in(&keys, &scopes, Action::CursorCancel) {
+            if selected.0.is_some() {
+                selected.0 = None;
+            } else {
+                cursor.active = false;
+            }
+        }
+        if cursor.active
+            && let Some(at) = cursor.pos
+            && actions::just_pressed_in(&keys, &scopes, Action::CursorSelect)
+        {
+            chosen.write(SquareChosen { at });
+        }
+        return;
+    };
+    let pos = match cursor.pos {
+        // The first key press only brings the cursor up, on the king's
+        // pawn of whoever is at the bottom.
+        None => Position::new(if flipped { 6 } else { 1 }, 4),
+        Some(pos) if !cursor.active => pos,
+        Some(pos) => Position::new(
+            (pos.row + row).clamp(0, BOARD_ROWS as i8 - 1),
+            (pos.col + col).clamp(0, BOARD_COLS as i8 - 1),
+        ),
+    };
+    cursor.pos = Some(pos);
+    cursor.active = true;
+}
+
+pub fn render_cursor(
+    mut commands: Commands,
+    cursor: Res<BoardCursor>,
+    markers: Query<Entity, With<CursorMarker>>,
+) {
+    if !cursor.is_changed() {
+        return;
+    }
+    for entity in markers.iter() {
+        commands.entity(entity).despawn();
+    }
+    let Some(pos) = cursor.pos.filter(|_| cursor.active) else {
+        return;
+    };
+    commands.spawn((
+        CursorMarker,
+        Sprite {
+            color: Color::srgba(1.0, 0.85, 0.2, 0.4),
+            custom_size: Some(Vec2::splat(TILE_SIZE)),
+            ..default()
+        },
+        Transform::from_translation(pos_to_vec3(pos, 0.4)),
+    ));
+}
+
+/// Follows the mouse every frame. The resource only changes when the
+/// hovered square or target does, so the markers aren't redrawn for
+/// nothing.
+pub fn track_hover(
+    windows: Query<&Window, With<PrimaryWindow>>,
+    camera_q: Query<(&Camera, &GlobalTransform), With<BoardCamera>>,
+    board: Res<BoardState>,
+    castling: Res<Castling>,
+    selected: Res<SelectedSquare>,
+    mut hovered: ResMut<HoveredSquare>,
+) {
+    let targets: Vec<Position> = selected
+        .0
+        .map(|selected_pos| {
+            legal_targets(&board.0, castling.0, selected_pos)
+                .into_iter()
+                .map(|(target, _)| target)
+                .collect()
+        })
+        .unwrap_or_default();
+    let cursor = windows
+        .iter()
+        .next()
+        .and_then(Window::cursor_position)
+        .zip(camera_q.iter().next());
+    let hit = |snap_targets: &[Position]| {
+        cursor.and_then(|(cursor_position, (camera, camera_transform))| {
+            cursor_to_board_position(cursor_position, camera, camera_transform, snap_targets)
+        })
+    };
+    let now = HoveredSquare {
+        square: hit(&[]).filter(|pos| board.0.pos_on_board(*pos)),
+        target: hit(&targets).filter(|pos| targets.contains(pos)),
+    };
+    hovered.set_if_neq(now);
+}
+
+/// A faint tint on the hovered square and a stronger one on the target a
+/// click would play to.
+pub fn render_hover(
+    mut commands: Commands,
+    hovered: Res<HoveredSquare>,
+    markers: Query<Entity, With<HoverMarker>>,
+) {
+    if !hovered.is_changed() {
+        return;
+    }
+    for entity in markers.iter() {
+        commands.entity(entity).despawn();
+    }
+    let squares = [
+        (hovered.square, Color::srgba(1.0, 1.0, 1.0, 0.15)),
+        (hovered.target, Color::srgba(0.2, 0.3, 0.1, 0.35)),
+    ];
+    for (pos, color) in squares {
+        let Some(pos) = pos else {
+            continue;
+        };
+        commands.spawn((
+            HoverMarker,
+            Sprite {
+                color,
+                custom_size: Some(Vec2::splat(TILE_SIZE)),
+                ..default()
+            },
+            Transform::from_translation(pos_to_vec3(pos, 0.35)),
+        ));
+    }
+}
@@ -0,0 +1,362 @@
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::time::Duration;
+
+use bevy::prelude::*;
+
+use crate::menu::{AppState, MenuAddress, MenuMessage};
+use crate::net::{PendingConnection, PlayerName};
+use crate::tcp::ConnectionType;
+use crate::tls;
+use crate::transport::TransportKind;
+use crate::widgets;
+
+/// The UDP port hosts broadcast their beacons to.
+pub const DISCOVERY_PORT: u16 = 8091;
+
+const BEACON_PREFIX: &str = "ChessBEACON";
+
+/// How often a waiting host announces itself.
+const BEACON_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a host stays listed after its last beacon.
+const HOST_TIMEOUT: Duration = Duration::from_secs(4);
+
+/// What a waiting host broadcasts: where to connect and how. The address
+/// is the one the beacon came from. An encrypted host includes its
+/// fingerprint, so joining from the list trusts the LAN as much as typing
+/// the address would.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Beacon {
+    pub port: u16,
+    pub transport: TransportKind,
+    pub fingerprint: Option<String>,
+    pub name: String,
+}
+
+impl Beacon {
+    fn to_string(&self) -> String {
+        format!(
+            "{BEACON_PREFIX}:{}:{}:{}:{}",
+            self.port,
+            self.transport.tag(),
+            self.fingerprint.as_deref().unwrap_or("-"),
+            self.name
+        )
+    }
+
+    fn from_string(text: &str) -> Result<Self, String> {
+        let parts: Vec<&str> = text.splitn(5, ':').collect();
+        let [BEACON_PREFIX, port, transport, fingerprint, name] = parts[..] else {
+            return Err("Invalid beacon format".to_string());
+        };
+        Ok(Beacon {
+            port: port.parse().map_err(|_| "Invalid port".to_string())?,
+            transport: TransportKind::from_tag(transport)?,
+            fingerprint: (fingerprint != "-").then(|| fingerprint.to_string()),
+            name: name.to_string(),
+        })
+    }
+}
+
+/// Present while hosting: the socket beacons go out on and the beacon.
+#[derive(Resource)]
+pub struct BeaconSender {
+    socket: UdpSocket,
+    beacon: String,
+    timer: Timer,
+}
+
+/// Starts announcing a hosted game. A host listening on a loopback
+/// address can't be reached from the LAN, so it stays quiet; hosting on
+/// e.g. `0.0.0.0:8080` makes it findable.
+pub fn start_beacon(
+    mut commands: Commands,
+    pending: Res<PendingConnection>,
+    transport: Res<TransportKind>,
+    name: Res<PlayerName>,
+) {
+    if !pending.hosting() {
+        return;
+    }
+    let Ok(address) = pending.address().parse::<SocketAddr>() else {
+        return;
+    };
+    if address.ip().is_loopback() {
+        info!("Not announcing a game hosted on {address} to the LAN");
+        return;
+    }
+    let beacon = Beacon {
+        port: address.port(),
+        transport: *transport,
+        fingerprint: (*transport == TransportKind::Tls).then(tls::host_fingerprint),
+        name: name.0.clone(),
+    };
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+        .and_then(|socket| socket.set_broadcast(true).map(|()| socket));
+    match socket {
+        Ok(socket) => commands.insert_resource(BeaconSender {
+            socket,
+            beacon: beacon.to_string(),
+            timer: Timer::new(BEACON_INTERVAL, TimerMode::Repeating),
+        }),
+        Err(err) => warn!("Could not announce the game to the LAN: {err}"),
+    }
+}
+
+pub fn broadcast_beacon(mut sender: ResMut<BeaconSender>, time: Res<Time<Real>>) {
+    if !sender.timer.tick(time.delta()).just_finished() {
+        return;
+    }
+    let target = (Ipv4Addr::BROADCAST, DISCOVERY_PORT);
+    if let Err(err) = sender.socket.send_to(sender.beacon.as_bytes(), target) {
+        warn!("Could not send beacon: {err}");
+    }
+}
+
+pub fn stop_beacon(mut commands: Commands) {
+    commands.remove_resource::<BeaconSender>();
+}
+
+/// A host heard on the LAN.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LanHost {
+    pub address: SocketAddr,
+    pub beacon: Beacon,
+    last_seen: Duration,
+}
+
+impl LanHost {
+    /// The address as typed into the menu, with the fingerprint appended
+    /// for encrypted games.
+    fn menu_address(&self) -> String {
+        match &self.beacon.fingerprint {
+            Some(fingerprint) => format!("{}#{fingerprint}", self.address),
+            None => self.address.to_string(),
+        }
+    }
+}
+
+/// Present on the LAN screen: the socket beacons are heard on and the
+/// hosts heard recently.
+#[derive(Resource)]
+pub struct LanGames {
+    socket: Option<UdpSocket>,
+    pub hosts: Vec<LanHost>,
+}
+
+#[derive(Component)]
+pub struct LanScreen;
+
+#[derive(Component)]
+pub struct LanGameList;
+
+#[derive(Component)]
+pub struct LanGameButton(pub usize);
+
+#[derive(Component)]
+pub struct LanBackButton;
+
+pub fn enter_lan_screen(mut commands: Commands, mut message: ResMut<MenuMessage>) {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, DISCOVERY_PORT))
+        .and_then(|socket| socket.set_nonblocking(true).map(|()| socket));
+    let socket = match socket {
+        Ok(socket) => Some(socket),
+        Err(err) => {
+            message.0 = format!("Could not listen for games: {err}");
+            None
+        }
+    };
+    commands.insert_resource(LanGames {
+        socket,
+        hosts: Vec::new(),
+    });
+    commands.spawn((
+        LanScreen,
+        widgets::screen(widgets::SCREEN_COLOR, 12.0, 5),
+        children![
+            widgets::title("Games on LAN", 28.0),
+            (
+                LanGameList,
+                Node {
+                    flex_direction: FlexDirection::Column,
+                    row_gap: Val::Px(8.0),
+                    ..default()
+                },
+            ),
+            (
+                Text::new(message.0.clone()),
+                TextColor(Color::srgb(0.9, 0.4, 0.4)),
+            ),
+            (
+                LanBackButton,
+                widgets::button(widgets::button_node(160.0, 40.0), widgets::label("Back")),
+            ),
+        ],
+    ));
+}
+
+pub fn exit_lan_screen(mut commands: Commands, screens: Query<Entity, With<LanScreen>>) {
+    commands.remove_resource::<LanGames>();
+    for entity in screens.iter() {
+        commands.entity(entity).despawn();
+    }
+}
+
+/// Lists every host whose beacon arrives and drops those that have gone
+/// quiet. Only changes to the list count as a change, so hearing the same
+/// hosts again doesn't redraw it.
+pub fn receive_beacons(mut games: ResMut<LanGames>, time: Res<Time<Real>>) {
+    let now = time.elapsed();
+    let hosts_changed = {
+        let games = games.bypass_change_detection();
+        let before = games.hosts.len();
+        let mut changed = false;
+        let mut buffer = [0; 512];
+        while let Some(socket) = &games.socket
+            && let Ok((len, from)) = socket.recv_from(&mut buffer)
+        {
+            let Ok(beacon) = Beacon::from_string(&String::from_utf8_lossy(&buffer[..len])) else {
+                continue;
+            };
+            let address = SocketAddr::new(from.ip(), beacon.port);
+            match games.hosts.iter_mut().find(|host| host.address == address) {
+                Some(host) => {
+                    changed |= host.beacon != beacon;
+                    host.beacon = beacon;
+                    host.last_seen = now;
+                }
+                None => {
+                    changed = true;
+                    games.hosts.push(LanHost {
+                        address,
+                        beacon,
+                        last_seen: now,
+                    });
+                }
+            }
+        }
+        games
+            .hosts
+            .retain(|host| now.saturating_sub(host.last_seen) < HOST_TIMEOUT);
+        changed || games.hosts.len() != before
+    };
+    if hosts_changed {
+        games.set_changed();
+    }
+}
+
+pub fn render_lan_games(
+    mut commands: Commands,
+    games: Res<LanGames>,
+    lists: Query<Entity, With<LanGameList>>,
+) {
+    if !games.is_changed() {
+        return;
+    }
+    for list in lists.iter() {
+        commands.entity(list).despawn_related::<Children>();
+        if games.hosts.is_empty() {
+            commands
+                .entity(list)
+                .with_child(Text::new("Looking for games\u{2026}"));
+            continue;
+        }
+        commands.entity(list).with_children(|parent| {
+            for (index, host) in games.hosts.iter().enumerate() {
+                parent.spawn((
+                    LanGameButton(index),
+                    widgets::button(
+                        widgets::button_node(320.0, 44.0),
+                        widgets::label(format!(
+                            "{} at {} ({})",
+                            host.beacon.name,
+                            host.address,
+                            host.beacon.transport.label()
+                        )),
+                    ),
+                ));
+            }
+        });
+    }
+}
+
+/// Joining a listed game takes over its transport, so both sides agree,
+/// and fills in its address for next time.
+pub fn handle_lan_buttons(
+    mut commands: Commands,
+    interactions: Query<
+        (&Interaction, Option<&LanGameButton>),
+        (
+            Changed<Interaction>,
+            Or<(With<LanGameButton>, With<LanBackButton>)>,
+        ),
+    >,
+    games: Res<LanGames>,
+    mut transport: ResMut<TransportKind>,
+    mut address: ResMut<MenuAddress>,
+    mut message: ResMut<MenuMessage>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    for (interaction, game) in interactions.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        message.0.clear();
+        let Some(host) = game.and_then(|game| games.hosts.get(game.0)) else {
+            next_state.set(AppState::Menu);
+            return;
+        };
+        *transport = host.beacon.transport;
+        address.0.set_text(host.menu_address());
+        commands.insert_resource(PendingConnection::start(
+            *transport,
+            ConnectionType::Client,
+            address.0.text.clone(),
+            ConnectionType::Client.player_color(),
+        ));
+        next_state.set(AppState::Connecting);
+        return;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn beacons_read_back_with_colons_in_the_name() {
+        let beacon = Beacon {
+            port: 8080,
+            transport: TransportKind::Tls,
+            fingerprint: Some("AB12".to_string()),
+            name: "Team: Alpha".to_string(),
+        };
+        assert_eq!(Beacon::from_string(&beacon.to_string()), Ok(beacon));
+        let plain = Beacon::from_string("ChessBEACON:9000:tcp:-:Host").unwrap();
+        assert_eq!(plain.fingerprint, None);
+    }
+
+    #[test]
+    fn other_broadcasts_are_not_beacons() {
+        for text in [
+            "ChessBEACON:8080:tcp:-",
+            "OtherGame:8080:tcp:-:Host",
+            "ChessBEACON:port:tcp:-:Host",
+            "ChessBEACON:8080:udp:-:Host",
+        ] {
+            assert!(Beacon::from_string(text).is_err(), "{text} was accepted");
+        }
+    }
+
+    #[test]
+    fn encrypted_hosts_are_joined_with_their_fingerprint() {
+        let mut host = LanHost {
+            address: "192.168.1.5:8080".parse().unwrap(),
+            beacon: Beacon::from_string("ChessBEACON:8080:tls:AB12:Host").unwrap(),
+            last_seen: Duration::ZERO,
+        };
+        assert_eq!(host.menu_address(), "192.168.1.5:8080#AB12");
+        host.beacon.fingerprint = None;
+        assert_eq!(host.menu_address(), "192.168.1.5:8080");
+    }
+}
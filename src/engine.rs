@@ -0,0 +1,34 @@
+use std::time::Duration;
+
+use hermanha_chess::{Board, PieceType, Position};
+
+use crate::rules::CastlingRights;
+
+pub mod builtin;
+
+/// The move an engine settled on.
+#[derive(Clone, Copy)]
+pub struct EngineMove {
+    pub from: Position,
+    pub to: Position,
+    pub promotion_piece: Option<PieceType>,
+    /// The engine's score of the position for the side to move, in
+    /// centipawns, beyond `builtin::MATE_SCORE` for a forced mate.
+    pub score: i32,
+    /// How many plies deep the last finished search looked.
+    pub depth: u32,
+}
+
+/// Something that picks moves for the computer player: the built-in
+/// search, or another program speaking UCI. It is asked for one move at a
+/// time and has to answer within `budget`.
+pub trait Engine: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn best_move(
+        &mut self,
+        board: &Board,
+        castling: CastlingRights,
+        budget: Duration,
+    ) -> Option<EngineMove>;
+}
@@ -0,0 +1,577 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use hermanha_chess::{BOARD_COLS, BOARD_ROWS, Board, Color as HermanhaColor, PieceType, Position};
+
+use crate::engine::{Engine, EngineMove};
+use crate::hint;
+use crate::rules::{self, CastlingRights};
+use crate::zobrist::Key;
+
+/// Score of being mated on the spot; mates further off score a little
+/// less, so quicker ones are preferred.
+pub const MATE_SCORE: i32 = 100_000;
+const INFINITY: i32 = 1_000_000;
+const MAX_DEPTH: u32 = 64;
+/// Scores this close to `MATE_SCORE` are forced mates.
+const MATE_BOUND: i32 = MATE_SCORE - MAX_DEPTH as i32;
+
+/// The transposition table is emptied once it holds this many positions,
+/// so a long game doesn't keep growing it.
+const MAX_TABLE_ENTRIES: usize = 1 << 20;
+
+/// How many nodes are searched between looks at the clock.
+const CLOCK_INTERVAL: u64 = 64;
+
+/// Bonuses in centipawns for a piece standing on each square, as seen from
+/// White's side with the eighth rank on top. Black's are the same tables
+/// turned upside down.
+#[rustfmt::skip]
+const PAWN_SQUARES: [i32; 64] = [
+     0,   0,   0,   0,   0,   0,   0,   0,
+    50,  50,  50,  50,  50,  50,  50,  50,
+    10,  10,  20,  30,  30,  20,  10,  10,
+     5,   5,  10,  25,  25,  10,   5,   5,
+     0,   0,   0,  20,  20,   0,   0,   0,
+     5,  -5, -10,   0,   0, -10,  -5,   5,
+     5,  10,  10, -20, -20,  10,  10,   5,
+     0,   0,   0,   0,   0,   0,   0,   0,
+];
+
+#[rustfmt::skip]
+const KNIGHT_SQUARES: [i32; 64] = [
+   -50, -40, -30, -30, -30, -30, -40, -50,
+   -40, -20,   0,   0,   0,   0, -20, -40,
+   -30,   0,  10,  15,  15,  10,   0, -30,
+   -30,   5,  15,  20,  20,  15,   5, -30,
+   -30,   0,  15,  20,  20,  15,   0, -30,
+   -30,   5,  10,  15,  15,  10,   5, -30,
+   -40, -20,   0,   5,   5,   0, -20, -40,
+   -50, -40, -30, -30, -30, -30, -40, -50,
+];
+
+#[rustfmt::skip]
+const BISHOP_SQUARES: [i32; 64] = [
+   -20, -10, -10, -10, -10, -10, -10, -20,
+   -10,   0,   0,   0,   0,   0,   0, -10,
+   -10,   0,   5,  10,  10,   5,   0, -10,
+   -10,   5,   5,  10,  10,   5,   5, -10,
+   -10,   0,  10,  10,  10,  10,   0, -10,
+   -10,  10,  10,  10,  10,  10,  10, -10,
+   -10,   5,   0,   0,   0,   0,   5, -10,
+   -20, -10, -10, -10, -10, -10, -10, -20,
+];
+
+#[rustfmt::skip]
+const ROOK_SQUARES: [i32; 64] = [
+     0,   0,   0,   0,   0,   0,   0,   0,
+     5,  10,  10,  10,  10,  10,  10,   5,
+    -5,   0,   0,   0,   0,   0,   0,  -5,
+    -5,   0,   0,   0,   0,   0,   0,  -5,
+    -5,   0,   0,   0,   0,   0,   0,  -5,
+    -5,   0,   0,   0,   0,   0,   0,  -5,
+    -5,   0,   0,   0,   0,   0,   0,  -5,
+     0,   0,   0,   5,   5,   0,   0,   0,
+];
+
+#[rustfmt::skip]
+const QUEEN_SQUARES: [i32; 64] = [
+   -20, -10, -10,  -5,  -5, -10, -10, -20,
+   -10,   0,   0,   0,   0,   0,   0, -10,
+   -10,   0,   5,   5,   5,   5,   0, -10,
+    -5,   0,   5,   5,   5,   5,   0,  -5,
+     0,   0,   5,   5,   5,   5,   0,  -5,
+   -10,   5,   5,   5,   5,   5,   0, -10,
+   -10,   0,   5,   0,   0,   0,   0, -10,
+   -20, -10, -10,  -5,  -5, -10, -10, -20,
+];
+
+#[rustfmt::skip]
+const KING_SQUARES: [i32; 64] = [
+   -30, -40, -40, -50, -50, -40, -40, -30,
+   -30, -40, -40, -50, -50, -40, -40, -30,
+   -30, -40, -40, -50, -50, -40, -40, -30,
+   -30, -40, -40, -50, -50, -40, -40, -30,
+   -20, -30, -30, -40, -40, -30, -30, -20,
+   -10, -20, -20, -20, -20, -20, -20, -10,
+    20,  20,   0,   0,   0,   0,  20,  20,
+    20,  30,  10,   0,   0,  10,  30,  20,
+];
+
+fn piece_value(piece_type: PieceType) -> i32 {
+    match piece_type {
+        PieceType::Pawn => 100,
+        PieceType::Knight => 320,
+        PieceType::Bishop => 330,
+        PieceType::Rook => 500,
+        PieceType::Queen => 900,
+        PieceType::King => 0,
+    }
+}
+
+fn square_bonus(piece_type: PieceType, color: HermanhaColor, pos: Position) -> i32 {
+    let table = match piece_type {
+        PieceType::Pawn => &PAWN_SQUARES,
+        PieceType::Knight => &KNIGHT_SQUARES,
+        PieceType::Bishop => &BISHOP_SQUARES,
+        PieceType::Rook => &ROOK_SQUARES,
+        PieceType::Queen => &QUEEN_SQUARES,
+        PieceType::King => &KING_SQUARES,
+    };
+    let row_from_top = match color {
+        HermanhaColor::White => BOARD_ROWS - 1 - pos.row as usize,
+        HermanhaColor::Black => pos.row as usize,
+    };
+    table[row_from_top * BOARD_COLS + pos.col as usize]
+}
+
+/// Material and piece placement in centipawns, for the side to move.
+pub fn evaluate(board: &Board) -> i32 {
+    let mut score = 0;
+    for row in 0..BOARD_ROWS as i8 {
+        for col in 0..BOARD_COLS as i8 {
+            let pos = Position::new(row, col);
+            let Some(piece) = board.get(pos) else {
+                continue;
+            };
+            let worth =
+                piece_value(piece.piece_type) + square_bonus(piece.piece_type, piece.color, pos);
+            if piece.color == board.move_turn {
+                score += worth;
+            } else {
+                score -= worth;
+            }
+        }
+    }
+    score
+}
+
+type Move = (Position, Position, Option<PieceType>);
+
+/// Mated `ply` plies from the root, or stalemated.
+fn no_moves_score(board: &Board, ply: u32) -> i32 {
+    if rules::in_check(board, board.move_turn) {
+        -MATE_SCORE + ply as i32
+    } else {
+        0
+    }
+}
+
+/// The legal moves, promoting only to a queen like the rest of the
+/// app's searches, and castling onto the rook in Chess960.
+fn legal_moves(board: &Board, castling: CastlingRights) -> Vec<Move> {
+    board
+        .legal_moves()
+        .into_iter()
+        .map(|(from, to, _)| (from, to, hint::promotion_for(board, from, to)))
+        .chain(
+            rules::rook_castles(board, castling)
+                .into_iter()
+                .map(|(king, rook)| (king, rook, None)),
+        )
+        .collect()
+}
+
+/// The value of the piece taken, or a pawn's for en passant.
+fn captured_value(board: &Board, (from, to, _): Move) -> Option<i32> {
+    if rules::is_castling(board, from, to) {
+        return None;
+    }
+    if let Some(target) = board.get(to) {
+        return Some(piece_value(target.piece_type));
+    }
+    let is_pawn = board
+        .get(from)
+        .is_some_and(|piece| matches!(piece.piece_type, PieceType::Pawn));
+    (is_pawn && from.col != to.col).then_some(piece_value(PieceType::Pawn))
+}
+
+fn play(board: &Board, castling: CastlingRights, mv: Move) -> Option<(Board, CastlingRights)> {
+    let (from, to, promotion_piece) = mv;
+    let next = rules::play_move(board, castling, from, to, promotion_piece)?;
+    let mut castling = castling;
+    castling.update(from, to);
+    Some((next, castling))
+}
+
+/// Counts the positions `depth` plies from `board`, the usual check of a
+/// move generator against known numbers. Promotions count once, as the
+/// search only plays them to a queen.
+pub fn perft(board: &Board, depth: u32) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    let moves = legal_moves(board, CastlingRights::default());
+    if depth == 1 {
+        return moves.len() as u64;
+    }
+    moves
+        .into_iter()
+        .filter_map(|mv| play(board, CastlingRights::default(), mv))
+        .map(|(next, _)| perft(&next, depth - 1))
+        .sum()
+}
+
+#[derive(Clone, Copy)]
+enum Bound {
+    Exact,
+    /// The position scored at least this much: a move was good enough
+    /// to stop looking at the rest.
+    Lower,
+    /// The position scored at most this much.
+    Upper,
+}
+
+/// Mates are scored by their distance from the root, but a position in the
+/// table can come up again at another ply. Its mate scores are stored as
+/// the distance from the position itself.
+fn score_to_table(score: i32, ply: u32) -> i32 {
+    if score >= MATE_BOUND {
+        score + ply as i32
+    } else if score <= -MATE_BOUND {
+        score - ply as i32
+    } else {
+        score
+    }
+}
+
+fn score_from_table(score: i32, ply: u32) -> i32 {
+    if score >= MATE_BOUND {
+        score - ply as i32
+    } else if score <= -MATE_BOUND {
+        score + ply as i32
+    } else {
+        score
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Entry {
+    depth: u32,
+    score: i32,
+    bound: Bound,
+    best: Option<Move>,
+}
+
+/// An iterative-deepening alpha-beta search on material and piece-square
+/// tables, with a quiescence search over captures. Keeps its transposition
+/// table between moves of a game, up to `MAX_TABLE_ENTRIES`.
+#[derive(Default)]
+pub struct Builtin {
+    table: HashMap<u64, Entry>,
+    nodes: u64,
+    deadline: Option<Instant>,
+    stopped: bool,
+    /// Set for an engine that searches this deep whatever its budget.
+    fixed_depth: Option<u32>,
+}
+
+impl Builtin {
+    /// An engine that always searches `depth` plies, however long that
+    /// takes, so it answers the same way every time it's asked.
+    pub fn fixed_depth(depth: u32) -> Self {
+        Builtin {
+            fixed_depth: Some(depth),
+            ..Builtin::default()
+        }
+    }
+
+    fn out_of_time(&mut self) -> bool {
+        self.nodes += 1;
+        if self.nodes.is_multiple_of(CLOCK_INTERVAL)
+            && self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            self.stopped = true;
+        }
+        self.stopped
+    }
+
+    /// Captures first, the most valuable victims before the rest, and the
+    /// table's best move ahead of everything.
+    fn ordered(&self, board: &Board, castling: CastlingRights, best: Option<Move>) -> Vec<Move> {
+        let mut moves = legal_moves(board, castling);
+        moves.sort_by_key(|&(from, to, _)| {
+            if best.is_some_and(|(best_from, best_to, _)| best_from == from && best_to == to) {
+                return i32::MIN;
+            }
+            -captured_value(board, (from, to, None)).unwrap_or(0)
+        });
+        moves
+    }
+
+    /// Only captures are searched further, so a position isn't scored in
+    /// the middle of an exchange. In check there is no standing pat: every
+    /// evasion is searched, since doing nothing isn't a move.
+    fn quiesce(
+        &mut self,
+        board: &Board,
+        castling: CastlingRights,
+        ply: u32,
+        mut alpha: i32,
+        beta: i32,
+    ) -> i32 {
+        if self.out_of_time() {
+            return 0;
+        }
+        let moves = legal_moves(board, castling);
+        if moves.is_empty() {
+            return no_moves_score(board, ply);
+        }
+        let in_check = rules::in_check(board, board.move_turn);
+        let mut best = if in_check {
+            -MATE_SCORE + ply as i32
+        } else {
+            let stand_pat = evaluate(board);
+            if stand_pat >= beta {
+                return stand_pat;
+            }
+            alpha = alpha.max(stand_pat);
+            stand_pat
+        };
+        let mut searched: Vec<(Move, i32)> = moves
+            .into_iter()
+            .filter_map(|mv| match captured_value(board, mv) {
+                Some(value) => Some((mv, value)),
+                None if in_check => Some((mv, 0)),
+                None => None,
+            })
+            .collect();
+        searched.sort_by_key(|(_, value)| -value);
+        for (mv, _) in searched {
+            let Some((next, next_castling)) = play(board, castling, mv) else {
+                continue;
+            };
+            let score = -self.quiesce(&next, next_castling, ply + 1, -beta, -alpha);
+            if self.stopped {
+                return 0;
+            }
+            best = best.max(score);
+            alpha = alpha.max(score);
+            if alpha >= beta {
+                break;
+            }
+        }
+        best
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn alpha_beta(
+        &mut self,
+        board: &Board,
+        castling: CastlingRights,
+        key: Key,
+        depth: u32,
+        ply: u32,
+        mut alpha: i32,
+        beta: i32,
+    ) -> i32 {
+        if self.out_of_time() {
+            return 0;
+        }
+        let entry = self.table.get(&key.hash).copied();
+        if let Some(entry) = entry
+            && entry.depth >= depth
+            && ply > 0
+        {
+            let score = score_from_table(entry.score, ply);
+            match entry.bound {
+                Bound::Exact => return score,
+                Bound::Lower if score >= beta => return score,
+                Bound::Upper if score <= alpha => return score,
+                _ => {}
+            }
+        }
+        if depth == 0 {
+            return self.quiesce(board, castling, ply, alpha, beta);
+        }
+        let moves = self.ordered(board, castling, entry.and_then(|entry| entry.best));
+        if moves.is_empty() {
+            return no_moves_score(board, ply);
+        }
+        let original_alpha = alpha;
+        let mut best_score = -INFINITY;
+        let mut best_move = None;
+        for mv in moves {
+            let Some((next, next_castling)) = play(board, castling, mv) else {
+                continue;
+            };
+            let next_key = key.after_move(board, castling, &next, next_castling, mv.0, mv.1);
+            let score = -self.alpha_beta(
+                &next,
+                next_castling,
+                next_key,
+                depth - 1,
+                ply + 1,
+                -beta,
+                -alpha,
+            );
+            if self.stopped {
+                return 0;
+            }
+            if score > best_score {
+                best_score = score;
+                best_move = Some(mv);
+            }
+            alpha = alpha.max(score);
+            if alpha >= beta {
+                break;
+            }
+        }
+        let bound = if best_score <= original_alpha {
+            Bound::Upper
+        } else if best_score >= beta {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        };
+        if self.table.len() >= MAX_TABLE_ENTRIES && !self.table.contains_key(&key.hash) {
+            self.table.clear();
+        }
+        self.table.insert(
+            key.hash,
+            Entry {
+                depth,
+                score: score_to_table(best_score, ply),
+                bound,
+                best: best_move,
+            },
+        );
+        best_score
+    }
+
+    /// Searches one ply deeper at a time until the budget runs out, and
+    /// answers with the best move of the last search that finished. The
+    /// first one always finishes, so there is an answer however short the
+    /// budget. An engine with a fixed depth ignores the budget.
+    pub fn search(
+        &mut self,
+        board: &Board,
+        castling: CastlingRights,
+        budget: Duration,
+    ) -> Option<EngineMove> {
+        let mut found = None;
+        let key = Key::new(board, castling);
+        let deadline = Instant::now() + budget;
+        for depth in 1..=self.fixed_depth.unwrap_or(MAX_DEPTH) {
+            self.nodes = 0;
+            self.stopped = false;
+            self.deadline = (depth > 1 && self.fixed_depth.is_none()).then_some(deadline);
+            let score = self.alpha_beta(board, castling, key, depth, 0, -INFINITY, INFINITY);
+            if self.stopped {
+                break;
+            }
+            let Some((from, to, promotion_piece)) =
+                self.table.get(&key.hash).and_then(|entry| entry.best)
+            else {
+                break;
+            };
+            found = Some(EngineMove {
+                from,
+                to,
+                promotion_piece,
+                score,
+                depth,
+            });
+            if score.abs() >= MATE_BOUND {
+                break;
+            }
+        }
+        found
+    }
+}
+
+impl Engine for Builtin {
+    fn name(&self) -> &str {
+        "Built-in"
+    }
+
+    fn best_move(
+        &mut self,
+        board: &Board,
+        castling: CastlingRights,
+        budget: Duration,
+    ) -> Option<EngineMove> {
+        self.search(board, castling, budget)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fen;
+
+    #[test]
+    fn perft_from_the_start_position() {
+        let board = Board::start_pos();
+        assert_eq!(perft(&board, 1), 20);
+        assert_eq!(perft(&board, 2), 400);
+        assert_eq!(perft(&board, 3), 8_902);
+    }
+
+    #[test]
+    fn perft_with_checks_and_en_passant() {
+        // Position 3 of the Chess Programming Wiki's perft results.
+        let (board, _) = fen::board_from_fen("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - -").unwrap();
+        assert_eq!(perft(&board, 1), 14);
+        assert_eq!(perft(&board, 2), 191);
+        assert_eq!(perft(&board, 3), 2_812);
+    }
+
+    #[test]
+    fn finds_a_mate_in_one() {
+        let (board, castling) = fen::board_from_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - -").unwrap();
+        let found = Builtin::default()
+            .search(&board, castling, Duration::from_millis(200))
+            .unwrap();
+        assert!(found.from == Position::new(0, 0) && found.to == Position::new(7, 0));
+        assert!(found.score >= MATE_BOUND);
+    }
+
+    #[test]
+    fn mates_found_through_the_table_keep_their_distance() {
+        let mut engine = Builtin::default();
+        // Kb6, Kb8 forced, then Rh8 mate.
+        let (board, castling) = fen::board_from_fen("k7/8/2K5/8/8/8/8/7R w - -").unwrap();
+        let found = engine
+            .search(&board, castling, Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(found.score, MATE_SCORE - 3);
+        let (board, castling) = fen::board_from_fen("1k6/8/1K6/8/8/8/8/7R w - -").unwrap();
+        let found = engine
+            .search(&board, castling, Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(found.score, MATE_SCORE - 1);
+    }
+
+    #[test]
+    fn a_fixed_depth_outlasts_the_budget() {
+        let found = Builtin::fixed_depth(3)
+            .search(
+                &Board::start_pos(),
+                CastlingRights::default(),
+                Duration::ZERO,
+            )
+            .unwrap();
+        assert_eq!(found.depth, 3);
+    }
+
+    #[test]
+    fn a_side_in_check_does_not_stand_pat() {
+        // The knight checks the king and forks the queen, which no evasion
+        // saves.
+        let (board, castling) = fen::board_from_fen("k7/8/8/8/8/8/5n2/3Q3K w - -").unwrap();
+        let score = Builtin::default().quiesce(&board, castling, 0, -MATE_SCORE, MATE_SCORE);
+        assert!(score < 0);
+    }
+
+    #[test]
+    fn takes_a_hanging_queen() {
+        let (board, castling) = fen::board_from_fen("4k3/8/8/3q4/8/8/8/3RK3 w - -").unwrap();
+        let found = Builtin::default()
+            .search(&board, castling, Duration::from_millis(200))
+            .unwrap();
+        assert!(found.from == Position::new(0, 3) && found.to == Position::new(4, 3));
+    }
+}
@@ -0,0 +1,485 @@
+use std::thread;
+
+use bevy::prelude::*;
+use crossbeam_channel::{Receiver, unbounded};
+use hermanha_chess::{Board, Color as HermanhaColor};
+
+use crate::game_state::BoardState;
+use crate::hint;
+use crate::history::MoveHistory;
+use crate::review::ReviewPosition;
+use crate::ui::GameUi;
+use crate::widgets;
+
+/// Scores beyond this many pawns either way fill the graph to its edge.
+const SCORE_CAP: f32 = 8.0;
+
+const GRAPH_WIDTH: f32 = 360.0;
+const GRAPH_HEIGHT: f32 = 80.0;
+
+/// Scores are capped at this many pawns before moves are judged, so that
+/// letting a mate slip costs no more than losing a lot of material.
+const JUDGED_CAP: i32 = 10;
+
+/// How many pawns a move has to give away to count as an inaccuracy, a
+/// mistake or a blunder.
+const INACCURACY_LOSS: i32 = 1;
+const MISTAKE_LOSS: i32 = 2;
+const BLUNDER_LOSS: i32 = 3;
+
+/// What a shared review starts with, ahead of the moves it is for.
+const SHARED_REVIEW_TAG: &str = "Review:";
+
+const WHITE_BAR: Color = Color::srgb(0.85, 0.85, 0.85);
+const BLACK_BAR: Color = Color::srgb(0.4, 0.4, 0.45);
+const SHOWN_COLUMN: Color = Color::srgb(0.3, 0.45, 0.3);
+
+/// How much a move gave away, by how far the search's score swung
+/// against the side that played it.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Judgement {
+    Blunder,
+    Mistake,
+    Inaccuracy,
+}
+
+impl Judgement {
+    const ALL: [Judgement; 3] = [
+        Judgement::Blunder,
+        Judgement::Mistake,
+        Judgement::Inaccuracy,
+    ];
+
+    fn from_loss(loss: i32) -> Option<Self> {
+        if loss >= BLUNDER_LOSS {
+            Some(Judgement::Blunder)
+        } else if loss >= MISTAKE_LOSS {
+            Some(Judgement::Mistake)
+        } else if loss >= INACCURACY_LOSS {
+            Some(Judgement::Inaccuracy)
+        } else {
+            None
+        }
+    }
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Judgement::Blunder => "??",
+            Judgement::Mistake => "?",
+            Judgement::Inaccuracy => "?!",
+        }
+    }
+
+    pub fn color(self) -> Color {
+        match self {
+            Judgement::Blunder => Color::srgb(0.9, 0.25, 0.25),
+            Judgement::Mistake => Color::srgb(0.95, 0.55, 0.2),
+            Judgement::Inaccuracy => Color::srgb(0.9, 0.8, 0.3),
+        }
+    }
+
+    /// "1 blunder", "2 mistakes" and so on.
+    fn count(self, count: usize) -> String {
+        let name = match self {
+            Judgement::Blunder => "blunder",
+            Judgement::Mistake => "mistake",
+            Judgement::Inaccuracy => "inaccuracy",
+        };
+        match (count, self) {
+            (1, _) => format!("1 {name}"),
+            (_, Judgement::Inaccuracy) => format!("{count} inaccuracies"),
+            _ => format!("{count} {name}s"),
+        }
+    }
+}
+
+/// The search's score of every position of the reviewed game, from the
+/// start to the final position, filled in as they come back from the
+/// thread working through them. Only present during `PlayState::Review`.
+#[derive(Resource)]
+pub struct Evaluations {
+    scores: Vec<Option<i32>>,
+    /// The side to move in each position.
+    turns: Vec<HermanhaColor>,
+    receiver: Receiver<(usize, i32)>,
+}
+
+impl Evaluations {
+    /// The judgement on the move at `ply` of the game, once the positions
+    /// on both sides of it are scored.
+    pub fn judgement(&self, ply: usize) -> Option<Judgement> {
+        let cap = |score: i32| score.clamp(-JUDGED_CAP, JUDGED_CAP);
+        let before = cap((*self.scores.get(ply)?)?);
+        let after = cap((*self.scores.get(ply + 1)?)?);
+        let loss = match self.turns[ply] {
+            HermanhaColor::White => before - after,
+            HermanhaColor::Black => after - before,
+        };
+        Judgement::from_loss(loss)
+    }
+
+    /// How far the search has got, or how many moves of each kind each
+    /// side made once it is done.
+    fn summary(&self) -> String {
+        let scored = self.scores.iter().filter(|score| score.is_some()).count();
+        if scored < self.scores.len() {
+            return format!("Analysing... {scored}/{}", self.scores.len());
+        }
+        let mut lines = Vec::new();
+        for (color, side) in [
+            (HermanhaColor::White, "White"),
+            (HermanhaColor::Black, "Black"),
+        ] {
+            let judgements: Vec<Judgement> = (0..self.scores.len() - 1)
+                .filter(|ply| self.turns[*ply] == color)
+                .filter_map(|ply| self.judgement(ply))
+                .collect();
+            let counts: Vec<String> = Judgement::ALL
+                .into_iter()
+                .map(|kind| (kind, judgements.iter().filter(|j| **j == kind).count()))
+                .filter(|(_, count)| *count > 0)
+                .map(|(kind, count)| kind.count(count))
+                .collect();
+            if counts.is_empty() {
+                lines.push(format!("{side}: no mistakes"));
+            } else {
+                lines.push(format!("{side}: {}", counts.join(", ")));
+            }
+        }
+        lines.join("\n")
+    }
+}
+
+impl Evaluations {
+    /// The scores as text for the opponent, once every position is
+    /// scored: the game's moves on the first line, the scores on the
+    /// second.
+    pub fn share(&self, history: &MoveHistory) -> Option<String> {
+        let scores: Option<Vec<String>> = self
+            .scores
+            .iter()
+            .map(|score| score.map(|score| score.to_string()))
+            .collect();
+        let moves: Vec<&str> = history
+            .moves
+            .iter()
+            .map(|played| played.san.as_str())
+            .collect();
+        Some(format!(
+            "{SHARED_REVIEW_TAG} {}\n{}",
+            moves.join(" "),
+            scores?.join(" ")
+        ))
+    }
+}
+
+/// The scores of the opponent's review, sent with Ctrl+Shift+R. They take
+/// the place of our own search's in a review of the same moves.
+#[derive(Resource, Debug, PartialEq)]
+pub struct SharedReview {
+    moves: Vec<String>,
+    scores: Vec<i32>,
+}
+
+impl SharedReview {
+    /// Reads what `Evaluations::share` wrote; anything else is `None`.
+    pub fn parse(text: &str) -> Option<Self> {
+        let (moves, scores) = text.strip_prefix(SHARED_REVIEW_TAG)?.split_once('\n')?;
+        let moves: Vec<String> = moves.split_whitespace().map(str::to_string).collect();
+        let scores = scores
+            .split_whitespace()
+            .map(|score| score.parse().ok())
+            .collect::<Option<Vec<i32>>>()?;
+        (scores.len() == moves.len() + 1).then_some(SharedReview { moves, scores })
+    }
+
+    /// The scores, if the review is of `history`'s moves.
+    fn scores_for(&self, history: &MoveHistory) -> Option<&[i32]> {
+        (self.moves.len() == history.moves.len()
+            && self
+                .moves
+                .iter()
+                .zip(&history.moves)
+                .all(|(san, played)| *san == played.san))
+        .then_some(&self.scores)
+    }
+}
+
+/// The graph under the board, one column per position.
+#[derive(Component)]
+pub struct EvalGraph;
+
+/// The count of each side's inaccuracies, mistakes and blunders, above
+/// the graph.
+#[derive(Component)]
+pub struct EvalSummary;
+
+/// The column for the position before move `0` of the game, or the final
+/// position after the last move.
+#[derive(Component, Clone, Copy)]
+pub struct EvalPoint(usize);
+
+/// Hands every position of the game to a thread that scores them one
+/// after the other, and puts up the empty graph. The opponent's shared
+/// review, if there is one for this game, leaves nothing to score.
+pub fn start_evaluations(
+    mut commands: Commands,
+    history: Res<MoveHistory>,
+    board: Res<BoardState>,
+    shared: Option<Res<SharedReview>>,
+) {
+    let mut positions: Vec<Board> = (0..history.moves.len())
+        .filter_map(|ply| history.position_before(ply))
+        .map(|(before, _)| before.clone())
+        .collect();
+    positions.push(board.0.clone());
+    let turns = positions
+        .iter()
+        .map(|position| position.move_turn)
+        .collect();
+    let (sender, receiver) = unbounded();
+    let shared = shared
+        .as_ref()
+        .and_then(|shared| shared.scores_for(&history));
+    let scores = match shared {
+        Some(scores) => scores.iter().copied().map(Some).collect(),
+        None => vec![None; positions.len()],
+    };
+    if shared.is_none() {
+        thread::spawn(move || {
+            for (index, position) in positions.iter().enumerate() {
+                // The review was left and nobody is waiting for the rest.
+                if sender.send((index, hint::evaluate(position))).is_err() {
+                    return;
+                }
+            }
+        });
+    }
+    commands.insert_resource(Evaluations {
+        scores,
+        turns,
+        receiver,
+    });
+    commands.spawn((
+        EvalSummary,
+        GameUi,
+        Text::new(""),
+        TextFont {
+            font_size: 14.0,
+            ..default()
+        },
+        Node {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(56.0 + GRAPH_HEIGHT),
+            left: Val::Percent(35.0),
+            ..default()
+        },
+    ));
+    commands.spawn((
+        EvalGraph,
+        GameUi,
+        Node {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(52.0),
+            left: Val::Percent(35.0),
+            width: Val::Px(GRAPH_WIDTH),
+            height: Val::Px(GRAPH_HEIGHT),
+            padding: UiRect::all(Val::Px(4.0)),
+            column_gap: Val::Px(1.0),
+            ..default()
+        },
+        BackgroundColor(widgets::PANEL_COLOR),
+    ));
+}
+
+pub fn stop_evaluations(
+    mut commands: Commands,
+    graphs: Query<Entity, Or<(With<EvalGraph>, With<EvalSummary>)>>,
+) {
+    for entity in graphs.iter() {
+        commands.entity(entity).despawn();
+    }
+    commands.remove_resource::<Evaluations>();
+}
+
+/// Takes in what the thread has scored, or the opponent's review once it
+/// arrives, which stops the thread.
+pub fn collect_evaluations(
+    mut evaluations: ResMut<Evaluations>,
+    history: Res<MoveHistory>,
+    shared: Option<Res<SharedReview>>,
+) {
+    if let Some(shared) = shared.filter(|shared| shared.is_changed())
+        && let Some(scores) = shared.scores_for(&history)
+    {
+        evaluations.scores = scores.iter().copied().map(Some).collect();
+        evaluations.receiver = unbounded().1;
+        return;
+    }
+    while let Ok((index, score)) = evaluations.receiver.try_recv() {
+        evaluations.scores[index] = Some(score);
+    }
+}
+
+/// Clicking a column shows that position of the game.
+pub fn handle_graph_clicks(
+    interactions: Query<(&Interaction, &EvalPoint), Changed<Interaction>>,
+    mut review: ResMut<ReviewPosition>,
+) {
+    for (interaction, point) in interactions.iter() {
+        if *interaction == Interaction::Pressed {
+            review.jump_to(point.0);
+        }
+    }
+}
+
+/// A bar filling `share` of its half of the column.
+fn bar(share: f32, color: Color) -> impl Bundle {
+    (
+        Node {
+            width: Val::Percent(100.0),
+            height: Val::Percent(share * 100.0),
+            ..default()
+        },
+        BackgroundColor(color),
+    )
+}
+
+/// Draws White's advantage upwards from the middle and Black's downwards,
+/// with the column of the shown position marked, and the summary above.
+pub fn render_eval_graph(
+    mut commands: Commands,
+    evaluations: Res<Evaluations>,
+    review: Res<ReviewPosition>,
+    graphs: Query<Entity, With<EvalGraph>>,
+    mut summaries: Query<&mut Text, With<EvalSummary>>,
+) {
+    if !evaluations.is_changed() && !review.is_changed() {
+        return;
+    }
+    let summary = evaluations.summary();
+    for mut text in summaries.iter_mut() {
+        text.0 = summary.clone();
+    }
+    let shown = (review.variation_ply == 0).then_some(review.ply);
+    for graph in graphs.iter() {
+        commands.entity(graph).despawn_related::<Children>();
+        for (index, score) in evaluations.scores.iter().enumerate() {
+            let share = score.map_or(0.0, |score| (score as f32 / SCORE_CAP).clamp(-1.0, 1.0));
+            commands.entity(graph).with_child((
+                EvalPoint(index),
+                Button,
+                Node {
+                    flex_grow: 1.0,
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    ..default()
+                },
+                BackgroundColor(if shown == Some(index) {
+                    SHOWN_COLUMN
+                } else {
+                    Color::NONE
+                }),
+                children![
+                    (
+                        Node {
+                            height: Val::Percent(50.0),
+                            flex_direction: FlexDirection::Column,
+                            justify_content: JustifyContent::FlexEnd,
+                            ..default()
+                        },
+                        children![bar(share.max(0.0), WHITE_BAR)],
+                    ),
+                    (
+                        Node {
+                            height: Val::Percent(50.0),
+                            flex_direction: FlexDirection::Column,
+                            ..default()
+                        },
+                        children![bar(-share.min(0.0), BLACK_BAR)],
+                    ),
+                ],
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scored(scores: &[i32]) -> Evaluations {
+        Evaluations {
+            scores: scores.iter().copied().map(Some).collect(),
+            turns: (0..scores.len())
+                .map(|ply| {
+                    if ply % 2 == 0 {
+                        HermanhaColor::White
+                    } else {
+                        HermanhaColor::Black
+                    }
+                })
+                .collect(),
+            receiver: unbounded().1,
+        }
+    }
+
+    #[test]
+    fn moves_are_judged_by_what_they_gave_away() {
+        // White drops three pawns, Black gives one back, then two.
+        let evaluations = scored(&[0, -3, -2, -4]);
+        assert!(evaluations.judgement(0) == Some(Judgement::Blunder));
+        assert!(evaluations.judgement(1) == Some(Judgement::Inaccuracy));
+        assert!(evaluations.judgement(2) == Some(Judgement::Mistake));
+        assert!(evaluations.judgement(3).is_none());
+    }
+
+    #[test]
+    fn a_missed_mate_costs_no_more_than_a_lot_of_material() {
+        // A mate, far past the cap, slips to nine pawns up.
+        let evaluations = scored(&[10_000, 9]);
+        assert!(evaluations.judgement(0) == Some(Judgement::Inaccuracy));
+    }
+
+    #[test]
+    fn the_summary_counts_each_sides_mistakes() {
+        let evaluations = scored(&[0, -3, -1, -1, -1]);
+        assert_eq!(evaluations.summary(), "White: 1 blunder\nBlack: 1 mistake");
+        let mut evaluations = scored(&[0, 0]);
+        evaluations.scores[1] = None;
+        assert_eq!(evaluations.summary(), "Analysing... 1/2");
+    }
+
+    #[test]
+    fn a_shared_review_reads_back_for_the_same_game() {
+        let history = MoveHistory::default();
+        let evaluations = Evaluations {
+            scores: vec![Some(3)],
+            turns: vec![HermanhaColor::White],
+            receiver: unbounded().1,
+        };
+        let text = evaluations.share(&history).unwrap();
+        let shared = SharedReview::parse(&text).unwrap();
+        assert_eq!(shared.scores_for(&history), Some(&[3][..]));
+    }
+
+    #[test]
+    fn a_review_is_only_shared_once_scored() {
+        let evaluations = Evaluations {
+            scores: vec![Some(0), None],
+            turns: vec![HermanhaColor::White, HermanhaColor::Black],
+            receiver: unbounded().1,
+        };
+        assert_eq!(evaluations.share(&MoveHistory::default()), None);
+    }
+
+    #[test]
+    fn a_shared_review_of_other_moves_is_not_used() {
+        let shared = SharedReview::parse("Review: e4 e5\n0 1 0").unwrap();
+        assert_eq!(shared.scores, [0, 1, 0]);
+        assert_eq!(shared.scores_for(&MoveHistory::default()), None);
+        assert_eq!(SharedReview::parse("Review: e4 e5\n0 1"), None);
+        assert_eq!(SharedReview::parse("Review: e4\n0 x"), None);
+        assert_eq!(SharedReview::parse("[Event \"Casual game\"]"), None);
+    }
+}
@@ -0,0 +1,210 @@
+use bevy::input::ButtonInput;
+use bevy::prelude::*;
+use hermanha_chess::{Color as HermanhaColor, PieceType};
+
+use crate::actions::{self, Action};
+use crate::clock::Clocks;
+use crate::game_state::{GameOutcome, MovePlayed};
+use crate::offers::{Concluded, Conclusion, DrawOffers};
+use crate::rules::{self, Outcome};
+use crate::san::square_name;
+use crate::ui::GameUi;
+use crate::widgets;
+
+/// How many of the latest lines the panel shows.
+const SHOWN_LINES: usize = 10;
+
+/// Every game event described in words, for players following the game
+/// with a screen reader and for scripts watching it. Each line is also
+/// printed to stdout when `stdout` is set, with `--event-log`.
+#[derive(Resource, Default)]
+pub struct EventLog {
+    pub lines: Vec<String>,
+    pub stdout: bool,
+}
+
+impl EventLog {
+    pub fn push(&mut self, line: String) {
+        if self.stdout {
+            println!("{line}");
+        }
+        self.lines.push(line);
+    }
+}
+
+/// Whether the log panel is shown.
+#[derive(Resource, Default)]
+pub struct ShowEventLog(pub bool);
+
+#[derive(Component)]
+pub struct EventLogPanel;
+
+fn side(color: HermanhaColor) -> &'static str {
+    match color {
+        HermanhaColor::White => "White",
+        HermanhaColor::Black => "Black",
+    }
+}
+
+fn piece_name(piece_type: PieceType) -> &'static str {
+    match piece_type {
+        PieceType::Pawn => "pawn",
+        PieceType::Knight => "knight",
+        PieceType::Bishop => "bishop",
+        PieceType::Rook => "rook",
+        PieceType::Queen => "queen",
+        PieceType::King => "king",
+    }
+}
+
+/// "White plays knight f3", "Black plays pawn takes bishop e4" or "White
+/// castles kingside", with a promotion added on the end.
+pub fn describe_move(played: &MovePlayed) -> String {
+    let before = &played.before;
+    let Some(moving) = before.get(played.from) else {
+        return format!(
+            "{} plays {}",
+            side(before.move_turn),
+            square_name(played.to)
+        );
+    };
+    let mover = side(moving.color);
+    let target = before.get(played.to);
+    if rules::is_castling(before, played.from, played.to) {
+        let wing = if played.to.col > played.from.col {
+            "kingside"
+        } else {
+            "queenside"
+        };
+        return format!("{mover} castles {wing}");
+    }
+    let is_pawn = matches!(moving.piece_type, PieceType::Pawn);
+    let captured = match target {
+        Some(target) => Some(target.piece_type),
+        // En passant.
+        None if is_pawn && played.from.col != played.to.col => Some(PieceType::Pawn),
+        None => None,
+    };
+    let mut text = format!("{mover} plays {}", piece_name(moving.piece_type));
+    if let Some(captured) = captured {
+        text.push_str(&format!(" takes {}", piece_name(captured)));
+    }
+    text.push_str(&format!(" {}", square_name(played.to)));
+    if let Some(promoted) = played.after.get(played.to)
+        && is_pawn
+        && !matches!(promoted.piece_type, PieceType::Pawn)
+    {
+        text.push_str(&format!(
+            ", promotes to {}",
+            piece_name(promoted.piece_type)
+        ));
+    }
+    text
+}
+
+/// Describes every played move and whether it gives check.
+pub fn log_moves(mut played: EventReader<MovePlayed>, mut log: ResMut<EventLog>) {
+    for played in played.read() {
+        log.push(describe_move(played));
+        let in_turn = played.after.move_turn;
+        if rules::in_check(&played.after, in_turn) {
+            log.push(format!("{} is in check", side(in_turn)));
+        }
+    }
+}
+
+/// What `log_game_events` last saw, so each event is logged once.
+#[derive(Default)]
+pub struct LoggedState {
+    offers: (bool, bool),
+    concluded: Option<Conclusion>,
+    outcome: Option<Outcome>,
+    flagged: Option<HermanhaColor>,
+}
+
+/// Draw offers, resignations, agreed draws, flag falls and the end of
+/// the game on the board, each logged once when it happens.
+pub fn log_game_events(
+    mut log: ResMut<EventLog>,
+    outcome: Res<GameOutcome>,
+    concluded: Res<Concluded>,
+    offers: Res<DrawOffers>,
+    clocks: Option<Res<Clocks>>,
+    mut logged: Local<LoggedState>,
+) {
+    if offers.sent && !logged.offers.0 {
+        log.push("Draw offered".to_string());
+    }
+    if offers.received && !logged.offers.1 {
+        log.push("Opponent offers a draw".to_string());
+    }
+    logged.offers = (offers.sent, offers.received);
+    if concluded.0 != logged.concluded
+        && let Some(conclusion) = concluded.0
+    {
+        log.push(conclusion.text());
+    }
+    logged.concluded = concluded.0;
+    if outcome.0 != logged.outcome
+        && let Some(outcome) = outcome.0
+    {
+        log.push(outcome.text().to_string());
+    }
+    logged.outcome = outcome.0;
+    let flagged = clocks.and_then(|clocks| clocks.flagged);
+    if flagged != logged.flagged
+        && let Some(color) = flagged
+    {
+        log.push(format!("{} runs out of time", side(color)));
+    }
+    logged.flagged = flagged;
+}
+
+pub fn spawn_event_log(mut commands: Commands) {
+    commands.spawn((
+        EventLogPanel,
+        GameUi,
+        Text::new(""),
+        TextFont {
+            font_size: 14.0,
+            ..default()
+        },
+        Node {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(12.0),
+            right: Val::Px(12.0),
+            max_width: Val::Px(320.0),
+            padding: UiRect::all(Val::Px(6.0)),
+            ..default()
+        },
+        BackgroundColor(widgets::PANEL_COLOR),
+        Visibility::Hidden,
+    ));
+}
+
+pub fn toggle_event_log(keys: Res<ButtonInput<KeyCode>>, mut show: ResMut<ShowEventLog>) {
+    if actions::just_pressed(&keys, Action::EventLog) {
+        show.0 = !show.0;
+    }
+}
+
+pub fn render_event_log(
+    log: Res<EventLog>,
+    show: Res<ShowEventLog>,
+    mut panels: Query<(&mut Text, &mut Visibility), With<EventLogPanel>>,
+    added: Query<(), Added<EventLogPanel>>,
+) {
+    if !log.is_changed() && !show.is_changed() && added.is_empty() {
+        return;
+    }
+    let start = log.lines.len().saturating_sub(SHOWN_LINES);
+    let text = log.lines[start..].join("\n");
+    for (mut panel, mut visibility) in panels.iter_mut() {
+        panel.0 = text.clone();
+        *visibility = if show.0 {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+    }
+}
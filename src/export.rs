@@ -0,0 +1,669 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bevy::asset::RenderAssetUsages;
+use bevy::input::ButtonInput;
+use bevy::prelude::*;
+use bevy::render::camera::{RenderTarget, ScalingMode};
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat, TextureUsages};
+use bevy::render::view::RenderLayers;
+use bevy::render::view::screenshot::{Screenshot, ScreenshotCaptured, save_to_disk};
+use bevy_svg::prelude::*;
+use crossbeam_channel::{Receiver, Sender, TryRecvError, bounded, unbounded};
+use hermanha_chess::{BOARD_COLS, BOARD_ROWS, Board, Color as HermanhaColor, Position};
+
+use crate::actions::{self, Action};
+use crate::board_render::BoardOrientation;
+use crate::game_state::{BoardState, Castling};
+use crate::history::MoveHistory;
+use crate::review::ReviewPosition;
+use crate::theme::Theme;
+use crate::toast::Toasts;
+use crate::{PIECE_SCALE, PIECE_Z, TILE_SIZE, pos_to_vec3};
+
+/// Image sizes the settings panel cycles through, in pixels.
+pub const SIZE_PRESETS: [u32; 4] = [512, 1024, 2048, 4096];
+
+/// The export scene is drawn on its own layer, so neither it nor the
+/// board on screen shows up in the other's camera.
+const EXPORT_LAYER: usize = 1;
+
+/// Room around the board for the labels, in world units.
+const LABEL_MARGIN: f32 = TILE_SIZE * 0.6;
+
+const LABEL_FONT_SIZE: f32 = TILE_SIZE * 0.3;
+
+const BACKGROUND: Color = Color::srgb(0.16, 0.16, 0.18);
+
+/// Frame delays the settings panel cycles through.
+pub const DELAY_PRESETS: [Duration; 4] = [
+    Duration::from_millis(500),
+    Duration::from_millis(1000),
+    Duration::from_millis(1500),
+    Duration::from_millis(2000),
+];
+
+/// Animations are drawn no larger than this, since every frame is held in
+/// memory until the file is written.
+const ANIMATION_MAX_SIZE: u32 = 512;
+
+/// How many frame delays the final position stays up before the
+/// animation starts over.
+const LAST_FRAME_HOLD: u16 = 3;
+
+/// Frames the scene is given to build its meshes and lay out its text
+/// before the image is taken.
+const SETTLE_FRAMES: u32 = 3;
+
+/// What is drawn around the board in an exported image.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ImageLabels {
+    All,
+    Coordinates,
+    SideToMove,
+    None,
+}
+
+impl ImageLabels {
+    const ALL: [ImageLabels; 4] = [
+        ImageLabels::All,
+        ImageLabels::Coordinates,
+        ImageLabels::SideToMove,
+        ImageLabels::None,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ImageLabels::All => "coordinates and side to move",
+            ImageLabels::Coordinates => "coordinates",
+            ImageLabels::SideToMove => "side to move",
+            ImageLabels::None => "none",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        ImageLabels::ALL
+            .into_iter()
+            .find(|labels| labels.name() == name)
+    }
+
+    pub fn next(self) -> Self {
+        let index = ImageLabels::ALL
+            .iter()
+            .position(|labels| *labels == self)
+            .unwrap_or(0);
+        ImageLabels::ALL[(index + 1) % ImageLabels::ALL.len()]
+    }
+
+    fn coordinates(self) -> bool {
+        matches!(self, ImageLabels::All | ImageLabels::Coordinates)
+    }
+
+    fn side_to_move(self) -> bool {
+        matches!(self, ImageLabels::All | ImageLabels::SideToMove)
+    }
+}
+
+/// How exported board images are drawn: their width and height in pixels,
+/// the labels around the board, and how long each position of an
+/// animated game is shown.
+#[derive(Resource, Clone, Copy, PartialEq)]
+pub struct ImageExport {
+    pub size: u32,
+    pub labels: ImageLabels,
+    pub frame_delay: Duration,
+}
+
+impl Default for ImageExport {
+    fn default() -> Self {
+        ImageExport {
+            size: 1024,
+            labels: ImageLabels::All,
+            frame_delay: Duration::from_millis(1000),
+        }
+    }
+}
+
+impl ImageExport {
+    pub fn parse_size(text: &str) -> Result<u32, String> {
+        match text.trim().parse::<u32>() {
+            Ok(size) if (64..=8192).contains(&size) => Ok(size),
+            _ => Err(format!("Expected a size from 64 to 8192 pixels: {text}")),
+        }
+    }
+
+    pub fn parse_frame_delay(text: &str) -> Result<Duration, String> {
+        match text.trim().parse::<u64>() {
+            Ok(ms) if (50..=10_000).contains(&ms) => Ok(Duration::from_millis(ms)),
+            _ => Err(format!(
+                "Expected a frame delay from 50 to 10000 milliseconds: {text}"
+            )),
+        }
+    }
+
+    pub fn next_frame_delay(&mut self) {
+        self.frame_delay = DELAY_PRESETS
+            .into_iter()
+            .find(|delay| *delay > self.frame_delay)
+            .unwrap_or(DELAY_PRESETS[0]);
+    }
+
+    pub fn next_size(&mut self) {
+        self.size = SIZE_PRESETS
+            .into_iter()
+            .find(|size| *size > self.size)
+            .unwrap_or(SIZE_PRESETS[0]);
+    }
+}
+
+/// The camera and copies of the squares, pieces and labels an image is
+/// being drawn from. None of it is shown on screen.
+#[derive(Component)]
+pub struct ExportScene;
+
+/// An image on its way to disk.
+#[derive(Resource)]
+pub struct PendingExport {
+    image: Handle<Image>,
+    path: PathBuf,
+    frames: u32,
+}
+
+fn label(commands: &mut Commands, text: String, at: Vec2, scale: f32) {
+    commands.spawn((
+        ExportScene,
+        RenderLayers::layer(EXPORT_LAYER),
+        Text2d::new(text),
+        // Laid out at the image's resolution and scaled back down, so the
+        // text stays sharp in large images.
+        TextFont {
+            font_size: LABEL_FONT_SIZE * scale,
+            ..default()
+        },
+        TextColor(Color::WHITE),
+        Transform::from_translation(at.extend(PIECE_Z)).with_scale(Vec3::splat(1.0 / scale)),
+    ));
+}
+
+/// A square image for the export camera to draw into.
+fn target_image(images: &mut Assets<Image>, size: u32) -> Handle<Image> {
+    let mut image = Image::new_fill(
+        Extent3d {
+            width: size,
+            height: size,
+            ..default()
+        },
+        TextureDimension::D2,
+        &[0, 0, 0, 0],
+        TextureFormat::Bgra8UnormSrgb,
+        RenderAssetUsages::default(),
+    );
+    image.texture_descriptor.usage = TextureUsages::TEXTURE_BINDING
+        | TextureUsages::COPY_DST
+        | TextureUsages::COPY_SRC
+        | TextureUsages::RENDER_ATTACHMENT;
+    images.add(image)
+}
+
+/// Spawns the export camera drawing into `image`, and the squares, pieces
+/// and labels of `board` seen with `bottom`'s side at the bottom.
+fn spawn_scene(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    theme: &Theme,
+    settings: ImageExport,
+    bottom: HermanhaColor,
+    board: &Board,
+    image: Handle<Image>,
+) {
+    // Where `pos` is drawn, in the coordinates of a board seen from
+    // White's side.
+    let flip = |pos: Position| match bottom {
+        HermanhaColor::White => pos,
+        HermanhaColor::Black => Position::new(
+            BOARD_ROWS as i8 - 1 - pos.row,
+            BOARD_COLS as i8 - 1 - pos.col,
+        ),
+    };
+    let margin = if settings.labels == ImageLabels::None {
+        0.0
+    } else {
+        LABEL_MARGIN
+    };
+    let extent = BOARD_COLS as f32 * TILE_SIZE + 2.0 * margin;
+    let scale = settings.size as f32 / extent;
+    commands.spawn((
+        ExportScene,
+        Camera2d,
+        Camera {
+            target: RenderTarget::Image(image.into()),
+            clear_color: ClearColorConfig::Custom(BACKGROUND),
+            ..default()
+        },
+        Projection::Orthographic(OrthographicProjection {
+            scaling_mode: ScalingMode::Fixed {
+                width: extent,
+                height: extent,
+            },
+            ..OrthographicProjection::default_2d()
+        }),
+        RenderLayers::layer(EXPORT_LAYER),
+    ));
+
+    for row in 0..BOARD_ROWS as i8 {
+        for col in 0..BOARD_COLS as i8 {
+            let pos = Position::new(row, col);
+            commands.spawn((
+                ExportScene,
+                RenderLayers::layer(EXPORT_LAYER),
+                Sprite {
+                    color: theme.square_color(pos),
+                    custom_size: Some(Vec2::splat(TILE_SIZE)),
+                    ..default()
+                },
+                Transform::from_translation(pos_to_vec3(flip(pos), 0.0)),
+            ));
+            let Some(piece) = board.get(pos) else {
+                continue;
+            };
+            commands.spawn((
+                ExportScene,
+                RenderLayers::layer(EXPORT_LAYER),
+                Svg2d(asset_server.load(theme.piece_path(piece.color, piece.piece_type))),
+                Origin::Center,
+                Transform {
+                    translation: pos_to_vec3(flip(pos), PIECE_Z),
+                    scale: Vec3::splat(PIECE_SCALE),
+                    ..default()
+                },
+            ));
+        }
+    }
+
+    let edge = BOARD_COLS as f32 * TILE_SIZE * 0.5 + margin * 0.5;
+    if settings.labels.coordinates() {
+        for index in 0..BOARD_COLS as i8 {
+            let file = flip(Position::new(0, index));
+            let x = pos_to_vec3(file, 0.0).x;
+            let name = (b'a' + index as u8) as char;
+            label(commands, name.to_string(), Vec2::new(x, -edge), scale);
+        }
+        for index in 0..BOARD_ROWS as i8 {
+            let rank = flip(Position::new(index, 0));
+            let y = pos_to_vec3(rank, 0.0).y;
+            label(
+                commands,
+                (index + 1).to_string(),
+                Vec2::new(-edge, y),
+                scale,
+            );
+        }
+    }
+    if settings.labels.side_to_move() {
+        let text = match board.move_turn {
+            HermanhaColor::White => "White to move",
+            HermanhaColor::Black => "Black to move",
+        };
+        label(commands, text.to_string(), Vec2::new(0.0, edge), scale);
+    }
+}
+
+fn despawn_scene(commands: &mut Commands, scene: &Query<Entity, With<ExportScene>>) {
+    for entity in scene.iter() {
+        commands.entity(entity).despawn();
+    }
+}
+
+/// A file name in the working directory that won't clash with earlier
+/// exports.
+fn export_path(prefix: &str) -> PathBuf {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    PathBuf::from(format!("{prefix}-{seconds}.png"))
+}
+
+/// Ctrl+P draws the board as it is shown, the reviewed position included,
+/// to a PNG in the working directory. The squares and pieces are drawn
+/// again off screen by a camera of their own, without the panels and
+/// markers around and on the board.
+#[allow(clippy::too_many_arguments)]
+pub fn export_image(
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut images: ResMut<Assets<Image>>,
+    asset_server: Res<AssetServer>,
+    theme: Res<Theme>,
+    settings: Res<ImageExport>,
+    orientation: Res<BoardOrientation>,
+    board: Res<BoardState>,
+    castling: Res<Castling>,
+    history: Res<MoveHistory>,
+    review: Option<Res<ReviewPosition>>,
+    busy: Option<Res<PendingAnimation>>,
+    pending: Option<Res<PendingExport>>,
+    mut toasts: ResMut<Toasts>,
+) {
+    if pending.is_some() || busy.is_some() || !actions::just_pressed(&keys, Action::ExportImage) {
+        return;
+    }
+    let (shown, _) = match &review {
+        Some(review) => review.shown(&history, (&board.0, castling.0)),
+        None => (&board.0, castling.0),
+    };
+    let image = target_image(&mut images, settings.size);
+    spawn_scene(
+        &mut commands,
+        &asset_server,
+        &theme,
+        *settings,
+        orientation.bottom(),
+        shown,
+        image.clone(),
+    );
+    let path = export_path("chess-position");
+    toasts.push(format!("Saving the board to {}", path.display()));
+    commands.insert_resource(PendingExport {
+        image,
+        path,
+        frames: SETTLE_FRAMES,
+    });
+}
+
+/// Takes the image once the scene has settled, and clears the scene away
+/// the frame after.
+pub fn finish_export(
+    mut commands: Commands,
+    mut pending: ResMut<PendingExport>,
+    scene: Query<Entity, With<ExportScene>>,
+) {
+    match pending.frames {
+        0 => {
+            despawn_scene(&mut commands, &scene);
+            commands.remove_resource::<PendingExport>();
+        }
+        1 => {
+            commands
+                .spawn(Screenshot::image(pending.image.clone()))
+                .observe(save_to_disk(pending.path.clone()));
+        }
+        _ => {}
+    }
+    pending.frames = pending.frames.saturating_sub(1);
+}
+
+/// Asks for the game to be saved as an animation, from the game over
+/// overlay.
+#[derive(Event)]
+pub struct AnimationRequested;
+
+/// The game being drawn into an animation, one position at a time.
+#[derive(Resource)]
+pub struct PendingAnimation {
+    positions: Vec<Board>,
+    bottom: HermanhaColor,
+    settings: ImageExport,
+    image: Handle<Image>,
+    /// The position being drawn.
+    index: usize,
+    /// Frames left before the current position is taken; zero while the
+    /// image is on its way back.
+    frames: u32,
+    captured: Vec<Image>,
+    sender: Sender<Image>,
+    receiver: Receiver<Image>,
+    path: PathBuf,
+}
+
+/// The animation being encoded and written to disk on a thread of its
+/// own, and where it went or why it couldn't be written.
+#[derive(Resource)]
+pub struct AnimationWriter(Receiver<Result<PathBuf, String>>);
+
+/// Ctrl+Shift+P, or the button after a game, saves the game as an
+/// animated PNG that steps through every position from the start, one
+/// frame per move.
+#[allow(clippy::too_many_arguments)]
+pub fn export_animation(
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut requested: EventReader<AnimationRequested>,
+    mut images: ResMut<Assets<Image>>,
+    asset_server: Res<AssetServer>,
+    theme: Res<Theme>,
+    settings: Res<ImageExport>,
+    orientation: Res<BoardOrientation>,
+    board: Res<BoardState>,
+    history: Res<MoveHistory>,
+    busy: (
+        Option<Res<PendingExport>>,
+        Option<Res<PendingAnimation>>,
+        Option<Res<AnimationWriter>>,
+    ),
+    mut toasts: ResMut<Toasts>,
+) {
+    let pressed = actions::just_pressed(&keys, Action::ExportAnimation);
+    if requested.read().count() == 0 && !pressed {
+        return;
+    }
+    if busy.0.is_some() || busy.1.is_some() || busy.2.is_some() {
+        toasts.push("Still saving the last export");
+        return;
+    }
+    if history.moves.is_empty() {
+        toasts.push("No moves to animate yet");
+        return;
+    }
+    let mut positions: Vec<Board> = (0..history.moves.len())
+        .filter_map(|ply| history.position_before(ply))
+        .map(|(before, _)| before.clone())
+        .collect();
+    positions.push(board.0.clone());
+    let settings = ImageExport {
+        size: settings.size.min(ANIMATION_MAX_SIZE),
+        ..*settings
+    };
+    let image = target_image(&mut images, settings.size);
+    spawn_scene(
+        &mut commands,
+        &asset_server,
+        &theme,
+        settings,
+        orientation.bottom(),
+        &positions[0],
+        image.clone(),
+    );
+    let path = export_path("chess-game");
+    toasts.push(format!(
+        "Drawing {} positions for {}",
+        positions.len(),
+        path.display()
+    ));
+    let (sender, receiver) = unbounded();
+    commands.insert_resource(PendingAnimation {
+        positions,
+        bottom: orientation.bottom(),
+        settings,
+        image,
+        index: 0,
+        frames: SETTLE_FRAMES,
+        captured: Vec::new(),
+        sender,
+        receiver,
+        path,
+    });
+}
+
+/// Takes each position once its scene has settled and moves on to the
+/// next one when the image comes back. After the last, the frames are
+/// handed to a thread to be encoded.
+pub fn advance_animation(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    theme: Res<Theme>,
+    mut animation: ResMut<PendingAnimation>,
+    scene: Query<Entity, With<ExportScene>>,
+) {
+    let animation = &mut *animation;
+    if let Ok(image) = animation.receiver.try_recv() {
+        animation.captured.push(image);
+        despawn_scene(&mut commands, &scene);
+        animation.index += 1;
+        if let Some(board) = animation.positions.get(animation.index) {
+            spawn_scene(
+                &mut commands,
+                &asset_server,
+                &theme,
+                animation.settings,
+                animation.bottom,
+                board,
+                animation.image.clone(),
+            );
+            animation.frames = SETTLE_FRAMES;
+            return;
+        }
+        let frames = mem::take(&mut animation.captured);
+        let path = animation.path.clone();
+        let delay = animation.settings.frame_delay;
+        let (sender, receiver) = bounded(1);
+        thread::spawn(move || {
+            let result = write_animation(&path, frames, delay).map(|()| path);
+            let _ = sender.send(result);
+        });
+        commands.remove_resource::<PendingAnimation>();
+        commands.insert_resource(AnimationWriter(receiver));
+        return;
+    }
+    if animation.frames == 0 {
+        return;
+    }
+    animation.frames -= 1;
+    if animation.frames == 0 {
+        let sender = animation.sender.clone();
+        commands
+            .spawn(Screenshot::image(animation.image.clone()))
+            .observe(move |trigger: Trigger<ScreenshotCaptured>| {
+                let image: &Image = trigger.event();
+                let _ = sender.send(image.clone());
+            });
+    }
+}
+
+pub fn report_animation(
+    mut commands: Commands,
+    writer: Res<AnimationWriter>,
+    mut toasts: ResMut<Toasts>,
+) {
+    let result = match writer.0.try_recv() {
+        Ok(result) => result,
+        Err(TryRecvError::Empty) => return,
+        Err(TryRecvError::Disconnected) => Err("the encoder stopped".to_string()),
+    };
+    match result {
+        Ok(path) => toasts.push(format!("Animation saved to {}", path.display())),
+        Err(err) => {
+            warn!("Could not save the animation: {err}");
+            toasts.push(format!("Could not save the animation: {err}"));
+        }
+    }
+    commands.remove_resource::<AnimationWriter>();
+}
+
+/// Writes `frames` as an animated PNG that loops forever, each frame
+/// shown for `delay` and the last one a while longer.
+fn write_animation(path: &Path, frames: Vec<Image>, delay: Duration) -> Result<(), String> {
+    let encoding = |err: png::EncodingError| err.to_string();
+    let Some(size) = frames.first().map(Image::width) else {
+        return Err("No frames".to_string());
+    };
+    let file = File::create(path).map_err(|err| err.to_string())?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), size, size);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .set_animated(frames.len() as u32, 0)
+        .map_err(encoding)?;
+    let delay_ms = delay.as_millis().min(u16::MAX as u128) as u16;
+    encoder.set_frame_delay(delay_ms, 1000).map_err(encoding)?;
+    let mut writer = encoder.write_header().map_err(encoding)?;
+    let last = frames.len() - 1;
+    for (index, frame) in frames.into_iter().enumerate() {
+        if index == last {
+            writer
+                .set_frame_delay(delay_ms.saturating_mul(LAST_FRAME_HOLD), 1000)
+                .map_err(encoding)?;
+        }
+        let rgba = frame
+            .try_into_dynamic()
+            .map_err(|err| err.to_string())?
+            .to_rgba8();
+        writer.write_image_data(&rgba).map_err(encoding)?;
+    }
+    writer.finish().map_err(encoding)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_read_back_by_name() {
+        for labels in ImageLabels::ALL {
+            assert!(ImageLabels::from_name(labels.name()) == Some(labels));
+        }
+        assert!(ImageLabels::from_name("everything").is_none());
+    }
+
+    #[test]
+    fn labels_and_sizes_cycle_round() {
+        let mut labels = ImageLabels::All;
+        for _ in ImageLabels::ALL {
+            labels = labels.next();
+        }
+        assert!(labels == ImageLabels::All);
+
+        let mut export = ImageExport::default();
+        export.next_size();
+        assert_eq!(export.size, 2048);
+        export.size = 3000;
+        export.next_size();
+        assert_eq!(export.size, 4096);
+        export.next_size();
+        assert_eq!(export.size, SIZE_PRESETS[0]);
+    }
+
+    #[test]
+    fn frame_delays_step_up_and_start_over() {
+        let mut export = ImageExport::default();
+        export.next_frame_delay();
+        assert_eq!(export.frame_delay, Duration::from_millis(1500));
+        export.frame_delay = Duration::from_millis(5000);
+        export.next_frame_delay();
+        assert_eq!(export.frame_delay, DELAY_PRESETS[0]);
+        assert_eq!(
+            ImageExport::parse_frame_delay("250"),
+            Ok(Duration::from_millis(250))
+        );
+        for text in ["49", "10001", "1.5", ""] {
+            assert!(
+                ImageExport::parse_frame_delay(text).is_err(),
+                "{text} was accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn sizes_are_kept_to_what_can_be_drawn() {
+        assert_eq!(ImageExport::parse_size(" 800 "), Ok(800));
+        for text in ["63", "8193", "-1", "big", ""] {
+            assert!(
+                ImageExport::parse_size(text).is_err(),
+                "{text} was accepted"
+            );
+        }
+    }
+}
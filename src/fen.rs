@@ -0,0 +1,182 @@
+use hermanha_chess::{Board, Color as HermanhaColor};
+
+use crate::rules::{self, CastlingFiles, CastlingRights};
+use crate::tcp::board_to_fen;
+
+/// Parses a FEN string into a board and castling rights. Only the piece
+/// placement, side to move and castling fields are used; en passant and the
+/// move counters are ignored.
+pub fn board_from_fen(fen: &str) -> Result<(Board, CastlingRights), String> {
+    let fields: Vec<&str> = fen.split_whitespace().collect();
+    let Some(placement) = fields.first() else {
+        return Err("Empty FEN".to_string());
+    };
+    validate_placement(placement)?;
+
+    let mut board = Board::start_pos();
+    board.setup_fen(placement);
+    board.move_turn = match fields.get(1).copied().unwrap_or("w") {
+        "w" => HermanhaColor::White,
+        "b" => HermanhaColor::Black,
+        other => return Err(format!("Invalid side to move: {other}")),
+    };
+    if rules::in_check(&board, rules::opponent(board.move_turn)) {
+        return Err("The side not to move is in check".to_string());
+    }
+
+    let castling_field = fields.get(2).copied().unwrap_or("-");
+    let mut castling = CastlingRights {
+        white_kingside: false,
+        white_queenside: false,
+        black_kingside: false,
+        black_queenside: false,
+        files: CastlingFiles::default(),
+    };
+    if castling_field != "-" {
+        for c in castling_field.chars() {
+            match c {
+                'K' => castling.white_kingside = true,
+                'Q' => castling.white_queenside = true,
+                'k' => castling.black_kingside = true,
+                'q' => castling.black_queenside = true,
+                _ => return Err(format!("Invalid castling rights: {castling_field}")),
+            }
+        }
+    }
+    Ok((board, castling))
+}
+
+/// The piece placement, side to move and castling fields of a FEN, the
+/// ones `board_from_fen` reads back.
+pub fn position_to_fen(board: &Board, castling: CastlingRights) -> String {
+    let side = match board.move_turn {
+        HermanhaColor::White => "w",
+        HermanhaColor::Black => "b",
+    };
+    format!(
+        "{} {side} {}",
+        board_to_fen(board),
+        castling_to_fen(castling)
+    )
+}
+
+/// The castling field of a FEN, e.g. "KQkq", or "-" without any rights.
+pub fn castling_to_fen(castling: CastlingRights) -> String {
+    let field: String = [
+        (castling.white_kingside, 'K'),
+        (castling.white_queenside, 'Q'),
+        (castling.black_kingside, 'k'),
+        (castling.black_queenside, 'q'),
+    ]
+    .iter()
+    .filter(|(allowed, _)| *allowed)
+    .map(|(_, c)| *c)
+    .collect();
+    if field.is_empty() {
+        "-".to_string()
+    } else {
+        field
+    }
+}
+
+/// Checks a FEN's piece placement: eight ranks of eight squares, a king
+/// on each side and no pawns on the first or last rank.
+pub fn validate_placement(placement: &str) -> Result<(), String> {
+    let ranks: Vec<&str> = placement.split('/').collect();
+    if ranks.len() != 8 {
+        return Err("FEN must have 8 ranks".to_string());
+    }
+    let mut white_kings = 0;
+    let mut black_kings = 0;
+    for (index, rank) in ranks.iter().enumerate() {
+        let mut squares = 0;
+        for c in rank.chars() {
+            match c {
+                '1'..='8' => squares += c.to_digit(10).unwrap(),
+                'K' => white_kings += 1,
+                'k' => black_kings += 1,
+                'P' | 'p' if index == 0 || index == 7 => {
+                    return Err("Pawns can't stand on the first or last rank".to_string());
+                }
+                'P' | 'N' | 'B' | 'R' | 'Q' | 'p' | 'n' | 'b' | 'r' | 'q' => {}
+                _ => return Err(format!("Invalid piece: {c}")),
+            }
+            if c.is_ascii_alphabetic() {
+                squares += 1;
+            }
+        }
+        if squares != 8 {
+            return Err(format!("Rank {} does not have 8 squares", 8 - index));
+        }
+    }
+    if white_kings != 1 || black_kings != 1 {
+        return Err("Each side must have exactly one king".to_string());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_side_to_move_and_castling_rights() {
+        let (board, castling) = board_from_fen("r3k2r/8/8/8/8/8/8/R3K2R b Kq").unwrap();
+        assert_eq!(board_to_fen(&board), "r3k2r/8/8/8/8/8/8/R3K2R");
+        assert_eq!(board.move_turn, HermanhaColor::Black);
+        assert!(castling.white_kingside && !castling.white_queenside);
+        assert!(!castling.black_kingside && castling.black_queenside);
+    }
+
+    #[test]
+    fn missing_fields_are_white_to_move_without_castling() {
+        let (board, castling) = board_from_fen("4k3/8/8/8/8/8/8/4K3").unwrap();
+        assert_eq!(board.move_turn, HermanhaColor::White);
+        assert!(!castling.white_kingside && !castling.white_queenside);
+        assert!(!castling.black_kingside && !castling.black_queenside);
+        // En passant and the move counters are read past.
+        assert!(board_from_fen("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 2").is_ok());
+    }
+
+    #[test]
+    fn castling_rights_read_back_as_written() {
+        for field in ["KQkq", "Kq", "Qk", "-"] {
+            let fen = format!("r3k2r/8/8/8/8/8/8/R3K2R w {field}");
+            let (_, castling) = board_from_fen(&fen).unwrap();
+            assert_eq!(castling_to_fen(castling), field);
+        }
+    }
+
+    #[test]
+    fn positions_read_back_as_written() {
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq",
+            "r3k2r/8/8/8/8/8/8/R3K2R w Kq",
+            "8/8/4k3/8/8/4K3/8/8 w -",
+        ] {
+            let (board, castling) = board_from_fen(fen).unwrap();
+            assert_eq!(position_to_fen(&board, castling), fen);
+        }
+    }
+
+    #[test]
+    fn invalid_positions_are_rejected() {
+        for fen in [
+            "",
+            "4k3/8/8/8/8/8/4K3 w - -",
+            "4k3/8/8/8/8/8/8/8/4K3 w - -",
+            "4k4/8/8/8/8/8/8/4K3 w - -",
+            "4k2/8/8/8/8/8/8/4K3 w - -",
+            "4k3/8/8/8/8/8/8/4K2x w - -",
+            "4k2P/8/8/8/8/8/8/4K3 w - -",
+            "4k3/8/8/8/8/8/8/p3K3 w - -",
+            "4k3/8/8/8/8/8/8/4KK2 w - -",
+            "8/8/8/8/8/8/8/4K3 w - -",
+            "4k3/8/8/8/8/8/8/4K3 x - -",
+            "4k3/8/8/8/8/8/8/R3K3 w QX -",
+            "4k3/8/8/8/8/8/8/4R1K1 w - -",
+        ] {
+            assert!(board_from_fen(fen).is_err(), "{fen:?} was accepted");
+        }
+    }
+}
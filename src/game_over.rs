@@ -0,0 +1,287 @@
+use bevy::prelude::*;
+use hermanha_chess::Color as HermanhaColor;
+
+use crate::clock::Clocks;
+use crate::export::AnimationRequested;
+use crate::game_state::{GameOutcome, NewGame, PlayerColor};
+use crate::menu::{AppState, PlayState};
+use crate::net::Connection;
+use crate::offers::{Concluded, GameAction};
+use crate::rules;
+use crate::ui::GameUi;
+use crate::widgets;
+
+/// Rematch proposals, in either direction. A new game starts once both
+/// sides have asked for one.
+#[derive(Resource, Default)]
+pub struct RematchOffers {
+    pub sent: bool,
+    pub received: bool,
+}
+
+#[derive(Component)]
+pub struct GameOverOverlay;
+
+/// The line under the result saying who has asked for a rematch.
+#[derive(Component)]
+pub struct RematchStatus;
+
+#[derive(Component, Clone, Copy)]
+pub enum GameOverButton {
+    Rematch,
+    Review,
+    Animation,
+    Menu,
+}
+
+impl GameOverButton {
+    fn label(self) -> &'static str {
+        match self {
+            GameOverButton::Rematch => "Rematch",
+            GameOverButton::Review => "Review game",
+            GameOverButton::Animation => "Save animation",
+            GameOverButton::Menu => "Back to menu",
+        }
+    }
+}
+
+fn button(kind: GameOverButton) -> impl Bundle {
+    (
+        kind,
+        widgets::button(
+            widgets::button_node(140.0, 36.0),
+            widgets::label(kind.label()),
+        ),
+    )
+}
+
+fn result_text(
+    concluded: &Concluded,
+    outcome: &GameOutcome,
+    clocks: Option<&Clocks>,
+) -> Option<String> {
+    if let Some(conclusion) = concluded.0 {
+        return Some(conclusion.text());
+    }
+    if let Some(outcome) = outcome.0 {
+        return Some(outcome.text().to_string());
+    }
+    let flagged = clocks.and_then(|clocks| clocks.flagged)?;
+    let winner = match rules::opponent(flagged) {
+        HermanhaColor::White => "White",
+        HermanhaColor::Black => "Black",
+    };
+    Some(format!("{winner} wins on time"))
+}
+
+fn rematch_status(offers: &RematchOffers) -> &'static str {
+    if offers.received {
+        "Your opponent wants a rematch"
+    } else if offers.sent {
+        "Rematch offered"
+    } else {
+        ""
+    }
+}
+
+/// Sent once when the game ends, whether by the moves on the board, a
+/// resignation or draw agreement, or a flag falling.
+#[derive(Event)]
+pub struct GameEnded;
+
+/// Spawns the result modal when the game ends. The overlay is built once
+/// per `GameEnded`; any overlay still around from before is replaced.
+pub fn show_game_over(
+    mut commands: Commands,
+    mut ended: EventReader<GameEnded>,
+    concluded: Res<Concluded>,
+    outcome: Res<GameOutcome>,
+    clocks: Option<Res<Clocks>>,
+    offers: Res<RematchOffers>,
+    overlays: Query<Entity, With<GameOverOverlay>>,
+) {
+    if ended.is_empty() {
+        return;
+    }
+    ended.clear();
+    let Some(text) = result_text(&concluded, &outcome, clocks.as_deref()) else {
+        return;
+    };
+    for entity in overlays.iter() {
+        commands.entity(entity).despawn();
+    }
+    commands.spawn((
+        GameOverOverlay,
+        GameUi,
+        widgets::modal(12.0, 7),
+        children![
+            widgets::title(text, 28.0),
+            (RematchStatus, widgets::small_label(rematch_status(&offers))),
+            (
+                widgets::button_row(),
+                children![
+                    button(GameOverButton::Rematch),
+                    button(GameOverButton::Review),
+                    button(GameOverButton::Animation),
+                    button(GameOverButton::Menu)
+                ],
+            ),
+        ],
+    ));
+}
+
+pub fn render_rematch_status(
+    offers: Res<RematchOffers>,
+    mut texts: Query<&mut Text, With<RematchStatus>>,
+) {
+    if !offers.is_changed() {
+        return;
+    }
+    for mut text in texts.iter_mut() {
+        text.0 = rematch_status(&offers).to_string();
+    }
+}
+
+/// Takes the overlay down once undo or a rematch brings the game back.
+pub fn clear_game_over(
+    mut commands: Commands,
+    concluded: Res<Concluded>,
+    outcome: Res<GameOutcome>,
+    clocks: Option<Res<Clocks>>,
+    overlays: Query<Entity, With<GameOverOverlay>>,
+) {
+    if result_text(&concluded, &outcome, clocks.as_deref()).is_some() {
+        return;
+    }
+    for entity in overlays.iter() {
+        commands.entity(entity).despawn();
+    }
+}
+
+/// Keeps `PlayState` in step with the result: over once there is one,
+/// and under way again when undo or a rematch takes it back. A review
+/// lasts until it is left, or the result is taken back.
+pub fn update_play_state(
+    concluded: Res<Concluded>,
+    outcome: Res<GameOutcome>,
+    clocks: Option<Res<Clocks>>,
+    state: Res<State<PlayState>>,
+    mut next_state: ResMut<NextState<PlayState>>,
+) {
+    let over = result_text(&concluded, &outcome, clocks.as_deref()).is_some();
+    match (state.get(), over) {
+        (PlayState::Ongoing, true) => next_state.set(PlayState::GameOver),
+        (PlayState::GameOver | PlayState::Review, false) => next_state.set(PlayState::Ongoing),
+        _ => {}
+    }
+}
+
+/// Online, "Rematch" proposes one to the opponent; locally there's nobody
+/// to ask, so the new game starts right away. Leaving for the menu ends
+/// the game, see `OnExit(AppState::Playing)`.
+pub fn handle_game_over_buttons(
+    interactions: Query<(&Interaction, &GameOverButton), Changed<Interaction>>,
+    connection: Option<Res<Connection>>,
+    mut offers: ResMut<RematchOffers>,
+    mut actions: EventWriter<GameAction>,
+    mut next_state: ResMut<NextState<AppState>>,
+    mut play_state: ResMut<NextState<PlayState>>,
+    mut animation: EventWriter<AnimationRequested>,
+) {
+    for (interaction, button) in interactions.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        match button {
+            GameOverButton::Rematch => {
+                if offers.sent {
+                    continue;
+                }
+                offers.sent = true;
+                if connection.is_some() {
+                    actions.write(GameAction::Rematch);
+                } else {
+                    offers.received = true;
+                }
+            }
+            GameOverButton::Review => play_state.set(PlayState::Review),
+            GameOverButton::Animation => {
+                animation.write(AnimationRequested);
+            }
+            GameOverButton::Menu => next_state.set(AppState::Menu),
+        }
+    }
+}
+
+/// Once both sides want a rematch the board is reset and, online, the
+/// players swap colors. Both sides see both proposals, so each makes the
+/// same swap on its own.
+pub fn start_rematch(
+    mut new_game: NewGame,
+    mut offers: ResMut<RematchOffers>,
+    player_color: Option<ResMut<PlayerColor>>,
+) {
+    if !offers.sent || !offers.received {
+        return;
+    }
+    new_game.start();
+    *offers = RematchOffers::default();
+    if let Some(mut player_color) = player_color {
+        player_color.0 = rules::opponent(player_color.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::Outcome;
+
+    fn headless_app() -> App {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_event::<GameEnded>()
+            .init_resource::<Concluded>()
+            .init_resource::<GameOutcome>()
+            .init_resource::<RematchOffers>()
+            .add_systems(
+                Update,
+                (show_game_over, render_rematch_status, clear_game_over).chain(),
+            );
+        app
+    }
+
+    fn overlay_count(app: &mut App) -> usize {
+        app.world_mut()
+            .query_filtered::<(), With<GameOverOverlay>>()
+            .iter(app.world())
+            .count()
+    }
+
+    #[test]
+    fn game_end_spawns_a_single_overlay() {
+        let mut app = headless_app();
+        app.world_mut().resource_mut::<GameOutcome>().0 = Some(Outcome::Stalemate);
+        app.world_mut().send_event(GameEnded);
+        for _ in 0..100 {
+            app.update();
+        }
+        assert_eq!(overlay_count(&mut app), 1);
+
+        app.world_mut().resource_mut::<RematchOffers>().sent = true;
+        app.update();
+        assert_eq!(overlay_count(&mut app), 1);
+    }
+
+    #[test]
+    fn overlay_goes_away_when_the_game_resumes() {
+        let mut app = headless_app();
+        app.world_mut().resource_mut::<GameOutcome>().0 = Some(Outcome::Stalemate);
+        app.world_mut().send_event(GameEnded);
+        app.update();
+        assert_eq!(overlay_count(&mut app), 1);
+
+        app.world_mut().resource_mut::<GameOutcome>().0 = None;
+        app.update();
+        assert_eq!(overlay_count(&mut app), 0);
+    }
+}
@@ -0,0 +1,360 @@
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use hermanha_chess::{Board, Color as HermanhaColor, MoveOk, PieceType, Position};
+
+use crate::GameSet;
+use crate::annotations::Annotations;
+use crate::chat::ChatLog;
+use crate::clock::{self, Clocks};
+use crate::computer::{self, Computer, Seed};
+use crate::game_over::{self, GameEnded, RematchOffers};
+use crate::games::{self, GameTabs, TabCommand};
+use crate::history::{self, MoveHistory};
+use crate::menu::{AppState, MenuAddress, MenuMessage, PlayState};
+use crate::net::{Desync, Opponent, PlayerName};
+use crate::offers::{Concluded, DrawOffers};
+use crate::premove::Premove;
+use crate::promotion::PendingPromotion;
+use crate::puzzle::{self, PuzzlePack, PuzzleSession};
+use crate::rules::{self, CastlingRights, GamePhase, Outcome};
+use crate::variant::{self, Variant, VariantChosen};
+
+/// The state of the game on the board and of the match around it, and the
+/// systems that keep what follows from the board up to date.
+pub struct GameStatePlugin;
+
+impl Plugin for GameStatePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_state::<AppState>()
+            .add_sub_state::<PlayState>()
+            .init_resource::<BoardState>()
+            .init_resource::<Castling>()
+            .init_resource::<SelectedSquare>()
+            .init_resource::<Paused>()
+            .init_resource::<LocalPlayer>()
+            .init_resource::<MoveHistory>()
+            .init_resource::<Phase>()
+            .init_resource::<GameOutcome>()
+            .init_resource::<Concluded>()
+            .init_resource::<DrawOffers>()
+            .init_resource::<RematchOffers>()
+            .init_resource::<ChatLog>()
+            .init_resource::<PendingPromotion>()
+            .init_resource::<PlayerName>()
+            .init_resource::<MenuAddress>()
+            .init_resource::<MenuMessage>()
+            .init_resource::<Variant>()
+            .init_resource::<GameTabs>()
+            .init_resource::<PuzzlePack>()
+            .add_event::<GameEnded>()
+            .add_event::<TabCommand>()
+            .add_event::<MoveRequested>()
+            .add_event::<MovePlayed>()
+            .add_event::<VariantChosen>()
+            .add_systems(
+                OnExit(AppState::Playing),
+                (
+                    reset_game,
+                    games::close_background_games,
+                    computer::stop_computer,
+                    puzzle::stop_puzzles,
+                ),
+            )
+            .add_systems(
+                Update,
+                games::apply_tab_commands
+                    .after(GameSet::Render)
+                    .run_if(in_state(AppState::Playing)),
+            )
+            .add_systems(
+                Update,
+                (
+                    update_local_player.in_set(GameSet::Input),
+                    computer::play_computer_move
+                        .in_set(GameSet::Input)
+                        .run_if(resource_exists::<Computer>),
+                    puzzle::advance_puzzles
+                        .in_set(GameSet::Input)
+                        .run_if(resource_exists::<PuzzleSession>),
+                    (apply_moves, history::record_moves)
+                        .chain()
+                        .in_set(GameSet::Moves),
+                    (
+                        update_outcome,
+                        game_over::start_rematch,
+                        update_game_phase,
+                        game_over::update_play_state,
+                    )
+                        .chain()
+                        .in_set(GameSet::Rules),
+                    puzzle::check_puzzle_moves
+                        .in_set(GameSet::Rules)
+                        .run_if(resource_exists::<PuzzleSession>),
+                    computer::next_exhibition_game
+                        .in_set(GameSet::Rules)
+                        .run_if(in_state(PlayState::GameOver).and(resource_exists::<Computer>)),
+                )
+                    .run_if(in_state(AppState::Playing)),
+            )
+            .add_systems(
+                Update,
+                variant::choose_variant
+                    .in_set(GameSet::Moves)
+                    .before(apply_moves),
+            )
+            .add_systems(
+                Update,
+                clock::tick_clocks
+                    .after(update_outcome)
+                    .in_set(GameSet::Rules)
+                    .run_if(resource_exists::<Clocks>)
+                    .run_if(in_state(AppState::Playing)),
+            );
+    }
+}
+
+#[derive(Resource, Deref)]
+pub struct BoardState(pub Board);
+
+impl Default for BoardState {
+    fn default() -> Self {
+        BoardState(Board::start_pos())
+    }
+}
+
+#[derive(Resource, Deref)]
+pub struct PlayerColor(pub HermanhaColor);
+
+/// Which colors can be moved from this machine: both in hotseat play,
+/// our own once an online game has started or against the computer, and
+/// none while waiting for the handshake or when only spectating. Follows
+/// `PlayerColor`, `Opponent`, `Computer` and `PuzzleSession`.
+#[derive(Resource, Default, PartialEq)]
+pub enum LocalPlayer {
+    #[default]
+    Both,
+    One(HermanhaColor),
+    Watching,
+}
+
+impl LocalPlayer {
+    pub fn controls(&self, color: HermanhaColor) -> bool {
+        match self {
+            LocalPlayer::Both => true,
+            LocalPlayer::One(own) => *own == color,
+            LocalPlayer::Watching => false,
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct SelectedSquare(pub Option<Position>);
+
+/// Set while the pause menu is open in a game played on this machine: the
+/// clocks stand still, the computer waits and the board takes no moves.
+/// Online games go on behind the menu.
+#[derive(Resource, Default)]
+pub struct Paused(pub bool);
+
+#[derive(Resource, Default)]
+pub struct Castling(pub CastlingRights);
+
+#[derive(Resource, Default, Deref)]
+pub struct Phase(GamePhase);
+
+/// How the game on the board ended, if it has. Kept up to date with the
+/// board by `update_outcome`.
+#[derive(Resource, Default, PartialEq)]
+pub struct GameOutcome(pub Option<Outcome>);
+
+/// Where a requested move comes from. Only the local player's moves are
+/// sent to the opponent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveOrigin {
+    Local,
+    Opponent,
+}
+
+/// A move someone wants to play: the local player, a premove or the
+/// opponent, over the network or as the built-in computer. Nothing happens to the board until
+/// `apply_moves` has checked it against the current position.
+#[derive(Event, Clone, Copy)]
+pub struct MoveRequested {
+    pub from: Position,
+    pub to: Position,
+    pub promotion_piece: Option<PieceType>,
+    pub origin: MoveOrigin,
+}
+
+/// A move that has been played on `BoardState`, with the position and
+/// castling rights from before it and the position after it.
+#[derive(Event)]
+pub struct MovePlayed {
+    pub from: Position,
+    pub to: Position,
+    pub promotion_piece: Option<PieceType>,
+    pub origin: MoveOrigin,
+    pub before: Board,
+    pub castling_before: CastlingRights,
+    pub after: Board,
+}
+
+/// Everything that belongs to one game and is put back to the start
+/// position for the next one.
+#[derive(SystemParam)]
+pub struct NewGame<'w> {
+    board: ResMut<'w, BoardState>,
+    castling: ResMut<'w, Castling>,
+    history: ResMut<'w, MoveHistory>,
+    selected: ResMut<'w, SelectedSquare>,
+    paused: ResMut<'w, Paused>,
+    pending_promotion: ResMut<'w, PendingPromotion>,
+    concluded: ResMut<'w, Concluded>,
+    draw_offers: ResMut<'w, DrawOffers>,
+    desync: ResMut<'w, Desync>,
+    premove: ResMut<'w, Premove>,
+    annotations: ResMut<'w, Annotations>,
+    variant: ResMut<'w, Variant>,
+    clocks: Option<ResMut<'w, Clocks>>,
+    seed: Option<ResMut<'w, Seed>>,
+}
+
+impl NewGame<'_> {
+    pub fn start(&mut self) {
+        let (board, castling) = self.variant.start();
+        self.board.0 = board;
+        self.castling.0 = castling;
+        *self.history = MoveHistory::default();
+        self.selected.0 = None;
+        self.paused.0 = false;
+        self.pending_promotion.0 = None;
+        self.concluded.0 = None;
+        *self.draw_offers = DrawOffers::default();
+        self.desync.0 = None;
+        self.premove.clear();
+        self.annotations.clear();
+        if let Some(clocks) = self.clocks.as_mut() {
+            clocks.reset(self.variant.time_odds());
+        }
+        if let Some(seed) = self.seed.as_mut() {
+            seed.next_game();
+        }
+    }
+
+    /// A new game from a position set up elsewhere, such as a puzzle's or a
+    /// pasted one, with `history` the moves that led to it.
+    pub fn start_from(&mut self, board: Board, castling: CastlingRights, history: MoveHistory) {
+        self.start();
+        self.board.0 = board;
+        self.castling.0 = castling;
+        *self.history = history;
+    }
+
+    pub fn start_variant(&mut self, variant: Variant) {
+        *self.variant = variant;
+        self.start();
+    }
+}
+
+fn reset_game(
+    mut new_game: NewGame,
+    mut rematch: ResMut<RematchOffers>,
+    mut chat: ResMut<ChatLog>,
+) {
+    new_game.start();
+    *rematch = RematchOffers::default();
+    chat.lines.clear();
+}
+
+fn update_game_phase(board: Res<BoardState>, history: Res<MoveHistory>, mut phase: ResMut<Phase>) {
+    let new_phase = rules::game_phase(&board.0, history.ply_count());
+    if phase.0 != new_phase {
+        phase.0 = new_phase;
+    }
+}
+
+fn update_local_player(
+    player_color: Option<Res<PlayerColor>>,
+    opponent: Option<Res<Opponent>>,
+    computer: Option<Res<Computer>>,
+    puzzle: Option<Res<PuzzleSession>>,
+    mut local_player: ResMut<LocalPlayer>,
+) {
+    local_player.set_if_neq(match (player_color, opponent) {
+        (None, _) => match (computer, puzzle) {
+            (Some(computer), _) => match *computer {
+                Computer::Plays(color) => LocalPlayer::One(rules::opponent(color)),
+                Computer::Both => LocalPlayer::Watching,
+            },
+            (None, Some(puzzle)) => puzzle.local_player(),
+            (None, None) => LocalPlayer::Both,
+        },
+        (Some(player_color), Some(_)) => LocalPlayer::One(player_color.0),
+        (Some(_), None) => LocalPlayer::Watching,
+    });
+}
+
+fn update_outcome(
+    board: Res<BoardState>,
+    castling: Res<Castling>,
+    history: Res<MoveHistory>,
+    mut outcome: ResMut<GameOutcome>,
+    mut ended: EventWriter<GameEnded>,
+) {
+    if board.is_changed()
+        && outcome.set_if_neq(GameOutcome(history.outcome(&board.0, castling.0)))
+        && outcome.0.is_some()
+    {
+        ended.write(GameEnded);
+    }
+}
+
+/// The only place moves are played on the board. Each request is checked
+/// against the position as it is by then; illegal ones and pawn moves to
+/// the last rank without a promotion piece are dropped, the rest update
+/// the castling rights and are announced with `MovePlayed`. A king moving
+/// onto its own rook castles, as in Chess960.
+pub fn apply_moves(
+    mut requests: EventReader<MoveRequested>,
+    mut board: ResMut<BoardState>,
+    mut castling: ResMut<Castling>,
+    mut played: EventWriter<MovePlayed>,
+) {
+    for request in requests.read() {
+        let MoveRequested {
+            from,
+            to,
+            promotion_piece,
+            origin,
+        } = *request;
+        let after = match rules::castle_onto_rook(&board.0, castling.0, from, to) {
+            Some(after) => after,
+            None => {
+                let mut after = board.0.clone();
+                match after.play((from.row, from.col), (to.row, to.col), promotion_piece) {
+                    Ok(MoveOk::NeedsPromotion) => {
+                        warn!("Dropped {origin:?} move without a promotion piece");
+                        continue;
+                    }
+                    Err(err) => {
+                        warn!("Dropped illegal {origin:?} move ({err:?})");
+                        continue;
+                    }
+                    Ok(_) => after,
+                }
+            }
+        };
+        let before = std::mem::replace(&mut board.0, after.clone());
+        let castling_before = castling.0;
+        castling.0.update(from, to);
+        played.write(MovePlayed {
+            from,
+            to,
+            promotion_piece,
+            origin,
+            before,
+            castling_before,
+            after,
+        });
+    }
+}
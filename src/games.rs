@@ -0,0 +1,439 @@
+use std::collections::HashMap;
+
+use bevy::input::ButtonInput;
+use bevy::prelude::*;
+
+use crate::SpawnGameUi;
+use crate::actions::{self, Action};
+use crate::annotations::Annotations;
+use crate::chat::ChatLog;
+use crate::clock::Clocks;
+use crate::computer::Computer;
+use crate::config::DefaultTimeControl;
+use crate::game_over::{GameEnded, RematchOffers};
+use crate::game_state::{
+    BoardState, Castling, GameOutcome, LocalPlayer, Phase, PlayerColor, SelectedSquare,
+};
+use crate::history::MoveHistory;
+use crate::menu::PlayState;
+use crate::net::{
+    self, Connection, Desync, Opponent, PeerAddress, Reconnecting, Session, Spectating, Transfers,
+};
+use crate::net_status::NetStatus;
+use crate::offers::{Concluded, DrawOffers};
+use crate::premove::Premove;
+use crate::promotion::PendingPromotion;
+use crate::puzzle::PuzzleSession;
+use crate::relay::WatchedPlayers;
+use crate::ui::GameUi;
+use crate::variant::Variant;
+
+/// Identifies one of the games open in tabs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GameId(pub u32);
+
+/// The games open in tabs. The shown game lives in the usual resources,
+/// `BoardState`, `MoveHistory`, `Connection` and the rest, so every
+/// system only ever sees one game. The others are kept here by id until
+/// their tab is shown again. Games in the background are paused: their
+/// clocks stop and the opponent's messages wait in the connection.
+#[derive(Resource)]
+pub struct GameTabs {
+    order: Vec<GameId>,
+    active: GameId,
+    stored: HashMap<GameId, StoredGame>,
+    next_id: u32,
+}
+
+impl Default for GameTabs {
+    fn default() -> Self {
+        GameTabs {
+            order: vec![GameId(0)],
+            active: GameId(0),
+            stored: HashMap::new(),
+            next_id: 1,
+        }
+    }
+}
+
+impl GameTabs {
+    /// The open games in tab order, with the shown one marked.
+    pub fn tabs(&self) -> impl Iterator<Item = (GameId, bool)> + '_ {
+        self.order.iter().map(|id| (*id, *id == self.active))
+    }
+
+    pub fn active(&self) -> GameId {
+        self.active
+    }
+
+    /// The name on a tab: the opponent's for online games, otherwise the
+    /// tab's number. The shown game's connection and opponent are passed
+    /// in, since they aren't kept here.
+    pub fn label(
+        &self,
+        id: GameId,
+        shown_online: bool,
+        shown_opponent: Option<&Opponent>,
+    ) -> String {
+        let (online, opponent) = match self.stored.get(&id) {
+            Some(game) => (game.connection.is_some(), game.opponent.as_ref()),
+            None => (shown_online, shown_opponent),
+        };
+        match (online, opponent) {
+            (true, Some(opponent)) => format!("vs {}", opponent.name),
+            (true, None) => "Online game".to_string(),
+            (false, _) => {
+                let number = self.order.iter().position(|tab| *tab == id).unwrap_or(0) + 1;
+                format!("Game {number}")
+            }
+        }
+    }
+}
+
+/// The per-game resources of a game whose tab isn't shown. `None` for
+/// the ones the game doesn't have, such as `Clocks` in an untimed game or
+/// `Connection` in a local one.
+#[derive(Default)]
+struct StoredGame {
+    board: Option<BoardState>,
+    castling: Option<Castling>,
+    selected: Option<SelectedSquare>,
+    history: Option<MoveHistory>,
+    phase: Option<Phase>,
+    outcome: Option<GameOutcome>,
+    concluded: Option<Concluded>,
+    draw_offers: Option<DrawOffers>,
+    rematch: Option<RematchOffers>,
+    chat: Option<ChatLog>,
+    pending_promotion: Option<PendingPromotion>,
+    variant: Option<Variant>,
+    desync: Option<Desync>,
+    premove: Option<Premove>,
+    annotations: Option<Annotations>,
+    local_player: Option<LocalPlayer>,
+    transfers: Option<Transfers>,
+    net_status: Option<NetStatus>,
+    clocks: Option<Clocks>,
+    connection: Option<Connection>,
+    opponent: Option<Opponent>,
+    player_color: Option<PlayerColor>,
+    peer: Option<PeerAddress>,
+    spectating: Option<Spectating>,
+    watched: Option<WatchedPlayers>,
+    reconnecting: Option<Reconnecting>,
+    session: Option<Session>,
+    computer: Option<Computer>,
+    puzzle: Option<PuzzleSession>,
+}
+
+impl StoredGame {
+    /// A local game of standard chess from the start position, on the
+    /// default time control if there is one.
+    fn new(clocks: Option<Clocks>) -> Self {
+        StoredGame {
+            board: Some(BoardState::default()),
+            castling: Some(Castling::default()),
+            selected: Some(SelectedSquare::default()),
+            history: Some(MoveHistory::default()),
+            phase: Some(Phase::default()),
+            outcome: Some(GameOutcome::default()),
+            concluded: Some(Concluded::default()),
+            draw_offers: Some(DrawOffers::default()),
+            rematch: Some(RematchOffers::default()),
+            chat: Some(ChatLog::default()),
+            pending_promotion: Some(PendingPromotion::default()),
+            variant: Some(Variant::default()),
+            desync: Some(Desync::default()),
+            premove: Some(Premove::default()),
+            annotations: Some(Annotations::default()),
+            local_player: Some(LocalPlayer::default()),
+            transfers: Some(Transfers::default()),
+            net_status: Some(NetStatus::default()),
+            clocks,
+            ..default()
+        }
+    }
+
+    /// Puts this game's resources into the world and keeps the world's in
+    /// their place.
+    fn swap_with(&mut self, world: &mut World) {
+        swap(world, &mut self.board);
+        swap(world, &mut self.castling);
+        swap(world, &mut self.selected);
+        swap(world, &mut self.history);
+        swap(world, &mut self.phase);
+        swap(world, &mut self.outcome);
+        swap(world, &mut self.concluded);
+        swap(world, &mut self.draw_offers);
+        swap(world, &mut self.rematch);
+        swap(world, &mut self.chat);
+        swap(world, &mut self.pending_promotion);
+        swap(world, &mut self.variant);
+        swap(world, &mut self.desync);
+        swap(world, &mut self.premove);
+        swap(world, &mut self.annotations);
+        swap(world, &mut self.local_player);
+        swap(world, &mut self.transfers);
+        swap(world, &mut self.net_status);
+        swap(world, &mut self.clocks);
+        swap(world, &mut self.connection);
+        swap(world, &mut self.opponent);
+        swap(world, &mut self.player_color);
+        swap(world, &mut self.peer);
+        swap(world, &mut self.spectating);
+        swap(world, &mut self.watched);
+        swap(world, &mut self.reconnecting);
+        swap(world, &mut self.session);
+        swap(world, &mut self.computer);
+        swap(world, &mut self.puzzle);
+    }
+
+    /// Tells the opponent of a game that is closed without being shown.
+    fn close(self) {
+        if let Some(mut connection) = self.connection {
+            net::hang_up(connection.0.as_mut());
+        }
+    }
+}
+
+fn swap<R: Resource>(world: &mut World, slot: &mut Option<R>) {
+    let shown = world.remove_resource::<R>();
+    if let Some(resource) = slot.take() {
+        world.insert_resource(resource);
+    }
+    *slot = shown;
+}
+
+/// Opens, shows or closes a tab.
+#[derive(Event, Clone, Copy)]
+pub enum TabCommand {
+    New,
+    Show(GameId),
+    Next,
+    Close(GameId),
+}
+
+/// Ctrl+T opens a local game in a new tab, Ctrl+Tab shows the next tab and
+/// Ctrl+W closes the shown one.
+pub fn handle_tab_keys(
+    keys: Res<ButtonInput<KeyCode>>,
+    tabs: Res<GameTabs>,
+    mut commands: EventWriter<TabCommand>,
+) {
+    if actions::just_pressed(&keys, Action::NewTab) {
+        commands.write(TabCommand::New);
+    } else if actions::just_pressed(&keys, Action::NextTab) {
+        commands.write(TabCommand::Next);
+    } else if actions::just_pressed(&keys, Action::CloseTab) {
+        commands.write(TabCommand::Close(tabs.active));
+    }
+}
+
+/// Carries out the tab commands at the end of the frame, once every system
+/// is done with the game that was shown. The game UI is then rebuilt for
+/// the game now shown, along with its result if it is over.
+pub fn apply_tab_commands(world: &mut World) {
+    let commands: Vec<TabCommand> = world.resource_mut::<Events<TabCommand>>().drain().collect();
+    if commands.is_empty() {
+        return;
+    }
+    let shown_before = world.resource::<GameTabs>().active;
+    for command in commands {
+        match command {
+            TabCommand::New => open_tab(world),
+            TabCommand::Show(id) => show_tab(world, id),
+            TabCommand::Next => {
+                let tabs = world.resource::<GameTabs>();
+                let index = tabs.order.iter().position(|id| *id == tabs.active);
+                let next = index.map(|index| tabs.order[(index + 1) % tabs.order.len()]);
+                if let Some(next) = next {
+                    show_tab(world, next);
+                }
+            }
+            TabCommand::Close(id) => close_tab(world, id),
+        }
+    }
+    let switched = world.resource::<GameTabs>().active != shown_before;
+    respawn_game_ui(world);
+    let over = world.resource::<Concluded>().0.is_some()
+        || world.resource::<GameOutcome>().0.is_some()
+        || world
+            .get_resource::<Clocks>()
+            .is_some_and(|clocks| clocks.flagged.is_some());
+    if switched && over {
+        world.send_event(GameEnded);
+    }
+    // A review belongs to the game it was started on.
+    if switched && *world.resource::<State<PlayState>>().get() == PlayState::Review {
+        world
+            .resource_mut::<NextState<PlayState>>()
+            .set(PlayState::GameOver);
+    }
+}
+
+fn open_tab(world: &mut World) {
+    let clocks = world
+        .get_resource::<DefaultTimeControl>()
+        .and_then(|time_control| time_control.0)
+        .map(Clocks::new);
+    let mut shown = StoredGame::default();
+    shown.swap_with(world);
+    StoredGame::new(clocks).swap_with(world);
+    let mut tabs = world.resource_mut::<GameTabs>();
+    let id = GameId(tabs.next_id);
+    tabs.next_id += 1;
+    let previous = tabs.active;
+    tabs.stored.insert(previous, shown);
+    tabs.order.push(id);
+    tabs.active = id;
+}
+
+fn show_tab(world: &mut World, id: GameId) {
+    let mut tabs = world.resource_mut::<GameTabs>();
+    if id == tabs.active {
+        return;
+    }
+    let Some(mut game) = tabs.stored.remove(&id) else {
+        return;
+    };
+    let previous = tabs.active;
+    tabs.active = id;
+    game.swap_with(world);
+    world
+        .resource_mut::<GameTabs>()
+        .stored
+        .insert(previous, game);
+}
+
+/// The last tab stays open. Closing the shown tab shows its neighbour.
+fn close_tab(world: &mut World, id: GameId) {
+    let tabs = world.resource::<GameTabs>();
+    let Some(index) = tabs.order.iter().position(|tab| *tab == id) else {
+        return;
+    };
+    if tabs.order.len() == 1 {
+        return;
+    }
+    if id == tabs.active {
+        let neighbour = tabs.order[if index == 0 { 1 } else { index - 1 }];
+        show_tab(world, neighbour);
+    }
+    let mut tabs = world.resource_mut::<GameTabs>();
+    tabs.order.retain(|tab| *tab != id);
+    if let Some(game) = tabs.stored.remove(&id) {
+        game.close();
+    }
+}
+
+fn respawn_game_ui(world: &mut World) {
+    let ui: Vec<Entity> = world
+        .query_filtered::<Entity, With<GameUi>>()
+        .iter(world)
+        .collect();
+    for entity in ui {
+        if let Ok(entity) = world.get_entity_mut(entity) {
+            entity.despawn();
+        }
+    }
+    world.run_schedule(SpawnGameUi);
+}
+
+/// Leaving for the menu ends every game, not just the shown one, which
+/// `leave_game` and `reset_game` take care of.
+pub fn close_background_games(mut tabs: ResMut<GameTabs>) {
+    for (_, game) in tabs.stored.drain() {
+        game.close();
+    }
+    *tabs = GameTabs::default();
+}
+
+/// One tab in the tab bar, showing its game when clicked.
+#[derive(Component)]
+pub struct TabButton(pub GameId);
+
+#[derive(Component)]
+pub struct NewTabButton;
+
+/// A button per open game, the shown one lit, and one for a new tab.
+/// Rebuilt whenever tabs change.
+pub fn spawn_tab_bar(
+    mut commands: Commands,
+    tabs: Res<GameTabs>,
+    connection: Option<Res<Connection>>,
+    opponent: Option<Res<Opponent>>,
+) {
+    let tab = |label: String, shown: bool| {
+        (
+            Button,
+            Node {
+                padding: UiRect::axes(Val::Px(10.0), Val::Px(4.0)),
+                ..default()
+            },
+            BackgroundColor(if shown {
+                Color::srgb(0.35, 0.35, 0.45)
+            } else {
+                Color::srgb(0.2, 0.2, 0.25)
+            }),
+            Text::new(label),
+            TextFont {
+                font_size: 14.0,
+                ..default()
+            },
+        )
+    };
+    commands
+        .spawn((
+            GameUi,
+            Node {
+                position_type: PositionType::Absolute,
+                top: Val::Px(8.0),
+                left: Val::Percent(20.0),
+                column_gap: Val::Px(4.0),
+                ..default()
+            },
+        ))
+        .with_children(|bar| {
+            for (id, shown) in tabs.tabs() {
+                let label = tabs.label(id, connection.is_some(), opponent.as_deref());
+                bar.spawn((TabButton(id), tab(label, shown)));
+            }
+            bar.spawn((NewTabButton, tab("+".to_string(), false)));
+        });
+}
+
+pub fn handle_tab_buttons(
+    tabs: Query<(&Interaction, &TabButton), Changed<Interaction>>,
+    new_tabs: Query<&Interaction, (Changed<Interaction>, With<NewTabButton>)>,
+    mut commands: EventWriter<TabCommand>,
+) {
+    for (interaction, tab) in tabs.iter() {
+        if *interaction == Interaction::Pressed {
+            commands.write(TabCommand::Show(tab.0));
+        }
+    }
+    if new_tabs
+        .iter()
+        .any(|interaction| *interaction == Interaction::Pressed)
+    {
+        commands.write(TabCommand::New);
+    }
+}
+
+/// The shown game's tab follows its opponent, whose name only arrives
+/// with the handshake.
+pub fn render_tab_labels(
+    tabs: Res<GameTabs>,
+    connection: Option<Res<Connection>>,
+    opponent: Option<Res<Opponent>>,
+    mut labels: Query<(&mut Text, &TabButton)>,
+) {
+    for (mut text, tab) in labels.iter_mut() {
+        if tab.0 != tabs.active {
+            continue;
+        }
+        let wanted = tabs.label(tab.0, connection.is_some(), opponent.as_deref());
+        if text.0 != wanted {
+            text.0 = wanted;
+        }
+    }
+}
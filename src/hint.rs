@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use hermanha_chess::{BOARD_ROWS, Board, Color as HermanhaColor, GameResult, PieceType, Position};
+
+use crate::actions::{self, Action};
+use crate::game_state::{BoardState, PlayerColor};
+use crate::promotion::PendingPromotion;
+use crate::rules::CastlingRights;
+use crate::toast::Toasts;
+use crate::ui::GameUi;
+use crate::widgets;
+use crate::{TILE_SIZE, pos_to_vec3, rules, zobrist};
+
+/// How many plies the built-in search looks ahead.
+const SEARCH_DEPTH: u32 = 2;
+const MATE_SCORE: i32 = 10_000;
+const HINT_SECONDS: f32 = 2.0;
+
+#[derive(Component)]
+pub struct HintButton;
+
+/// One of the two squares of a suggested move. Removed when its timer runs
+/// out or the position changes.
+#[derive(Component)]
+pub struct HintMarker(Timer);
+
+pub fn spawn_hint_button(mut commands: Commands) {
+    commands.spawn((
+        HintButton,
+        GameUi,
+        widgets::button(
+            Node {
+                position_type: PositionType::Absolute,
+                left: Val::Px(8.0),
+                bottom: Val::Px(8.0),
+                ..widgets::button_node(80.0, 32.0)
+            },
+            widgets::small_label("Hint"),
+        ),
+    ));
+}
+
+/// The pawn is promoted to a queen when a move reaches the last rank; the
+/// search doesn't consider underpromotions.
+pub fn promotion_for(board: &Board, from: Position, to: Position) -> Option<PieceType> {
+    let is_pawn = board
+        .get(from)
+        .is_some_and(|piece| matches!(piece.piece_type, PieceType::Pawn));
+    (is_pawn && (to.row == 0 || to.row == BOARD_ROWS as i8 - 1)).then_some(PieceType::Queen)
+}
+
+/// Scores already searched, by the position's Zobrist hash, with the
+/// depth they were searched to. The same position is often reached by
+/// playing the same moves in another order.
+type TranspositionTable = HashMap<u64, (u32, i32)>;
+
+/// Score of the position for the side to move, searched `depth` plies deep
+/// on material alone. Quicker mates score higher.
+fn negamax(
+    board: &Board,
+    castling: CastlingRights,
+    depth: u32,
+    table: &mut TranspositionTable,
+) -> i32 {
+    let key = zobrist::hash(board, castling);
+    if let Some(&(searched, score)) = table.get(&key)
+        && searched == depth
+    {
+        return score;
+    }
+    let score = search(board, castling, depth, table);
+    table.insert(key, (depth, score));
+    score
+}
+
+fn search(
+    board: &Board,
+    castling: CastlingRights,
+    depth: u32,
+    table: &mut TranspositionTable,
+) -> i32 {
+    if let Some(result) = board.game_over() {
+        return match result {
+            GameResult::Checkmate(winner) if winner == board.move_turn => MATE_SCORE,
+            GameResult::Checkmate(_) => -MATE_SCORE - depth as i32,
+            GameResult::Stalemate => 0,
+        };
+    }
+    if depth == 0 {
+        let balance = rules::material_balance(board);
+        return match board.move_turn {
+            HermanhaColor::White => balance,
+            HermanhaColor::Black => -balance,
+        };
+    }
+    board
+        .legal_moves()
+        .into_iter()
+        .filter_map(|(from, to, _)| {
+            let (next, next_castling) = play(board, castling, from, to)?;
+            Some(-negamax(&next, next_castling, depth - 1, table))
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+fn play(
+    board: &Board,
+    castling: CastlingRights,
+    from: Position,
+    to: Position,
+) -> Option<(Board, CastlingRights)> {
+    let mut next = board.clone();
+    next.play(
+        (from.row, from.col),
+        (to.row, to.col),
+        promotion_for(board, from, to),
+    )
+    .ok()?;
+    let mut castling = castling;
+    castling.update(from, to);
+    Some((next, castling))
+}
+
+/// The search starts from every castling right, not knowing the game's.
+/// That only keeps apart positions the table could otherwise mix up: a
+/// right is lost in the keys whenever a move would lose it on the board.
+fn root_castling() -> CastlingRights {
+    CastlingRights::default()
+}
+
+/// The built-in search's score of the position in pawns, from White's
+/// side: positive when White is better, beyond `MATE_SCORE` for a mate.
+pub fn evaluate(board: &Board) -> i32 {
+    let score = negamax(board, root_castling(), SEARCH_DEPTH, &mut HashMap::new());
+    match board.move_turn {
+        HermanhaColor::White => score,
+        HermanhaColor::Black => -score,
+    }
+}
+
+/// The move the built-in search likes best for the side to move.
+pub fn suggest_move(board: &Board) -> Option<(Position, Position)> {
+    let mut table = HashMap::new();
+    let mut best: Option<((Position, Position), i32)> = None;
+    for (from, to, _) in board.legal_moves() {
+        let Some((next, castling)) = play(board, root_castling(), from, to) else {
+            continue;
+        };
+        let score = -negamax(&next, castling, SEARCH_DEPTH - 1, &mut table);
+        if best.is_none_or(|(_, best_score)| score > best_score) {
+            best = Some(((from, to), score));
+        }
+    }
+    best.map(|(mv, _)| mv)
+}
+
+fn spawn_hint_marker(commands: &mut Commands, pos: Position) {
+    commands.spawn((
+        HintMarker(Timer::from_seconds(HINT_SECONDS, TimerMode::Once)),
+        Sprite {
+            color: Color::srgba(0.2, 0.55, 0.95, 0.5),
+            custom_size: Some(Vec2::splat(TILE_SIZE)),
+            ..default()
+        },
+        Transform::from_translation(pos_to_vec3(pos, 0.35)),
+    ));
+}
+
+/// The Hint button or H briefly highlights a suggested move for the side
+/// to move without playing it. Only available in local play.
+#[allow(clippy::too_many_arguments)]
+pub fn request_hint(
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    interactions: Query<&Interaction, (Changed<Interaction>, With<HintButton>)>,
+    board: Res<BoardState>,
+    player_color: Option<Res<PlayerColor>>,
+    pending_promotion: Res<PendingPromotion>,
+    markers: Query<Entity, With<HintMarker>>,
+    mut toasts: ResMut<Toasts>,
+) {
+    let clicked = interactions
+        .iter()
+        .any(|interaction| *interaction == Interaction::Pressed);
+    if !clicked && !actions::just_pressed(&keys, Action::Hint) {
+        return;
+    }
+    if player_color.is_some() || pending_promotion.0.is_some() {
+        return;
+    }
+    for entity in markers.iter() {
+        commands.entity(entity).despawn();
+    }
+    match suggest_move(&board.0) {
+        Some((from, to)) => {
+            spawn_hint_marker(&mut commands, from);
+            spawn_hint_marker(&mut commands, to);
+        }
+        None => toasts.push("No move to suggest"),
+    }
+}
+
+pub fn expire_hint(
+    mut commands: Commands,
+    time: Res<Time>,
+    board: Res<BoardState>,
+    mut markers: Query<(Entity, &mut HintMarker)>,
+) {
+    for (entity, mut marker) in markers.iter_mut() {
+        if board.is_changed() || marker.0.tick(time.delta()).finished() {
+            commands.entity(entity).despawn();
+        }
+    }
+}
@@ -0,0 +1,434 @@
+use bevy::input::mouse::{MouseScrollUnit, MouseWheel};
+use bevy::prelude::*;
+use hermanha_chess::{Board, Color as HermanhaColor, GameResult, PieceType, Position};
+
+use crate::actions::{self, Action};
+use crate::game_state::{BoardState, Castling, MovePlayed, SelectedSquare};
+use crate::net::Connection;
+use crate::promotion::PendingPromotion;
+use crate::rules::{self, CastlingRights, Outcome};
+use crate::ui::GameUi;
+use crate::{san, widgets, zobrist};
+
+pub struct PlayedMove {
+    pub from: Position,
+    pub to: Position,
+    pub promotion_piece: Option<PieceType>,
+    pub san: String,
+    /// Snapshots from just before the move, restored on undo.
+    before: Board,
+    castling_before: CastlingRights,
+}
+
+impl PlayedMove {
+    /// The piece this move took off the board, if any. A pawn changing
+    /// file onto an empty square took the pawn beside it en passant, and a
+    /// king moving onto its own rook castled.
+    pub fn captured(&self) -> Option<(HermanhaColor, PieceType)> {
+        if rules::is_castling(&self.before, self.from, self.to) {
+            return None;
+        }
+        if let Some(piece) = self.before.get(self.to) {
+            return Some((piece.color, piece.piece_type));
+        }
+        let moving = self.before.get(self.from)?;
+        let en_passant =
+            matches!(moving.piece_type, PieceType::Pawn) && self.from.col != self.to.col;
+        en_passant.then_some((rules::opponent(moving.color), PieceType::Pawn))
+    }
+}
+
+/// Every move played this game, in order, by either side, plus the moves
+/// taken back with undo that can still be redone.
+#[derive(Resource, Default)]
+pub struct MoveHistory {
+    pub moves: Vec<PlayedMove>,
+    undone: Vec<PlayedMove>,
+}
+
+impl MoveHistory {
+    /// Records a move. `before` and `castling_before` are the board and
+    /// castling rights as they were before the move was played. Playing a
+    /// new move drops anything that could have been redone.
+    pub fn push(
+        &mut self,
+        before: &Board,
+        castling_before: CastlingRights,
+        from: Position,
+        to: Position,
+        promotion_piece: Option<PieceType>,
+    ) {
+        self.undone.clear();
+        self.moves.push(PlayedMove {
+            from,
+            to,
+            promotion_piece,
+            san: san::move_to_san(before, from, to, promotion_piece),
+            before: before.clone(),
+            castling_before,
+        });
+    }
+
+    /// The pieces `color` has captured so far, most valuable first.
+    pub fn captured_by(&self, color: HermanhaColor) -> Vec<PieceType> {
+        let mut captured: Vec<PieceType> = self
+            .moves
+            .iter()
+            .filter_map(PlayedMove::captured)
+            .filter(|(captured_color, _)| *captured_color != color)
+            .map(|(_, piece_type)| piece_type)
+            .collect();
+        captured.sort_by_key(|piece_type| std::cmp::Reverse(rules::tray_order(*piece_type)));
+        captured
+    }
+
+    /// The position and castling rights the first move was played from.
+    pub fn start(&self) -> Option<(&Board, CastlingRights)> {
+        self.moves
+            .first()
+            .map(|played| (&played.before, played.castling_before))
+    }
+
+    /// The board and castling rights as they were before the move at
+    /// `ply`, or `None` from the end of the game on.
+    pub fn position_before(&self, ply: usize) -> Option<(&Board, CastlingRights)> {
+        self.moves
+            .get(ply)
+            .map(|played| (&played.before, played.castling_before))
+    }
+
+    pub fn ply_count(&self) -> u32 {
+        self.moves.len() as u32
+    }
+
+    /// Half-moves since the last capture or pawn move. Positions loaded
+    /// from a FEN start counting from zero.
+    pub fn halfmove_clock(&self) -> u32 {
+        self.moves
+            .iter()
+            .rev()
+            .take_while(|played| {
+                let pawn_move = played
+                    .before
+                    .get(played.from)
+                    .is_some_and(|piece| matches!(piece.piece_type, PieceType::Pawn));
+                !pawn_move && played.captured().is_none()
+            })
+            .count() as u32
+    }
+
+    /// How many times `board` with `castling` has stood on the board this
+    /// game, counting the current position.
+    fn repetitions(&self, board: &Board, castling: CastlingRights) -> usize {
+        let hash = zobrist::hash(board, castling);
+        1 + self
+            .moves
+            .iter()
+            .filter(|played| zobrist::hash(&played.before, played.castling_before) == hash)
+            .count()
+    }
+
+    /// Whether the game on `board` is over, including the draws that
+    /// depend on the moves that led to it.
+    pub fn outcome(&self, board: &Board, castling: CastlingRights) -> Option<Outcome> {
+        if let Some(result) = board.game_over() {
+            return Some(match result {
+                GameResult::Checkmate(winner) => Outcome::Checkmate(winner),
+                GameResult::Stalemate => Outcome::Stalemate,
+            });
+        }
+        if rules::insufficient_material(board) {
+            Some(Outcome::InsufficientMaterial)
+        } else if self.halfmove_clock() >= 100 {
+            Some(Outcome::FiftyMoves)
+        } else if self.repetitions(board, castling) >= 3 {
+            Some(Outcome::Repetition)
+        } else {
+            None
+        }
+    }
+
+    /// Numbered movetext, e.g. "1. e4 e5 2. Nf3", one full move per line.
+    pub fn movetext(&self) -> String {
+        self.moves
+            .chunks(2)
+            .enumerate()
+            .map(|(index, pair)| {
+                let black = pair.get(1).map(|played| played.san.as_str()).unwrap_or("");
+                format!("{}. {} {}", index + 1, pair[0].san, black)
+                    .trim_end()
+                    .to_string()
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+#[derive(Component)]
+pub struct MoveListPanel;
+
+/// The moves as plain text. Reviewing a finished game hides it for
+/// `ReviewMoveList`, whose moves can be clicked to jump to them.
+#[derive(Component)]
+pub struct MoveListText;
+
+pub fn spawn_move_list(mut commands: Commands) {
+    commands.spawn((
+        MoveListPanel,
+        GameUi,
+        Node {
+            position_type: PositionType::Absolute,
+            right: Val::Px(0.0),
+            top: Val::Px(0.0),
+            width: Val::Px(220.0),
+            height: Val::Percent(100.0),
+            padding: UiRect::all(Val::Px(10.0)),
+            overflow: Overflow::scroll_y(),
+            ..default()
+        },
+        ScrollPosition::default(),
+        BackgroundColor(widgets::PANEL_COLOR),
+        children![(
+            MoveListText,
+            Text::new(""),
+            TextFont {
+                font_size: 16.0,
+                ..default()
+            },
+        )],
+    ));
+}
+
+pub fn render_move_list(
+    history: Res<MoveHistory>,
+    mut texts: Query<&mut Text, With<MoveListText>>,
+    mut panels: Query<&mut ScrollPosition, With<MoveListPanel>>,
+) {
+    if !history.is_changed() {
+        return;
+    }
+    for mut text in texts.iter_mut() {
+        text.0 = history.movetext();
+    }
+    // Layout clamps the offset, so this keeps the latest move in view.
+    for mut scroll in panels.iter_mut() {
+        scroll.offset_y = f32::MAX;
+    }
+}
+
+pub fn scroll_move_list(
+    mut wheel: EventReader<MouseWheel>,
+    mut panels: Query<&mut ScrollPosition, With<MoveListPanel>>,
+) {
+    for event in wheel.read() {
+        let lines = match event.unit {
+            MouseScrollUnit::Line => event.y * 20.0,
+            MouseScrollUnit::Pixel => event.y,
+        };
+        for mut scroll in panels.iter_mut() {
+            scroll.offset_y = (scroll.offset_y - lines).max(0.0);
+        }
+    }
+}
+
+/// Adds every move played on the board to the history.
+pub fn record_moves(mut played: EventReader<MovePlayed>, mut history: ResMut<MoveHistory>) {
+    for played in played.read() {
+        history.push(
+            &played.before,
+            played.castling_before,
+            played.from,
+            played.to,
+            played.promotion_piece,
+        );
+    }
+}
+
+/// Ctrl+Z takes back the last move and Ctrl+Y plays it again. Only
+/// available in local play, since the opponent can't take moves back.
+#[allow(clippy::too_many_arguments)]
+pub fn undo_redo(
+    keys: Res<ButtonInput<KeyCode>>,
+    connection: Option<Res<Connection>>,
+    mut history: ResMut<MoveHistory>,
+    mut board_state: ResMut<BoardState>,
+    mut castling: ResMut<Castling>,
+    mut selected: ResMut<SelectedSquare>,
+    mut pending_promotion: ResMut<PendingPromotion>,
+) {
+    if connection.is_some() {
+        return;
+    }
+    let undo = actions::just_pressed(&keys, Action::Undo);
+    let redo = actions::just_pressed(&keys, Action::Redo);
+    if !undo && !redo {
+        return;
+    }
+    selected.0 = None;
+    // A pending promotion hasn't touched the board yet, so backing out of
+    // it is all an undo needs to do.
+    if pending_promotion.0.is_some() {
+        pending_promotion.0 = None;
+        return;
+    }
+    if undo {
+        let Some(played) = history.moves.pop() else {
+            return;
+        };
+        board_state.0 = played.before.clone();
+        castling.0 = played.castling_before;
+        history.undone.push(played);
+    } else {
+        let Some(played) = history.undone.pop() else {
+            return;
+        };
+        match rules::castle_onto_rook(&board_state.0, castling.0, played.from, played.to) {
+            Some(after) => board_state.0 = after,
+            None => {
+                board_state
+                    .0
+                    .play(
+                        (played.from.row, played.from.col),
+                        (played.to.row, played.to.col),
+                        played.promotion_piece,
+                    )
+                    .expect("Redone move not valid");
+            }
+        }
+        castling.0.update(played.from, played.to);
+        history.moves.push(played);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fen;
+
+    fn position(fen: &str) -> (Board, CastlingRights) {
+        fen::board_from_fen(fen).unwrap()
+    }
+
+    /// Plays `from` to `to` on `position`, writing the move down first.
+    fn play(
+        history: &mut MoveHistory,
+        position: &mut (Board, CastlingRights),
+        from: (i8, i8),
+        to: (i8, i8),
+    ) {
+        let (board, castling) = position;
+        history.push(
+            board,
+            *castling,
+            Position::new(from.0, from.1),
+            Position::new(to.0, to.1),
+            None,
+        );
+        board.play(from, to, None).expect("Test move not valid");
+        castling.update(Position::new(from.0, from.1), Position::new(to.0, to.1));
+    }
+
+    #[test]
+    fn the_third_time_a_position_stands_is_a_draw() {
+        let mut current = position("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq -");
+        let mut history = MoveHistory::default();
+        // The knights go out and back twice, bringing the start position
+        // round a second and a third time.
+        let knights = [
+            ((0, 6), (2, 5)),
+            ((7, 6), (5, 5)),
+            ((2, 5), (0, 6)),
+            ((5, 5), (7, 6)),
+        ];
+        for (from, to) in knights.iter().chain(&knights) {
+            assert_eq!(history.outcome(&current.0, current.1), None);
+            play(&mut history, &mut current, *from, *to);
+        }
+        assert_eq!(
+            history.outcome(&current.0, current.1),
+            Some(Outcome::Repetition)
+        );
+    }
+
+    #[test]
+    fn a_hundred_quiet_half_moves_are_a_draw() {
+        let mut current = position("4k3/8/8/8/8/8/8/R3K3 w - -");
+        let mut history = MoveHistory::default();
+        let shuffle = [
+            ((0, 0), (1, 0)),
+            ((7, 4), (7, 3)),
+            ((1, 0), (0, 0)),
+            ((7, 3), (7, 4)),
+        ];
+        for (from, to) in shuffle.iter().cycle().take(99) {
+            play(&mut history, &mut current, *from, *to);
+        }
+        assert_eq!(history.halfmove_clock(), 99);
+        // The rule comes before the repetitions the shuffling made.
+        play(&mut history, &mut current, (7, 3), (7, 4));
+        assert_eq!(history.halfmove_clock(), 100);
+        assert_eq!(
+            history.outcome(&current.0, current.1),
+            Some(Outcome::FiftyMoves)
+        );
+    }
+
+    #[test]
+    fn pawn_moves_start_the_fifty_moves_over() {
+        let mut current = position("4k3/8/8/8/8/8/P7/4K2R w - -");
+        let mut history = MoveHistory::default();
+        play(&mut history, &mut current, (0, 7), (1, 7));
+        play(&mut history, &mut current, (7, 4), (7, 3));
+        assert_eq!(history.halfmove_clock(), 2);
+        play(&mut history, &mut current, (1, 0), (2, 0));
+        assert_eq!(history.halfmove_clock(), 0);
+        play(&mut history, &mut current, (7, 3), (7, 4));
+        assert_eq!(history.halfmove_clock(), 1);
+    }
+
+    #[test]
+    fn positions_no_one_can_mate_in_are_drawn() {
+        let history = MoveHistory::default();
+        for fen in [
+            "8/8/4k3/8/8/4K3/8/8 w - -",
+            "8/8/4k3/8/8/4K3/8/5N2 w - -",
+            "8/8/4k3/8/8/4K3/8/2B5 w - -",
+            // Bishops that all stand on dark squares.
+            "5b2/8/4k3/8/8/4K3/8/2B5 w - -",
+        ] {
+            let (board, castling) = position(fen);
+            assert_eq!(
+                history.outcome(&board, castling),
+                Some(Outcome::InsufficientMaterial),
+                "{fen}"
+            );
+        }
+        for fen in [
+            "8/8/4k3/8/8/4K3/8/1N4N1 w - -",
+            "2b5/8/4k3/8/8/4K3/8/2B5 w - -",
+            "2b5/8/4k3/8/8/4K3/8/5N2 w - -",
+            "8/8/4k3/8/8/4K3/4P3/8 w - -",
+            "8/8/4k3/8/8/4K3/8/7R w - -",
+        ] {
+            let (board, castling) = position(fen);
+            assert_eq!(history.outcome(&board, castling), None, "{fen}");
+        }
+    }
+
+    #[test]
+    fn castling_onto_the_rook_takes_nothing() {
+        // A Chess960 king on b1 castling queenside with its rook on a1.
+        let (board, castling) = fen::board_from_fen("3k4/8/8/8/8/8/8/RK6 w - -").unwrap();
+        let mut history = MoveHistory::default();
+        history.push(
+            &board,
+            castling,
+            Position::new(0, 1),
+            Position::new(0, 0),
+            None,
+        );
+        assert!(history.moves[0].captured().is_none());
+        assert!(history.captured_by(HermanhaColor::Black).is_empty());
+        assert_eq!(history.halfmove_clock(), 1);
+    }
+}
@@ -0,0 +1,213 @@
+use bevy::input::ButtonInput;
+use bevy::prelude::*;
+use hermanha_chess::{Board, PieceType, Position};
+
+use crate::actions::ActiveScopes;
+use crate::annotations::{self, Annotations};
+use crate::board_render::AutoRotate;
+use crate::clipboard::{self, PendingPaste};
+use crate::clock::Clocks;
+use crate::cursor::{self, BoardCursor, HoveredSquare, SquareChosen};
+use crate::game_state::{
+    self, BoardState, Castling, GameOutcome, LocalPlayer, MoveOrigin, MoveRequested, Paused,
+    SelectedSquare,
+};
+use crate::history;
+use crate::menu::{AppState, PlayState};
+use crate::net::Desync;
+use crate::offers::Concluded;
+use crate::premove::{self, Premove};
+use crate::promotion::{PendingPromotion, Promotion};
+use crate::rules::{self, CastlingRights};
+use crate::{GameSet, hint};
+
+/// The local player's moves: picking pieces and target squares with the
+/// mouse or the keyboard cursor, premoves, undo/redo and pasted positions.
+/// Moves are sent on as `MoveRequested` for `apply_moves` to play.
+pub struct BoardInputPlugin;
+
+impl Plugin for BoardInputPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<BoardCursor>()
+            .init_resource::<ActiveScopes>()
+            .init_resource::<HoveredSquare>()
+            .init_resource::<AutoRotate>()
+            .init_resource::<Premove>()
+            .init_resource::<Annotations>()
+            .init_resource::<PendingPaste>()
+            .add_event::<SquareChosen>()
+            .add_event::<IllegalMove>()
+            .add_systems(
+                Update,
+                (
+                    (
+                        (
+                            cursor::move_cursor.run_if(not(in_state(PlayState::Review))),
+                            cursor::track_hover,
+                            handle_square_selection.run_if(not(in_state(PlayState::Review))),
+                        )
+                            .chain(),
+                        (premove::queue_premove, annotations::annotate)
+                            .run_if(not(in_state(PlayState::Review))),
+                    )
+                        .in_set(GameSet::Input),
+                    (
+                        (history::undo_redo, clipboard::paste_from_clipboard)
+                            .before(game_state::apply_moves),
+                        premove::play_premove.after(game_state::apply_moves),
+                    )
+                        .in_set(GameSet::Moves)
+                        .run_if(not(in_state(PlayState::Review))),
+                )
+                    .run_if(in_state(AppState::Playing)),
+            );
+    }
+}
+
+/// A click on a square the selected piece cannot move to.
+#[derive(Event)]
+pub struct IllegalMove {
+    pub at: Position,
+    pub reason: &'static str,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum TargetKind {
+    Quiet,
+    Capture,
+}
+
+/// Squares the piece on `selected_pos` can move to, and whether moving
+/// there captures. A pawn changing file onto an empty square is en passant.
+/// In Chess960 the king castles by moving onto its own rook; from the
+/// standard start squares it castles by moving two files as usual.
+pub fn legal_targets(
+    board: &Board,
+    castling: CastlingRights,
+    selected_pos: Position,
+) -> Vec<(Position, TargetKind)> {
+    let is_pawn = board
+        .get(selected_pos)
+        .is_some_and(|piece| matches!(piece.piece_type, PieceType::Pawn));
+    let mut targets: Vec<(Position, TargetKind)> = board
+        .legal_moves()
+        .into_iter()
+        .filter(|(from, _, _)| *from == selected_pos)
+        .map(|(from, to, _)| {
+            let capture = board.get(to).is_some() || (is_pawn && from.col != to.col);
+            let kind = if capture {
+                TargetKind::Capture
+            } else {
+                TargetKind::Quiet
+            };
+            (to, kind)
+        })
+        .collect();
+    targets.extend(
+        rules::rook_castles(board, castling)
+            .into_iter()
+            .filter(|(king, _)| *king == selected_pos)
+            .map(|(_, rook)| (rook, TargetKind::Quiet)),
+    );
+    targets
+}
+
+/// Clicks, snapped to a nearby legal target, and the keyboard cursor pick
+/// a piece of the side to move and then its target square. The move
+/// itself is only requested here and played by `apply_moves`; a pawn
+/// reaching the last rank first opens the promotion dialog.
+#[allow(clippy::too_many_arguments)]
+fn handle_square_selection(
+    mut selected: ResMut<SelectedSquare>,
+    buttons: Res<ButtonInput<MouseButton>>,
+    hovered: Res<HoveredSquare>,
+    board: Res<BoardState>,
+    castling: Res<Castling>,
+    local_player: Res<LocalPlayer>,
+    mut illegal_moves: EventWriter<IllegalMove>,
+    mut pending_promotion: ResMut<PendingPromotion>,
+    mut requests: EventWriter<MoveRequested>,
+    clocks: Option<Res<Clocks>>,
+    concluded: Res<Concluded>,
+    desync: Res<Desync>,
+    outcome: Res<GameOutcome>,
+    paused: Res<Paused>,
+    mut chosen: EventReader<SquareChosen>,
+) {
+    // The keyboard cursor stands in for a click.
+    let keyboard_choice = chosen.read().last().map(|chosen| chosen.at);
+    if paused.0 {
+        return;
+    }
+    let board = &board.0;
+    let flagged = clocks.is_some_and(|clocks| clocks.flagged.is_some());
+    if flagged
+        || concluded.0.is_some()
+        || desync.0.is_some()
+        || outcome.0.is_some()
+        || !local_player.controls(board.move_turn)
+    {
+        selected.0 = None;
+        return;
+    }
+    let clicked = buttons
+        .just_pressed(MouseButton::Left)
+        .then(|| hovered.clicked())
+        .flatten();
+    let Some(position) = keyboard_choice.or(clicked) else {
+        return;
+    };
+    if !board.pos_on_board(position) {
+        return;
+    }
+    if let Some(promotion) = pending_promotion.0 {
+        let Some(piece_type) = promotion.choice_at(position) else {
+            return;
+        };
+        pending_promotion.0 = None;
+        requests.write(MoveRequested {
+            from: promotion.from,
+            to: promotion.to,
+            promotion_piece: Some(piece_type),
+            origin: MoveOrigin::Local,
+        });
+        return;
+    }
+    if let Some(moving_pos) = selected.0 {
+        if legal_targets(board, castling.0, moving_pos)
+            .iter()
+            .any(|(target, _)| *target == position)
+        {
+            selected.0 = None;
+            if hint::promotion_for(board, moving_pos, position).is_some() {
+                pending_promotion.0 = Some(Promotion {
+                    from: moving_pos,
+                    to: position,
+                    color: board.move_turn,
+                });
+                return;
+            }
+            requests.write(MoveRequested {
+                from: moving_pos,
+                to: position,
+                promotion_piece: None,
+                origin: MoveOrigin::Local,
+            });
+            return;
+        }
+        let reselecting = board
+            .get(position)
+            .zip(board.get(moving_pos))
+            .is_some_and(|(target, moving)| target.color == moving.color);
+        if !reselecting && let Some(reason) = rules::explain_illegal(board, moving_pos, position) {
+            illegal_moves.write(IllegalMove {
+                at: position,
+                reason,
+            });
+        }
+    }
+    let own_piece = board
+        .get(position)
+        .is_some_and(|piece| piece.color == board.move_turn);
+    selected.0 = own_piece.then_some(position);
+}
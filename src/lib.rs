@@ -0,0 +1,194 @@
+pub mod actions;
+pub mod analysis;
+pub mod annotations;
+pub mod board_render;
+pub mod chat;
+pub mod clipboard;
+pub mod clock;
+pub mod computer;
+pub mod config;
+pub mod confirm;
+pub mod coordinates;
+pub mod cursor;
+pub mod discovery;
+pub mod engine;
+pub mod eval_graph;
+pub mod event_log;
+pub mod export;
+pub mod fen;
+pub mod game_over;
+pub mod game_state;
+pub mod games;
+pub mod hint;
+pub mod history;
+pub mod input;
+pub mod lobby;
+pub mod menu;
+pub mod move_input;
+pub mod move_timer;
+pub mod name_plates;
+pub mod net;
+pub mod net_status;
+pub mod offers;
+pub mod opponent_move;
+pub mod pause;
+pub mod pgn;
+pub mod piece_images;
+pub mod premove;
+pub mod promotion;
+pub mod puzzle;
+pub mod relay;
+pub mod replay;
+pub mod review;
+pub mod rules;
+pub mod san;
+pub mod save;
+pub mod serve;
+pub mod setup;
+pub mod tcp;
+pub mod text_input;
+pub mod theme;
+pub mod tls;
+pub mod toast;
+pub mod training;
+pub mod transport;
+pub mod ui;
+pub mod validate;
+pub mod variant;
+pub mod widgets;
+pub mod window;
+pub mod ws;
+pub mod zobrist;
+
+use bevy::ecs::schedule::ScheduleLabel;
+use bevy::prelude::*;
+use hermanha_chess::{BOARD_COLS, Position};
+
+use crate::board_render::BoardRenderPlugin;
+use crate::event_log::EventLog;
+use crate::game_state::GameStatePlugin;
+use crate::input::BoardInputPlugin;
+use crate::menu::AppState;
+use crate::net::NetworkPlugin;
+use crate::replay::ReplayPlugin;
+use crate::toast::Toasts;
+use crate::ui::UiPlugin;
+
+pub const TILE_SIZE: f32 = 64.0;
+/// The piece SVGs are drawn on a canvas this many units across.
+pub const PIECE_SIZE: f32 = 45.0;
+pub const PIECE_SCALE: f32 = TILE_SIZE / PIECE_SIZE;
+pub const PIECE_Z: f32 = 1.0;
+const BOARD_OFFSET: f32 = (BOARD_COLS as f32 - 1.0) * 0.5;
+
+/// The order of the in-game systems within a frame, run one after the
+/// other. A move is always on the board before anything follows from it
+/// or is drawn.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum GameSet {
+    /// Mouse, keyboard and button presses and the opponent's messages are
+    /// read and turned into selections, move requests and game actions.
+    Input,
+    /// Requested moves are played on `BoardState` by `apply_moves`, along
+    /// with undo/redo and pasted FENs.
+    Moves,
+    /// The outcome, phase and clocks follow the new position, and played
+    /// moves and game actions are sent to the opponent.
+    Rules,
+    /// The board, markers and panels are redrawn.
+    Render,
+}
+
+/// Spawns the panels and labels around the board for the game being
+/// shown: on entering `AppState::Playing`, and again whenever another
+/// game's tab is shown.
+#[derive(ScheduleLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SpawnGameUi;
+
+fn spawn_game_ui(world: &mut World) {
+    world.run_schedule(SpawnGameUi);
+}
+
+fn pos_to_vec3(pos: Position, z: f32) -> Vec3 {
+    Vec3::new(
+        (pos.col as f32 - BOARD_OFFSET) * TILE_SIZE,
+        (pos.row as f32 - BOARD_OFFSET) * TILE_SIZE,
+        z,
+    )
+}
+
+/// How far from a square's center, in squares, the cursor still counts as
+/// pointing at it when it is one of the squares to snap to.
+const SNAP_DISTANCE: f32 = 0.7;
+
+/// The square under the cursor. When the cursor is off all of
+/// `snap_targets`, the nearest of them within `SNAP_DISTANCE` is taken
+/// instead, so a slightly-off click on a legal target still counts.
+fn cursor_to_board_position(
+    cursor_position: Vec2,
+    camera: &Camera,
+    camera_transform: &GlobalTransform,
+    snap_targets: &[Position],
+) -> Option<Position> {
+    let world_position = camera
+        .viewport_to_world_2d(camera_transform, cursor_position)
+        .ok()?;
+    // Board coordinates, with square centers on whole numbers.
+    let point = world_position / TILE_SIZE + Vec2::splat(BOARD_OFFSET);
+    let under_cursor = Position::new(point.y.round() as i8, point.x.round() as i8);
+    if snap_targets.is_empty() || snap_targets.contains(&under_cursor) {
+        return Some(under_cursor);
+    }
+    let distance = |pos: &Position| point.distance(Vec2::new(pos.col as f32, pos.row as f32));
+    snap_targets
+        .iter()
+        .filter(|pos| distance(pos) <= SNAP_DISTANCE)
+        .min_by(|a, b| distance(a).total_cmp(&distance(b)))
+        .copied()
+        .or(Some(under_cursor))
+}
+
+/// The game itself: board state, move input and application, game end,
+/// clocks and the network connection. Needs nothing beyond
+/// `MinimalPlugins`, `StatesPlugin` and `InputPlugin`, so tests and bots
+/// can run a game headlessly.
+pub struct ChessPlugin;
+
+impl Plugin for ChessPlugin {
+    fn build(&self, app: &mut App) {
+        app.configure_sets(
+            Update,
+            (
+                GameSet::Input,
+                GameSet::Moves,
+                GameSet::Rules,
+                GameSet::Render,
+            )
+                .chain(),
+        )
+        .init_resource::<Toasts>()
+        .init_resource::<EventLog>()
+        .add_systems(
+            Update,
+            (event_log::log_moves, event_log::log_game_events)
+                .chain()
+                .after(GameSet::Rules)
+                .before(GameSet::Render),
+        )
+        .init_schedule(SpawnGameUi)
+        .add_systems(OnEnter(AppState::Playing), spawn_game_ui)
+        .add_plugins((GameStatePlugin, BoardInputPlugin, NetworkPlugin));
+    }
+}
+
+/// Everything drawn on screen and the input that only concerns the
+/// display. Needs `DefaultPlugins` and `SvgPlugin` as well as
+/// `ChessPlugin`, and expects `Theme`, `WindowFlags` and
+/// `DefaultTimeControl` to be inserted.
+pub struct ChessUiPlugin;
+
+impl Plugin for ChessUiPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins((BoardRenderPlugin, UiPlugin, ReplayPlugin));
+    }
+}
@@ -0,0 +1,663 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+use bevy::prelude::*;
+use crossbeam_channel::{Receiver, TryRecvError, bounded};
+
+use crate::menu::{AppState, MenuAddress};
+use crate::net::{PendingConnection, PlayerName};
+use crate::tcp::ConnectionType;
+use crate::tls;
+use crate::transport::TransportKind;
+use crate::widgets;
+
+/// Where the lobby server listens unless told otherwise.
+pub const DEFAULT_LOBBY_PORT: u16 = 8100;
+
+/// The longest line either side of a lobby connection sends, newline
+/// included. Anything longer is taken as a broken peer.
+pub const MAX_LINE: u64 = 1024;
+
+/// How often the lobby screen asks for the open games.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// A game waiting for an opponent: how to connect and who is hosting.
+/// Which address to connect to is up to the lobby, which knows where the
+/// host's registration came from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Challenge {
+    pub transport: TransportKind,
+    pub fingerprint: Option<String>,
+    pub name: String,
+}
+
+impl Challenge {
+    /// Names go on the lobby's lines as they are, so they can't hold a
+    /// newline or any other control character.
+    pub fn check_name(name: &str) -> Result<(), String> {
+        if name.chars().any(char::is_control) {
+            return Err("Names can't contain control characters".to_string());
+        }
+        Ok(())
+    }
+
+    fn to_fields(&self) -> String {
+        format!(
+            "{} {} {}",
+            self.transport.tag(),
+            self.fingerprint.as_deref().unwrap_or("-"),
+            self.name
+        )
+    }
+
+    /// The name comes last, so it can hold spaces.
+    fn from_fields(fields: &str) -> Result<Self, String> {
+        let parts: Vec<&str> = fields.splitn(3, ' ').collect();
+        let [transport, fingerprint, name] = parts[..] else {
+            return Err("Invalid challenge".to_string());
+        };
+        Challenge::check_name(name)?;
+        Ok(Challenge {
+            transport: TransportKind::from_tag(transport)?,
+            fingerprint: (fingerprint != "-").then(|| fingerprint.to_string()),
+            name: name.to_string(),
+        })
+    }
+}
+
+/// A line sent to the lobby. The lobby answers each with `Response`s.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Request {
+    /// Lists a game hosted on `port`. It stays listed until someone
+    /// accepts it or the host's lobby connection closes.
+    Open { port: u16, challenge: Challenge },
+    /// Asks for every open game.
+    List,
+    /// Takes the open game with this id.
+    Accept(u32),
+}
+
+impl Request {
+    pub fn to_line(&self) -> String {
+        match self {
+            Request::Open { port, challenge } => format!("OPEN {port} {}\n", challenge.to_fields()),
+            Request::List => "LIST\n".to_string(),
+            Request::Accept(id) => format!("ACCEPT {id}\n"),
+        }
+    }
+
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let (command, rest) = line
+            .trim_end()
+            .split_once(' ')
+            .unwrap_or((line.trim_end(), ""));
+        match command {
+            "OPEN" => {
+                let (port, challenge) = rest.split_once(' ').ok_or("Invalid OPEN")?;
+                Ok(Request::Open {
+                    port: port.parse().map_err(|_| "Invalid port".to_string())?,
+                    challenge: Challenge::from_fields(challenge)?,
+                })
+            }
+            "LIST" => Ok(Request::List),
+            "ACCEPT" => rest
+                .parse()
+                .map(Request::Accept)
+                .map_err(|_| "Invalid game id".to_string()),
+            _ => Err(format!("Unknown request: {command}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Response {
+    /// The host's game is listed under this id.
+    Opened(u32),
+    /// One open game, in answer to `List`.
+    Game(u32, Challenge),
+    /// The end of the games listed.
+    End,
+    /// The accepted game and the host's address to connect to.
+    Match(String, Challenge),
+    Error(String),
+}
+
+impl Response {
+    pub fn to_line(&self) -> String {
+        match self {
+            Response::Opened(id) => format!("OPENED {id}\n"),
+            Response::Game(id, challenge) => format!("GAME {id} {}\n", challenge.to_fields()),
+            Response::End => "END\n".to_string(),
+            Response::Match(address, challenge) => {
+                format!("MATCH {address} {}\n", challenge.to_fields())
+            }
+            Response::Error(message) => format!("ERROR {message}\n"),
+        }
+    }
+
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let (command, rest) = line
+            .trim_end()
+            .split_once(' ')
+            .unwrap_or((line.trim_end(), ""));
+        let id_and_rest = || -> Result<(u32, &str), String> {
+            let (id, rest) = rest.split_once(' ').ok_or("Invalid response")?;
+            Ok((id.parse().map_err(|_| "Invalid game id")?, rest))
+        };
+        match command {
+            "OPENED" => rest
+                .parse()
+                .map(Response::Opened)
+                .map_err(|_| "Invalid game id".to_string()),
+            "GAME" => {
+                let (id, challenge) = id_and_rest()?;
+                Ok(Response::Game(id, Challenge::from_fields(challenge)?))
+            }
+            "END" => Ok(Response::End),
+            "MATCH" => {
+                let (address, challenge) = rest.split_once(' ').ok_or("Invalid MATCH")?;
+                Ok(Response::Match(
+                    address.to_string(),
+                    Challenge::from_fields(challenge)?,
+                ))
+            }
+            "ERROR" => Ok(Response::Error(rest.to_string())),
+            _ => Err(format!("Unknown response: {command}")),
+        }
+    }
+}
+
+/// Reads one line of at most `MAX_LINE` bytes into `line`, returning how
+/// many bytes were read, 0 at the end of the stream.
+pub fn read_line(reader: &mut impl BufRead, line: &mut String) -> io::Result<usize> {
+    let read = reader.take(MAX_LINE).read_line(line)?;
+    if read as u64 == MAX_LINE && !line.ends_with('\n') {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "line too long"));
+    }
+    Ok(read)
+}
+
+/// A connection to the lobby, sending requests and reading the answers.
+/// Blocks, so use it off the main thread.
+struct LobbyClient {
+    stream: TcpStream,
+    reader: BufReader<TcpStream>,
+}
+
+impl LobbyClient {
+    fn connect(address: &str) -> io::Result<Self> {
+        let stream = TcpStream::connect(address)?;
+        Ok(LobbyClient {
+            reader: BufReader::new(stream.try_clone()?),
+            stream,
+        })
+    }
+
+    fn send(&mut self, request: &Request) -> io::Result<()> {
+        self.stream.write_all(request.to_line().as_bytes())
+    }
+
+    fn receive(&mut self) -> io::Result<Response> {
+        let mut line = String::new();
+        if read_line(&mut self.reader, &mut line)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "lobby closed the connection",
+            ));
+        }
+        match Response::parse(&line) {
+            Ok(Response::Error(message)) => Err(io::Error::other(message)),
+            Ok(response) => Ok(response),
+            Err(err) => Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+        }
+    }
+}
+
+fn unexpected(response: Response) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("unexpected answer from the lobby: {response:?}"),
+    )
+}
+
+fn list_games(lobby: &str) -> io::Result<Vec<(u32, Challenge)>> {
+    let mut client = LobbyClient::connect(lobby)?;
+    client.send(&Request::List)?;
+    let mut games = Vec::new();
+    loop {
+        match client.receive()? {
+            Response::Game(id, challenge) => games.push((id, challenge)),
+            Response::End => return Ok(games),
+            other => return Err(unexpected(other)),
+        }
+    }
+}
+
+fn accept_game(lobby: &str, id: u32) -> io::Result<(String, Challenge)> {
+    let mut client = LobbyClient::connect(lobby)?;
+    client.send(&Request::Accept(id))?;
+    match client.receive()? {
+        Response::Match(address, challenge) => Ok((address, challenge)),
+        other => Err(unexpected(other)),
+    }
+}
+
+/// Lists a game and returns the connection that keeps it listed.
+fn open_game(lobby: &str, port: u16, challenge: Challenge) -> io::Result<TcpStream> {
+    let mut client = LobbyClient::connect(lobby)?;
+    client.send(&Request::Open { port, challenge })?;
+    match client.receive()? {
+        Response::Opened(_) => Ok(client.stream),
+        other => Err(unexpected(other)),
+    }
+}
+
+fn in_background<T: Send + 'static>(
+    task: impl FnOnce() -> io::Result<T> + Send + 'static,
+) -> Receiver<io::Result<T>> {
+    let (sender, result) = bounded(1);
+    thread::spawn(move || {
+        let _ = sender.send(task());
+    });
+    result
+}
+
+/// The lobby server's address, set with `--lobby=`.
+#[derive(Resource)]
+pub struct LobbyAddress(pub String);
+
+impl Default for LobbyAddress {
+    fn default() -> Self {
+        LobbyAddress(format!("127.0.0.1:{DEFAULT_LOBBY_PORT}"))
+    }
+}
+
+/// Present on the lobby screen: the open games last heard of and the
+/// requests to the lobby still under way.
+#[derive(Resource)]
+pub struct LobbyBrowser {
+    pub games: Vec<(u32, Challenge)>,
+    status: String,
+    refresh: Timer,
+    listing: Option<Receiver<io::Result<Vec<(u32, Challenge)>>>>,
+    joining: Option<Receiver<io::Result<(String, Challenge)>>>,
+}
+
+/// Present while a game opened in the lobby waits for an opponent. The
+/// lobby lists it for as long as `stream` stays open.
+#[derive(Resource)]
+pub struct LobbyListing {
+    opened: Receiver<io::Result<TcpStream>>,
+    stream: Option<TcpStream>,
+}
+
+#[derive(Component)]
+pub struct LobbyScreen;
+
+#[derive(Component)]
+pub struct LobbyGameList;
+
+#[derive(Component)]
+pub struct LobbyStatus;
+
+#[derive(Component)]
+pub struct LobbyGameButton(pub u32);
+
+#[derive(Component, Clone, Copy)]
+pub enum LobbyButton {
+    Open,
+    Back,
+}
+
+pub fn enter_lobby(mut commands: Commands) {
+    commands.insert_resource(LobbyBrowser {
+        games: Vec::new(),
+        status: "Asking the lobby\u{2026}".to_string(),
+        // Finished, so the first refresh goes out right away.
+        refresh: Timer::new(Duration::ZERO, TimerMode::Once),
+        listing: None,
+        joining: None,
+    });
+    commands.spawn((
+        LobbyScreen,
+        widgets::screen(widgets::SCREEN_COLOR, 12.0, 5),
+        children![
+            widgets::title("Lobby", 28.0),
+            (
+                LobbyGameList,
+                Node {
+                    flex_direction: FlexDirection::Column,
+                    row_gap: Val::Px(8.0),
+                    ..default()
+                },
+            ),
+            (LobbyStatus, widgets::small_label("")),
+            (
+                LobbyButton::Open,
+                widgets::colored_button(
+                    widgets::button_node(200.0, 40.0),
+                    widgets::ACCENT_BUTTON_COLOR,
+                    widgets::label("Open a game"),
+                ),
+            ),
+            (
+                LobbyButton::Back,
+                widgets::button(widgets::button_node(200.0, 40.0), widgets::label("Back")),
+            ),
+        ],
+    ));
+}
+
+pub fn exit_lobby(mut commands: Commands, screens: Query<Entity, With<LobbyScreen>>) {
+    commands.remove_resource::<LobbyBrowser>();
+    for entity in screens.iter() {
+        commands.entity(entity).despawn();
+    }
+}
+
+/// Asks the lobby for the open games every `REFRESH_INTERVAL`, and joins
+/// the accepted game once the lobby answers with its address.
+pub fn poll_lobby(
+    mut commands: Commands,
+    mut browser: ResMut<LobbyBrowser>,
+    lobby: Res<LobbyAddress>,
+    time: Res<Time<Real>>,
+    mut transport: ResMut<TransportKind>,
+    mut address: ResMut<MenuAddress>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    let listed = browser.listing.as_ref().map(Receiver::try_recv);
+    match listed {
+        Some(Ok(Ok(games))) => {
+            let status = if games.is_empty() {
+                "No open games".to_string()
+            } else {
+                String::new()
+            };
+            if browser.games != games || browser.status != status {
+                browser.games = games;
+                browser.status = status;
+            }
+            browser.bypass_change_detection().listing = None;
+        }
+        Some(Ok(Err(err))) => {
+            browser.status = format!("Could not reach the lobby: {err}");
+            browser.bypass_change_detection().listing = None;
+        }
+        Some(Err(TryRecvError::Disconnected)) => browser.bypass_change_detection().listing = None,
+        Some(Err(TryRecvError::Empty)) | None => {}
+    }
+    // Only the games and the status are drawn, so the bookkeeping doesn't
+    // count as a change.
+    let requests = browser.bypass_change_detection();
+    if requests.listing.is_none() && requests.refresh.tick(time.delta()).finished() {
+        let lobby = lobby.0.clone();
+        requests.listing = Some(in_background(move || list_games(&lobby)));
+        requests.refresh = Timer::new(REFRESH_INTERVAL, TimerMode::Once);
+    }
+    let Some(joining) = &requests.joining else {
+        return;
+    };
+    let result = match joining.try_recv() {
+        Ok(result) => result,
+        Err(TryRecvError::Empty) => return,
+        Err(TryRecvError::Disconnected) => Err(io::Error::other("lobby thread stopped")),
+    };
+    requests.joining = None;
+    match result {
+        Ok((host, challenge)) => {
+            *transport = challenge.transport;
+            address.0.set_text(match &challenge.fingerprint {
+                Some(fingerprint) => format!("{host}#{fingerprint}"),
+                None => host,
+            });
+            commands.insert_resource(PendingConnection::start(
+                *transport,
+                ConnectionType::Client,
+                address.0.text.clone(),
+                ConnectionType::Client.player_color(),
+            ));
+            next_state.set(AppState::Connecting);
+        }
+        Err(err) => browser.status = format!("Could not join: {err}"),
+    }
+}
+
+pub fn render_lobby(
+    mut commands: Commands,
+    browser: Res<LobbyBrowser>,
+    lists: Query<Entity, With<LobbyGameList>>,
+    mut statuses: Query<&mut Text, With<LobbyStatus>>,
+) {
+    if !browser.is_changed() {
+        return;
+    }
+    for mut status in statuses.iter_mut() {
+        if status.0 != browser.status {
+            status.0 = browser.status.clone();
+        }
+    }
+    for list in lists.iter() {
+        commands.entity(list).despawn_related::<Children>();
+        commands.entity(list).with_children(|parent| {
+            for (id, challenge) in &browser.games {
+                parent.spawn((
+                    LobbyGameButton(*id),
+                    widgets::button(
+                        widgets::button_node(320.0, 44.0),
+                        widgets::label(format!(
+                            "{} ({})",
+                            challenge.name,
+                            challenge.transport.label()
+                        )),
+                    ),
+                ));
+            }
+        });
+    }
+}
+
+/// Accepting a listed game asks the lobby for the host's address. Opening
+/// one hosts on the menu's address, as the host button does, and lists it
+/// under our name; the address has to be reachable by others, e.g.
+/// `0.0.0.0:8080`.
+#[allow(clippy::too_many_arguments)]
+pub fn handle_lobby_buttons(
+    mut commands: Commands,
+    games: Query<(&Interaction, &LobbyGameButton), Changed<Interaction>>,
+    buttons: Query<(&Interaction, &LobbyButton), Changed<Interaction>>,
+    mut browser: ResMut<LobbyBrowser>,
+    lobby: Res<LobbyAddress>,
+    address: Res<MenuAddress>,
+    transport: Res<TransportKind>,
+    name: Res<PlayerName>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    for (interaction, game) in games.iter() {
+        if *interaction != Interaction::Pressed || browser.joining.is_some() {
+            continue;
+        }
+        let lobby = lobby.0.clone();
+        let id = game.0;
+        browser.bypass_change_detection().joining =
+            Some(in_background(move || accept_game(&lobby, id)));
+        browser.status = "Joining\u{2026}".to_string();
+    }
+    for (interaction, button) in buttons.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        match button {
+            LobbyButton::Back => next_state.set(AppState::Menu),
+            LobbyButton::Open => {
+                let Some(port) = address
+                    .0
+                    .text
+                    .rsplit_once(':')
+                    .and_then(|(_, port)| port.parse().ok())
+                else {
+                    browser.status = format!("No port in the address {}", address.0.text);
+                    continue;
+                };
+                if let Err(err) = Challenge::check_name(&name.0) {
+                    browser.status = err;
+                    continue;
+                }
+                let challenge = Challenge {
+                    transport: *transport,
+                    fingerprint: (*transport == TransportKind::Tls).then(tls::host_fingerprint),
+                    name: name.0.clone(),
+                };
+                let lobby = lobby.0.clone();
+                commands.insert_resource(LobbyListing {
+                    opened: in_background(move || open_game(&lobby, port, challenge)),
+                    stream: None,
+                });
+                commands.insert_resource(PendingConnection::start(
+                    *transport,
+                    ConnectionType::Server,
+                    address.0.text.clone(),
+                    ConnectionType::Server.player_color(),
+                ));
+                next_state.set(AppState::Connecting);
+            }
+        }
+    }
+}
+
+/// Holds on to the lobby connection that keeps our game listed. Failing to
+/// list it doesn't stop the host from waiting for an opponent who has the
+/// address.
+pub fn keep_listing(mut listing: ResMut<LobbyListing>) {
+    match listing.opened.try_recv() {
+        Ok(Ok(stream)) => listing.stream = Some(stream),
+        Ok(Err(err)) => warn!("Could not list the game in the lobby: {err}"),
+        Err(_) => {}
+    }
+}
+
+/// Dropping the listing closes the lobby connection, which takes the game
+/// off the list.
+pub fn stop_listing(mut commands: Commands) {
+    commands.remove_resource::<LobbyListing>();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn challenge(name: &str) -> Challenge {
+        Challenge {
+            transport: TransportKind::Tls,
+            fingerprint: Some("ab12".to_string()),
+            name: name.to_string(),
+        }
+    }
+
+    #[test]
+    fn requests_parse_what_they_send() {
+        let requests = [
+            Request::Open {
+                port: 8080,
+                challenge: challenge("Magnus C"),
+            },
+            Request::Open {
+                port: 1,
+                challenge: Challenge {
+                    transport: TransportKind::Tcp,
+                    fingerprint: None,
+                    name: "Hikaru".to_string(),
+                },
+            },
+            Request::List,
+            Request::Accept(7),
+        ];
+        for request in requests {
+            assert_eq!(Request::parse(&request.to_line()), Ok(request));
+        }
+    }
+
+    #[test]
+    fn malformed_requests_are_rejected() {
+        for line in [
+            "",
+            "HELLO\n",
+            "OPEN\n",
+            "OPEN 8080\n",
+            "OPEN port tcp - name\n",
+            "OPEN 70000 tcp - name\n",
+            "OPEN 8080 udp - name\n",
+            "OPEN 8080 tcp -\n",
+            "OPEN 8080 tcp - bad\u{7}name\n",
+            "ACCEPT\n",
+            "ACCEPT -1\n",
+            "ACCEPT one\n",
+        ] {
+            assert!(Request::parse(line).is_err(), "{line:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn responses_parse_what_they_send() {
+        let responses = [
+            Response::Opened(3),
+            Response::Game(4, challenge("Judit")),
+            Response::End,
+            Response::Match("10.0.0.2:8080".to_string(), challenge("Judit P")),
+            Response::Error("Game 4 is no longer open".to_string()),
+        ];
+        for response in responses {
+            assert_eq!(Response::parse(&response.to_line()), Ok(response));
+        }
+    }
+
+    #[test]
+    fn malformed_responses_are_rejected() {
+        for line in [
+            "",
+            "OPEN 3\n",
+            "OPENED\n",
+            "OPENED x\n",
+            "GAME 4\n",
+            "GAME x tcp - name\n",
+            "GAME 4 tcp\n",
+            "MATCH 10.0.0.2:8080\n",
+            "MATCH 10.0.0.2:8080 smoke - name\n",
+        ] {
+            assert!(Response::parse(line).is_err(), "{line:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn names_with_control_characters_are_refused() {
+        assert!(Challenge::check_name("Magnus Carlsen").is_ok());
+        for name in ["two\nlines", "tab\tbed", "nul\0"] {
+            assert!(Challenge::check_name(name).is_err(), "{name:?} was allowed");
+        }
+    }
+
+    #[test]
+    fn lines_longer_than_the_limit_are_refused() {
+        let mut line = String::new();
+        let short = "LIST\nEND\n";
+        let mut reader = short.as_bytes();
+        assert_eq!(read_line(&mut reader, &mut line).unwrap(), 5);
+        assert_eq!(line, "LIST\n");
+
+        let long = "x".repeat(MAX_LINE as usize * 2) + "\n";
+        let mut reader = long.as_bytes();
+        line.clear();
+        let err = read_line(&mut reader, &mut line).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let fits = "x".repeat(MAX_LINE as usize - 1) + "\n";
+        let mut reader = fits.as_bytes();
+        line.clear();
+        assert_eq!(
+            read_line(&mut reader, &mut line).unwrap(),
+            MAX_LINE as usize
+        );
+    }
+}
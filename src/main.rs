@@ -1,371 +1,272 @@
-mod tcp;
-
 use std::env;
+use std::path::Path;
+use std::process;
+use std::time::Duration;
 
-use bevy::input::ButtonInput;
 use bevy::prelude::*;
-use bevy::window::PrimaryWindow;
 use bevy_svg::prelude::*;
-use hermanha_chess::{
-    BOARD_COLS, BOARD_ROWS, Board, Color as HermanhaColor, GameResult, MoveOk,
-    Piece as HermanhaPiece, PieceType, Position,
-};
-
-use crate::tcp::{
-    ConnectionType, Message, MoveMessage, QuitMessage, TcpConnection, TcpError, board_to_fen,
-};
-
-const TILE_SIZE: f32 = 64.0;
-const PIECE_SCALE: f32 = TILE_SIZE / 45.0;
-const PIECE_Z: f32 = 1.0;
-const BOARD_OFFSET: f32 = (BOARD_COLS as f32 - 1.0) * 0.5;
-
-#[derive(Resource, Deref)]
-struct BoardState(Board);
-
-#[derive(Resource, Deref)]
-struct PlayerColor(HermanhaColor);
-
-#[derive(Resource)]
-struct Connection(TcpConnection);
-
-#[derive(Resource, Default)]
-struct SelectedSquare(Option<Position>);
+use chess_app::clock::{Clocks, TimeControl};
+use chess_app::computer::Seed;
+use chess_app::config::{Config, DefaultTimeControl};
+use chess_app::event_log::EventLog;
+use chess_app::game_state::{BoardState, Castling};
+use chess_app::lobby::LobbyAddress;
+use chess_app::menu::{AppState, MenuAddress};
+use chess_app::move_timer::MoveTimeout;
+use chess_app::net::{PendingConnection, PlayerName};
+use chess_app::net_status::StallTimeout;
+use chess_app::puzzle::PuzzlePack;
+use chess_app::replay::{Recording, Replay};
+use chess_app::save::{ResumableGame, SavedGame};
+use chess_app::tcp::ConnectionType;
+use chess_app::theme::{Palette, Theme};
+use chess_app::transport::TransportKind;
+use chess_app::variant::Variant;
+use chess_app::window::WindowFlags;
+use chess_app::{ChessPlugin, ChessUiPlugin, fen, serve, validate};
+use hermanha_chess::Color as HermanhaColor;
 
-#[derive(Component, Debug, Clone, Copy)]
-#[require(Transform, Sprite)]
-struct Piece {}
-
-#[derive(Component)]
-struct Highlight;
-
-fn pos_to_vec3(pos: Position, z: f32) -> Vec3 {
-    Vec3::new(
-        (pos.col as f32 - BOARD_OFFSET) * TILE_SIZE,
-        (pos.row as f32 - BOARD_OFFSET) * TILE_SIZE,
-        z,
-    )
-}
-
-fn cursor_to_board_position(
-    cursor_position: Vec2,
-    camera: &Camera,
-    camera_transform: &GlobalTransform,
-) -> Option<Position> {
-    let world_position = camera
-        .viewport_to_world_2d(camera_transform, cursor_position)
-        .ok()?;
-    let col = ((world_position.x / TILE_SIZE) + BOARD_OFFSET).round() as i8;
-    let row = ((world_position.y / TILE_SIZE) + BOARD_OFFSET).round() as i8;
-    Some(Position::new(row, col))
-}
-
-fn legal_targets(board: &Board, selected_pos: Position) -> Vec<Position> {
-    board
-        .legal_moves()
-        .into_iter()
-        .filter(|(from, _, _)| *from == selected_pos)
-        .map(|(_, to, _)| to)
-        .collect()
-}
-
-fn square_color(pos: Position) -> Color {
-    if (pos.row + pos.col) % 2 == 0 {
-        Color::srgb(0.62, 0.42, 0.32)
-    } else {
-        Color::srgb(0.87, 0.81, 0.74)
-    }
-}
-
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    assert_eq!(
-        args.len(),
-        3,
-        "Two args has to be provided. <server/client> <address>"
-    );
-    let connection_type = &args[1];
-    let addr = &args[2];
-
-    let connection_type = match connection_type.as_str() {
-        "server" => ConnectionType::Server,
-        "client" => ConnectionType::Client,
-        _ => {
-            panic!("Invalid argument: {}", connection_type);
-        }
-    };
-    let connection = match connection_type {
-        ConnectionType::Server => TcpConnection::start_server(addr).unwrap(),
-        ConnectionType::Client => TcpConnection::connect_to_server(addr).unwrap(),
-    };
-    let player_color = if connection_type == ConnectionType::Client {
-        PlayerColor(HermanhaColor::White)
-    } else {
-        PlayerColor(HermanhaColor::Black)
+/// Removes `<name> <value>` (or `<name>=<value>`) from the arguments,
+/// returning the remaining arguments and the value if one was given.
+fn take_value_arg(mut args: Vec<String>, name: &str) -> (Vec<String>, Option<String>) {
+    let prefix = format!("{name}=");
+    let Some(index) = args
+        .iter()
+        .position(|arg| arg == name || arg.starts_with(&prefix))
+    else {
+        return (args, None);
     };
-
-    App::new()
-        .add_plugins((DefaultPlugins, SvgPlugin))
-        .insert_resource(BoardState(Board::start_pos()))
-        .insert_resource(player_color)
-        .insert_resource(Connection(connection))
-        .init_resource::<SelectedSquare>()
-        .add_systems(Startup, (setup_camera, render_board))
-        .add_systems(
-            Update,
-            (
-                handle_square_selection,
-                render_highlights,
-                render_pieces,
-                render_game_over,
-            ),
-        )
-        .run();
-}
-
-fn setup_camera(mut commands: Commands) {
-    commands.spawn(Camera2d);
-}
-
-fn render_board(mut commands: Commands) {
-    for row in 0..BOARD_ROWS as usize {
-        for col in 0..BOARD_COLS as usize {
-            let render_pos = Position::new(row as i8, col as i8);
-
-            let color = square_color(render_pos);
-            spawn_square(&mut commands, render_pos, color);
+    let arg = args.remove(index);
+    let value = match arg.strip_prefix(&prefix) {
+        Some(value) => value.to_string(),
+        None if index < args.len() => args.remove(index),
+        None => {
+            eprintln!("Usage: {name} \"<value>\"");
+            process::exit(1);
         }
-    }
+    };
+    (args, Some(value))
 }
 
-fn render_pieces(
-    mut commands: Commands,
-    asset_server: Res<AssetServer>,
-    board: Res<BoardState>,
-    pieces: Query<Entity, With<Piece>>,
-) {
-    let piece_entities: Vec<Entity> = pieces.iter().collect();
-    for entity in piece_entities {
-        commands.entity(entity).despawn();
-    }
-    let board = &board.0;
-    for row in 0..BOARD_ROWS as usize {
-        for col in 0..BOARD_COLS as usize {
-            let render_pos = Position::new(row as i8, col as i8);
-            let square = board.get(render_pos);
-            if let Some(piece) = square {
-                spawn_piece(&mut commands, &asset_server, piece, render_pos);
+fn main() {
+    let raw_args: Vec<String> = env::args().collect();
+    if raw_args.get(1).map(String::as_str) == Some("--validate-log") {
+        let Some(path) = raw_args.get(2) else {
+            eprintln!("Usage: --validate-log <session log>");
+            process::exit(1);
+        };
+        match validate::validate_log(path) {
+            Ok(frames) => println!("{frames} frames replayed without divergence"),
+            Err(err) => {
+                eprintln!("{err}");
+                process::exit(1);
             }
         }
+        return;
     }
-}
 
-fn render_highlights(
-    mut commands: Commands,
-    board: Res<BoardState>,
-    selected: Res<SelectedSquare>,
-    highlights: Query<Entity, With<Highlight>>,
-) {
-    for entity in highlights.iter() {
-        commands.entity(entity).despawn();
+    if raw_args.get(1).map(String::as_str) == Some("--serve") {
+        let address = raw_args
+            .get(2)
+            .map_or(serve::DEFAULT_SERVE_ADDRESS, String::as_str);
+        if let Err(err) = serve::run(address) {
+            eprintln!("Could not serve games on {address}: {err}");
+            process::exit(1);
+        }
+        return;
     }
 
-    let Some(selected_pos) = selected.0 else {
-        return;
+    // A replay starts the app the way the recorded session was started.
+    let (raw_args, replay) = take_value_arg(raw_args, "--replay");
+    let replay = replay.map(|path| {
+        Replay::load(Path::new(&path)).unwrap_or_else(|err| {
+            eprintln!("Invalid replay: {err}");
+            process::exit(1);
+        })
+    });
+    let raw_args = match &replay {
+        Some(replay) => replay.args.clone(),
+        None => raw_args,
     };
-    let board = &board.0;
-    let legal_targets = legal_targets(board, selected_pos);
-
-    for target in legal_targets {
-        spawn_highlight(&mut commands, target);
+    let (raw_args, record) = take_value_arg(raw_args, "--record");
+    let recording = record.map(|path| {
+        Recording::create(Path::new(&path), &raw_args).unwrap_or_else(|err| {
+            eprintln!("Could not start recording: {err}");
+            process::exit(1);
+        })
+    });
+    let (raw_args, fen) = take_value_arg(raw_args, "--fen");
+    let (args, flags): (Vec<String>, Vec<String>) =
+        raw_args.into_iter().partition(|arg| !arg.starts_with("--"));
+    if args.len() != 1 && args.len() != 3 {
+        eprintln!(
+            "Either no args (menu) or two args has to be provided. <server/client> <address>"
+        );
+        process::exit(1);
     }
-}
-
-fn render_game_over(mut commands: Commands, board: Res<BoardState>) {
-    let Some(game_result) = board.0.game_over() else {
-        return;
+    if fen.is_some() && args.len() != 1 {
+        eprintln!("--fen can only be used for local games");
+        process::exit(1);
+    }
+    let variant = if flags.iter().any(|flag| flag == "--chess960") {
+        Variant::random_chess960()
+    } else {
+        Variant::Standard
     };
-    let text = match game_result {
-        GameResult::Checkmate(HermanhaColor::White) => "White wins by checkmate".to_string(),
-        GameResult::Checkmate(HermanhaColor::Black) => "Black wins by checkmate".to_string(),
-        GameResult::Stalemate => "Stalemate".to_string(),
+    if fen.is_some() && variant != Variant::Standard {
+        eprintln!("--fen can't be combined with --chess960");
+        process::exit(1);
+    }
+    let (board, castling) = match fen {
+        Some(fen) => fen::board_from_fen(&fen).unwrap_or_else(|err| {
+            eprintln!("Invalid FEN: {err}");
+            process::exit(1);
+        }),
+        None => variant.start(),
     };
-    commands.spawn(Text2d::new(text));
-}
+    let config = Config::load();
+    let window_flags = WindowFlags::from_args(&flags, config.window_flags());
+    let time_control = TimeControl::from_args(&flags).unwrap_or_else(|err| {
+        eprintln!("Invalid time control: {err}");
+        process::exit(1);
+    });
+    let default_time_control = config.time_control();
+    let time_control = time_control.or(default_time_control);
 
-fn handle_square_selection(
-    mut selected: ResMut<SelectedSquare>,
-    buttons: Res<ButtonInput<MouseButton>>,
-    windows: Query<&Window, With<PrimaryWindow>>,
-    camera_q: Query<(&Camera, &GlobalTransform), With<Camera2d>>,
-    mut board: ResMut<BoardState>,
-    player_color: Res<PlayerColor>,
-    mut connection: ResMut<Connection>,
-) {
-    let board = &mut board.0;
-    if board.move_turn != player_color.0 {
-        selected.0 = None;
-        let msg = match connection.0.read() {
-            Ok(msg) => msg,
-            Err(TcpError::WouldBlock) => return,
-            Err(err) => panic!("Error reading message: {}", err),
-        };
-        let (from, to, promotion_piece, result, new_board) = match msg {
-            Message::Move(move_data) => (
-                move_data.from,
-                move_data.to,
-                move_data.promotion_piece,
-                move_data.result,
-                move_data.new_board,
-            ),
-            Message::Quit(quit_data) => {
-                panic!("{}", quit_data.message.unwrap_or("Quit".to_string()))
-            }
-        };
-        board
-            .play((from.row, from.col), (to.row, to.col), promotion_piece)
-            .expect("Move not valid");
-        if board_to_fen(board) != board_to_fen(&new_board) {
-            connection
-                .0
-                .write(Message::Quit(QuitMessage {
-                    message: Some("Boards does not match!".to_string()),
-                }))
-                .unwrap();
-            panic!("Boards does not match");
-        }
-        match result {
-            Some(GameResult::Checkmate(color)) => panic!("Checkmate for {:?}", color),
-            Some(GameResult::Stalemate) => panic!("Stalemate"),
-            None => (),
-        }
-        return;
+    let player_name = flags
+        .iter()
+        .find_map(|flag| flag.strip_prefix("--name="))
+        .or(config.player_name.as_deref())
+        .map(|name| PlayerName(name.to_string()))
+        .unwrap_or_default();
+    let mut theme = Theme::load();
+    if let Some(name) = &config.board_theme {
+        theme.select_board(name);
     }
-    if !buttons.just_pressed(MouseButton::Left) {
-        return;
+    if let Some(name) = &config.piece_set {
+        theme.select_piece_set(name);
     }
-    let Some(window) = windows.iter().next() else {
-        return;
-    };
-    let Some(cursor_position) = window.cursor_position() else {
-        return;
-    };
-    let Some((camera, camera_transform)) = camera_q.iter().next() else {
-        return;
-    };
-    let Some(position) = cursor_to_board_position(cursor_position, camera, camera_transform) else {
-        return;
-    };
-    if !board.pos_on_board(position) {
-        return;
+    if let Some(name) = &config.palette {
+        theme.palette = Palette::from_name(name).unwrap_or_default();
     }
-    if let Some(moving_pos) = selected.0 {
-        if legal_targets(board, moving_pos).contains(&position) {
-            let mut promotion_piece: Option<PieceType> = None;
-            if let Ok(MoveOk::NeedsPromotion) = board.play(
-                (moving_pos.row, moving_pos.col),
-                (position.row, position.col),
-                promotion_piece,
-            ) {
-                promotion_piece = Some(PieceType::Queen);
-                _ = board.play(
-                    (moving_pos.row, moving_pos.col),
-                    (position.row, position.col),
-                    promotion_piece,
-                );
+    theme.shape_markers = config.shape_markers.as_deref() == Some("on");
+    let stall_timeout = match flags
+        .iter()
+        .find_map(|flag| flag.strip_prefix("--stall-timeout="))
+    {
+        Some(secs) => match secs.parse::<f32>().map(Duration::try_from_secs_f32) {
+            Ok(Ok(timeout)) if !timeout.is_zero() => StallTimeout(timeout),
+            _ => {
+                eprintln!("Invalid stall timeout: {secs}");
+                process::exit(1);
             }
-            let result = board.game_over();
-            let move_msg = MoveMessage {
-                from: moving_pos,
-                to: position,
-                promotion_piece,
-                result,
-                new_board: board.clone(),
-            };
-            let msg = Message::Move(move_msg);
-            connection.0.write(msg).unwrap();
-            selected.0 = None;
-            return;
-        }
-    }
-    selected.0 = Some(position);
-}
-
-fn spawn_square(commands: &mut Commands, pos: Position, color: Color) {
-    commands.spawn((
-        Sprite {
-            color,
-            custom_size: Some(Vec2::splat(TILE_SIZE)),
-            ..default()
         },
-        Transform::from_translation(pos_to_vec3(pos, 0.0)),
-    ));
-}
-
-fn spawn_highlight(commands: &mut Commands, pos: Position) {
-    commands.spawn((
-        Highlight,
-        Sprite {
-            color: Color::srgba(0.72, 0.82, 0.46, 0.6),
-            custom_size: Some(Vec2::splat(TILE_SIZE)),
-            ..default()
+        None => StallTimeout::default(),
+    };
+    let move_timeout = match flags
+        .iter()
+        .find_map(|flag| flag.strip_prefix("--move-timeout="))
+    {
+        Some(secs) => match secs.parse::<u16>() {
+            Ok(secs) if secs > 0 => MoveTimeout(Some(Duration::from_secs(secs.into()))),
+            _ => {
+                eprintln!("Invalid move timeout: {secs}");
+                process::exit(1);
+            }
         },
-        Transform::from_translation(pos_to_vec3(pos, 0.5)),
-    ));
-}
-
-fn spawn_piece(
-    commands: &mut Commands,
-    asset_server: &AssetServer,
-    piece: HermanhaPiece,
-    pos: Position,
-) {
-    let svg = match (piece.color, piece.piece_type) {
-        (hermanha_chess::Color::White, PieceType::Pawn) => {
-            asset_server.load("pieces/Chess_plt45.svg")
-        }
-        (hermanha_chess::Color::White, PieceType::Rook) => {
-            asset_server.load("pieces/Chess_rlt45.svg")
-        }
-        (hermanha_chess::Color::White, PieceType::Knight) => {
-            asset_server.load("pieces/Chess_nlt45.svg")
-        }
-        (hermanha_chess::Color::White, PieceType::Bishop) => {
-            asset_server.load("pieces/Chess_blt45.svg")
-        }
-        (hermanha_chess::Color::White, PieceType::Queen) => {
-            asset_server.load("pieces/Chess_qlt45.svg")
-        }
-        (hermanha_chess::Color::White, PieceType::King) => {
-            asset_server.load("pieces/Chess_klt45.svg")
-        }
-        (hermanha_chess::Color::Black, PieceType::Pawn) => {
-            asset_server.load("pieces/Chess_pdt45.svg")
-        }
-        (hermanha_chess::Color::Black, PieceType::Rook) => {
-            asset_server.load("pieces/Chess_rdt45.svg")
-        }
-        (hermanha_chess::Color::Black, PieceType::Knight) => {
-            asset_server.load("pieces/Chess_ndt45.svg")
-        }
-        (hermanha_chess::Color::Black, PieceType::Bishop) => {
-            asset_server.load("pieces/Chess_bdt45.svg")
-        }
-        (hermanha_chess::Color::Black, PieceType::Queen) => {
-            asset_server.load("pieces/Chess_qdt45.svg")
-        }
-        (hermanha_chess::Color::Black, PieceType::King) => {
-            asset_server.load("pieces/Chess_kdt45.svg")
+        None => MoveTimeout::default(),
+    };
+    let transport = if flags.iter().any(|flag| flag == "--websocket") {
+        TransportKind::WebSocket
+    } else if flags.iter().any(|flag| flag == "--tls") {
+        TransportKind::Tls
+    } else {
+        TransportKind::Tcp
+    };
+    let host_color = match flags.iter().find_map(|flag| flag.strip_prefix("--color=")) {
+        Some("white") => Some(HermanhaColor::White),
+        Some("black") => Some(HermanhaColor::Black),
+        Some(other) => {
+            eprintln!("Invalid color: {other}");
+            process::exit(1);
         }
+        None => None,
     };
-    commands.spawn((
-        Piece {},
-        Svg2d(svg),
-        Origin::Center,
-        Transform {
-            translation: pos_to_vec3(pos, PIECE_Z),
-            scale: Vec3::splat(PIECE_SCALE),
+
+    let mut app = App::new();
+    app.add_plugins((DefaultPlugins, SvgPlugin, ChessPlugin, ChessUiPlugin))
+        .insert_resource(player_name)
+        .insert_resource(stall_timeout)
+        .insert_resource(move_timeout)
+        .insert_resource(transport)
+        .insert_resource(DefaultTimeControl(default_time_control))
+        .insert_resource(config.image_export())
+        .insert_resource(variant)
+        .insert_resource(ResumableGame(SavedGame::load()))
+        .insert_resource(BoardState(board))
+        .insert_resource(Castling(castling))
+        .insert_resource(window_flags)
+        .insert_resource(theme);
+    if let Some(seed) = flags.iter().find_map(|flag| flag.strip_prefix("--seed=")) {
+        let seed = seed.parse().unwrap_or_else(|_| {
+            eprintln!("Invalid seed: {seed}");
+            process::exit(1);
+        });
+        app.insert_resource(Seed(seed));
+    }
+    if let Some(lobby) = flags.iter().find_map(|flag| flag.strip_prefix("--lobby=")) {
+        app.insert_resource(LobbyAddress(lobby.to_string()));
+    }
+    if let Some(path) = flags
+        .iter()
+        .find_map(|flag| flag.strip_prefix("--puzzles="))
+    {
+        let pack = PuzzlePack::load(Path::new(path)).unwrap_or_else(|err| {
+            eprintln!("Invalid puzzle pack: {err}");
+            process::exit(1);
+        });
+        app.insert_resource(pack);
+    }
+    if let Some(address) = &config.address {
+        app.insert_resource(MenuAddress::new(address.clone()));
+    }
+    if flags.iter().any(|flag| flag == "--event-log") {
+        app.insert_resource(EventLog {
+            stdout: true,
             ..default()
-        },
-    ));
+        });
+    }
+    if let Some(recording) = recording {
+        app.insert_resource(recording);
+    }
+    if let Some(replay) = replay {
+        app.insert_resource(replay);
+    }
+    if let Some(time_control) = time_control {
+        app.insert_resource(Clocks::new(time_control));
+    }
+    if args.len() == 3 {
+        let connection_type = match args[1].as_str() {
+            "server" => ConnectionType::Server,
+            "client" | "watch" => ConnectionType::Client,
+            other => {
+                eprintln!("Invalid argument: {other}");
+                process::exit(1);
+            }
+        };
+        let player_color = match connection_type {
+            ConnectionType::Server => host_color.unwrap_or(connection_type.player_color()),
+            ConnectionType::Client => connection_type.player_color(),
+        };
+        let pending =
+            PendingConnection::start(transport, connection_type, args[2].clone(), player_color);
+        app.insert_resource(if args[1] == "watch" {
+            pending.watching()
+        } else {
+            pending
+        })
+        .insert_resource(MenuAddress::new(args[2].clone()))
+        .insert_state(AppState::Connecting);
+    }
+    app.run();
 }
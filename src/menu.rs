@@ -0,0 +1,481 @@
+use bevy::input::ButtonInput;
+use bevy::input::keyboard::KeyboardInput;
+use bevy::prelude::*;
+use hermanha_chess::Color as HermanhaColor;
+
+use crate::clock::Clocks;
+use crate::computer::{Computer, EXHIBITION_TIME_CONTROL};
+use crate::config::DefaultTimeControl;
+use crate::net::PendingConnection;
+use crate::puzzle::PuzzleSession;
+use crate::save::ResumableGame;
+use crate::tcp::ConnectionType;
+use crate::text_input::{self, TextEdit, TextInput};
+use crate::tls;
+use crate::transport::TransportKind;
+use crate::variant::{HANDICAP_TIME_CONTROL, Variant, VariantChosen};
+use crate::widgets;
+
+#[derive(States, Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AppState {
+    #[default]
+    Menu,
+    /// Waiting for the opponent to join or for the server to answer.
+    Connecting,
+    Playing,
+    /// Placing pieces for a local game that starts from a custom position.
+    Setup,
+    /// Listing the games hosted on the LAN.
+    FindingGames,
+    /// Browsing the open games in the lobby.
+    Lobby,
+    /// Practising square names on an empty board.
+    Coordinates,
+}
+
+/// Where a game on the board stands: under way, over with the result
+/// showing, or being stepped through afterwards. Only exists while
+/// `AppState::Playing`.
+#[derive(SubStates, Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[source(AppState = AppState::Playing)]
+pub enum PlayState {
+    #[default]
+    Ongoing,
+    GameOver,
+    Review,
+}
+
+/// Shown under the menu buttons, e.g. why the last connection failed.
+#[derive(Resource, Default)]
+pub struct MenuMessage(pub String);
+
+/// Longer than any address with a fingerprint after it.
+const ADDRESS_LEN: usize = 120;
+
+/// The address typed into the menu, used both to host and to join.
+#[derive(Resource)]
+pub struct MenuAddress(pub TextInput);
+
+impl MenuAddress {
+    pub fn new(address: String) -> Self {
+        let mut input = TextInput::new(ADDRESS_LEN, is_address_char, validate_address);
+        input.set_text(address);
+        MenuAddress(input)
+    }
+}
+
+impl Default for MenuAddress {
+    fn default() -> Self {
+        MenuAddress::new("127.0.0.1:8080".to_string())
+    }
+}
+
+fn is_address_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || ".:-[]#".contains(c)
+}
+
+/// `host:port`, with the host's fingerprint after a `#` for encrypted
+/// games.
+pub fn validate_address(text: &str) -> Result<(), String> {
+    let (address, fingerprint) = match text.split_once('#') {
+        Some((address, fingerprint)) => (address, Some(fingerprint)),
+        None => (text, None),
+    };
+    let Some((host, port)) = address.rsplit_once(':') else {
+        return Err("Type the address as host:port".to_string());
+    };
+    if host.is_empty() {
+        return Err("The address has no host".to_string());
+    }
+    port.parse::<u16>()
+        .map_err(|_| format!("Invalid port: {port}"))?;
+    if let Some(fingerprint) = fingerprint {
+        tls::parse_fingerprint(fingerprint)?;
+    }
+    Ok(())
+}
+
+#[derive(Component)]
+pub struct MenuRoot;
+
+#[derive(Component, Clone, Copy)]
+pub enum MenuButton {
+    Local,
+    Computer,
+    Exhibition,
+    Host,
+    Join,
+    Watch,
+    FindLan,
+    Lobby,
+    Setup,
+    Coordinates,
+    Puzzles,
+    Variant,
+    Handicap,
+    Transport,
+}
+
+impl MenuButton {
+    fn label(self) -> &'static str {
+        match self {
+            MenuButton::Local => "Local two-player",
+            MenuButton::Computer => "Play vs Computer",
+            MenuButton::Exhibition => "Engine vs engine",
+            MenuButton::Host => "Host online game",
+            MenuButton::Join => "Join online game",
+            MenuButton::Watch => "Watch online game",
+            MenuButton::FindLan => "Find games on LAN",
+            MenuButton::Lobby => "Browse lobby",
+            MenuButton::Setup => "Set up position",
+            MenuButton::Coordinates => "Coordinate training",
+            MenuButton::Puzzles => "Solve puzzles",
+            MenuButton::Variant => "Switch variant",
+            MenuButton::Handicap => "Coach vs student",
+            MenuButton::Transport => "Switch transport",
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct AddressText;
+
+#[derive(Component)]
+pub struct MenuStatus;
+
+#[derive(Component)]
+pub struct VariantText;
+
+#[derive(Component)]
+pub struct TransportText;
+
+/// Only shown when there is a saved game that isn't over yet.
+#[derive(Component)]
+pub struct ResumeButton;
+
+pub fn spawn_menu(
+    mut commands: Commands,
+    address: Res<MenuAddress>,
+    message: Res<MenuMessage>,
+    variant: Res<Variant>,
+    transport: Res<TransportKind>,
+    resumable: Res<ResumableGame>,
+) {
+    commands
+        .spawn((MenuRoot, widgets::screen(widgets::SCREEN_COLOR, 12.0, 5)))
+        .with_children(|parent| {
+            parent.spawn(widgets::title("Chess", 40.0));
+            if let Some(saved) = &resumable.0 {
+                parent.spawn((
+                    ResumeButton,
+                    widgets::colored_button(
+                        widgets::button_node(260.0, 44.0),
+                        widgets::ACCENT_BUTTON_COLOR,
+                        widgets::label(format!(
+                            "Resume saved game (move {})",
+                            saved.moves.len() / 2 + 1
+                        )),
+                    ),
+                ));
+            }
+            for button in [
+                MenuButton::Local,
+                MenuButton::Computer,
+                MenuButton::Exhibition,
+                MenuButton::Setup,
+                MenuButton::Coordinates,
+                MenuButton::Puzzles,
+                MenuButton::Host,
+                MenuButton::Join,
+                MenuButton::Watch,
+                MenuButton::FindLan,
+                MenuButton::Lobby,
+                MenuButton::Variant,
+                MenuButton::Handicap,
+                MenuButton::Transport,
+            ] {
+                parent.spawn((
+                    button,
+                    widgets::button(
+                        widgets::button_node(260.0, 44.0),
+                        widgets::label(button.label()),
+                    ),
+                ));
+            }
+            parent.spawn((VariantText, Text::new(variant.label())));
+            parent.spawn((TransportText, Text::new(transport.label())));
+            parent.spawn(widgets::small_label("Address (type to edit)"));
+            parent.spawn((AddressText, Text::new(address.0.with_cursor())));
+            parent.spawn((
+                MenuStatus,
+                Text::new(message.0.clone()),
+                TextColor(Color::srgb(0.9, 0.4, 0.4)),
+            ));
+        });
+}
+
+pub fn despawn_menu(mut commands: Commands, menus: Query<Entity, With<MenuRoot>>) {
+    for entity in menus.iter() {
+        commands.entity(entity).despawn();
+    }
+}
+
+/// The variant button switches between standard chess and Chess960 from
+/// a freshly picked start position, and the handicap button steps through
+/// the "Coach vs student" levels, which set up both the material and the
+/// time odds. A hosted game is played in the variant chosen here; a joined
+/// one in whatever the host chose. The
+/// transport button cycles through plain TCP, encrypted TCP and WebSocket,
+/// which both sides have to agree on. "Engine vs engine" has the
+/// computer play both sides, on the default time control or a fixed one
+/// if there is none.
+#[allow(clippy::too_many_arguments)]
+pub fn handle_menu_buttons(
+    mut commands: Commands,
+    interactions: Query<(&Interaction, &MenuButton), Changed<Interaction>>,
+    address: Res<MenuAddress>,
+    variant: Res<Variant>,
+    time_control: Res<DefaultTimeControl>,
+    mut transport: ResMut<TransportKind>,
+    mut variants: EventWriter<VariantChosen>,
+    mut next_state: ResMut<NextState<AppState>>,
+    mut message: ResMut<MenuMessage>,
+) {
+    for (interaction, button) in interactions.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        let (connection_type, watching) = match button {
+            MenuButton::Local => {
+                next_state.set(AppState::Playing);
+                return;
+            }
+            MenuButton::Computer => {
+                commands.insert_resource(Computer::Plays(HermanhaColor::Black));
+                next_state.set(AppState::Playing);
+                return;
+            }
+            MenuButton::Exhibition => {
+                commands.insert_resource(Computer::Both);
+                if time_control.0.is_none() {
+                    commands.insert_resource(Clocks::new(EXHIBITION_TIME_CONTROL));
+                }
+                next_state.set(AppState::Playing);
+                return;
+            }
+            MenuButton::Setup => {
+                next_state.set(AppState::Setup);
+                return;
+            }
+            MenuButton::Coordinates => {
+                next_state.set(AppState::Coordinates);
+                return;
+            }
+            MenuButton::Puzzles => {
+                commands.insert_resource(PuzzleSession::default());
+                next_state.set(AppState::Playing);
+                return;
+            }
+            MenuButton::FindLan => {
+                message.0.clear();
+                next_state.set(AppState::FindingGames);
+                return;
+            }
+            MenuButton::Lobby => {
+                message.0.clear();
+                next_state.set(AppState::Lobby);
+                return;
+            }
+            MenuButton::Variant => {
+                let next = match *variant {
+                    Variant::Standard => Variant::random_chess960(),
+                    Variant::Chess960(_) | Variant::Handicap(_) => Variant::Standard,
+                };
+                handicap_clocks(&mut commands, &time_control, *variant, next);
+                variants.write(VariantChosen(next));
+                continue;
+            }
+            MenuButton::Handicap => {
+                let next = variant.next_handicap();
+                handicap_clocks(&mut commands, &time_control, *variant, next);
+                variants.write(VariantChosen(next));
+                continue;
+            }
+            MenuButton::Transport => {
+                *transport = match *transport {
+                    TransportKind::Tcp => TransportKind::Tls,
+                    TransportKind::Tls => TransportKind::WebSocket,
+                    TransportKind::WebSocket => TransportKind::Tcp,
+                };
+                continue;
+            }
+            MenuButton::Host => (ConnectionType::Server, false),
+            MenuButton::Join => (ConnectionType::Client, false),
+            MenuButton::Watch => (ConnectionType::Client, true),
+        };
+        if let Err(err) = address.0.validate() {
+            message.0 = err;
+            continue;
+        }
+        message.0.clear();
+        let pending = PendingConnection::start(
+            *transport,
+            connection_type,
+            address.0.text.clone(),
+            connection_type.player_color(),
+        );
+        commands.insert_resource(if watching {
+            pending.watching()
+        } else {
+            pending
+        });
+        next_state.set(AppState::Connecting);
+    }
+}
+
+/// Without a default time control, a handicap game brings clocks of its
+/// own to give the time odds on, which go again along with the handicap.
+fn handicap_clocks(
+    commands: &mut Commands,
+    time_control: &DefaultTimeControl,
+    from: Variant,
+    to: Variant,
+) {
+    if time_control.0.is_some() {
+        return;
+    }
+    match (from, to) {
+        (_, Variant::Handicap(_)) => commands.insert_resource(Clocks::with_time_odds(
+            HANDICAP_TIME_CONTROL,
+            to.time_odds(),
+        )),
+        (Variant::Handicap(_), _) => commands.remove_resource::<Clocks>(),
+        _ => {}
+    }
+}
+
+/// Typing on the menu edits the address. Enter checks it, so a mistake
+/// shows before connecting.
+pub fn edit_address(
+    mut keyboard: EventReader<KeyboardInput>,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut address: ResMut<MenuAddress>,
+    mut message: ResMut<MenuMessage>,
+) {
+    let ctrl = text_input::ctrl_held(&keys);
+    for event in keyboard.read() {
+        if !event.state.is_pressed() {
+            continue;
+        }
+        match address
+            .bypass_change_detection()
+            .0
+            .edit(&event.logical_key, ctrl)
+        {
+            TextEdit::Unchanged | TextEdit::Cancelled => {}
+            TextEdit::Changed => address.set_changed(),
+            TextEdit::Submitted => message.0.clear(),
+            TextEdit::Invalid(err) => message.0 = err,
+        }
+    }
+}
+
+pub fn render_address(address: Res<MenuAddress>, mut texts: Query<&mut Text, With<AddressText>>) {
+    if !address.is_changed() {
+        return;
+    }
+    for mut text in texts.iter_mut() {
+        text.0 = address.0.with_cursor();
+    }
+}
+
+pub fn render_menu_message(
+    message: Res<MenuMessage>,
+    mut texts: Query<&mut Text, With<MenuStatus>>,
+) {
+    if !message.is_changed() {
+        return;
+    }
+    for mut text in texts.iter_mut() {
+        text.0 = message.0.clone();
+    }
+}
+
+pub fn render_variant(variant: Res<Variant>, mut texts: Query<&mut Text, With<VariantText>>) {
+    if !variant.is_changed() {
+        return;
+    }
+    for mut text in texts.iter_mut() {
+        text.0 = variant.label();
+    }
+}
+
+pub fn render_transport(
+    transport: Res<TransportKind>,
+    mut texts: Query<&mut Text, With<TransportText>>,
+) {
+    if !transport.is_changed() {
+        return;
+    }
+    for mut text in texts.iter_mut() {
+        text.0 = transport.label().to_string();
+    }
+}
+
+#[derive(Component)]
+pub struct WaitingScreen;
+
+#[derive(Component)]
+pub struct CancelButton;
+
+/// An encrypted host also shows the fingerprint the opponent has to type
+/// after the address.
+pub fn spawn_waiting_screen(
+    mut commands: Commands,
+    address: Res<MenuAddress>,
+    transport: Res<TransportKind>,
+    pending: Option<Res<PendingConnection>>,
+) {
+    let hosting = pending.is_some_and(|pending| pending.hosting());
+    let fingerprint = if hosting && *transport == TransportKind::Tls {
+        format!("Fingerprint: {}", tls::host_fingerprint())
+    } else {
+        String::new()
+    };
+    commands.spawn((
+        WaitingScreen,
+        widgets::screen(widgets::SCREEN_COLOR, 12.0, 5),
+        children![
+            widgets::label("Waiting for opponent\u{2026}"),
+            widgets::small_label(address.0.text.clone()),
+            widgets::small_label(fingerprint),
+            (
+                CancelButton,
+                widgets::button(widgets::button_node(160.0, 40.0), widgets::label("Cancel")),
+            ),
+        ],
+    ));
+}
+
+pub fn despawn_waiting_screen(mut commands: Commands, screens: Query<Entity, With<WaitingScreen>>) {
+    for entity in screens.iter() {
+        commands.entity(entity).despawn();
+    }
+}
+
+/// Going back to the menu drops the pending connection. A server thread
+/// still blocked in `accept` is left to finish on its own; its result is
+/// simply never read.
+pub fn handle_cancel_button(
+    mut commands: Commands,
+    interactions: Query<&Interaction, (Changed<Interaction>, With<CancelButton>)>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    if interactions
+        .iter()
+        .any(|interaction| *interaction == Interaction::Pressed)
+    {
+        commands.remove_resource::<PendingConnection>();
+        next_state.set(AppState::Menu);
+    }
+}
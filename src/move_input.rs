@@ -0,0 +1,162 @@
+use bevy::input::ButtonInput;
+use bevy::input::keyboard::KeyboardInput;
+use bevy::prelude::*;
+
+use crate::actions::{self, Action};
+use crate::game_state::{
+    BoardState, Castling, GameOutcome, LocalPlayer, MoveOrigin, MoveRequested, SelectedSquare,
+};
+use crate::offers::Concluded;
+use crate::san;
+use crate::text_input::{self, TextEdit, TextInput};
+use crate::ui::GameUi;
+
+/// Longer than any move, in SAN or UCI.
+const MAX_LEN: usize = 12;
+
+/// The move being typed into the command box, and why the last one wasn't
+/// played. While `open` is set the box owns the keyboard.
+#[derive(Resource)]
+pub struct MoveInput {
+    open: bool,
+    field: TextInput,
+    error: String,
+}
+
+impl Default for MoveInput {
+    fn default() -> Self {
+        MoveInput {
+            open: false,
+            field: TextInput::new(MAX_LEN, is_move_char, text_input::always_valid),
+            error: String::new(),
+        }
+    }
+}
+
+fn is_move_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "=+#-".contains(c)
+}
+
+impl MoveInput {
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+}
+
+#[derive(Component)]
+pub struct MoveInputPanel;
+
+pub fn spawn_move_input(mut commands: Commands) {
+    commands.spawn((
+        MoveInputPanel,
+        GameUi,
+        Text::new(""),
+        TextFont {
+            font_size: 14.0,
+            ..default()
+        },
+        Node {
+            position_type: PositionType::Absolute,
+            left: Val::Px(8.0),
+            bottom: Val::Px(48.0),
+            min_width: Val::Px(160.0),
+            padding: UiRect::all(Val::Px(6.0)),
+            display: Display::None,
+            ..default()
+        },
+        BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.6)),
+    ));
+}
+
+/// "/" opens the box. Enter plays the typed move if it is legal and ours
+/// to play, and otherwise keeps the box open with the reason; Escape
+/// closes it. Like chat, the typing is kept from the rest of the app.
+/// Runs in `PreUpdate`, after chat and the name field have had their keys.
+#[allow(clippy::too_many_arguments)]
+pub fn type_move(
+    mut keyboard: EventReader<KeyboardInput>,
+    mut keys: ResMut<ButtonInput<KeyCode>>,
+    mut input: ResMut<MoveInput>,
+    board: Res<BoardState>,
+    castling: Res<Castling>,
+    local_player: Res<LocalPlayer>,
+    outcome: Res<GameOutcome>,
+    concluded: Res<Concluded>,
+    mut selected: ResMut<SelectedSquare>,
+    mut requests: EventWriter<MoveRequested>,
+) {
+    if !input.open {
+        if actions::just_pressed(&keys, Action::TypeMove) {
+            input.open = true;
+            input.error.clear();
+            keys.reset_all();
+        }
+        keyboard.clear();
+        return;
+    }
+    let ctrl = text_input::ctrl_held(&keys);
+    for event in keyboard.read() {
+        if !event.state.is_pressed() {
+            continue;
+        }
+        match input.field.edit(&event.logical_key, ctrl) {
+            TextEdit::Unchanged | TextEdit::Changed | TextEdit::Invalid(_) => {}
+            TextEdit::Submitted => {
+                let result = if outcome.0.is_some() || concluded.0.is_some() {
+                    Err("The game is over".to_string())
+                } else if !local_player.controls(board.move_turn) {
+                    Err("It's not your move".to_string())
+                } else {
+                    san::parse_move(&board.0, castling.0, &input.field.text)
+                };
+                match result {
+                    Ok((from, to, promotion_piece)) => {
+                        selected.0 = None;
+                        requests.write(MoveRequested {
+                            from,
+                            to,
+                            promotion_piece,
+                            origin: MoveOrigin::Local,
+                        });
+                        input.open = false;
+                        input.field.clear();
+                        input.error.clear();
+                    }
+                    Err(err) => input.error = err,
+                }
+            }
+            TextEdit::Cancelled => {
+                input.open = false;
+                input.field.clear();
+                input.error.clear();
+            }
+        }
+    }
+    keys.reset_all();
+}
+
+pub fn render_move_input(
+    input: Res<MoveInput>,
+    mut panels: Query<(&mut Text, &mut Node), With<MoveInputPanel>>,
+) {
+    if !input.is_changed() {
+        return;
+    }
+    let mut text = format!("Move: {}", input.field.with_cursor());
+    if !input.error.is_empty() {
+        text.push('\n');
+        text.push_str(&input.error);
+    }
+    for (mut panel, mut node) in panels.iter_mut() {
+        panel.0 = text.clone();
+        node.display = if input.open {
+            Display::Flex
+        } else {
+            Display::None
+        };
+    }
+}
+
+pub fn close_move_input(mut input: ResMut<MoveInput>) {
+    *input = MoveInput::default();
+}
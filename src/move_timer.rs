@@ -0,0 +1,113 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+
+use crate::game_over::GameEnded;
+use crate::game_state::{BoardState, GameOutcome, PlayerColor};
+use crate::history::MoveHistory;
+use crate::offers::{Concluded, Conclusion};
+use crate::toast::Toasts;
+
+/// Seconds left on the move at which the players are warned.
+const WARN_AT_SECS: [u64; 2] = [30, 10];
+
+/// How much longer than the limit we wait for the opponent before calling
+/// the game, so a move sent just in time still gets here.
+const GRACE: Duration = Duration::from_secs(3);
+
+/// The limit on every move of the online games we host, set with
+/// `--move-timeout=SECS`. The client plays with whatever the host's
+/// handshake says instead.
+#[derive(Resource, Default)]
+pub struct MoveTimeout(pub Option<Duration>);
+
+/// The per-move limit of the online game under way, there only when it has
+/// one. Unlike the clocks it starts over with every move, and going over it
+/// loses the game at once. Both sides time the moves themselves.
+#[derive(Resource)]
+pub struct MoveTimer {
+    pub limit: Duration,
+    /// The ply the time is being counted for.
+    ply: u32,
+    elapsed: Duration,
+    /// How many of `WARN_AT_SECS` have been shown for this move.
+    warned: usize,
+}
+
+impl MoveTimer {
+    pub fn new(limit: Duration) -> Self {
+        MoveTimer {
+            limit,
+            ply: 0,
+            elapsed: Duration::ZERO,
+            warned: 0,
+        }
+    }
+
+    /// Counts the move under way from zero again.
+    pub fn restart(&mut self) {
+        *self = MoveTimer::new(self.limit);
+    }
+
+    pub fn remaining(&self) -> Duration {
+        self.limit.saturating_sub(self.elapsed)
+    }
+}
+
+/// A new game starts the count over, as does a rematch.
+pub fn restart_move_timer(mut timer: ResMut<MoveTimer>) {
+    timer.restart();
+}
+
+/// Counts the time taken over the move under way, warning the players at
+/// 30 and 10 seconds left. A player who goes over the limit forfeits: on
+/// their own screen right away, and on the opponent's after `GRACE`.
+#[allow(clippy::too_many_arguments)]
+pub fn tick_move_timer(
+    time: Res<Time>,
+    board: Res<BoardState>,
+    history: Res<MoveHistory>,
+    player_color: Res<PlayerColor>,
+    outcome: Res<GameOutcome>,
+    mut concluded: ResMut<Concluded>,
+    mut timer: ResMut<MoveTimer>,
+    mut toasts: ResMut<Toasts>,
+    mut ended: EventWriter<GameEnded>,
+) {
+    if concluded.0.is_some() || outcome.0.is_some() {
+        return;
+    }
+    let ply_count = history.ply_count();
+    if ply_count != timer.ply {
+        timer.restart();
+        timer.ply = ply_count;
+    }
+    timer.elapsed += time.delta();
+    let to_move = board.0.move_turn;
+    let ours = to_move == player_color.0;
+    while let Some(&secs) = WARN_AT_SECS.get(timer.warned) {
+        if timer.remaining() > Duration::from_secs(secs) {
+            break;
+        }
+        timer.warned += 1;
+        // A limit shorter than the warning has nothing to warn about.
+        if timer.limit <= Duration::from_secs(secs) {
+            continue;
+        }
+        if ours {
+            toasts.push(format!("{secs} seconds left to move"));
+        } else {
+            toasts.push(format!("Opponent has {secs} seconds left to move"));
+        }
+    }
+    let allowed = if ours {
+        timer.limit
+    } else {
+        timer.limit + GRACE
+    };
+    if timer.elapsed >= allowed {
+        info!("The player to move went over the move timeout");
+        concluded.0 = Some(Conclusion::MoveTimedOut(to_move));
+        ended.write(GameEnded);
+    }
+}
@@ -0,0 +1,242 @@
+use bevy::prelude::*;
+use bevy::sprite::Anchor;
+use hermanha_chess::{BOARD_ROWS, Color as HermanhaColor};
+
+use crate::TILE_SIZE;
+use crate::board_render::Upright;
+use crate::clock::{self, Clocks};
+use crate::computer::Computer;
+use crate::game_state::{BoardState, LocalPlayer};
+use crate::menu::PlayState;
+use crate::net::{Opponent, PlayerName};
+use crate::relay::WatchedPlayers;
+use crate::ui::GameUi;
+
+const PLATE_WIDTH: f32 = TILE_SIZE * 5.0;
+const PLATE_HEIGHT: f32 = TILE_SIZE * 0.6;
+const AVATAR_SIZE: f32 = PLATE_HEIGHT * 0.8;
+const PLATE_Z: f32 = 2.0;
+
+const PLATE_COLOR: Color = Color::srgba(0.1, 0.1, 0.1, 0.8);
+const TO_MOVE_COLOR: Color = Color::srgba(0.2, 0.45, 0.25, 0.9);
+
+/// A player's plate at their edge of the board: an avatar, their name and
+/// color, their clock, and whether it's their move.
+#[derive(Component)]
+pub struct NamePlate(pub HermanhaColor);
+
+#[derive(Component)]
+pub struct PlateAvatar;
+
+#[derive(Component)]
+pub struct PlateInitial;
+
+#[derive(Component)]
+pub struct PlateName;
+
+#[derive(Component)]
+pub struct PlateClock;
+
+#[derive(Component)]
+pub struct PlateTurn;
+
+fn side(color: HermanhaColor) -> &'static str {
+    match color {
+        HermanhaColor::White => "White",
+        HermanhaColor::Black => "Black",
+    }
+}
+
+/// Who plays `color`: the players the host relayed when spectating, the
+/// computer, the name from the settings for our own side, and the name
+/// from the opponent's handshake for theirs. In a local game for two the
+/// settings name goes to White.
+pub fn plate_name(
+    color: HermanhaColor,
+    local_player: &LocalPlayer,
+    own_name: &str,
+    opponent: Option<&Opponent>,
+    watched: Option<&WatchedPlayers>,
+    computer: Option<Computer>,
+) -> String {
+    if let Some(players) = watched {
+        return match color {
+            HermanhaColor::White => players.white.clone(),
+            HermanhaColor::Black => players.black.clone(),
+        };
+    }
+    if computer.is_some_and(|computer| computer.plays(color)) {
+        return "Computer".to_string();
+    }
+    match local_player {
+        LocalPlayer::Both if color == HermanhaColor::Black => "Guest".to_string(),
+        LocalPlayer::Both => own_name.to_string(),
+        LocalPlayer::One(own) if *own == color => own_name.to_string(),
+        LocalPlayer::One(_) => opponent.map_or_else(
+            || "Waiting for opponent...".to_string(),
+            |opponent| opponent.name.clone(),
+        ),
+        LocalPlayer::Watching => side(color).to_string(),
+    }
+}
+
+/// A color of its own for every name, so the placeholder avatars are told
+/// apart.
+fn avatar_color(name: &str) -> Color {
+    let hue = name.bytes().fold(0u32, |hash, byte| {
+        hash.wrapping_mul(31).wrapping_add(byte as u32)
+    }) % 360;
+    Color::hsl(hue as f32, 0.45, 0.45)
+}
+
+/// White's plate goes under White's edge of the board and Black's over
+/// Black's, in the margin the window leaves around the board, so they
+/// follow the board when it's flipped.
+pub fn spawn_name_plates(mut commands: Commands) {
+    let edge = BOARD_ROWS as f32 * 0.5 * TILE_SIZE + PLATE_HEIGHT * 0.5 + TILE_SIZE * 0.05;
+    for (color, y) in [(HermanhaColor::White, -edge), (HermanhaColor::Black, edge)] {
+        commands.spawn((
+            NamePlate(color),
+            GameUi,
+            Upright,
+            Sprite::from_color(PLATE_COLOR, Vec2::new(PLATE_WIDTH, PLATE_HEIGHT)),
+            Transform::from_xyz(0.0, y, PLATE_Z),
+            children![
+                (
+                    PlateAvatar,
+                    Sprite::from_color(Color::NONE, Vec2::splat(AVATAR_SIZE)),
+                    Transform::from_xyz(-PLATE_WIDTH * 0.5 + PLATE_HEIGHT * 0.5, 0.0, 0.1),
+                    children![(
+                        PlateInitial,
+                        Text2d::new(""),
+                        TextFont {
+                            font_size: 16.0,
+                            ..default()
+                        },
+                        Transform::from_xyz(0.0, 0.0, 0.1),
+                    )],
+                ),
+                (
+                    PlateName,
+                    Text2d::new(""),
+                    TextFont {
+                        font_size: 16.0,
+                        ..default()
+                    },
+                    Anchor::CenterLeft,
+                    Transform::from_xyz(-PLATE_WIDTH * 0.5 + PLATE_HEIGHT * 1.1, 0.0, 0.1),
+                ),
+                (
+                    PlateTurn,
+                    Text2d::new("to move"),
+                    TextFont {
+                        font_size: 13.0,
+                        ..default()
+                    },
+                    TextColor(Color::srgb(1.0, 0.85, 0.55)),
+                    Anchor::CenterRight,
+                    Transform::from_xyz(PLATE_WIDTH * 0.5 - TILE_SIZE * 1.3, 0.0, 0.1),
+                    Visibility::Hidden,
+                ),
+                (
+                    PlateClock,
+                    Text2d::new(""),
+                    TextFont {
+                        font_size: 18.0,
+                        ..default()
+                    },
+                    Anchor::CenterRight,
+                    Transform::from_xyz(PLATE_WIDTH * 0.5 - TILE_SIZE * 0.15, 0.0, 0.1),
+                ),
+            ],
+        ));
+    }
+}
+
+/// Keeps the names, avatars and clocks up to date and lights up the plate
+/// of the player to move while the game goes on.
+#[allow(clippy::too_many_arguments)]
+pub fn render_name_plates(
+    board: Res<BoardState>,
+    play_state: Res<State<PlayState>>,
+    local_player: Res<LocalPlayer>,
+    player_name: Res<PlayerName>,
+    opponent: Option<Res<Opponent>>,
+    watched: Option<Res<WatchedPlayers>>,
+    computer: Option<Res<Computer>>,
+    clocks: Option<Res<Clocks>>,
+    mut plates: Query<(Entity, &NamePlate, &mut Sprite), Without<PlateAvatar>>,
+    mut avatars: Query<&mut Sprite, With<PlateAvatar>>,
+    children: Query<&Children>,
+    mut texts: Query<(
+        &mut Text2d,
+        &mut Visibility,
+        Has<PlateInitial>,
+        Has<PlateName>,
+        Has<PlateClock>,
+        Has<PlateTurn>,
+    )>,
+) {
+    for (entity, plate, mut background) in plates.iter_mut() {
+        let color = plate.0;
+        let name = plate_name(
+            color,
+            &local_player,
+            &player_name.0,
+            opponent.as_deref(),
+            watched.as_deref(),
+            computer.as_deref().copied(),
+        );
+        let to_move = board.0.move_turn == color && *play_state.get() == PlayState::Ongoing;
+        let plate_color = if to_move { TO_MOVE_COLOR } else { PLATE_COLOR };
+        if background.color != plate_color {
+            background.color = plate_color;
+        }
+        let initial = name
+            .chars()
+            .next()
+            .map(|initial| initial.to_uppercase().to_string())
+            .unwrap_or_default();
+        let label = format!("{name} ({})", side(color));
+        let clock = clocks
+            .as_ref()
+            .map(|clocks| clock::format_duration(clocks.remaining(color)))
+            .unwrap_or_default();
+        for descendant in children.iter_descendants(entity) {
+            if let Ok(mut avatar) = avatars.get_mut(descendant) {
+                let color = avatar_color(&name);
+                if avatar.color != color {
+                    avatar.color = color;
+                }
+                continue;
+            }
+            let Ok((mut text, mut visibility, is_initial, is_name, is_clock, is_turn)) =
+                texts.get_mut(descendant)
+            else {
+                continue;
+            };
+            let wanted = if is_initial {
+                &initial
+            } else if is_name {
+                &label
+            } else if is_clock {
+                &clock
+            } else {
+                if is_turn {
+                    let shown = if to_move {
+                        Visibility::Inherited
+                    } else {
+                        Visibility::Hidden
+                    };
+                    if *visibility != shown {
+                        *visibility = shown;
+                    }
+                }
+                continue;
+            };
+            if text.0 != *wanted {
+                text.0 = wanted.clone();
+            }
+        }
+    }
+}
@@ -0,0 +1,1433 @@
+use std::collections::HashMap;
+use std::collections::hash_map::{Entry, RandomState};
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+use bevy::ecs::system::SystemParam;
+use bevy::input::ButtonInput;
+use bevy::prelude::*;
+use crossbeam_channel::{Receiver, TryRecvError, bounded};
+use hermanha_chess::Color as HermanhaColor;
+
+use crate::actions::{self, Action, ActiveScopes};
+use crate::chat::{ChatLog, OutgoingChat};
+use crate::clipboard::{self, GameRecord};
+use crate::clock::Clocks;
+use crate::discovery::{self, BeaconSender};
+use crate::eval_graph::{Evaluations, SharedReview};
+use crate::game_over::{GameEnded, RematchOffers};
+use crate::game_state::{
+    BoardState, Castling, GameOutcome, LocalPlayer, MoveOrigin, MovePlayed, MoveRequested,
+    PlayerColor,
+};
+use crate::history::MoveHistory;
+use crate::menu::{AppState, MenuMessage, PlayState};
+use crate::move_timer::{self, MoveTimeout, MoveTimer};
+use crate::net_status::{self, NetMonitor, NetStatus, StallTimeout};
+use crate::offers::{Concluded, Conclusion, DrawOffers, GameAction};
+use crate::relay::{self, WatchedPlayers};
+use crate::replay::Recording;
+use crate::tcp::{
+    ChatMessage, ChunkMessage, ChunkStatus, ConnectionType, DrawAction, DrawMessage, HelloMessage,
+    IncomingTransfer, Message, MoveMessage, OutgoingTransfer, PROTOCOL_VERSION, PongMessage,
+    ProtocolError, QuitMessage, RematchMessage, ResignMessage, ResyncMessage, SyncMessage,
+    TcpError, board_to_fen,
+};
+use crate::toast::Toasts;
+use crate::transport::{Transport, TransportKind};
+use crate::ui::GameUi;
+use crate::variant::{HANDICAP_TIME_CONTROL, Variant, VariantChosen};
+use crate::{GameSet, SpawnGameUi, fen, pgn, rules, zobrist};
+
+/// Owns the TCP connection to the opponent. Every frame it drains incoming
+/// messages, requesting the opponent's moves with `MoveRequested`, and
+/// sends a `MoveMessage` for every local `MovePlayed` and a draw or resign
+/// message for every `GameAction`. Pings measure the connection for the
+/// network HUD. A waiting host announces itself on the LAN.
+pub struct NetworkPlugin;
+
+impl Plugin for NetworkPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Transfers>()
+            .init_resource::<Desync>()
+            .init_resource::<NetStatus>()
+            .init_resource::<StallTimeout>()
+            .init_resource::<TransportKind>()
+            .init_resource::<MoveTimeout>()
+            .add_event::<GameAction>()
+            .add_event::<OutgoingChat>()
+            .add_systems(
+                Update,
+                poll_pending_connection
+                    .run_if(resource_exists::<PendingConnection>)
+                    .run_if(in_state(AppState::Connecting)),
+            )
+            .add_systems(
+                OnEnter(AppState::Connecting),
+                discovery::start_beacon.run_if(resource_exists::<PendingConnection>),
+            )
+            .add_systems(
+                Update,
+                discovery::broadcast_beacon
+                    .run_if(resource_exists::<BeaconSender>)
+                    .run_if(in_state(AppState::Connecting)),
+            )
+            .add_systems(OnExit(AppState::Connecting), discovery::stop_beacon)
+            .add_systems(
+                OnEnter(AppState::Playing),
+                send_hello
+                    .run_if(resource_exists::<Connection>)
+                    .run_if(not(resource_exists::<Spectating>)),
+            )
+            .add_systems(
+                SpawnGameUi,
+                (
+                    spawn_opponent_label,
+                    spawn_spectator_label,
+                    spawn_waiting_indicator,
+                    net_status::spawn_net_hud,
+                )
+                    .run_if(resource_exists::<Connection>),
+            )
+            .add_systems(
+                OnExit(AppState::Playing),
+                leave_game.run_if(resource_exists::<Connection>),
+            )
+            .add_systems(
+                Update,
+                net_status::render_net_hud
+                    .in_set(GameSet::Render)
+                    .run_if(in_state(AppState::Playing)),
+            )
+            .add_systems(
+                Update,
+                render_opponent_label
+                    .in_set(GameSet::Render)
+                    .run_if(resource_exists::<Opponent>)
+                    .run_if(in_state(AppState::Playing)),
+            )
+            .add_systems(
+                Update,
+                (
+                    receive_messages.in_set(GameSet::Input),
+                    (
+                        send_played_moves,
+                        send_game_actions,
+                        send_chat,
+                        send_pgn.run_if(not(resource_exists::<Spectating>)),
+                        share_review
+                            .run_if(not(resource_exists::<Spectating>))
+                            .run_if(in_state(PlayState::Review)),
+                        expire_transfers,
+                        net_status::ping_opponent,
+                        relay::relay_to_spectators,
+                    )
+                        .in_set(GameSet::Rules),
+                    (
+                        render_spectator_label,
+                        render_desync_warning,
+                        render_waiting_indicator,
+                    )
+                        .in_set(GameSet::Render),
+                )
+                    .run_if(resource_exists::<Connection>)
+                    .run_if(in_state(AppState::Playing)),
+            )
+            .add_systems(
+                OnEnter(PlayState::Ongoing),
+                move_timer::restart_move_timer.run_if(resource_exists::<MoveTimer>),
+            )
+            .add_systems(
+                Update,
+                move_timer::tick_move_timer
+                    .in_set(GameSet::Rules)
+                    .run_if(resource_exists::<MoveTimer>)
+                    .run_if(resource_exists::<Opponent>)
+                    .run_if(not(resource_exists::<Spectating>))
+                    .run_if(in_state(PlayState::Ongoing)),
+            )
+            .add_systems(
+                Update,
+                (reconnect, claim_abandoned_game)
+                    .run_if(resource_exists::<Reconnecting>)
+                    .run_if(in_state(AppState::Playing)),
+            )
+            .add_systems(
+                Last,
+                say_goodbye
+                    .run_if(resource_exists::<Connection>)
+                    .run_if(not(resource_exists::<Spectating>)),
+            );
+    }
+}
+
+#[derive(Resource)]
+pub struct Connection(pub Box<dyn Transport>);
+
+/// Where the connection goes, kept so a dropped connection can be set up
+/// again the same way.
+#[derive(Resource, Clone)]
+pub struct PeerAddress {
+    transport: TransportKind,
+    connection_type: ConnectionType,
+    address: String,
+}
+
+impl PeerAddress {
+    /// Connects, or for the server waits for the client, on a background
+    /// thread so the app keeps running meanwhile.
+    fn connect_in_background(&self) -> Receiver<io::Result<Box<dyn Transport>>> {
+        let (sender, result) = bounded(1);
+        let PeerAddress {
+            transport,
+            connection_type,
+            address,
+        } = self.clone();
+        thread::spawn(move || {
+            let _ = sender.send(transport.connect(connection_type, &address));
+        });
+        result
+    }
+}
+
+/// A connection being set up on a background thread, so waiting for an
+/// opponent to join doesn't freeze the app.
+#[derive(Resource)]
+pub struct PendingConnection {
+    peer: PeerAddress,
+    player_color: HermanhaColor,
+    result: Receiver<io::Result<Box<dyn Transport>>>,
+    watching: bool,
+}
+
+impl PendingConnection {
+    pub fn start(
+        transport: TransportKind,
+        connection_type: ConnectionType,
+        address: String,
+        player_color: HermanhaColor,
+    ) -> Self {
+        let peer = PeerAddress {
+            transport,
+            connection_type,
+            address,
+        };
+        PendingConnection {
+            result: peer.connect_in_background(),
+            peer,
+            player_color,
+            watching: false,
+        }
+    }
+
+    /// Joins only to watch: no handshake is sent and input stays locked,
+    /// as for a spectator the host turned away. The game has to have
+    /// started, or the host takes us for its opponent.
+    pub fn watching(mut self) -> Self {
+        self.watching = true;
+        self
+    }
+
+    /// Whether we are waiting for an opponent to join rather than joining.
+    pub fn hosting(&self) -> bool {
+        self.peer.connection_type == ConnectionType::Server
+    }
+
+    pub fn address(&self) -> &str {
+        &self.peer.address
+    }
+
+    /// Takes `transport` as the connection instead of the one being made,
+    /// as a replayed session does with its recorded messages.
+    pub fn resolve(&mut self, transport: Box<dyn Transport>) {
+        let (sender, result) = bounded(1);
+        let _ = sender.send(Ok(transport));
+        self.result = result;
+    }
+}
+
+/// Longest wait between reconnection attempts.
+const MAX_RECONNECT_DELAY_SECS: f32 = 30.0;
+
+/// How long an opponent who dropped out of a game under way has to come
+/// back before the game is claimed.
+const CLAIM_AFTER_SECS: f32 = 60.0;
+
+/// How long a host waiting for its opponent to come back gives whoever
+/// connects to say hello.
+const REJOIN_HELLO_SECS: f32 = 5.0;
+
+/// The host's token for the game under way, sent in its handshake. A
+/// client that lost the connection repeats it when it comes back, and the
+/// host lets no one else take its place.
+#[derive(Resource, Clone, Copy)]
+pub struct Session(pub u32);
+
+impl Session {
+    fn new() -> Self {
+        // `RandomState` is seeded randomly for every instance.
+        Session(RandomState::new().build_hasher().finish() as u32)
+    }
+}
+
+/// Present while a dropped connection is being set up again. The game
+/// state is kept as it was and input stays locked until the opponent's
+/// handshake arrives on the new connection.
+#[derive(Resource)]
+pub struct Reconnecting {
+    attempt: u32,
+    delay: Timer,
+    result: Option<Receiver<io::Result<Box<dyn Transport>>>>,
+    /// Counts down to claiming the game.
+    claim: Timer,
+    /// Who connected to the host, before their hello shows they are the
+    /// opponent coming back.
+    candidate: Option<Box<dyn Transport>>,
+    candidate_wait: Timer,
+}
+
+impl Reconnecting {
+    fn new() -> Self {
+        Reconnecting {
+            attempt: 0,
+            delay: Timer::from_seconds(0.0, TimerMode::Once),
+            result: None,
+            claim: Timer::from_seconds(CLAIM_AFTER_SECS, TimerMode::Once),
+            candidate: None,
+            candidate_wait: Timer::from_seconds(REJOIN_HELLO_SECS, TimerMode::Once),
+        }
+    }
+
+    /// Hangs up on the candidate and waits for someone else.
+    fn turn_away(&mut self, reason: &str) {
+        warn!("Turned away a connection while waiting for the opponent: {reason}");
+        if let Some(mut candidate) = self.candidate.take() {
+            send(
+                candidate.as_mut(),
+                Message::Quit(QuitMessage {
+                    message: Some("This game is waiting for another player".to_string()),
+                }),
+            );
+        }
+        self.delay = Timer::from_seconds(0.0, TimerMode::Once);
+        self.result = None;
+    }
+}
+
+/// What the host has heard from a candidate so far.
+enum Rejoin {
+    Waiting,
+    Accepted(HelloMessage),
+    Refused(&'static str),
+}
+
+/// Reads the candidate's messages up to its hello, which has to carry the
+/// game's session. What the client sends after it is left for
+/// `receive_messages`.
+fn read_rejoin(candidate: &mut dyn Transport, session: Option<&Session>) -> Rejoin {
+    loop {
+        match candidate.read() {
+            Ok(Message::Hello(hello)) => {
+                return match session {
+                    Some(session) if hello.session == Some(session.0) => Rejoin::Accepted(hello),
+                    _ => Rejoin::Refused("its hello didn't carry the game's session"),
+                };
+            }
+            Ok(_) | Err(TcpError::InvalidMessage(_)) => {}
+            Err(TcpError::WouldBlock) => return Rejoin::Waiting,
+            Err(TcpError::Io(_)) => return Rejoin::Refused("it hung up"),
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct ReconnectBanner;
+
+#[derive(Component)]
+pub struct ReconnectBannerText;
+
+/// The name sent to the opponent in the handshake, set with `--name=`.
+#[derive(Resource)]
+pub struct PlayerName(pub String);
+
+impl Default for PlayerName {
+    fn default() -> Self {
+        PlayerName("Player".to_string())
+    }
+}
+
+/// The other player, known once their handshake has arrived. Input stays
+/// locked until then, since the client only learns its color from it.
+#[derive(Resource)]
+pub struct Opponent {
+    pub name: String,
+    /// The protocol version their handshake announced.
+    pub version: u16,
+}
+
+#[derive(Component)]
+pub struct OpponentLabel;
+
+/// Why our board no longer matches the opponent's, if it doesn't. While
+/// set, input is locked and a warning is shown.
+#[derive(Resource, Default)]
+pub struct Desync(pub Option<String>);
+
+#[derive(Component)]
+pub struct DesyncWarning;
+
+/// Set when the server has told us we only get to watch. Spectators never
+/// get a handshake, so input stays locked.
+#[derive(Resource)]
+pub struct Spectating;
+
+#[derive(Component)]
+pub struct SpectatorLabel;
+
+/// Faint note shown while it's the opponent's turn.
+#[derive(Component)]
+pub struct WaitingIndicator;
+
+/// How long a transfer is kept once nothing more is heard of it: an
+/// incoming one with no new chunks, or an outgoing one nobody asks to have
+/// resent.
+const TRANSFER_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How many of the opponent's transfers are put together at once.
+const MAX_INCOMING_TRANSFERS: usize = 4;
+
+/// How many chunks the opponent's unfinished transfers may add up to,
+/// about 160 KB of data.
+const MAX_INCOMING_CHUNKS: usize = 4096;
+
+/// Bulk transfers in flight over the connection, keyed by transfer id.
+#[derive(Resource, Default)]
+pub struct Transfers {
+    incoming: HashMap<u16, IncomingTransfer>,
+    outgoing: HashMap<u16, OutgoingTransfer>,
+    next_id: u16,
+}
+
+impl Transfers {
+    pub fn send(&mut self, connection: &mut dyn Transport, data: &[u8]) -> Result<u16, String> {
+        let transfer_id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        let transfer = OutgoingTransfer::new(transfer_id, data).map_err(|err| err.to_string())?;
+        transfer
+            .send_from(connection, 0)
+            .map_err(|err| err.to_string())?;
+        self.outgoing.insert(transfer_id, transfer);
+        Ok(transfer_id)
+    }
+
+    /// Passes a chunk on to its transfer, starting the transfer if it's
+    /// new. A new transfer is refused when too many are in flight or their
+    /// chunks would add up to too many, and so are the rest of its chunks.
+    fn receive(&mut self, chunk: ChunkMessage) -> Option<ChunkStatus> {
+        let transfer_id = chunk.transfer_id;
+        let transfer = match self.incoming.entry(transfer_id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let chunks: usize = self
+                    .incoming
+                    .values()
+                    .map(|transfer| transfer.total() as usize)
+                    .sum();
+                if self.incoming.len() >= MAX_INCOMING_TRANSFERS
+                    || chunks + chunk.total as usize > MAX_INCOMING_CHUNKS
+                {
+                    if chunk.seq == 0 {
+                        warn!("Refused incoming transfer {transfer_id}");
+                    }
+                    return None;
+                }
+                entry.insert(IncomingTransfer::new(transfer_id, chunk.total))
+            }
+        };
+        let status = transfer.accept(chunk);
+        if let ChunkStatus::Complete(_) = status {
+            self.incoming.remove(&transfer_id);
+        }
+        Some(status)
+    }
+
+    /// Forgets the transfers that have gone quiet for `TRANSFER_TIMEOUT`.
+    fn expire(&mut self) {
+        self.incoming.retain(|transfer_id, transfer| {
+            let alive = transfer.idle_for() < TRANSFER_TIMEOUT;
+            if !alive {
+                warn!("Gave up on incoming transfer {transfer_id}");
+            }
+            alive
+        });
+        self.outgoing
+            .retain(|_, transfer| transfer.idle_for() < TRANSFER_TIMEOUT);
+    }
+}
+
+/// A finished incoming transfer: a game as PGN, which goes to the
+/// clipboard, or the opponent's review, which stands in for our own.
+fn transfer_received(
+    commands: &mut Commands,
+    toasts: &mut Toasts,
+    opponent: Option<&Opponent>,
+    data: Vec<u8>,
+) {
+    let len = data.len();
+    let name = opponent.map_or("The opponent", |opponent| opponent.name.as_str());
+    let text = match String::from_utf8(data) {
+        Ok(text) => text,
+        Err(_) => {
+            info!("Received a transfer of {len} bytes that isn't text");
+            return;
+        }
+    };
+    if let Some(review) = SharedReview::parse(&text) {
+        commands.insert_resource(review);
+        toasts.push(format!("{name} shared their review"));
+        return;
+    }
+    if !pgn::looks_like_pgn(&text) {
+        info!("Received a transfer of {len} bytes that isn't a game");
+        return;
+    }
+    match clipboard::write_text(text) {
+        Ok(()) => toasts.push(format!("{name} sent their game, copied to the clipboard")),
+        Err(err) => {
+            warn!("Could not write clipboard: {err}");
+            toasts.push(format!(
+                "{name} sent their game, but it could not be copied: {err}"
+            ));
+        }
+    }
+}
+
+/// The game on our side, which the opponent's messages are checked
+/// against. The clocks are the host's to keep; a client takes over the
+/// times in the host's syncs.
+#[derive(SystemParam)]
+struct OurGame<'w> {
+    board: Res<'w, BoardState>,
+    castling: Res<'w, Castling>,
+    history: Res<'w, MoveHistory>,
+    variant: Res<'w, Variant>,
+    outcome: Res<'w, GameOutcome>,
+    clocks: Option<ResMut<'w, Clocks>>,
+}
+
+/// White's and Black's remaining time for a sync, which only the host
+/// sends.
+fn sync_clocks(
+    connection: &dyn Transport,
+    clocks: Option<&Clocks>,
+) -> Option<(Duration, Duration)> {
+    if connection.connection_type() != ConnectionType::Server {
+        return None;
+    }
+    clocks.map(|clocks| {
+        (
+            clocks.remaining(HermanhaColor::White),
+            clocks.remaining(HermanhaColor::Black),
+        )
+    })
+}
+
+/// Where what the opponent says ends up: chat lines in the chat panel,
+/// and everything else as toasts.
+#[derive(SystemParam)]
+struct Inbox<'w> {
+    chat_log: ResMut<'w, ChatLog>,
+    toasts: ResMut<'w, Toasts>,
+    recording: Option<ResMut<'w, Recording>>,
+}
+
+/// The opponent's moves are checked on a copy of the board that follows
+/// the moves requested so far, so a position or ply count sent back in
+/// the same frame already includes them. The client plays whichever
+/// variant the server's handshake names.
+#[allow(clippy::too_many_arguments)]
+fn receive_messages(
+    mut connection: ResMut<Connection>,
+    mut game: OurGame,
+    mut transfers: ResMut<Transfers>,
+    mut requests: EventWriter<MoveRequested>,
+    mut variants: EventWriter<VariantChosen>,
+    mut commands: Commands,
+    mut player_color: ResMut<PlayerColor>,
+    mut offers: ResMut<DrawOffers>,
+    mut concluded: ResMut<Concluded>,
+    mut inbox: Inbox,
+    opponent: Option<Res<Opponent>>,
+    spectating: Option<Res<Spectating>>,
+    mut desync: ResMut<Desync>,
+    mut rematch: ResMut<RematchOffers>,
+    mut ended: EventWriter<GameEnded>,
+    mut monitor: NetMonitor,
+) {
+    let mut position = game.board.0.clone();
+    let mut castling = game.castling.0;
+    let mut ply_count = game.history.ply_count();
+    if monitor.peer_gone(opponent.as_deref()) {
+        warn!("Connection lost: the opponent stopped answering pings");
+        if let Some(recording) = &mut inbox.recording {
+            recording.lost();
+        }
+        connection_lost(&mut commands, spectating.is_some());
+        return;
+    }
+    loop {
+        let msg = match connection.0.read() {
+            Ok(msg) => msg,
+            Err(TcpError::WouldBlock) => return,
+            Err(TcpError::Io(err)) => {
+                warn!("Connection lost: {err}");
+                if let Some(recording) = &mut inbox.recording {
+                    recording.lost();
+                }
+                connection_lost(&mut commands, spectating.is_some());
+                return;
+            }
+            Err(TcpError::InvalidMessage(err)) => {
+                reject_message(&mut inbox.toasts, err);
+                continue;
+            }
+        };
+        monitor.received();
+        if let Some(recording) = &mut inbox.recording {
+            recording.message(&msg);
+        }
+        match msg {
+            Message::Move(move_msg) => {
+                let MoveMessage {
+                    from,
+                    to,
+                    promotion_piece,
+                    new_board,
+                    position_hash,
+                    ..
+                } = move_msg;
+                if let Some(after) = rules::castle_onto_rook(&position, castling, from, to) {
+                    position = after;
+                } else if let Err(err) =
+                    position.play((from.row, from.col), (to.row, to.col), promotion_piece)
+                {
+                    report_desync(
+                        connection.0.as_mut(),
+                        &mut desync,
+                        format!("opponent played an illegal move ({err:?})"),
+                    );
+                    continue;
+                }
+                castling.update(from, to);
+                ply_count += 1;
+                requests.write(MoveRequested {
+                    from,
+                    to,
+                    promotion_piece,
+                    origin: MoveOrigin::Opponent,
+                });
+                let hash_differs =
+                    position_hash.is_some_and(|hash| hash != zobrist::hash(&position, castling));
+                if board_to_fen(&position) != board_to_fen(&new_board) || hash_differs {
+                    warn!(
+                        "Our position: {}; the opponent's: {} (hash {})",
+                        fen::position_to_fen(&position, castling),
+                        board_to_fen(&new_board),
+                        position_hash.map_or("not sent".to_string(), |hash| format!("{hash:016X}")),
+                    );
+                    report_desync(
+                        connection.0.as_mut(),
+                        &mut desync,
+                        "position after the opponent's move differs".to_string(),
+                    );
+                }
+            }
+            Message::Sync(sync) => {
+                if connection.0.connection_type() == ConnectionType::Client
+                    && let (Some(clocks), Some((white, black))) = (&mut game.clocks, sync.clocks)
+                {
+                    clocks.set_remaining(white, black);
+                }
+                let matches = sync.ply_count == ply_count
+                    && board_to_fen(&sync.board) == board_to_fen(&position);
+                if matches {
+                    if desync.0.take().is_some() {
+                        info!("Back in sync with the opponent");
+                    }
+                } else {
+                    warn!("Opponent's position differs from ours");
+                    desync.0 = Some("positions differ after resync".to_string());
+                }
+            }
+            Message::Resync(_) => {
+                let clocks = sync_clocks(connection.0.as_ref(), game.clocks.as_deref());
+                send(
+                    connection.0.as_mut(),
+                    Message::Sync(SyncMessage {
+                        ply_count,
+                        board: position.clone(),
+                        clocks,
+                    }),
+                );
+            }
+            Message::Quit(quit_msg) => {
+                let message = quit_msg
+                    .message
+                    .unwrap_or("Opponent left the game".to_string());
+                // Leaving a game under way loses it, or calls it off if the
+                // opponent hadn't moved yet. The result stays on screen.
+                if spectating.is_none()
+                    && opponent.is_some()
+                    && concluded.0.is_none()
+                    && game.outcome.0.is_none()
+                {
+                    concluded.0 = Some(opponent_left(player_color.0, ply_count));
+                    ended.write(GameEnded);
+                    inbox.toasts.push(message);
+                    drop_connection(&mut commands);
+                    return;
+                }
+                commands.insert_resource(MenuMessage(message));
+                drop_connection(&mut commands);
+                commands.set_state(AppState::Menu);
+                return;
+            }
+            Message::Rematch(_) => rematch.received = true,
+            Message::Ping(ping) => send(
+                connection.0.as_mut(),
+                Message::Pong(PongMessage { id: ping.id }),
+            ),
+            Message::Pong(pong) => monitor.pong(pong.id),
+            Message::Chunk(chunk) => match transfers.receive(chunk) {
+                None | Some(ChunkStatus::Accepted | ChunkStatus::Dropped) => {}
+                Some(ChunkStatus::Complete(data)) => {
+                    transfer_received(&mut commands, &mut inbox.toasts, opponent.as_deref(), data);
+                }
+                Some(ChunkStatus::Resend(resend)) => {
+                    send(connection.0.as_mut(), Message::Resend(resend));
+                }
+            },
+            Message::Resend(resend) => {
+                if let Some(transfer) = transfers.outgoing.get_mut(&resend.transfer_id)
+                    && let Err(err) = transfer.send_from(connection.0.as_mut(), resend.from_seq)
+                {
+                    warn!("Could not resend transfer chunks: {err}");
+                }
+            }
+            Message::Draw(DrawMessage { action }) => match action {
+                DrawAction::Offer => offers.received = true,
+                DrawAction::Accept => {
+                    offers.sent = false;
+                    concluded.0 = Some(Conclusion::DrawAgreed);
+                    ended.write(GameEnded);
+                }
+                DrawAction::Decline => {
+                    if offers.sent {
+                        inbox.toasts.push("Draw offer declined");
+                    }
+                    offers.sent = false;
+                }
+            },
+            Message::Hello(hello) => {
+                if hello.version != PROTOCOL_VERSION {
+                    warn!(
+                        "Opponent speaks protocol version {}, we speak {PROTOCOL_VERSION}",
+                        hello.version
+                    );
+                }
+                if connection.0.connection_type() == ConnectionType::Client {
+                    if let Some(color) = hello.client_color {
+                        player_color.0 = color;
+                    }
+                    match hello.move_timeout {
+                        Some(secs) => commands
+                            .insert_resource(MoveTimer::new(Duration::from_secs(secs.into()))),
+                        None => commands.remove_resource::<MoveTimer>(),
+                    }
+                    if let Some(session) = hello.session {
+                        commands.insert_resource(Session(session));
+                    }
+                    let variant = hello.variant;
+                    // A handicap game needs clocks to give time odds on.
+                    if variant.time_odds() > 1 && game.clocks.is_none() {
+                        commands.insert_resource(Clocks::with_time_odds(
+                            HANDICAP_TIME_CONTROL,
+                            variant.time_odds(),
+                        ));
+                    }
+                    if variant != *game.variant {
+                        variants.write(VariantChosen(variant));
+                        (position, castling) = variant.start();
+                        ply_count = 0;
+                    }
+                }
+                if opponent.is_none() {
+                    inbox.toasts.push(format!("{} connected", hello.name));
+                }
+                commands.insert_resource(Opponent {
+                    name: hello.name,
+                    version: hello.version,
+                });
+            }
+            Message::Spectate(_) => commands.insert_resource(Spectating),
+            Message::Relay(_) if connection.0.connection_type() == ConnectionType::Server => {
+                reject_message(&mut inbox.toasts, ProtocolError::UnexpectedMessage("relay"));
+            }
+            Message::Relay(relay) => commands.run_system_cached_with(relay::watch_relay, relay),
+            Message::Chat(chat) => {
+                let name = opponent
+                    .as_ref()
+                    .map(|opponent| opponent.name.as_str())
+                    .unwrap_or("Opponent");
+                inbox.chat_log.lines.push(format!("{name}: {}", chat.text));
+            }
+            Message::Resign(_) => {
+                concluded.0 = Some(Conclusion::Resigned(rules::opponent(player_color.0)));
+                ended.write(GameEnded);
+            }
+        }
+    }
+}
+
+/// Writes a message, logging instead of panicking if the connection is
+/// gone. A dead connection is noticed and handled on the next read.
+pub fn send(connection: &mut dyn Transport, message: Message) {
+    if let Err(err) = connection.write(message) {
+        warn!("Could not send message: {err}");
+    }
+}
+
+/// Skips a message from the opponent that we can't make sense of, telling
+/// the player rather than giving up on the game.
+fn reject_message(toasts: &mut Toasts, err: ProtocolError) {
+    warn!("Ignoring a message from the opponent: {err}");
+    toasts.push(format!("Invalid message received: {err}"));
+}
+
+/// Locks the game and asks the opponent for their position, in case the
+/// mismatch can be explained and cleared.
+fn report_desync(connection: &mut dyn Transport, desync: &mut Desync, reason: String) {
+    warn!("Out of sync with opponent: {reason}");
+    desync.0 = Some(reason);
+    send(connection, Message::Resync(ResyncMessage));
+}
+
+/// How a game ends when the opponent leaves it: lost for them, or called
+/// off if they hadn't made a move yet.
+fn opponent_left(own: HermanhaColor, ply_count: u32) -> Conclusion {
+    let leaver = rules::opponent(own);
+    let first_move = match leaver {
+        HermanhaColor::White => 1,
+        HermanhaColor::Black => 2,
+    };
+    if ply_count < first_move {
+        Conclusion::Aborted
+    } else {
+        Conclusion::Abandoned(leaver)
+    }
+}
+
+/// Forgets everything about the online game, leaving local play.
+fn drop_connection(commands: &mut Commands) {
+    commands.remove_resource::<Connection>();
+    commands.remove_resource::<Opponent>();
+    commands.remove_resource::<PlayerColor>();
+    commands.remove_resource::<PeerAddress>();
+    commands.remove_resource::<Spectating>();
+    commands.remove_resource::<WatchedPlayers>();
+    commands.remove_resource::<Reconnecting>();
+    commands.remove_resource::<MoveTimer>();
+    commands.remove_resource::<Session>();
+}
+
+/// Tells the opponent we're leaving the game.
+pub fn hang_up(connection: &mut dyn Transport) {
+    send(
+        connection,
+        Message::Quit(QuitMessage {
+            message: Some("Opponent left the game".to_string()),
+        }),
+    );
+}
+
+/// How long closing the app waits for the goodbye to go out.
+const GOODBYE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Closing the window in an online game tells the opponent, who would
+/// otherwise only see the connection drop. The message is sent before the
+/// app is gone, waiting briefly for the I/O thread to get it out.
+fn say_goodbye(mut exits: EventReader<AppExit>, mut connection: ResMut<Connection>) {
+    if exits.is_empty() {
+        return;
+    }
+    exits.clear();
+    send(
+        connection.0.as_mut(),
+        Message::Quit(QuitMessage {
+            message: Some("Opponent closed the application".to_string()),
+        }),
+    );
+    connection.0.close(GOODBYE_TIMEOUT);
+}
+
+/// Going back to the menu tells the opponent and hangs up.
+fn leave_game(mut commands: Commands, mut connection: ResMut<Connection>) {
+    hang_up(connection.0.as_mut());
+    drop_connection(&mut commands);
+}
+
+/// Drops the dead connection and starts reconnecting. Removing `Opponent`
+/// locks input until the handshake is redone. Spectators don't reconnect,
+/// since the server would take them for the returning opponent.
+fn connection_lost(commands: &mut Commands, spectating: bool) {
+    commands.remove_resource::<Connection>();
+    commands.remove_resource::<Opponent>();
+    let banner = if spectating {
+        "Connection lost"
+    } else {
+        commands.insert_resource(Reconnecting::new());
+        "Connection lost \u{2014} reconnecting"
+    };
+    commands.spawn((
+        ReconnectBanner,
+        GameUi,
+        Node {
+            position_type: PositionType::Absolute,
+            width: Val::Percent(100.0),
+            top: Val::Px(40.0),
+            justify_content: JustifyContent::Center,
+            ..default()
+        },
+        children![(
+            ReconnectBannerText,
+            Text::new(banner),
+            TextColor(Color::srgb(0.95, 0.6, 0.3)),
+        )],
+    ));
+}
+
+/// Retries with exponential backoff: 1s, 2s, 4s, ... up to
+/// `MAX_RECONNECT_DELAY_SECS`. The host only takes back a client whose
+/// hello carries the game's `Session`, and turns anyone else away. Once
+/// connected, both sides redo the handshake and exchange a `SyncMessage`
+/// to check the game still matches, and the host's puts the client's
+/// clocks back to its own.
+#[allow(clippy::too_many_arguments)]
+fn reconnect(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut reconnecting: ResMut<Reconnecting>,
+    peer: Res<PeerAddress>,
+    player_name: Res<PlayerName>,
+    player_color: Res<PlayerColor>,
+    variant: Res<Variant>,
+    move_timeout: Res<MoveTimeout>,
+    session: Option<Res<Session>>,
+    board: Res<BoardState>,
+    history: Res<MoveHistory>,
+    clocks: Option<Res<Clocks>>,
+    move_timer: Option<ResMut<MoveTimer>>,
+    banners: Query<Entity, With<ReconnectBanner>>,
+    mut toasts: ResMut<Toasts>,
+) {
+    let mut connection = if let Some(candidate) = &mut reconnecting.candidate {
+        match read_rejoin(candidate.as_mut(), session.as_deref()) {
+            Rejoin::Waiting => {
+                if reconnecting.candidate_wait.tick(time.delta()).finished() {
+                    reconnecting.turn_away("it said nothing");
+                }
+                return;
+            }
+            Rejoin::Refused(reason) => {
+                reconnecting.turn_away(reason);
+                return;
+            }
+            Rejoin::Accepted(hello) => {
+                commands.insert_resource(Opponent {
+                    name: hello.name,
+                    version: hello.version,
+                });
+                reconnecting.candidate.take().unwrap()
+            }
+        }
+    } else {
+        let Some(result) = &reconnecting.result else {
+            if reconnecting.delay.tick(time.delta()).finished() {
+                reconnecting.result = Some(peer.connect_in_background());
+            }
+            return;
+        };
+        let result = match result.try_recv() {
+            Ok(result) => result,
+            Err(TryRecvError::Empty) => return,
+            Err(TryRecvError::Disconnected) => Err(io::Error::other("connection thread stopped")),
+        };
+        match result {
+            Ok(connection) if connection.connection_type() == ConnectionType::Server => {
+                reconnecting.candidate = Some(connection);
+                reconnecting.candidate_wait.reset();
+                return;
+            }
+            Ok(connection) => connection,
+            Err(err) => {
+                warn!(
+                    "Reconnect attempt {} failed: {err}",
+                    reconnecting.attempt + 1
+                );
+                reconnecting.attempt += 1;
+                let delay = 2f32
+                    .powi(reconnecting.attempt as i32 - 1)
+                    .min(MAX_RECONNECT_DELAY_SECS);
+                reconnecting.delay = Timer::from_seconds(delay, TimerMode::Once);
+                reconnecting.result = None;
+                return;
+            }
+        }
+    };
+    info!("Reconnected after {} attempts", reconnecting.attempt + 1);
+    toasts.push("Reconnected");
+    write_hello(
+        connection.as_mut(),
+        &player_name,
+        &player_color,
+        *variant,
+        &move_timeout,
+        session.as_deref(),
+    );
+    // The time lost to the outage isn't held against the player to move.
+    if let Some(mut move_timer) = move_timer {
+        move_timer.restart();
+    }
+    let clocks = sync_clocks(connection.as_ref(), clocks.as_deref());
+    send(
+        connection.as_mut(),
+        Message::Sync(SyncMessage {
+            ply_count: history.ply_count(),
+            board: board.0.clone(),
+            clocks,
+        }),
+    );
+    commands.insert_resource(Connection(connection));
+    commands.remove_resource::<Reconnecting>();
+    for entity in banners.iter() {
+        commands.entity(entity).despawn();
+    }
+}
+
+/// An opponent who drops out of a game under way has `CLAIM_AFTER_SECS`
+/// to come back, counted down on the banner, before the game ends as if
+/// they had left it.
+#[allow(clippy::too_many_arguments)]
+fn claim_abandoned_game(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut reconnecting: ResMut<Reconnecting>,
+    player_color: Res<PlayerColor>,
+    history: Res<MoveHistory>,
+    outcome: Res<GameOutcome>,
+    mut concluded: ResMut<Concluded>,
+    mut ended: EventWriter<GameEnded>,
+    banners: Query<Entity, With<ReconnectBanner>>,
+    mut texts: Query<&mut Text, With<ReconnectBannerText>>,
+) {
+    if concluded.0.is_some() || outcome.0.is_some() {
+        return;
+    }
+    let claim = &mut reconnecting.claim;
+    if !claim.tick(time.delta()).finished() {
+        let banner = format!(
+            "Opponent disconnected \u{2014} reconnecting, claiming the game in {}s",
+            claim.remaining_secs().ceil()
+        );
+        for mut text in texts.iter_mut() {
+            if text.0 != banner {
+                text.0 = banner.clone();
+            }
+        }
+        return;
+    }
+    info!("The opponent didn't come back, claiming the game");
+    concluded.0 = Some(opponent_left(player_color.0, history.ply_count()));
+    ended.write(GameEnded);
+    drop_connection(&mut commands);
+    for entity in banners.iter() {
+        commands.entity(entity).despawn();
+    }
+}
+
+fn send_played_moves(
+    mut played: EventReader<MovePlayed>,
+    mut connection: ResMut<Connection>,
+    castling: Res<Castling>,
+    history: Res<MoveHistory>,
+) {
+    for played in played.read() {
+        if played.origin != MoveOrigin::Local {
+            continue;
+        }
+        let move_msg = MoveMessage {
+            from: played.from,
+            to: played.to,
+            promotion_piece: played.promotion_piece,
+            result: history.outcome(&played.after, castling.0),
+            new_board: played.after.clone(),
+            position_hash: Some(zobrist::hash(&played.after, castling.0)),
+        };
+        send(connection.0.as_mut(), Message::Move(move_msg));
+    }
+}
+
+fn send_game_actions(mut actions: EventReader<GameAction>, mut connection: ResMut<Connection>) {
+    for action in actions.read() {
+        let message = match *action {
+            GameAction::Draw(action) => Message::Draw(DrawMessage { action }),
+            GameAction::Resign => Message::Resign(ResignMessage),
+            GameAction::Rematch => Message::Rematch(RematchMessage),
+        };
+        send(connection.0.as_mut(), message);
+    }
+}
+
+/// The host starts the game's session and times the moves if it set a
+/// limit; the client waits to hear both in the host's handshake.
+fn send_hello(
+    mut commands: Commands,
+    mut connection: ResMut<Connection>,
+    player_name: Res<PlayerName>,
+    player_color: Res<PlayerColor>,
+    variant: Res<Variant>,
+    move_timeout: Res<MoveTimeout>,
+) {
+    let session = match connection.0.connection_type() {
+        ConnectionType::Server => {
+            if let Some(limit) = move_timeout.0 {
+                commands.insert_resource(MoveTimer::new(limit));
+            }
+            let session = Session::new();
+            commands.insert_resource(session);
+            Some(session)
+        }
+        ConnectionType::Client => None,
+    };
+    write_hello(
+        connection.0.as_mut(),
+        &player_name,
+        &player_color,
+        *variant,
+        &move_timeout,
+        session.as_ref(),
+    );
+}
+
+/// Ctrl+Shift+S sends the game so far to the opponent as PGN, for them to
+/// keep.
+fn send_pgn(
+    keys: Res<ButtonInput<KeyCode>>,
+    game: GameRecord,
+    mut connection: ResMut<Connection>,
+    mut transfers: ResMut<Transfers>,
+    mut toasts: ResMut<Toasts>,
+) {
+    if !actions::just_pressed(&keys, Action::SendPgn) {
+        return;
+    }
+    match transfers.send(connection.0.as_mut(), game.pgn().as_bytes()) {
+        Ok(_) => toasts.push("Game sent to the opponent"),
+        Err(err) => {
+            warn!("Could not send the game: {err}");
+            toasts.push(format!("Could not send the game: {err}"));
+        }
+    }
+}
+
+/// Ctrl+Shift+R in review sends the scores of every position to the
+/// opponent, once the search is done, so both study the same analysis.
+fn share_review(
+    keys: Res<ButtonInput<KeyCode>>,
+    scopes: Res<ActiveScopes>,
+    evaluations: Option<Res<Evaluations>>,
+    history: Res<MoveHistory>,
+    mut connection: ResMut<Connection>,
+    mut transfers: ResMut<Transfers>,
+    mut toasts: ResMut<Toasts>,
+) {
+    if !actions::just_pressed_in(&keys, &scopes, Action::ShareReview) {
+        return;
+    }
+    let Some(review) = evaluations.and_then(|evaluations| evaluations.share(&history)) else {
+        toasts.push("The review is still being analysed");
+        return;
+    };
+    match transfers.send(connection.0.as_mut(), review.as_bytes()) {
+        Ok(_) => toasts.push("Review sent to the opponent"),
+        Err(err) => {
+            warn!("Could not send the review: {err}");
+            toasts.push(format!("Could not send the review: {err}"));
+        }
+    }
+}
+
+/// Drops the bulk transfers that went quiet.
+fn expire_transfers(mut transfers: ResMut<Transfers>) {
+    transfers.expire();
+}
+
+fn send_chat(mut outgoing: EventReader<OutgoingChat>, mut connection: ResMut<Connection>) {
+    for chat in outgoing.read() {
+        send(
+            connection.0.as_mut(),
+            Message::Chat(ChatMessage {
+                text: chat.0.clone(),
+            }),
+        );
+    }
+}
+
+/// The server tells the client which color it plays, the opposite of its
+/// own, which variant or handicap the game is played in and how long a
+/// move may take. Both send the session, which the client only has once it
+/// has heard from the server.
+fn write_hello(
+    connection: &mut dyn Transport,
+    player_name: &PlayerName,
+    player_color: &PlayerColor,
+    variant: Variant,
+    move_timeout: &MoveTimeout,
+    session: Option<&Session>,
+) {
+    let (client_color, variant, move_timeout) = match connection.connection_type() {
+        ConnectionType::Server => {
+            let move_timeout = move_timeout
+                .0
+                .map(|limit| u16::try_from(limit.as_secs()).unwrap_or(u16::MAX));
+            (Some(rules::opponent(player_color.0)), variant, move_timeout)
+        }
+        ConnectionType::Client => (None, Variant::Standard, None),
+    };
+    send(
+        connection,
+        Message::Hello(HelloMessage {
+            version: PROTOCOL_VERSION,
+            name: player_name.0.clone(),
+            client_color,
+            variant,
+            move_timeout,
+            session: session.map(|session| session.0),
+        }),
+    );
+}
+
+fn spawn_opponent_label(mut commands: Commands) {
+    commands.spawn((
+        OpponentLabel,
+        GameUi,
+        Text::new("Waiting for opponent..."),
+        TextFont {
+            font_size: 16.0,
+            ..default()
+        },
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(8.0),
+            left: Val::Percent(40.0),
+            ..default()
+        },
+    ));
+}
+
+fn render_desync_warning(
+    mut commands: Commands,
+    desync: Res<Desync>,
+    warnings: Query<Entity, With<DesyncWarning>>,
+) {
+    if !desync.is_changed() {
+        return;
+    }
+    for entity in warnings.iter() {
+        commands.entity(entity).despawn();
+    }
+    let Some(reason) = &desync.0 else {
+        return;
+    };
+    commands.spawn((
+        DesyncWarning,
+        GameUi,
+        Node {
+            position_type: PositionType::Absolute,
+            width: Val::Percent(100.0),
+            top: Val::Px(64.0),
+            justify_content: JustifyContent::Center,
+            ..default()
+        },
+        children![(
+            Text::new(format!("DESYNC - out of sync with opponent: {reason}")),
+            TextColor(Color::srgb(0.95, 0.3, 0.3)),
+        )],
+    ));
+}
+
+fn spawn_spectator_label(mut commands: Commands) {
+    commands.spawn((
+        SpectatorLabel,
+        GameUi,
+        Text::new(""),
+        TextFont {
+            font_size: 14.0,
+            ..default()
+        },
+        TextColor(Color::srgba(1.0, 1.0, 1.0, 0.6)),
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(28.0),
+            left: Val::Percent(40.0),
+            ..default()
+        },
+    ));
+}
+
+fn spawn_waiting_indicator(mut commands: Commands) {
+    commands.spawn((
+        WaitingIndicator,
+        GameUi,
+        Text::new(""),
+        TextFont {
+            font_size: 14.0,
+            ..default()
+        },
+        TextColor(Color::srgba(1.0, 1.0, 1.0, 0.6)),
+        Node {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(8.0),
+            left: Val::Percent(40.0),
+            ..default()
+        },
+    ));
+}
+
+fn render_waiting_indicator(
+    board: Res<BoardState>,
+    local_player: Res<LocalPlayer>,
+    mut labels: Query<&mut Text, With<WaitingIndicator>>,
+) {
+    if !board.is_changed() && !local_player.is_changed() {
+        return;
+    }
+    let waiting =
+        matches!(*local_player, LocalPlayer::One(_)) && !local_player.controls(board.0.move_turn);
+    let text = if waiting {
+        "Waiting for opponent\u{2026}"
+    } else {
+        ""
+    };
+    for mut label in labels.iter_mut() {
+        label.0 = text.to_string();
+    }
+}
+
+/// Spectators see that they're only watching, and who plays once the
+/// host has relayed it; the server sees how many people are watching.
+fn render_spectator_label(
+    connection: Res<Connection>,
+    spectating: Option<Res<Spectating>>,
+    watched: Option<Res<WatchedPlayers>>,
+    mut labels: Query<&mut Text, With<SpectatorLabel>>,
+) {
+    let text = if let Some(players) = watched {
+        format!(
+            "Watching {} (White) vs {} (Black)",
+            players.white, players.black
+        )
+    } else if spectating.is_some() {
+        "Spectating".to_string()
+    } else {
+        match connection.0.spectator_count() {
+            0 => String::new(),
+            1 => "1 spectator".to_string(),
+            count => format!("{count} spectators"),
+        }
+    };
+    for mut label in labels.iter_mut() {
+        if label.0 != text {
+            label.0 = text.clone();
+        }
+    }
+}
+
+fn render_opponent_label(
+    opponent: Res<Opponent>,
+    mut labels: Query<&mut Text, With<OpponentLabel>>,
+) {
+    if !opponent.is_changed() {
+        return;
+    }
+    for mut label in labels.iter_mut() {
+        label.0 = format!("vs {}", opponent.name);
+    }
+}
+
+fn poll_pending_connection(
+    mut commands: Commands,
+    pending: Res<PendingConnection>,
+    mut next_state: ResMut<NextState<AppState>>,
+    mut menu_message: ResMut<MenuMessage>,
+) {
+    let result = match pending.result.try_recv() {
+        Ok(result) => result,
+        Err(TryRecvError::Empty) => return,
+        Err(TryRecvError::Disconnected) => Err(io::Error::other("connection thread stopped")),
+    };
+    commands.remove_resource::<PendingConnection>();
+    match result {
+        Ok(connection) => {
+            commands.insert_resource(Connection(connection));
+            commands.insert_resource(pending.peer.clone());
+            commands.insert_resource(PlayerColor(pending.player_color));
+            if pending.watching {
+                commands.insert_resource(Spectating);
+            }
+            next_state.set(AppState::Playing);
+        }
+        Err(err) => {
+            menu_message.0 = format!("Could not connect: {err}");
+            next_state.set(AppState::Menu);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The first chunk of a transfer of `total` chunks.
+    fn first_chunk(transfer_id: u16, total: u16) -> ChunkMessage {
+        ChunkMessage {
+            transfer_id,
+            seq: 0,
+            total,
+            checksum: 0,
+            payload: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn only_so_many_transfers_come_in_at_once() {
+        let mut transfers = Transfers::default();
+        for transfer_id in 0..MAX_INCOMING_TRANSFERS as u16 {
+            assert!(transfers.receive(first_chunk(transfer_id, 2)).is_some());
+        }
+        assert!(transfers.receive(first_chunk(99, 2)).is_none());
+        // The ones already coming in go on.
+        assert!(transfers.receive(first_chunk(0, 2)).is_some());
+    }
+
+    #[test]
+    fn transfers_adding_up_to_too_many_chunks_are_refused() {
+        let mut transfers = Transfers::default();
+        let most = MAX_INCOMING_CHUNKS as u16 - 1;
+        assert!(transfers.receive(first_chunk(0, most)).is_some());
+        assert!(transfers.receive(first_chunk(1, 2)).is_none());
+        assert!(transfers.receive(first_chunk(2, 1)).is_some());
+    }
+}
@@ -0,0 +1,333 @@
+use std::time::Duration;
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+
+use crate::game_state::MovePlayed;
+use crate::net::{self, Connection, Opponent, Reconnecting};
+use crate::tcp::{Message, PingMessage};
+use crate::ui::GameUi;
+
+/// How often the opponent is pinged while moves are coming, so a dropped
+/// connection shows quickly.
+const ACTIVE_PING_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How often the opponent is pinged during a long think or while the
+/// window is in the background, to wake up and send less.
+const IDLE_PING_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long after a move the game still counts as being played actively.
+const ACTIVE_PLAY: Duration = Duration::from_secs(30);
+
+/// First protocol version that answers pings.
+const PING_VERSION: u16 = 4;
+
+/// How many pings in a row can go out with nothing at all from the
+/// opponent before the connection is taken as dead, for sockets that go
+/// quiet without ever failing. Counting pings rather than time keeps this
+/// right as the interval changes.
+const MISSED_HEARTBEATS: u32 = 5;
+
+const CONNECTED_COLOR: Color = Color::srgb(0.3, 0.8, 0.35);
+const RECONNECTING_COLOR: Color = Color::srgb(0.95, 0.6, 0.3);
+const DISCONNECTED_COLOR: Color = Color::srgb(0.95, 0.3, 0.3);
+
+/// How long nothing may arrive from the opponent before the HUD warns
+/// about it, set with `--stall-timeout=`. Pings keep a healthy connection
+/// busy, so silence means trouble even while the opponent is thinking;
+/// while pings are far apart, the warning waits for two of them.
+#[derive(Resource)]
+pub struct StallTimeout(pub Duration);
+
+impl Default for StallTimeout {
+    fn default() -> Self {
+        StallTimeout(Duration::from_secs(10))
+    }
+}
+
+/// The health of the connection. Times are the real time elapsed since
+/// startup, so they keep counting while the game is paused.
+#[derive(Resource, Default)]
+pub struct NetStatus {
+    last_received: Duration,
+    last_ping: Duration,
+    /// The interval the last ping went out at.
+    ping_interval: Duration,
+    /// Pings sent since anything last arrived from the opponent.
+    unanswered: u32,
+    last_move: Duration,
+    /// The ping still waiting for its pong and when it was sent.
+    pending_ping: Option<(u16, Duration)>,
+    next_ping_id: u16,
+    round_trip: Option<Duration>,
+}
+
+impl NetStatus {
+    /// The round trip of the last answered ping.
+    pub fn round_trip(&self) -> Option<Duration> {
+        self.round_trip
+    }
+
+    /// How long nothing has arrived from the opponent.
+    pub fn silence(&self, now: Duration) -> Duration {
+        now.saturating_sub(self.last_received)
+    }
+
+    /// How long a healthy connection may go quiet for before `timeout`
+    /// says it has stalled.
+    fn stall_after(&self, timeout: Duration) -> Duration {
+        timeout.max(self.ping_interval * 2)
+    }
+}
+
+/// How often to ping, given how long ago the last move was played and
+/// whether the window is in the background.
+fn ping_interval(since_move: Duration, unfocused: bool) -> Duration {
+    if unfocused || since_move >= ACTIVE_PLAY {
+        IDLE_PING_INTERVAL
+    } else {
+        ACTIVE_PING_INTERVAL
+    }
+}
+
+/// `NetStatus` along with the clock it is measured by.
+#[derive(SystemParam)]
+pub struct NetMonitor<'w> {
+    status: ResMut<'w, NetStatus>,
+    time: Res<'w, Time<Real>>,
+}
+
+impl NetMonitor<'_> {
+    /// Notes that a message arrived from the opponent.
+    pub fn received(&mut self) {
+        self.status.last_received = self.time.elapsed();
+        self.status.unanswered = 0;
+    }
+
+    /// Whether an opponent who answers pings has been silent through
+    /// `MISSED_HEARTBEATS` of them.
+    pub fn peer_gone(&self, opponent: Option<&Opponent>) -> bool {
+        opponent.is_some_and(|opponent| opponent.version >= PING_VERSION)
+            && self.status.unanswered >= MISSED_HEARTBEATS
+    }
+
+    /// Only the pong to the latest ping counts; answers to earlier ones
+    /// arrived too late to say anything about the connection now.
+    pub fn pong(&mut self, id: u16) {
+        if let Some((pending_id, sent)) = self.status.pending_ping
+            && pending_id == id
+        {
+            self.status.round_trip = Some(self.time.elapsed().saturating_sub(sent));
+            self.status.pending_ping = None;
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct NetHud;
+
+#[derive(Component)]
+pub struct NetHudDot;
+
+#[derive(Component)]
+pub struct NetHudText;
+
+#[derive(Component)]
+pub struct StallWarning;
+
+#[derive(Component)]
+pub struct StallWarningText;
+
+/// Pings the opponent once their handshake shows they answer, at the
+/// `ping_interval` for how the game is going. A new connection, after
+/// reconnecting too, starts the measurements over.
+pub fn ping_opponent(
+    mut connection: ResMut<Connection>,
+    opponent: Option<Res<Opponent>>,
+    mut played: EventReader<MovePlayed>,
+    windows: Query<&Window>,
+    mut monitor: NetMonitor,
+) {
+    let now = monitor.time.elapsed();
+    let status = &mut monitor.status;
+    if connection.is_added() {
+        **status = NetStatus {
+            last_received: now,
+            last_ping: now,
+            last_move: now,
+            next_ping_id: status.next_ping_id,
+            ..default()
+        };
+    }
+    if played.read().count() > 0 {
+        status.last_move = now;
+    }
+    let unfocused = !windows.is_empty() && !windows.iter().any(|window| window.focused);
+    let interval = ping_interval(now.saturating_sub(status.last_move), unfocused);
+    if opponent.is_none_or(|opponent| opponent.version < PING_VERSION)
+        || now.saturating_sub(status.last_ping) < interval
+    {
+        return;
+    }
+    let id = status.next_ping_id;
+    status.next_ping_id = id.wrapping_add(1);
+    status.last_ping = now;
+    status.ping_interval = interval;
+    status.unanswered += 1;
+    status.pending_ping = Some((id, now));
+    net::send(connection.0.as_mut(), Message::Ping(PingMessage { id }));
+}
+
+/// A colored dot for the connection state, the state and round trip, and a
+/// warning badge shown while the opponent has gone quiet.
+pub fn spawn_net_hud(mut commands: Commands) {
+    let font = TextFont {
+        font_size: 14.0,
+        ..default()
+    };
+    commands.spawn((
+        NetHud,
+        GameUi,
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(8.0),
+            left: Val::Percent(60.0),
+            align_items: AlignItems::Center,
+            column_gap: Val::Px(6.0),
+            ..default()
+        },
+        children![
+            (
+                NetHudDot,
+                Node {
+                    width: Val::Px(10.0),
+                    height: Val::Px(10.0),
+                    ..default()
+                },
+                BorderRadius::MAX,
+                BackgroundColor(DISCONNECTED_COLOR),
+            ),
+            (
+                NetHudText,
+                Text::new(""),
+                font.clone(),
+                TextColor(Color::srgba(1.0, 1.0, 1.0, 0.6)),
+            ),
+            (
+                StallWarning,
+                Node {
+                    display: Display::None,
+                    align_items: AlignItems::Center,
+                    column_gap: Val::Px(4.0),
+                    ..default()
+                },
+                children![
+                    (
+                        Node {
+                            width: Val::Px(16.0),
+                            height: Val::Px(16.0),
+                            justify_content: JustifyContent::Center,
+                            align_items: AlignItems::Center,
+                            ..default()
+                        },
+                        BorderRadius::MAX,
+                        BackgroundColor(RECONNECTING_COLOR),
+                        children![(Text::new("!"), font.clone(), TextColor(Color::BLACK))],
+                    ),
+                    (
+                        StallWarningText,
+                        Text::new(""),
+                        font,
+                        TextColor(RECONNECTING_COLOR),
+                    ),
+                ],
+            ),
+        ],
+    ));
+}
+
+/// Spectators never get a handshake and so never ping: they only see the
+/// connection state.
+#[allow(clippy::too_many_arguments)]
+pub fn render_net_hud(
+    connection: Option<Res<Connection>>,
+    reconnecting: Option<Res<Reconnecting>>,
+    opponent: Option<Res<Opponent>>,
+    status: Res<NetStatus>,
+    timeout: Res<StallTimeout>,
+    time: Res<Time<Real>>,
+    mut dots: Query<&mut BackgroundColor, With<NetHudDot>>,
+    mut labels: Query<&mut Text, With<NetHudText>>,
+    mut warnings: Query<&mut Node, With<StallWarning>>,
+    mut warning_texts: Query<&mut Text, (With<StallWarningText>, Without<NetHudText>)>,
+) {
+    let (color, text) = match (&connection, &reconnecting) {
+        (Some(_), _) => {
+            let text = match status.round_trip() {
+                Some(round_trip) => format!("Connected ({} ms)", round_trip.as_millis()),
+                None => "Connected".to_string(),
+            };
+            (CONNECTED_COLOR, text)
+        }
+        (None, Some(_)) => (RECONNECTING_COLOR, "Reconnecting\u{2026}".to_string()),
+        (None, None) => (DISCONNECTED_COLOR, "Disconnected".to_string()),
+    };
+    for mut dot in dots.iter_mut() {
+        if dot.0 != color {
+            dot.0 = color;
+        }
+    }
+    for mut label in labels.iter_mut() {
+        if label.0 != text {
+            label.0 = text.clone();
+        }
+    }
+    let silence = status.silence(time.elapsed());
+    let stalled = connection.is_some()
+        && opponent.is_some_and(|opponent| opponent.version >= PING_VERSION)
+        && silence >= status.stall_after(timeout.0);
+    let display = if stalled {
+        Display::Flex
+    } else {
+        Display::None
+    };
+    for mut node in warnings.iter_mut() {
+        if node.display != display {
+            node.display = display;
+        }
+    }
+    if !stalled {
+        return;
+    }
+    let warning = format!("No data for {}s", silence.as_secs());
+    for mut text in warning_texts.iter_mut() {
+        if text.0 != warning {
+            text.0 = warning.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pings_are_far_apart_unless_moves_are_coming() {
+        let just_moved = Duration::from_secs(5);
+        assert_eq!(ping_interval(just_moved, false), ACTIVE_PING_INTERVAL);
+        assert_eq!(ping_interval(just_moved, true), IDLE_PING_INTERVAL);
+        assert_eq!(ping_interval(ACTIVE_PLAY, false), IDLE_PING_INTERVAL);
+    }
+
+    #[test]
+    fn far_apart_pings_hold_the_stall_warning_back() {
+        let timeout = StallTimeout::default().0;
+        let mut status = NetStatus {
+            ping_interval: ACTIVE_PING_INTERVAL,
+            ..default()
+        };
+        assert_eq!(status.stall_after(timeout), timeout);
+        status.ping_interval = IDLE_PING_INTERVAL;
+        assert_eq!(status.stall_after(timeout), IDLE_PING_INTERVAL * 2);
+    }
+}
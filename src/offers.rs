@@ -0,0 +1,252 @@
+use bevy::prelude::*;
+use hermanha_chess::Color as HermanhaColor;
+
+use crate::confirm::{self, ConfirmDialog, Confirmable, Confirmed};
+use crate::game_over::GameEnded;
+use crate::game_state::{BoardState, LocalPlayer, PlayerColor};
+use crate::net::Connection;
+use crate::rules;
+use crate::tcp::DrawAction;
+use crate::ui::GameUi;
+use crate::widgets;
+
+/// How an online game ended when it wasn't decided on the board.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Conclusion {
+    DrawAgreed,
+    Resigned(HermanhaColor),
+    /// The player of this color left the game, and so lost it.
+    Abandoned(HermanhaColor),
+    /// The player of this color went over the move timeout, and so lost.
+    MoveTimedOut(HermanhaColor),
+    /// The opponent left before the game had properly started.
+    Aborted,
+}
+
+impl Conclusion {
+    pub fn text(self) -> String {
+        match self {
+            Conclusion::DrawAgreed => "Draw by agreement".to_string(),
+            Conclusion::Resigned(color) => {
+                let winner = match rules::opponent(color) {
+                    HermanhaColor::White => "White",
+                    HermanhaColor::Black => "Black",
+                };
+                format!("{winner} wins by resignation")
+            }
+            Conclusion::Abandoned(color) => {
+                let (winner, loser) = match color {
+                    HermanhaColor::White => ("Black", "White"),
+                    HermanhaColor::Black => ("White", "Black"),
+                };
+                format!("{loser} left \u{2014} {winner} wins")
+            }
+            Conclusion::MoveTimedOut(color) => {
+                let (winner, loser) = match color {
+                    HermanhaColor::White => ("Black", "White"),
+                    HermanhaColor::Black => ("White", "Black"),
+                };
+                format!("{loser} took too long to move \u{2014} {winner} wins")
+            }
+            Conclusion::Aborted => "Opponent left \u{2014} game aborted".to_string(),
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct Concluded(pub Option<Conclusion>);
+
+/// Draw offers waiting for an answer, in either direction.
+#[derive(Resource, Default)]
+pub struct DrawOffers {
+    pub sent: bool,
+    pub received: bool,
+}
+
+/// Something the local player did that the opponent has to be told about.
+/// `NetworkPlugin` turns these into protocol messages.
+#[derive(Event, Clone, Copy)]
+pub enum GameAction {
+    Draw(DrawAction),
+    Resign,
+    Rematch,
+}
+
+#[derive(Component, Clone, Copy)]
+pub enum OfferButton {
+    OfferDraw,
+    Resign,
+    AcceptDraw,
+    DeclineDraw,
+}
+
+impl OfferButton {
+    pub fn label(self) -> &'static str {
+        match self {
+            OfferButton::OfferDraw => "Offer draw",
+            OfferButton::Resign => "Resign",
+            OfferButton::AcceptDraw => "Accept",
+            OfferButton::DeclineDraw => "Decline",
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct OfferDialog;
+
+#[derive(Component)]
+pub struct OfferStatus;
+
+fn button(kind: OfferButton) -> impl Bundle {
+    (
+        kind,
+        widgets::button(
+            widgets::button_node(110.0, 32.0),
+            widgets::small_label(kind.label()),
+        ),
+    )
+}
+
+pub fn spawn_offer_buttons(mut commands: Commands) {
+    commands.spawn((
+        GameUi,
+        Node {
+            position_type: PositionType::Absolute,
+            left: Val::Px(8.0),
+            bottom: Val::Px(8.0),
+            flex_direction: FlexDirection::Column,
+            row_gap: Val::Px(6.0),
+            ..default()
+        },
+        children![
+            (OfferStatus, widgets::small_label("")),
+            button(OfferButton::OfferDraw),
+            button(OfferButton::Resign),
+        ],
+    ));
+}
+
+fn spawn_dialog(commands: &mut Commands, question: &str, yes: OfferButton, no: OfferButton) {
+    commands.spawn((
+        OfferDialog,
+        GameUi,
+        widgets::modal(10.0, 8),
+        children![
+            widgets::label(question),
+            (widgets::button_row(), children![button(yes), button(no)],),
+        ],
+    ));
+}
+
+fn close_dialogs(commands: &mut Commands, dialogs: &Query<Entity, With<OfferDialog>>) {
+    for entity in dialogs.iter() {
+        commands.entity(entity).despawn();
+    }
+}
+
+/// Who resigns from this machine: our own color online or against the
+/// computer, and the side to move when two players share the board.
+fn resigning_side(
+    player_color: Option<&PlayerColor>,
+    local_player: &LocalPlayer,
+    board: &BoardState,
+) -> HermanhaColor {
+    match (player_color, local_player) {
+        (Some(player_color), _) => player_color.0,
+        (None, LocalPlayer::One(color)) => *color,
+        (None, _) => board.0.move_turn,
+    }
+}
+
+/// The buttons beside the board online, and the same ones in the pause
+/// menu in any game. Locally there's nobody to send an offer to, so two
+/// players sharing the board agree to a draw on the spot. Resigning is
+/// asked about first.
+#[allow(clippy::too_many_arguments)]
+pub fn handle_offer_buttons(
+    mut commands: Commands,
+    interactions: Query<(&Interaction, &OfferButton), Changed<Interaction>>,
+    dialogs: Query<Entity, With<OfferDialog>>,
+    confirm_dialogs: Query<(), With<ConfirmDialog>>,
+    mut confirmed: EventReader<Confirmed>,
+    connection: Option<Res<Connection>>,
+    player_color: Option<Res<PlayerColor>>,
+    local_player: Res<LocalPlayer>,
+    board: Res<BoardState>,
+    mut offers: ResMut<DrawOffers>,
+    mut concluded: ResMut<Concluded>,
+    mut actions: EventWriter<GameAction>,
+    mut ended: EventWriter<GameEnded>,
+) {
+    let resigned = confirmed
+        .read()
+        .any(|confirmed| confirmed.0 == Confirmable::Resign);
+    if resigned && concluded.0.is_none() {
+        concluded.0 = Some(Conclusion::Resigned(resigning_side(
+            player_color.as_deref(),
+            &local_player,
+            &board,
+        )));
+        ended.write(GameEnded);
+        actions.write(GameAction::Resign);
+    }
+    for (interaction, button) in interactions.iter() {
+        if *interaction != Interaction::Pressed || concluded.0.is_some() {
+            continue;
+        }
+        match button {
+            OfferButton::OfferDraw if connection.is_none() => {
+                if *local_player == LocalPlayer::Both {
+                    concluded.0 = Some(Conclusion::DrawAgreed);
+                    ended.write(GameEnded);
+                }
+            }
+            OfferButton::OfferDraw => {
+                if !offers.sent && !offers.received {
+                    offers.sent = true;
+                    actions.write(GameAction::Draw(DrawAction::Offer));
+                }
+            }
+            OfferButton::Resign => {
+                confirm::ask(&mut commands, &confirm_dialogs, Confirmable::Resign)
+            }
+            OfferButton::AcceptDraw => {
+                close_dialogs(&mut commands, &dialogs);
+                offers.received = false;
+                concluded.0 = Some(Conclusion::DrawAgreed);
+                ended.write(GameEnded);
+                actions.write(GameAction::Draw(DrawAction::Accept));
+            }
+            OfferButton::DeclineDraw => {
+                close_dialogs(&mut commands, &dialogs);
+                offers.received = false;
+                actions.write(GameAction::Draw(DrawAction::Decline));
+            }
+        }
+    }
+}
+
+/// Asks the local player about a draw the opponent just offered.
+pub fn show_draw_offer(mut commands: Commands, offers: Res<DrawOffers>) {
+    if offers.is_changed() && offers.received {
+        spawn_dialog(
+            &mut commands,
+            "Your opponent offers a draw",
+            OfferButton::AcceptDraw,
+            OfferButton::DeclineDraw,
+        );
+    }
+}
+
+pub fn render_offer_status(
+    offers: Res<DrawOffers>,
+    mut texts: Query<&mut Text, With<OfferStatus>>,
+) {
+    if !offers.is_changed() {
+        return;
+    }
+    let status = if offers.sent { "Draw offered" } else { "" };
+    for mut text in texts.iter_mut() {
+        text.0 = status.to_string();
+    }
+}
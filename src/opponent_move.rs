@@ -0,0 +1,131 @@
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+use hermanha_chess::{BOARD_COLS, BOARD_ROWS, Position};
+
+use crate::annotations;
+use crate::board_render::Piece;
+use crate::game_state::{MoveOrigin, MovePlayed};
+use crate::theme::Theme;
+use crate::ui::GameUi;
+use crate::{PIECE_SCALE, PIECE_Z, rules};
+
+const ARROW_Z: f32 = PIECE_Z + 0.8;
+const ARROW_ALPHA: f32 = 0.7;
+/// How long the arrow stays before it has faded out.
+const ARROW_SECS: f32 = 2.5;
+const PULSE_SECS: f32 = 0.8;
+/// How much bigger a pulsing piece gets at the top of a pulse.
+const PULSE_GROWTH: f32 = 0.18;
+
+/// The arrow over the opponent's last move, fading out as its timer runs
+/// unless shape markers are on.
+#[derive(Component)]
+pub struct OpponentMoveArrow {
+    timer: Timer,
+    material: Handle<ColorMaterial>,
+}
+
+/// A piece the opponent just moved, briefly growing and shrinking back.
+#[derive(Component)]
+pub struct Pulse(Timer);
+
+/// Every move clears the last arrow. A move from the opponent gets a new
+/// one and pulses the pieces it moved, so castling pulses both the king
+/// and the rook. Runs after the pieces have been brought up to date.
+pub fn mark_opponent_move(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut played: EventReader<MovePlayed>,
+    theme: Res<Theme>,
+    arrows: Query<Entity, With<OpponentMoveArrow>>,
+    pieces: Query<(Entity, &Piece)>,
+) {
+    let Some(played) = played.read().last() else {
+        return;
+    };
+    for entity in arrows.iter() {
+        commands.entity(entity).despawn();
+    }
+    if played.origin != MoveOrigin::Opponent {
+        return;
+    }
+    let material = materials.add(theme.palette.last_move().with_alpha(ARROW_ALPHA));
+    for (mesh, transform) in annotations::arrow(&mut meshes, played.from, played.to, ARROW_Z) {
+        commands.spawn((
+            OpponentMoveArrow {
+                timer: Timer::from_seconds(ARROW_SECS, TimerMode::Once),
+                material: material.clone(),
+            },
+            GameUi,
+            mesh,
+            MeshMaterial2d(material.clone()),
+            transform,
+        ));
+    }
+    let mover = played.before.move_turn;
+    let moved: Vec<Position> = (0..BOARD_ROWS as i8)
+        .flat_map(|row| (0..BOARD_COLS as i8).map(move |col| Position::new(row, col)))
+        .filter(|pos| {
+            let Some(after) = played.after.get(*pos) else {
+                return false;
+            };
+            after.color == mover
+                && played.before.get(*pos).is_none_or(|before| {
+                    before.color != mover || !rules::same_type(before.piece_type, after.piece_type)
+                })
+        })
+        .collect();
+    for (entity, piece) in pieces.iter() {
+        if moved.contains(&piece.pos) {
+            commands
+                .entity(entity)
+                .insert(Pulse(Timer::from_seconds(PULSE_SECS, TimerMode::Once)));
+        }
+    }
+}
+
+/// With shape markers on, the arrow stays until the next move, so the
+/// last move can be found without catching it in time.
+pub fn fade_opponent_move(
+    mut commands: Commands,
+    time: Res<Time>,
+    theme: Res<Theme>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut arrows: Query<(Entity, &mut OpponentMoveArrow)>,
+) {
+    if theme.shape_markers {
+        return;
+    }
+    for (entity, mut arrow) in arrows.iter_mut() {
+        arrow.timer.tick(time.delta());
+        if arrow.timer.finished() {
+            commands.entity(entity).despawn();
+            continue;
+        }
+        if let Some(material) = materials.get_mut(&arrow.material) {
+            material
+                .color
+                .set_alpha(ARROW_ALPHA * (1.0 - arrow.timer.fraction()));
+        }
+    }
+}
+
+/// Two quick pulses, then the piece is back to its normal size.
+pub fn pulse_pieces(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut pieces: Query<(Entity, &mut Pulse, &mut Transform)>,
+) {
+    for (entity, mut pulse, mut transform) in pieces.iter_mut() {
+        pulse.0.tick(time.delta());
+        let growth = if pulse.0.finished() {
+            commands.entity(entity).remove::<Pulse>();
+            0.0
+        } else {
+            (pulse.0.fraction() * 2.0 * PI).sin().abs() * PULSE_GROWTH
+        };
+        transform.scale = Vec3::splat(PIECE_SCALE * (1.0 + growth));
+    }
+}
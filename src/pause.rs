@@ -0,0 +1,165 @@
+use bevy::prelude::*;
+
+use crate::actions::{self, Action, ActiveScopes};
+use crate::config::{self, SettingsPanel};
+use crate::confirm::{self, ConfirmDialog, Confirmable, Confirmed};
+use crate::game_state::{LocalPlayer, Paused};
+use crate::menu::AppState;
+use crate::net::Connection;
+use crate::offers::OfferButton;
+use crate::save::{GameToSave, SaveRequested};
+use crate::ui::GameUi;
+use crate::widgets;
+
+/// The menu Escape opens while a game goes on.
+#[derive(Component)]
+pub struct PauseMenu;
+
+#[derive(Component, Clone, Copy)]
+pub enum PauseButton {
+    Resume,
+    Settings,
+    Save,
+    Menu,
+}
+
+impl PauseButton {
+    fn label(self) -> &'static str {
+        match self {
+            PauseButton::Resume => "Resume",
+            PauseButton::Settings => "Settings",
+            PauseButton::Save => "Save game",
+            PauseButton::Menu => "Back to menu",
+        }
+    }
+}
+
+fn button(kind: impl Component, label: &str) -> impl Bundle {
+    (
+        kind,
+        widgets::button(widgets::button_node(200.0, 36.0), widgets::label(label)),
+    )
+}
+
+/// Offering a draw and resigning are the offer buttons' own, so they work
+/// as they do beside the board. Saving is only for local games, like
+/// Ctrl+S.
+fn spawn_pause_menu(commands: &mut Commands, online: bool, local_player: &LocalPlayer) {
+    let playing = *local_player != LocalPlayer::Watching;
+    commands
+        .spawn((PauseMenu, GameUi, widgets::modal(10.0, 6)))
+        .with_children(|parent| {
+            if online {
+                parent.spawn(widgets::title("Menu", 28.0));
+                parent.spawn(widgets::small_label("The game goes on meanwhile"));
+            } else {
+                parent.spawn(widgets::title("Paused", 28.0));
+            }
+            parent.spawn(button(PauseButton::Resume, PauseButton::Resume.label()));
+            if playing && (online || *local_player == LocalPlayer::Both) {
+                parent.spawn(button(
+                    OfferButton::OfferDraw,
+                    OfferButton::OfferDraw.label(),
+                ));
+            }
+            if playing {
+                parent.spawn(button(OfferButton::Resign, OfferButton::Resign.label()));
+            }
+            parent.spawn(button(PauseButton::Settings, PauseButton::Settings.label()));
+            if !online {
+                parent.spawn(button(PauseButton::Save, PauseButton::Save.label()));
+            }
+            parent.spawn(button(PauseButton::Menu, PauseButton::Menu.label()));
+        });
+}
+
+fn close(commands: &mut Commands, menus: &Query<Entity, With<PauseMenu>>, paused: &mut Paused) {
+    for entity in menus.iter() {
+        commands.entity(entity).despawn();
+    }
+    paused.0 = false;
+}
+
+/// Escape opens the pause menu and closes it again. A selected piece is
+/// dropped first, the key belonging to the cursor's scope while there is
+/// one, and the settings panel and questions keep the key while they're
+/// open. Only games played on this machine stop; online the menu just
+/// covers the board.
+#[allow(clippy::too_many_arguments)]
+pub fn toggle_pause(
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    scopes: Res<ActiveScopes>,
+    connection: Option<Res<Connection>>,
+    local_player: Res<LocalPlayer>,
+    menus: Query<Entity, With<PauseMenu>>,
+    above: Query<(), Or<(With<SettingsPanel>, With<ConfirmDialog>)>>,
+    mut paused: ResMut<Paused>,
+) {
+    if !actions::just_pressed_in(&keys, &scopes, Action::Pause) || !above.is_empty() {
+        return;
+    }
+    if !menus.is_empty() {
+        close(&mut commands, &menus, &mut paused);
+        return;
+    }
+    spawn_pause_menu(&mut commands, connection.is_some(), &local_player);
+    paused.0 = connection.is_none();
+}
+
+/// Going back to the menu from a game under way is asked about first
+/// online, where it ends the game for the opponent too, and locally when
+/// moves would be lost.
+#[allow(clippy::too_many_arguments)]
+pub fn handle_pause_buttons(
+    mut commands: Commands,
+    interactions: Query<(&Interaction, &PauseButton), Changed<Interaction>>,
+    menus: Query<Entity, With<PauseMenu>>,
+    settings: Query<(), With<SettingsPanel>>,
+    dialogs: Query<(), With<ConfirmDialog>>,
+    mut confirmed: EventReader<Confirmed>,
+    connection: Option<Res<Connection>>,
+    game: GameToSave,
+    mut paused: ResMut<Paused>,
+    mut saves: EventWriter<SaveRequested>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    if confirmed
+        .read()
+        .any(|confirmed| matches!(confirmed.0, Confirmable::Quit | Confirmable::LeaveUnsaved))
+    {
+        next_state.set(AppState::Menu);
+    }
+    for (interaction, button) in interactions.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        match button {
+            PauseButton::Resume => close(&mut commands, &menus, &mut paused),
+            PauseButton::Settings => {
+                if settings.is_empty() {
+                    config::spawn_settings_panel(&mut commands);
+                }
+            }
+            PauseButton::Save => {
+                saves.write(SaveRequested);
+            }
+            PauseButton::Menu if connection.is_some() => {
+                confirm::ask(&mut commands, &dialogs, Confirmable::Quit)
+            }
+            PauseButton::Menu if game.unsaved() => {
+                confirm::ask(&mut commands, &dialogs, Confirmable::LeaveUnsaved)
+            }
+            PauseButton::Menu => next_state.set(AppState::Menu),
+        }
+    }
+}
+
+/// The menu goes away, and the clocks start again, once the game is over.
+pub fn close_pause_menu(
+    mut commands: Commands,
+    menus: Query<Entity, With<PauseMenu>>,
+    mut paused: ResMut<Paused>,
+) {
+    close(&mut commands, &menus, &mut paused);
+}
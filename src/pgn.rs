@@ -0,0 +1,171 @@
+use hermanha_chess::{Board, Color as HermanhaColor};
+
+use crate::fen::{board_from_fen, position_to_fen};
+use crate::history::MoveHistory;
+use crate::offers::Conclusion;
+use crate::rules::{self, CastlingRights, Outcome};
+use crate::san;
+use crate::variant::Variant;
+
+/// Movetext lines are wrapped before this many characters.
+const LINE_WIDTH: usize = 80;
+
+/// The PGN result token for a game: "1-0", "0-1", "1/2-1/2", or "*" while
+/// it is still going on.
+pub fn result_token(
+    outcome: Option<Outcome>,
+    conclusion: Option<Conclusion>,
+    flagged: Option<HermanhaColor>,
+) -> &'static str {
+    let winner = match (conclusion, outcome, flagged) {
+        (Some(Conclusion::DrawAgreed), _, _) => None,
+        (
+            Some(
+                Conclusion::Resigned(color)
+                | Conclusion::Abandoned(color)
+                | Conclusion::MoveTimedOut(color),
+            ),
+            _,
+            _,
+        ) => Some(rules::opponent(color)),
+        (Some(Conclusion::Aborted), _, _) => return "*",
+        (None, Some(Outcome::Checkmate(winner)), _) => Some(winner),
+        (None, Some(_), _) => None,
+        (None, None, Some(flagged)) => Some(rules::opponent(flagged)),
+        (None, None, None) => return "*",
+    };
+    match winner {
+        Some(HermanhaColor::White) => "1-0",
+        Some(HermanhaColor::Black) => "0-1",
+        None => "1/2-1/2",
+    }
+}
+
+/// The game as PGN: the tags for the players and the result, the start
+/// position if it isn't the standard one, the seed the computer played
+/// from if it did, and the moves.
+pub fn to_pgn(
+    history: &MoveHistory,
+    start: (&Board, CastlingRights),
+    white: &str,
+    black: &str,
+    result: &str,
+    seed: Option<u64>,
+) -> String {
+    let mut pgn = String::new();
+    for (tag, value) in [
+        ("Event", "Casual game"),
+        ("White", white),
+        ("Black", black),
+        ("Result", result),
+    ] {
+        pgn.push_str(&format!("[{tag} \"{}\"]\n", value.replace('"', "'")));
+    }
+    let (standard, standard_castling) = Variant::Standard.start();
+    let start_fen = position_to_fen(start.0, start.1);
+    if start_fen != position_to_fen(&standard, standard_castling) {
+        pgn.push_str("[SetUp \"1\"]\n");
+        pgn.push_str(&format!("[FEN \"{start_fen} - 0 1\"]\n"));
+    }
+    if let Some(seed) = seed {
+        pgn.push_str(&format!("[Seed \"{seed}\"]\n"));
+    }
+    pgn.push('\n');
+
+    let black_starts = start.0.move_turn == HermanhaColor::Black;
+    let mut tokens: Vec<String> = Vec::new();
+    for (index, played) in history.moves.iter().enumerate() {
+        let ply = index + black_starts as usize;
+        if ply % 2 == 0 {
+            tokens.push(format!("{}.", ply / 2 + 1));
+        } else if index == 0 {
+            tokens.push(format!("{}...", ply / 2 + 1));
+        }
+        tokens.push(played.san.clone());
+    }
+    tokens.push(result.to_string());
+    let mut line = String::new();
+    for token in tokens {
+        if !line.is_empty() && line.len() + 1 + token.len() > LINE_WIDTH {
+            pgn.push_str(&line);
+            pgn.push('\n');
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(&token);
+    }
+    pgn.push_str(&line);
+    pgn.push('\n');
+    pgn
+}
+
+/// Whether `text` looks like PGN rather than a FEN: it has tags, or starts
+/// with a move number.
+pub fn looks_like_pgn(text: &str) -> bool {
+    let text = text.trim_start();
+    text.starts_with('[') || text.starts_with("1.")
+}
+
+/// Plays the moves of a PGN game from its start position, the standard
+/// one unless a FEN tag gives another, and gives the board, castling
+/// rights and history they lead to. Comments, variations, move numbers,
+/// annotations and the result are skipped.
+pub fn parse_pgn(text: &str) -> Result<(Board, CastlingRights, MoveHistory), String> {
+    let mut start = None;
+    let mut movetext = String::new();
+    for line in text.lines().map(str::trim) {
+        if let Some(tag) = line.strip_prefix('[') {
+            let tag = tag.trim_end_matches(']');
+            if let Some(value) = tag.strip_prefix("FEN ") {
+                start = Some(board_from_fen(value.trim().trim_matches('"'))?);
+            }
+        } else if !line.starts_with('%') {
+            movetext.push_str(line);
+            movetext.push(' ');
+        }
+    }
+    let (mut board, mut castling) = start.unwrap_or_else(|| Variant::Standard.start());
+    let mut history = MoveHistory::default();
+    let mut depth = 0;
+    let mut in_comment = false;
+    let mut cleaned = String::new();
+    for c in movetext.chars() {
+        match c {
+            '{' => in_comment = true,
+            '}' => in_comment = false,
+            '(' if !in_comment => depth += 1,
+            ')' if !in_comment => depth -= 1,
+            _ if !in_comment && depth == 0 => cleaned.push(c),
+            _ => {}
+        }
+    }
+    for token in cleaned.split_whitespace() {
+        if ["1-0", "0-1", "1/2-1/2", "*"].contains(&token) {
+            continue;
+        }
+        // "12.e4" has its move number attached.
+        let token = token.rsplit('.').next().unwrap_or(token);
+        if token.is_empty() || token.starts_with('$') {
+            continue;
+        }
+        let (from, to, promotion_piece) = san::parse_move(&board, castling, token)?;
+        let before = board.clone();
+        board = rules::play_move(&board, castling, from, to, promotion_piece)
+            .ok_or_else(|| format!("Illegal move {token}"))?;
+        history.push(&before, castling, from, to, promotion_piece);
+        castling.update(from, to);
+    }
+    Ok((board, castling, history))
+}
+
+/// A FEN or PGN, whichever `text` is.
+pub fn parse_fen_or_pgn(text: &str) -> Result<(Board, CastlingRights, MoveHistory), String> {
+    if looks_like_pgn(text) {
+        parse_pgn(text)
+    } else {
+        let (board, castling) = board_from_fen(text.trim())?;
+        Ok((board, castling, MoveHistory::default()))
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+use std::fs;
+
+use bevy::asset::RenderAssetUsages;
+use bevy::asset::io::file::FileAssetReader;
+use bevy::prelude::*;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+use bevy::window::PrimaryWindow;
+use hermanha_chess::{Color as HermanhaColor, PieceType};
+use resvg::{tiny_skia, usvg};
+
+use crate::board_render::BoardCamera;
+use crate::theme::Theme;
+use crate::{PIECE_SIZE, TILE_SIZE, san};
+
+/// Raster sizes are rounded up to a multiple of this many pixels, so
+/// dragging the window's edge doesn't redraw the pieces every frame.
+const RASTER_STEP: u32 = 16;
+
+const MIN_RASTER_SIZE: u32 = 32;
+const MAX_RASTER_SIZE: u32 = 512;
+
+/// The piece SVGs of the current set, rasterized at the size a square
+/// takes on screen in physical pixels. Drawing them as vector meshes
+/// scaled up from their 45px canvas leaves them soft on high-DPI
+/// displays, so they are redrawn whenever the window's scale factor, the
+/// board's zoom or the piece set changes.
+#[derive(Resource, Default)]
+pub struct PieceImages {
+    /// Pixels across each image; 0 until the pieces are first drawn.
+    pixels: u32,
+    piece_set: String,
+    images: HashMap<(bool, char), Handle<Image>>,
+}
+
+impl PieceImages {
+    fn key(color: HermanhaColor, piece_type: PieceType) -> (bool, char) {
+        (color == HermanhaColor::White, san::piece_letter(piece_type))
+    }
+
+    /// A sprite of the piece, the size of the SVG canvas so that
+    /// `PIECE_SCALE` brings it to a square's size. Stays blank until the
+    /// pieces are first rasterized.
+    pub fn sprite(&self, color: HermanhaColor, piece_type: PieceType) -> impl Bundle {
+        let image = self
+            .images
+            .get(&PieceImages::key(color, piece_type))
+            .cloned()
+            .unwrap_or_default();
+        (
+            PieceImage { color, piece_type },
+            Sprite {
+                image,
+                custom_size: Some(Vec2::splat(PIECE_SIZE)),
+                ..default()
+            },
+        )
+    }
+}
+
+/// A sprite showing a piece, given a new image whenever the pieces are
+/// rasterized again.
+#[derive(Component, Clone, Copy)]
+pub struct PieceImage {
+    pub color: HermanhaColor,
+    pub piece_type: PieceType,
+}
+
+/// Draws the SVG at `path`, relative to the asset directory, into a
+/// `pixels`-wide square image.
+fn rasterize(path: &str, pixels: u32) -> Result<Image, String> {
+    let path = FileAssetReader::new("assets").root_path().join(path);
+    let data = fs::read(&path).map_err(|err| format!("{}: {err}", path.display()))?;
+    let tree = usvg::Tree::from_data(&data, &usvg::Options::default())
+        .map_err(|err| format!("{}: {err}", path.display()))?;
+    let mut pixmap = tiny_skia::Pixmap::new(pixels, pixels).ok_or("Empty image")?;
+    let size = tree.size();
+    let transform = tiny_skia::Transform::from_scale(
+        pixels as f32 / size.width(),
+        pixels as f32 / size.height(),
+    );
+    resvg::render(&tree, transform, &mut pixmap.as_mut());
+    // tiny-skia keeps colors premultiplied by alpha; Bevy expects them
+    // straight.
+    let data = pixmap
+        .pixels()
+        .iter()
+        .flat_map(|pixel| {
+            let color = pixel.demultiply();
+            [color.red(), color.green(), color.blue(), color.alpha()]
+        })
+        .collect();
+    Ok(Image::new(
+        Extent3d {
+            width: pixels,
+            height: pixels,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::RENDER_WORLD,
+    ))
+}
+
+/// How many physical pixels a square takes on screen: `TILE_SIZE` world
+/// units, shrunk by the camera's zoom and grown by the window's scale
+/// factor, rounded up to the next `RASTER_STEP`.
+fn raster_size(scale_factor: f32, zoom: f32) -> u32 {
+    let pixels = (TILE_SIZE * scale_factor / zoom).ceil() as u32;
+    pixels
+        .div_ceil(RASTER_STEP)
+        .saturating_mul(RASTER_STEP)
+        .clamp(MIN_RASTER_SIZE, MAX_RASTER_SIZE)
+}
+
+/// Rasterizes the pieces again when the main window moves to a display
+/// with another scale factor, the board is zoomed to fit a resized window,
+/// or another piece set is chosen, and hands the new images to every
+/// piece sprite.
+pub fn rasterize_pieces(
+    mut piece_images: ResMut<PieceImages>,
+    mut images: ResMut<Assets<Image>>,
+    theme: Res<Theme>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    projections: Query<&Projection, With<BoardCamera>>,
+    mut sprites: Query<(&PieceImage, &mut Sprite)>,
+) {
+    let Ok(window) = windows.single() else {
+        return;
+    };
+    let zoom = projections
+        .iter()
+        .find_map(|projection| match projection {
+            Projection::Orthographic(orthographic) => Some(orthographic.scale),
+            _ => None,
+        })
+        .unwrap_or(1.0);
+    let pixels = raster_size(window.scale_factor(), zoom);
+    let piece_set = &theme.piece_set().dir;
+    if pixels == piece_images.pixels && *piece_set == piece_images.piece_set {
+        return;
+    }
+    piece_images.pixels = pixels;
+    piece_images.piece_set = piece_set.clone();
+    for color in [HermanhaColor::White, HermanhaColor::Black] {
+        for piece_type in [
+            PieceType::Pawn,
+            PieceType::Knight,
+            PieceType::Bishop,
+            PieceType::Rook,
+            PieceType::Queen,
+            PieceType::King,
+        ] {
+            match rasterize(&theme.piece_path(color, piece_type), pixels) {
+                Ok(image) => {
+                    piece_images
+                        .images
+                        .insert(PieceImages::key(color, piece_type), images.add(image));
+                }
+                Err(err) => warn!("Could not draw piece: {err}"),
+            }
+        }
+    }
+    for (piece, mut sprite) in sprites.iter_mut() {
+        if let Some(image) = piece_images
+            .images
+            .get(&PieceImages::key(piece.color, piece.piece_type))
+        {
+            sprite.image = image.clone();
+        }
+    }
+}
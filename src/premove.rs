@@ -0,0 +1,154 @@
+use bevy::prelude::*;
+use bevy::window::PrimaryWindow;
+use hermanha_chess::Position;
+
+use crate::board_render::BoardCamera;
+use crate::game_state::{BoardState, Castling, LocalPlayer, MoveOrigin, MoveRequested};
+use crate::input::legal_targets;
+use crate::net::Desync;
+use crate::offers::Concluded;
+use crate::{TILE_SIZE, cursor_to_board_position, hint, pos_to_vec3};
+
+/// A move queued during the opponent's turn. `to` is empty while only the
+/// piece has been picked.
+#[derive(Resource, Default)]
+pub struct Premove {
+    from: Option<Position>,
+    to: Option<Position>,
+}
+
+impl Premove {
+    pub fn clear(&mut self) {
+        *self = Premove::default();
+    }
+}
+
+#[derive(Component)]
+pub struct PremoveMarker;
+
+fn waiting(board: &BoardState, local_player: &LocalPlayer) -> bool {
+    matches!(local_player, LocalPlayer::One(_)) && !local_player.controls(board.0.move_turn)
+}
+
+/// While the opponent is to move, a click on one of our pieces and then on
+/// a target square queues that move. Right click throws it away. The
+/// target isn't checked yet since the position is about to change.
+pub fn queue_premove(
+    buttons: Res<ButtonInput<MouseButton>>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    camera_q: Query<(&Camera, &GlobalTransform), With<BoardCamera>>,
+    board: Res<BoardState>,
+    local_player: Res<LocalPlayer>,
+    mut premove: ResMut<Premove>,
+) {
+    if !waiting(&board, &local_player) {
+        return;
+    }
+    if buttons.just_pressed(MouseButton::Right) {
+        premove.clear();
+        return;
+    }
+    if !buttons.just_pressed(MouseButton::Left) {
+        return;
+    }
+    let Some(cursor_position) = windows.iter().next().and_then(Window::cursor_position) else {
+        return;
+    };
+    let Some((camera, camera_transform)) = camera_q.iter().next() else {
+        return;
+    };
+    let Some(position) = cursor_to_board_position(cursor_position, camera, camera_transform, &[])
+    else {
+        return;
+    };
+    if !board.0.pos_on_board(position) {
+        return;
+    }
+    let own_piece = board
+        .0
+        .get(position)
+        .is_some_and(|piece| local_player.controls(piece.color));
+    match (premove.from, premove.to) {
+        (Some(from), None) if from == position => premove.clear(),
+        (Some(_), None) => premove.to = Some(position),
+        _ if own_piece => {
+            premove.from = Some(position);
+            premove.to = None;
+        }
+        _ => premove.clear(),
+    }
+}
+
+/// Requests the queued move as soon as it's our turn again, if it's legal
+/// in the new position. Pawns reaching the last rank become queens.
+pub fn play_premove(
+    board: Res<BoardState>,
+    castling: Res<Castling>,
+    local_player: Res<LocalPlayer>,
+    mut requests: EventWriter<MoveRequested>,
+    mut premove: ResMut<Premove>,
+    concluded: Res<Concluded>,
+    desync: Res<Desync>,
+) {
+    let (Some(from), Some(to)) = (premove.from, premove.to) else {
+        return;
+    };
+    if waiting(&board, &local_player) {
+        return;
+    }
+    premove.clear();
+    let legal = legal_targets(&board.0, castling.0, from)
+        .iter()
+        .any(|(target, _)| *target == to);
+    if !legal || concluded.0.is_some() || desync.0.is_some() {
+        return;
+    }
+    requests.write(MoveRequested {
+        from,
+        to,
+        promotion_piece: hint::promotion_for(&board.0, from, to),
+        origin: MoveOrigin::Local,
+    });
+}
+
+pub fn render_premove(
+    mut commands: Commands,
+    premove: Res<Premove>,
+    markers: Query<Entity, With<PremoveMarker>>,
+) {
+    if !premove.is_changed() {
+        return;
+    }
+    for entity in markers.iter() {
+        commands.entity(entity).despawn();
+    }
+    for pos in premove.from.into_iter().chain(premove.to) {
+        commands.spawn((
+            PremoveMarker,
+            Sprite {
+                color: Color::srgba(0.55, 0.3, 0.8, 0.45),
+                custom_size: Some(Vec2::splat(TILE_SIZE)),
+                ..default()
+            },
+            Transform::from_translation(pos_to_vec3(pos, 0.3)),
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hermanha_chess::{Board, Color as HermanhaColor};
+
+    #[test]
+    fn premoves_wait_only_for_an_opponent() {
+        let mut board = BoardState(Board::start_pos());
+        let white = LocalPlayer::One(HermanhaColor::White);
+        assert!(!waiting(&board, &white));
+        assert!(!waiting(&board, &LocalPlayer::Both));
+        board.0.move_turn = HermanhaColor::Black;
+        assert!(waiting(&board, &white));
+        assert!(!waiting(&board, &LocalPlayer::Both));
+        assert!(!waiting(&board, &LocalPlayer::Watching));
+    }
+}
@@ -0,0 +1,94 @@
+use bevy::prelude::*;
+use hermanha_chess::{BOARD_COLS, BOARD_ROWS, Color as HermanhaColor, PieceType, Position};
+
+use crate::board_render::Upright;
+use crate::piece_images::PieceImages;
+use crate::{PIECE_SCALE, PIECE_Z, TILE_SIZE, pos_to_vec3};
+
+const CHOICES: [PieceType; 4] = [
+    PieceType::Queen,
+    PieceType::Rook,
+    PieceType::Bishop,
+    PieceType::Knight,
+];
+
+#[derive(Clone, Copy)]
+pub struct Promotion {
+    pub from: Position,
+    pub to: Position,
+    pub color: HermanhaColor,
+}
+
+impl Promotion {
+    /// The choices are stacked on the promotion file, starting on the
+    /// promotion square and running toward the middle of the board.
+    fn square(&self, index: usize) -> Position {
+        let dir = if self.to.row == 0 { 1 } else { -1 };
+        Position::new(self.to.row + dir * index as i8, self.to.col)
+    }
+
+    pub fn choice_at(&self, pos: Position) -> Option<PieceType> {
+        (0..CHOICES.len())
+            .find(|index| self.square(*index) == pos)
+            .map(|index| CHOICES[index])
+    }
+}
+
+/// A pawn move waiting for the player to pick a piece. While this is set
+/// the board ignores every click that isn't on one of the choices.
+#[derive(Resource, Default)]
+pub struct PendingPromotion(pub Option<Promotion>);
+
+#[derive(Component)]
+pub struct PromotionDialog;
+
+pub fn render_promotion_dialog(
+    mut commands: Commands,
+    piece_images: Res<PieceImages>,
+    pending: Res<PendingPromotion>,
+    dialogs: Query<Entity, With<PromotionDialog>>,
+) {
+    if !pending.is_changed() {
+        return;
+    }
+    for entity in dialogs.iter() {
+        commands.entity(entity).despawn();
+    }
+    let Some(promotion) = pending.0 else {
+        return;
+    };
+    commands.spawn((
+        PromotionDialog,
+        Sprite {
+            color: Color::srgba(0.0, 0.0, 0.0, 0.5),
+            custom_size: Some(Vec2::new(
+                BOARD_COLS as f32 * TILE_SIZE,
+                BOARD_ROWS as f32 * TILE_SIZE,
+            )),
+            ..default()
+        },
+        Transform::from_translation(Vec3::new(0.0, 0.0, 4.0)),
+    ));
+    for (index, piece_type) in CHOICES.into_iter().enumerate() {
+        let pos = promotion.square(index);
+        commands.spawn((
+            PromotionDialog,
+            Sprite {
+                color: Color::srgb(0.95, 0.95, 0.95),
+                custom_size: Some(Vec2::splat(TILE_SIZE)),
+                ..default()
+            },
+            Transform::from_translation(pos_to_vec3(pos, 4.5)),
+        ));
+        commands.spawn((
+            PromotionDialog,
+            Upright,
+            piece_images.sprite(promotion.color, piece_type),
+            Transform {
+                translation: pos_to_vec3(pos, 4.5 + PIECE_Z),
+                scale: Vec3::splat(PIECE_SCALE),
+                ..default()
+            },
+        ));
+    }
+}
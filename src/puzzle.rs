@@ -0,0 +1,410 @@
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use bevy::prelude::*;
+use hermanha_chess::{Color as HermanhaColor, PieceType, Position};
+
+use crate::fen;
+use crate::game_state::{LocalPlayer, MoveOrigin, MovePlayed, MoveRequested, NewGame};
+use crate::history::MoveHistory;
+use crate::rules;
+use crate::san;
+use crate::ui::GameUi;
+use crate::widgets;
+
+/// How long the opponent's side of the solution waits before it is
+/// played, so it can be followed.
+const REPLY_DELAY: Duration = Duration::from_millis(700);
+
+/// How long a solved or failed puzzle stays on the board before the next
+/// one comes up.
+const NEXT_DELAY: Duration = Duration::from_secs(2);
+
+/// The pack played without `--puzzles=`, in the Lichess puzzle CSV format.
+const BUILT_IN: &str = "\
+PuzzleId,FEN,Moves,Rating,RatingDeviation,Popularity,NbPlays,Themes,GameUrl,OpeningTags
+backrank,6k1/p4ppp/8/8/8/8/5PPP/3R2K1 b - - 0 1,a7a6 d1d8,600,,,,backRankMate mateIn1,,
+fork,r3k3/7p/8/1N6/8/8/8/4K3 b - - 0 1,h7h6 b5c7 e8d7 c7a8,800,,,,fork,,
+corner,k7/7p/1K6/8/8/8/8/5Q2 b - - 0 1,h7h6 f1f8,600,,,,mateIn1,,
+";
+
+type PuzzleMove = (Position, Position, Option<PieceType>);
+
+/// A position and the moves that solve it. The first move is the
+/// opponent's and is played before the solver gets to move; from there
+/// the moves alternate between the solver and the opponent, ending with
+/// the solver's.
+pub struct Puzzle {
+    pub id: String,
+    pub fen: String,
+    pub moves: Vec<PuzzleMove>,
+}
+
+impl Puzzle {
+    /// One line of the Lichess puzzle database:
+    /// `PuzzleId,FEN,Moves,Rating,...` with the moves in UCI notation.
+    /// Only the first three fields are read.
+    pub fn from_csv_line(line: &str) -> Result<Self, String> {
+        let mut fields = line.split(',');
+        let (Some(id), Some(fen), Some(moves)) = (fields.next(), fields.next(), fields.next())
+        else {
+            return Err(format!("Expected PuzzleId,FEN,Moves: {line}"));
+        };
+        fen::board_from_fen(fen).map_err(|err| format!("Puzzle {id}: {err}"))?;
+        let moves = moves
+            .split_whitespace()
+            .map(san::parse_uci)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| format!("Puzzle {id}: {err}"))?;
+        if moves.len() < 2 || moves.len() % 2 != 0 {
+            return Err(format!("Puzzle {id}: expected an even number of moves"));
+        }
+        Ok(Puzzle {
+            id: id.to_string(),
+            fen: fen.to_string(),
+            moves,
+        })
+    }
+}
+
+/// The puzzles on offer: the built-in ones, or those of a Lichess puzzle
+/// CSV given with `--puzzles=`.
+#[derive(Resource)]
+pub struct PuzzlePack(pub Vec<Puzzle>);
+
+impl Default for PuzzlePack {
+    fn default() -> Self {
+        PuzzlePack::parse(BUILT_IN).expect("the built-in puzzles are valid")
+    }
+}
+
+impl PuzzlePack {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|err| err.to_string())?;
+        PuzzlePack::parse(&text)
+    }
+
+    /// The header line is optional; lines after it are puzzles.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let puzzles = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with("PuzzleId,"))
+            .map(Puzzle::from_csv_line)
+            .collect::<Result<Vec<_>, _>>()?;
+        if puzzles.is_empty() {
+            return Err("No puzzles in the pack".to_string());
+        }
+        Ok(PuzzlePack(puzzles))
+    }
+}
+
+/// Present while puzzles are being solved: which one is on the board, how
+/// far into its solution the board is, and the score so far. Every
+/// puzzle attempted moves on to the next one in the pack.
+#[derive(Resource)]
+pub struct PuzzleSession {
+    index: usize,
+    /// The move of the solution to be played next, by either side.
+    step: usize,
+    solver: HermanhaColor,
+    /// Counts down to the opponent's next move.
+    reply: Option<Timer>,
+    /// Counts down to the next puzzle; the current one is over.
+    next: Option<Timer>,
+    solved: u32,
+    attempted: u32,
+    streak: u32,
+    best_streak: u32,
+    feedback: String,
+}
+
+impl Default for PuzzleSession {
+    fn default() -> Self {
+        PuzzleSession {
+            index: 0,
+            step: 0,
+            solver: HermanhaColor::White,
+            reply: None,
+            next: Some(Timer::new(Duration::ZERO, TimerMode::Once)),
+            solved: 0,
+            attempted: 0,
+            streak: 0,
+            best_streak: 0,
+            feedback: String::new(),
+        }
+    }
+}
+
+impl PuzzleSession {
+    /// The solver moves their own pieces, and nothing once the puzzle is
+    /// over.
+    pub fn local_player(&self) -> LocalPlayer {
+        match self.next {
+            Some(_) => LocalPlayer::Watching,
+            None => LocalPlayer::One(self.solver),
+        }
+    }
+
+    /// Ends the current puzzle and moves on to the next one after `delay`.
+    fn finish(&mut self, solved: bool, feedback: String, delay: Duration) {
+        self.attempted += 1;
+        if solved {
+            self.solved += 1;
+            self.streak += 1;
+            self.best_streak = self.best_streak.max(self.streak);
+        } else {
+            self.streak = 0;
+        }
+        self.feedback = feedback;
+        self.reply = None;
+        self.next = Some(Timer::new(delay, TimerMode::Once));
+    }
+}
+
+#[derive(Component)]
+pub struct PuzzlePanel;
+
+#[derive(Component)]
+pub struct PuzzleStatus;
+
+#[derive(Component)]
+pub struct SkipPuzzleButton;
+
+pub fn spawn_puzzle_panel(mut commands: Commands) {
+    commands.spawn((
+        PuzzlePanel,
+        GameUi,
+        Node {
+            position_type: PositionType::Absolute,
+            left: Val::Px(8.0),
+            top: Val::Px(120.0),
+            width: Val::Px(220.0),
+            padding: UiRect::all(Val::Px(10.0)),
+            flex_direction: FlexDirection::Column,
+            row_gap: Val::Px(8.0),
+            ..default()
+        },
+        BackgroundColor(widgets::PANEL_COLOR),
+        children![
+            widgets::label("Puzzles"),
+            (PuzzleStatus, widgets::small_label("")),
+            (
+                SkipPuzzleButton,
+                widgets::button(widgets::button_node(200.0, 28.0), widgets::label("Skip")),
+            ),
+        ],
+    ));
+}
+
+/// Sets up the next puzzle once the last one has been on the board long
+/// enough, and plays the opponent's moves of the solution, starting with
+/// the one before the solver's first. The pack starts over after its last
+/// puzzle.
+pub fn advance_puzzles(
+    mut session: ResMut<PuzzleSession>,
+    pack: Res<PuzzlePack>,
+    time: Res<Time>,
+    mut new_game: NewGame,
+    mut requests: EventWriter<MoveRequested>,
+) {
+    if let Some(timer) = session.next.as_mut() {
+        if !timer.tick(time.delta()).finished() {
+            return;
+        }
+        session.index = session.attempted as usize % pack.0.len();
+        let puzzle = &pack.0[session.index];
+        let Ok((board, castling)) = fen::board_from_fen(&puzzle.fen) else {
+            return;
+        };
+        session.solver = rules::opponent(board.move_turn);
+        new_game.start_from(board, castling, MoveHistory::default());
+        session.step = 0;
+        session.next = None;
+        session.reply = Some(Timer::new(REPLY_DELAY, TimerMode::Once));
+        session.feedback = match session.solver {
+            HermanhaColor::White => "Find the best move for White".to_string(),
+            HermanhaColor::Black => "Find the best move for Black".to_string(),
+        };
+        return;
+    }
+    let Some(timer) = session.reply.as_mut() else {
+        return;
+    };
+    if !timer.tick(time.delta()).finished() {
+        return;
+    }
+    session.reply = None;
+    let Some(&(from, to, promotion_piece)) = pack.0[session.index].moves.get(session.step) else {
+        return;
+    };
+    requests.write(MoveRequested {
+        from,
+        to,
+        promotion_piece,
+        origin: MoveOrigin::Opponent,
+    });
+}
+
+/// Checks the solver's moves against the solution. Any move that mates
+/// counts on the last move, since a puzzle can have more than one mate.
+/// A wrong move ends the puzzle and shows what the solution was.
+pub fn check_puzzle_moves(
+    mut session: ResMut<PuzzleSession>,
+    pack: Res<PuzzlePack>,
+    mut played: EventReader<MovePlayed>,
+) {
+    for played in played.read() {
+        if session.next.is_some() {
+            continue;
+        }
+        let moves = &pack.0[session.index].moves;
+        let Some(&(from, to, promotion_piece)) = moves.get(session.step) else {
+            continue;
+        };
+        if played.origin == MoveOrigin::Opponent {
+            session.step += 1;
+            continue;
+        }
+        let last = session.step + 1 == moves.len();
+        let mates = played.after.legal_moves().is_empty()
+            && rules::in_check(&played.after, played.after.move_turn);
+        let correct = played.from == from
+            && played.to == to
+            && (promotion_piece.is_none() || played.promotion_piece == promotion_piece);
+        if !correct && !(last && mates) {
+            let solution = san::move_to_san(&played.before, from, to, promotion_piece);
+            session.finish(
+                false,
+                format!("Not quite, the solution was {solution}"),
+                NEXT_DELAY * 2,
+            );
+        } else if last {
+            session.finish(true, "Solved!".to_string(), NEXT_DELAY);
+        } else {
+            session.step += 1;
+            session.feedback = "Correct, keep going".to_string();
+            session.reply = Some(Timer::new(REPLY_DELAY, TimerMode::Once));
+        }
+    }
+}
+
+/// Skipping a puzzle counts as failing it.
+pub fn handle_puzzle_buttons(
+    interactions: Query<&Interaction, (Changed<Interaction>, With<SkipPuzzleButton>)>,
+    mut session: ResMut<PuzzleSession>,
+) {
+    if session.next.is_some()
+        || !interactions
+            .iter()
+            .any(|interaction| *interaction == Interaction::Pressed)
+    {
+        return;
+    }
+    session.finish(false, "Skipped".to_string(), Duration::ZERO);
+}
+
+pub fn render_puzzle_panel(
+    session: Res<PuzzleSession>,
+    pack: Res<PuzzlePack>,
+    mut statuses: Query<&mut Text, With<PuzzleStatus>>,
+) {
+    let status = format!(
+        "Puzzle {} of {} ({})\n\n{}\n\nSolved {} of {}\nStreak {}, best {}",
+        session.index + 1,
+        pack.0.len(),
+        pack.0[session.index].id,
+        session.feedback,
+        session.solved,
+        session.attempted,
+        session.streak,
+        session.best_streak
+    );
+    for mut text in statuses.iter_mut() {
+        if text.0 != status {
+            text.0 = status.clone();
+        }
+    }
+}
+
+pub fn stop_puzzles(mut commands: Commands) {
+    commands.remove_resource::<PuzzleSession>();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BACK_RANK: &str = "6k1/p4ppp/8/8/8/8/5PPP/3R2K1 b - - 0 1";
+
+    #[test]
+    fn lichess_rows_are_read() {
+        assert_eq!(PuzzlePack::default().0.len(), 3);
+        let line = format!("00sHx,{BACK_RANK},a7a6 d1d8,600,75,95,420,mateIn1,,");
+        let puzzle = Puzzle::from_csv_line(&line).unwrap();
+        assert_eq!(
+            (puzzle.id.as_str(), puzzle.fen.as_str()),
+            ("00sHx", BACK_RANK)
+        );
+        assert_eq!(puzzle.moves.len(), 2);
+        // Without the header, too.
+        assert_eq!(PuzzlePack::parse(&line).unwrap().0.len(), 1);
+    }
+
+    #[test]
+    fn malformed_rows_are_rejected() {
+        let cases = [
+            ("no-fields".to_string(), "Expected PuzzleId,FEN,Moves"),
+            (
+                format!("no-moves,{BACK_RANK}"),
+                "Expected PuzzleId,FEN,Moves",
+            ),
+            ("bad-fen,6k1/8/8,a7a6 d1d8".to_string(), "Puzzle bad-fen: "),
+            (
+                format!("off-board,{BACK_RANK},a7a6 d1d9"),
+                "Puzzle off-board: Invalid move: d1d9",
+            ),
+            (
+                format!("bad-promotion,{BACK_RANK},a7a6 d1d8k"),
+                "Puzzle bad-promotion: Invalid move: d1d8k",
+            ),
+            (
+                format!("san,{BACK_RANK},a6 Rd8#"),
+                "Puzzle san: Invalid move: a6",
+            ),
+            (
+                format!("empty,{BACK_RANK},"),
+                "Puzzle empty: expected an even number of moves",
+            ),
+            (
+                format!("one,{BACK_RANK},a7a6"),
+                "Puzzle one: expected an even number of moves",
+            ),
+            (
+                format!("odd,{BACK_RANK},a7a6 d1d8 g8f8"),
+                "Puzzle odd: expected an even number of moves",
+            ),
+        ];
+        for (line, error) in cases {
+            let err = Puzzle::from_csv_line(&line).err().unwrap();
+            assert!(err.starts_with(error), "{line}: {err}");
+        }
+    }
+
+    #[test]
+    fn packs_need_every_row_valid_and_at_least_one() {
+        for text in ["", "\n\n", "PuzzleId,FEN,Moves,Rating\n"] {
+            assert_eq!(
+                PuzzlePack::parse(text).err().as_deref(),
+                Some("No puzzles in the pack")
+            );
+        }
+        let text =
+            format!("PuzzleId,FEN,Moves\ngood,{BACK_RANK},a7a6 d1d8\nbad,{BACK_RANK},a7a6\n");
+        assert_eq!(
+            PuzzlePack::parse(&text).err().as_deref(),
+            Some("Puzzle bad: expected an even number of moves")
+        );
+    }
+}
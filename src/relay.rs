@@ -0,0 +1,95 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+use hermanha_chess::Color as HermanhaColor;
+
+use crate::clock::{self, Clocks, TimeControl};
+use crate::game_over::GameEnded;
+use crate::game_state::PlayerColor;
+use crate::net::{self, Connection, Opponent, PlayerName};
+use crate::offers::Concluded;
+use crate::tcp::{Message, RelayMessage};
+
+/// How often a host with spectators relays the clocks.
+const RELAY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Who plays the watched game, as its host relays it.
+#[derive(Resource)]
+pub struct WatchedPlayers {
+    pub white: String,
+    pub black: String,
+}
+
+/// Keeps the host's spectators up to date with the players, the clocks and
+/// the result, every `RELAY_INTERVAL` and as soon as the game is
+/// concluded. The moves reach them by themselves.
+#[allow(clippy::too_many_arguments)]
+pub fn relay_to_spectators(
+    mut connection: ResMut<Connection>,
+    player_name: Res<PlayerName>,
+    player_color: Res<PlayerColor>,
+    opponent: Option<Res<Opponent>>,
+    clocks: Option<Res<Clocks>>,
+    concluded: Res<Concluded>,
+    time: Res<Time<Real>>,
+    mut since_relay: Local<Duration>,
+) {
+    if connection.0.spectator_count() == 0 {
+        return;
+    }
+    *since_relay += time.delta();
+    if *since_relay < RELAY_INTERVAL && !concluded.is_changed() {
+        return;
+    }
+    *since_relay = Duration::ZERO;
+    let opponent_name = opponent.map_or("Opponent".to_string(), |opponent| opponent.name.clone());
+    let (white, black) = match player_color.0 {
+        HermanhaColor::White => (player_name.0.clone(), opponent_name),
+        HermanhaColor::Black => (opponent_name, player_name.0.clone()),
+    };
+    let relay = RelayMessage {
+        white,
+        black,
+        clocks: clocks.map(|clocks| {
+            (
+                clocks.remaining(HermanhaColor::White),
+                clocks.remaining(HermanhaColor::Black),
+            )
+        }),
+        conclusion: concluded.0,
+    };
+    net::send(connection.0.as_mut(), Message::Relay(relay));
+}
+
+/// Takes over what the host relayed. The clocks keep running locally in
+/// between and are set right by every relay; they appear with the first
+/// one, since a spectator has no time control of its own.
+pub fn watch_relay(
+    In(relay): In<RelayMessage>,
+    mut commands: Commands,
+    clocks: Option<ResMut<Clocks>>,
+    mut concluded: ResMut<Concluded>,
+    mut ended: EventWriter<GameEnded>,
+) {
+    commands.insert_resource(WatchedPlayers {
+        white: relay.white,
+        black: relay.black,
+    });
+    if let Some((white, black)) = relay.clocks {
+        match clocks {
+            Some(mut clocks) => clocks.set_remaining(white, black),
+            None => {
+                let time_control = TimeControl {
+                    base: white.max(black),
+                    increment: Duration::ZERO,
+                };
+                commands.insert_resource(Clocks::resumed(time_control, white, black, 0));
+                commands.run_system_cached(clock::spawn_clock_display);
+            }
+        }
+    }
+    if relay.conclusion.is_some() && concluded.0 != relay.conclusion {
+        concluded.0 = relay.conclusion;
+        ended.write(GameEnded);
+    }
+}
@@ -0,0 +1,433 @@
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use bevy::input::keyboard::KeyboardInput;
+use bevy::input::mouse::{MouseButtonInput, MouseWheel};
+use bevy::picking::PickSet;
+use bevy::prelude::*;
+use bevy::time::{TimeSystem, TimeUpdateStrategy};
+use bevy::window::{CursorMoved, PrimaryWindow};
+use crossbeam_channel::{Receiver, Sender, unbounded};
+
+use crate::config::{quote, unquote};
+use crate::net::{Connection, PendingConnection};
+use crate::tcp::{ConnectionType, Message, TcpError};
+use crate::toast::Toasts;
+use crate::transport::Transport;
+
+/// The first line of every replay file, so an old or foreign file is
+/// turned away instead of misread.
+const HEADER: &str = "chess-app replay 1";
+
+/// One thing that happened in a recorded frame. A replay file holds one
+/// per line as `<frame> <kind> <payload>`, after the header and the
+/// command line the session was started with.
+enum Entry {
+    /// How long the frame took, which the replay's clock advances by.
+    Time(Duration),
+    Key(KeyboardInput),
+    Button(MouseButtonInput),
+    Cursor(CursorMoved),
+    Wheel(MouseWheel),
+    /// The connection being set up was made.
+    Connected,
+    /// A message read from the opponent, as its frame on the wire.
+    Message(Message),
+    /// The connection dropped.
+    Lost,
+}
+
+/// Writes every input event and every message from the opponent to a
+/// file, frame by frame, while the app runs with `--record <file>`.
+#[derive(Resource)]
+pub struct Recording {
+    file: BufWriter<File>,
+    frame: u64,
+}
+
+impl Recording {
+    /// Starts the file with the command line, which `--replay` starts the
+    /// app with again.
+    pub fn create(path: &Path, args: &[String]) -> io::Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        writeln!(file, "{HEADER}")?;
+        for arg in args {
+            writeln!(file, "0 arg {}", quote(arg))?;
+        }
+        Ok(Recording { file, frame: 0 })
+    }
+
+    fn write(&mut self, kind: &str, payload: &str) {
+        let result = if payload.is_empty() {
+            writeln!(self.file, "{} {kind}", self.frame)
+        } else {
+            writeln!(self.file, "{} {kind} {payload}", self.frame)
+        };
+        if let Err(err) = result {
+            warn!("Could not write to the recording: {err}");
+        }
+    }
+
+    fn write_ron(&mut self, kind: &str, ron: Result<String, ron::Error>) {
+        match ron {
+            Ok(payload) => self.write(kind, &payload),
+            Err(err) => warn!("Could not record {kind} event: {err}"),
+        }
+    }
+
+    pub fn message(&mut self, message: &Message) {
+        match message.encode() {
+            Ok(frame) => self.write("message", &quote(&frame)),
+            Err(err) => warn!("Could not record a message: {err}"),
+        }
+    }
+
+    pub fn lost(&mut self) {
+        self.write("lost", "");
+    }
+}
+
+/// A recorded session being played back with `--replay <file>`.
+#[derive(Resource)]
+pub struct Replay {
+    /// The command line the session was recorded with.
+    pub args: Vec<String>,
+    entries: VecDeque<(u64, Entry)>,
+    frame: u64,
+    /// Where the opponent's recorded messages go once the connection is
+    /// made.
+    incoming: Option<Sender<Incoming>>,
+}
+
+impl Replay {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|err| err.to_string())?;
+        let mut lines = text.lines().enumerate();
+        if lines.next().map(|(_, line)| line) != Some(HEADER) {
+            return Err("Not a replay file".to_string());
+        }
+        let mut args = Vec::new();
+        let mut entries = VecDeque::new();
+        for (index, line) in lines {
+            let parse = || -> Result<(u64, Option<Entry>), String> {
+                let (frame, rest) = line.split_once(' ').ok_or("Missing kind")?;
+                let frame = frame
+                    .parse()
+                    .map_err(|_| format!("Invalid frame: {frame}"))?;
+                let (kind, payload) = rest.split_once(' ').unwrap_or((rest, ""));
+                let ron_error = |err: ron::error::SpannedError| err.to_string();
+                let entry = match kind {
+                    "arg" => {
+                        args.push(unquote(payload)?);
+                        None
+                    }
+                    "time" => Some(Entry::Time(Duration::from_nanos(
+                        payload
+                            .parse()
+                            .map_err(|_| format!("Invalid time: {payload}"))?,
+                    ))),
+                    "key" => Some(Entry::Key(ron::from_str(payload).map_err(ron_error)?)),
+                    "button" => Some(Entry::Button(ron::from_str(payload).map_err(ron_error)?)),
+                    "cursor" => Some(Entry::Cursor(ron::from_str(payload).map_err(ron_error)?)),
+                    "wheel" => Some(Entry::Wheel(ron::from_str(payload).map_err(ron_error)?)),
+                    "connected" => Some(Entry::Connected),
+                    "message" => Some(Entry::Message(
+                        Message::decode(unquote(payload)?.as_bytes())
+                            .map_err(|err| err.to_string())?,
+                    )),
+                    "lost" => Some(Entry::Lost),
+                    other => return Err(format!("Unknown kind: {other}")),
+                };
+                Ok((frame, entry))
+            };
+            match parse() {
+                Ok((frame, Some(entry))) => entries.push_back((frame, entry)),
+                Ok((_, None)) => {}
+                Err(err) => return Err(format!("line {}: {err}", index + 1)),
+            }
+        }
+        if args.is_empty() {
+            return Err("The command line is missing".to_string());
+        }
+        Ok(Replay {
+            args,
+            entries,
+            frame: 0,
+            incoming: None,
+        })
+    }
+}
+
+enum Incoming {
+    Message(Message),
+    Lost,
+}
+
+/// Stands in for the connection during a replay: reads give the recorded
+/// messages on the frames they arrived on, and what we send goes nowhere.
+pub struct ReplayTransport {
+    connection_type: ConnectionType,
+    incoming: Receiver<Incoming>,
+}
+
+impl Transport for ReplayTransport {
+    fn connection_type(&self) -> ConnectionType {
+        self.connection_type
+    }
+
+    fn read(&mut self) -> Result<Message, TcpError> {
+        match self.incoming.try_recv() {
+            Ok(Incoming::Message(message)) => Ok(message),
+            Ok(Incoming::Lost) => Err(TcpError::Io(io::Error::other(
+                "connection lost in the recording",
+            ))),
+            Err(_) => Err(TcpError::WouldBlock),
+        }
+    }
+
+    fn write(&mut self, _message: Message) -> Result<(), TcpError> {
+        Ok(())
+    }
+}
+
+/// Records the frame's length and its input events, before anything has
+/// read them.
+pub fn record_frame(
+    mut recording: ResMut<Recording>,
+    time: Res<Time<Real>>,
+    mut keys: EventReader<KeyboardInput>,
+    mut buttons: EventReader<MouseButtonInput>,
+    mut cursors: EventReader<CursorMoved>,
+    mut wheels: EventReader<MouseWheel>,
+) {
+    recording.frame += 1;
+    recording.write("time", &time.delta().as_nanos().to_string());
+    for event in keys.read() {
+        recording.write_ron("key", ron::to_string(event));
+    }
+    for event in buttons.read() {
+        recording.write_ron("button", ron::to_string(event));
+    }
+    for event in cursors.read() {
+        recording.write_ron("cursor", ron::to_string(event));
+    }
+    for event in wheels.read() {
+        recording.write_ron("wheel", ron::to_string(event));
+    }
+}
+
+pub fn record_connection(mut recording: ResMut<Recording>) {
+    recording.write("connected", "");
+}
+
+/// Flushed every frame, so a crash still leaves the session up to it on
+/// disk.
+pub fn flush_recording(mut recording: ResMut<Recording>) {
+    if let Err(err) = recording.file.flush() {
+        warn!("Could not write to the recording: {err}");
+    }
+}
+
+/// Plays back the frame's recorded entries: the clock advances by the
+/// recorded frame length, the player's own input is dropped for the
+/// recorded events, and the opponent's messages are handed to the
+/// connection. Ends once every entry has been played.
+#[allow(clippy::too_many_arguments)]
+pub fn replay_frame(
+    mut commands: Commands,
+    mut replay: ResMut<Replay>,
+    mut time_strategy: ResMut<TimeUpdateStrategy>,
+    mut key_events: ResMut<Events<KeyboardInput>>,
+    mut button_events: ResMut<Events<MouseButtonInput>>,
+    mut cursor_events: ResMut<Events<CursorMoved>>,
+    mut wheel_events: ResMut<Events<MouseWheel>>,
+    mut windows: Query<(Entity, &mut Window), With<PrimaryWindow>>,
+    mut pending: Option<ResMut<PendingConnection>>,
+    mut toasts: ResMut<Toasts>,
+) {
+    key_events.clear();
+    button_events.clear();
+    cursor_events.clear();
+    wheel_events.clear();
+    let Ok((window_entity, mut window)) = windows.single_mut() else {
+        return;
+    };
+    replay.frame += 1;
+    let (mut keys, mut buttons, mut cursors, mut wheels) = (vec![], vec![], vec![], vec![]);
+    while replay
+        .entries
+        .front()
+        .is_some_and(|(frame, _)| *frame <= replay.frame)
+    {
+        let Some((_, entry)) = replay.entries.pop_front() else {
+            break;
+        };
+        match entry {
+            Entry::Time(delta) => *time_strategy = TimeUpdateStrategy::ManualDuration(delta),
+            Entry::Key(event) => keys.push(KeyboardInput {
+                window: window_entity,
+                ..event
+            }),
+            Entry::Button(event) => buttons.push(MouseButtonInput {
+                window: window_entity,
+                ..event
+            }),
+            Entry::Cursor(event) => {
+                window.set_cursor_position(Some(event.position));
+                cursors.push(CursorMoved {
+                    window: window_entity,
+                    ..event
+                });
+            }
+            Entry::Wheel(event) => wheels.push(MouseWheel {
+                window: window_entity,
+                ..event
+            }),
+            Entry::Connected => {
+                let Some(pending) = pending.as_mut() else {
+                    warn!(
+                        "Replay diverged at frame {}: no connection was being set up",
+                        replay.frame
+                    );
+                    continue;
+                };
+                let (sender, incoming) = unbounded();
+                let connection_type = if pending.hosting() {
+                    ConnectionType::Server
+                } else {
+                    ConnectionType::Client
+                };
+                pending.resolve(Box::new(ReplayTransport {
+                    connection_type,
+                    incoming,
+                }));
+                replay.incoming = Some(sender);
+            }
+            Entry::Message(message) => {
+                if let Some(incoming) = &replay.incoming {
+                    let _ = incoming.send(Incoming::Message(message));
+                }
+            }
+            Entry::Lost => {
+                if let Some(incoming) = &replay.incoming {
+                    let _ = incoming.send(Incoming::Lost);
+                }
+            }
+        }
+    }
+    key_events.extend(keys);
+    button_events.extend(buttons);
+    cursor_events.extend(cursors);
+    wheel_events.extend(wheels);
+    if replay.entries.is_empty() {
+        *time_strategy = TimeUpdateStrategy::Automatic;
+        commands.remove_resource::<Replay>();
+        toasts.push("Replay finished");
+    }
+}
+
+/// Recording with `--record` and playing back with `--replay`. Needs the
+/// window and input events of `DefaultPlugins`.
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            First,
+            (
+                record_frame
+                    .after(TimeSystem)
+                    .run_if(resource_exists::<Recording>),
+                replay_frame
+                    .before(TimeSystem)
+                    .before(PickSet::Input)
+                    .run_if(resource_exists::<Replay>),
+            ),
+        )
+        .add_systems(
+            Last,
+            (
+                record_connection.run_if(resource_added::<Connection>),
+                flush_recording,
+            )
+                .chain()
+                .run_if(resource_exists::<Recording>),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::tcp::QuitMessage;
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("replay-{}-{name}", std::process::id()))
+    }
+
+    /// Loads a replay file holding `text`.
+    fn load(name: &str, text: &str) -> Result<Replay, String> {
+        let path = temp_path(name);
+        fs::write(&path, text).unwrap();
+        let replay = Replay::load(&path);
+        let _ = fs::remove_file(&path);
+        replay
+    }
+
+    #[test]
+    fn a_recording_loads_back() {
+        let path = temp_path("recording");
+        let args = [
+            "chess-app".to_string(),
+            "--name".to_string(),
+            "Ann Lee".to_string(),
+        ];
+        let mut recording = Recording::create(&path, &args).unwrap();
+        recording.frame = 3;
+        recording.message(&Message::Quit(QuitMessage { message: None }));
+        recording.frame = 4;
+        recording.lost();
+        drop(recording);
+        let replay = Replay::load(&path);
+        let _ = fs::remove_file(&path);
+
+        let replay = replay.unwrap();
+        assert_eq!(replay.args, args);
+        assert_eq!(replay.entries.len(), 2);
+        assert!(matches!(
+            replay.entries[0],
+            (3, Entry::Message(Message::Quit(_)))
+        ));
+        assert!(matches!(replay.entries[1], (4, Entry::Lost)));
+    }
+
+    #[test]
+    fn a_bad_file_is_turned_away() {
+        let error = |name: &str, text: &str| load(name, text).err().unwrap();
+        assert_eq!(
+            error("foreign", "chess-app replay 0\n"),
+            "Not a replay file"
+        );
+        assert_eq!(
+            error("no-args", &format!("{HEADER}\n1 connected\n")),
+            "The command line is missing"
+        );
+        assert_eq!(
+            error(
+                "unknown",
+                &format!("{HEADER}\n0 arg \"chess-app\"\n2 jump\n")
+            ),
+            "line 3: Unknown kind: jump"
+        );
+        assert_eq!(
+            error("frame", &format!("{HEADER}\nx lost\n")),
+            "line 2: Invalid frame: x"
+        );
+    }
+}
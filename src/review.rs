@@ -0,0 +1,610 @@
+use bevy::input::ButtonInput;
+use bevy::prelude::*;
+use hermanha_chess::{Board, Position};
+
+use crate::actions::{self, Action, ActiveScopes};
+use crate::cursor::HoveredSquare;
+use crate::eval_graph::{Evaluations, Judgement};
+use crate::game_over::{GameEnded, GameOverOverlay};
+use crate::game_state::{BoardState, Castling};
+use crate::history::{MoveHistory, MoveListPanel, MoveListText};
+use crate::input::legal_targets;
+use crate::menu::PlayState;
+use crate::rules::{self, CastlingRights};
+use crate::ui::GameUi;
+use crate::{PIECE_Z, TILE_SIZE, hint, pos_to_vec3, san, widgets};
+
+const SELECTION_Z: f32 = PIECE_Z - 0.5;
+
+/// Moves tried out from a position of the finished game. The game itself
+/// knows nothing of them.
+pub struct Variation {
+    /// The ply of the game it branches off at, in place of the move played
+    /// there.
+    pub from: usize,
+    pub moves: Vec<String>,
+    /// The position after each of `moves`.
+    positions: Vec<(Board, CastlingRights)>,
+}
+
+/// Where in the finished game the review is: the position before move
+/// `ply`, up to the final position at the number of moves played, or
+/// `variation_ply` moves into the variation. Only one variation is kept;
+/// branching off elsewhere replaces it. Only present during
+/// `PlayState::Review`; the game itself is left as it ended.
+#[derive(Resource, Default)]
+pub struct ReviewPosition {
+    pub ply: usize,
+    pub variation: Option<Variation>,
+    pub variation_ply: usize,
+    selected: Option<Position>,
+}
+
+impl ReviewPosition {
+    /// The position on the board, given the one the game ended in.
+    pub fn shown<'a>(
+        &'a self,
+        history: &'a MoveHistory,
+        end: (&'a Board, CastlingRights),
+    ) -> (&'a Board, CastlingRights) {
+        if let Some(variation) = &self.variation
+            && self.variation_ply > 0
+        {
+            let (board, castling) = &variation.positions[self.variation_ply - 1];
+            return (board, *castling);
+        }
+        history.position_before(self.ply).unwrap_or(end)
+    }
+
+    /// Leaves any variation for the position before move `ply` of the
+    /// game.
+    pub fn jump_to(&mut self, ply: usize) {
+        self.ply = ply;
+        self.variation_ply = 0;
+        self.selected = None;
+    }
+
+    fn in_variation(&self) -> bool {
+        self.variation_ply > 0
+    }
+
+    /// Plays a move on the shown position. Following the game or the
+    /// variation just steps along it; anything else starts a variation
+    /// here, or cuts the current one short and continues it.
+    fn play(
+        &mut self,
+        history: &MoveHistory,
+        end: (&Board, CastlingRights),
+        from: Position,
+        to: Position,
+    ) {
+        let (board, castling) = self.shown(history, end);
+        let promotion_piece = hint::promotion_for(board, from, to);
+        let san = san::move_to_san(board, from, to, promotion_piece);
+        let Some(after) = rules::play_move(board, castling, from, to, promotion_piece) else {
+            return;
+        };
+        let mut castling = castling;
+        castling.update(from, to);
+        if !self.in_variation() {
+            if history
+                .moves
+                .get(self.ply)
+                .is_some_and(|played| played.san == san)
+            {
+                self.ply += 1;
+                return;
+            }
+            if self
+                .variation
+                .as_ref()
+                .is_none_or(|line| line.from != self.ply)
+            {
+                self.variation = Some(Variation {
+                    from: self.ply,
+                    moves: Vec::new(),
+                    positions: Vec::new(),
+                });
+            }
+        }
+        let ply = self.variation_ply;
+        let Some(variation) = self.variation.as_mut() else {
+            return;
+        };
+        if variation.moves.get(ply) != Some(&san) {
+            variation.moves.truncate(ply);
+            variation.positions.truncate(ply);
+            variation.moves.push(san);
+            variation.positions.push((after, castling));
+        }
+        self.variation_ply += 1;
+    }
+}
+
+#[derive(Component)]
+pub struct ReviewBar;
+
+#[derive(Component)]
+pub struct ReviewLabel;
+
+#[derive(Component, Clone, Copy)]
+pub enum ReviewButton {
+    Start,
+    Back,
+    Forward,
+    End,
+    Result,
+}
+
+impl ReviewButton {
+    fn label(self) -> &'static str {
+        match self {
+            ReviewButton::Start => "|<",
+            ReviewButton::Back => "<",
+            ReviewButton::Forward => ">",
+            ReviewButton::End => ">|",
+            ReviewButton::Result => "Result",
+        }
+    }
+}
+
+/// The move list while reviewing, one button per move, standing in for
+/// the plain text of `MoveListText`.
+#[derive(Component)]
+pub struct ReviewMoveList;
+
+/// A move in the review's move list: the one at `ply` in the game, or
+/// with `variation` the one at `ply` in the variation.
+#[derive(Component, Clone, Copy)]
+pub struct ReviewMoveButton {
+    ply: usize,
+    variation: bool,
+}
+
+/// The button of the shown move, which `follow_shown_move` keeps in view.
+#[derive(Component)]
+pub struct ShownMove;
+
+#[derive(Component)]
+pub struct ReviewSelection;
+
+/// Takes the result down and starts at the final position.
+pub fn enter_review(
+    mut commands: Commands,
+    history: Res<MoveHistory>,
+    overlays: Query<Entity, With<GameOverOverlay>>,
+    panels: Query<Entity, With<MoveListPanel>>,
+    mut texts: Query<&mut Node, With<MoveListText>>,
+) {
+    for entity in overlays.iter() {
+        commands.entity(entity).despawn();
+    }
+    commands.insert_resource(ReviewPosition {
+        ply: history.moves.len(),
+        ..default()
+    });
+    for mut node in texts.iter_mut() {
+        node.display = Display::None;
+    }
+    for panel in panels.iter() {
+        commands.entity(panel).with_child((
+            ReviewMoveList,
+            Node {
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(2.0),
+                ..default()
+            },
+        ));
+    }
+    commands
+        .spawn((
+            ReviewBar,
+            GameUi,
+            Node {
+                position_type: PositionType::Absolute,
+                bottom: Val::Px(8.0),
+                left: Val::Percent(35.0),
+                padding: UiRect::all(Val::Px(6.0)),
+                column_gap: Val::Px(6.0),
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            BackgroundColor(widgets::PANEL_COLOR),
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                ReviewLabel,
+                Text::new(""),
+                TextFont {
+                    font_size: 14.0,
+                    ..default()
+                },
+                Node {
+                    width: Val::Px(140.0),
+                    ..default()
+                },
+            ));
+            for button in [
+                ReviewButton::Start,
+                ReviewButton::Back,
+                ReviewButton::Forward,
+                ReviewButton::End,
+                ReviewButton::Result,
+            ] {
+                parent.spawn((
+                    button,
+                    widgets::button(
+                        Node {
+                            width: Val::Auto,
+                            min_width: Val::Px(36.0),
+                            padding: UiRect::horizontal(Val::Px(6.0)),
+                            ..widgets::button_node(36.0, 28.0)
+                        },
+                        widgets::label(button.label()),
+                    ),
+                ));
+            }
+        });
+}
+
+pub fn exit_review(
+    mut commands: Commands,
+    review_ui: Query<Entity, Or<(With<ReviewBar>, With<ReviewMoveList>, With<ReviewSelection>)>>,
+    mut texts: Query<&mut Node, With<MoveListText>>,
+) {
+    for entity in review_ui.iter() {
+        commands.entity(entity).despawn();
+    }
+    for mut node in texts.iter_mut() {
+        node.display = Display::Flex;
+    }
+    commands.remove_resource::<ReviewPosition>();
+}
+
+/// The buttons and the arrow keys step through the game, or through the
+/// variation while in it; clicking a move in the list jumps to it. "Result"
+/// goes back to the result, which `show_game_over` puts up again.
+#[allow(clippy::too_many_arguments)]
+pub fn step_review(
+    keys: Res<ButtonInput<KeyCode>>,
+    scopes: Res<ActiveScopes>,
+    buttons: Query<(&Interaction, &ReviewButton), Changed<Interaction>>,
+    moves: Query<(&Interaction, &ReviewMoveButton), Changed<Interaction>>,
+    history: Res<MoveHistory>,
+    mut review: ResMut<ReviewPosition>,
+    mut next_state: ResMut<NextState<PlayState>>,
+    mut ended: EventWriter<GameEnded>,
+) {
+    for (interaction, button) in moves.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        if button.variation {
+            review.selected = None;
+            review.variation_ply = button.ply + 1;
+        } else {
+            review.jump_to(button.ply + 1);
+        }
+    }
+    let mut pressed: Vec<ReviewButton> = buttons
+        .iter()
+        .filter(|(interaction, _)| **interaction == Interaction::Pressed)
+        .map(|(_, button)| *button)
+        .collect();
+    if actions::just_pressed_in(&keys, &scopes, Action::ReviewBack) {
+        pressed.push(ReviewButton::Back);
+    }
+    if actions::just_pressed_in(&keys, &scopes, Action::ReviewForward) {
+        pressed.push(ReviewButton::Forward);
+    }
+    let end = history.moves.len();
+    for button in pressed {
+        review.selected = None;
+        match button {
+            ReviewButton::Result => {
+                next_state.set(PlayState::GameOver);
+                ended.write(GameEnded);
+                return;
+            }
+            ReviewButton::Back if review.in_variation() => review.variation_ply -= 1,
+            ReviewButton::Forward if review.in_variation() => {
+                let length = review.variation.as_ref().map_or(0, |line| line.moves.len());
+                review.variation_ply = (review.variation_ply + 1).min(length);
+            }
+            ReviewButton::Start => {
+                review.ply = 0;
+                review.variation_ply = 0;
+            }
+            ReviewButton::Back => review.ply = review.ply.saturating_sub(1),
+            ReviewButton::Forward => review.ply = (review.ply + 1).min(end),
+            ReviewButton::End => {
+                review.ply = end;
+                review.variation_ply = 0;
+            }
+        }
+    }
+}
+
+/// Picking a piece and a square to move it to plays the move on the
+/// reviewed position, see `ReviewPosition::play`. Pawns always promote to
+/// a queen here.
+pub fn play_review_moves(
+    buttons: Res<ButtonInput<MouseButton>>,
+    hovered: Res<HoveredSquare>,
+    history: Res<MoveHistory>,
+    board: Res<BoardState>,
+    castling: Res<Castling>,
+    mut review: ResMut<ReviewPosition>,
+) {
+    if !buttons.just_pressed(MouseButton::Left) {
+        return;
+    }
+    let Some(clicked) = hovered.clicked() else {
+        return;
+    };
+    let end = (&board.0, castling.0);
+    let (shown, shown_castling) = review.shown(&history, end);
+    let own_piece = shown
+        .get(clicked)
+        .is_some_and(|piece| piece.color == shown.move_turn);
+    let target = review.selected.filter(|from| {
+        legal_targets(shown, shown_castling, *from)
+            .iter()
+            .any(|(to, _)| *to == clicked)
+    });
+    match target {
+        Some(from) => {
+            review.selected = None;
+            review.play(&history, end, from, clicked);
+        }
+        None if own_piece => review.selected = Some(clicked),
+        None => review.selected = None,
+    }
+}
+
+/// The label in the review bar, the move list with the shown move and the
+/// search's judgement of each move marked, and the selected square.
+pub fn render_review(
+    mut commands: Commands,
+    review: Res<ReviewPosition>,
+    history: Res<MoveHistory>,
+    evaluations: Option<Res<Evaluations>>,
+    mut labels: Query<&mut Text, With<ReviewLabel>>,
+    lists: Query<Entity, With<ReviewMoveList>>,
+    selections: Query<Entity, With<ReviewSelection>>,
+) {
+    let judged = evaluations
+        .as_ref()
+        .is_some_and(|evaluations| evaluations.is_changed());
+    if !review.is_changed() && !judged {
+        return;
+    }
+    let label = match &review.variation {
+        Some(variation) if review.in_variation() => format!(
+            "Variation: {}",
+            move_name(
+                variation.from + review.variation_ply - 1,
+                &variation.moves[review.variation_ply - 1]
+            )
+        ),
+        _ => match review.ply.checked_sub(1).map(|last| &history.moves[last]) {
+            None => "Start position".to_string(),
+            Some(played) => move_name(review.ply - 1, &played.san),
+        },
+    };
+    for mut text in labels.iter_mut() {
+        text.0 = label.clone();
+    }
+
+    for entity in selections.iter() {
+        commands.entity(entity).despawn();
+    }
+    if let Some(selected) = review.selected {
+        commands.spawn((
+            ReviewSelection,
+            Sprite::from_color(Color::srgba(0.9, 0.8, 0.2, 0.45), Vec2::splat(TILE_SIZE)),
+            Transform::from_translation(pos_to_vec3(selected, SELECTION_Z)),
+        ));
+    }
+
+    for list in lists.iter() {
+        commands.entity(list).despawn_related::<Children>();
+        let mut rows: Vec<(Vec<(String, ReviewMoveButton)>, bool)> = Vec::new();
+        for (ply, played) in history.moves.iter().enumerate() {
+            let button = ReviewMoveButton {
+                ply,
+                variation: false,
+            };
+            let continues_row = ply % 2 == 1 && rows.last().is_some_and(|(_, indented)| !indented);
+            if !continues_row {
+                rows.push((Vec::new(), false));
+            }
+            let name = if continues_row {
+                played.san.clone()
+            } else {
+                move_name(ply, &played.san)
+            };
+            if let Some((row, _)) = rows.last_mut() {
+                row.push((name, button));
+            }
+            if let Some(variation) = review.variation.as_ref().filter(|line| line.from == ply) {
+                rows.push((variation_row(variation), true));
+            }
+        }
+        if let Some(variation) = review
+            .variation
+            .as_ref()
+            .filter(|line| line.from == history.moves.len())
+        {
+            rows.push((variation_row(variation), true));
+        }
+        for (row, indented) in rows {
+            commands.entity(list).with_children(|parent| {
+                parent
+                    .spawn(Node {
+                        flex_wrap: FlexWrap::Wrap,
+                        column_gap: Val::Px(4.0),
+                        margin: UiRect::left(Val::Px(if indented { 16.0 } else { 0.0 })),
+                        ..default()
+                    })
+                    .with_children(|parent| {
+                        for (name, button) in row {
+                            let shown = if button.variation {
+                                review.in_variation() && review.variation_ply == button.ply + 1
+                            } else {
+                                !review.in_variation() && review.ply == button.ply + 1
+                            };
+                            let judgement = evaluations
+                                .as_ref()
+                                .filter(|_| !button.variation)
+                                .and_then(|evaluations| evaluations.judgement(button.ply));
+                            let mut entry = parent.spawn((
+                                button,
+                                Button,
+                                Node {
+                                    padding: UiRect::horizontal(Val::Px(4.0)),
+                                    ..default()
+                                },
+                                BackgroundColor(if shown {
+                                    widgets::SELECTED_BUTTON_COLOR
+                                } else {
+                                    Color::NONE
+                                }),
+                                children![(
+                                    Text::new(name),
+                                    TextFont {
+                                        font_size: if indented { 14.0 } else { 16.0 },
+                                        ..default()
+                                    },
+                                    children![(
+                                        TextSpan::new(judgement.map_or("", Judgement::symbol)),
+                                        TextColor(judgement.map_or(Color::NONE, Judgement::color)),
+                                    )],
+                                )],
+                            ));
+                            if shown {
+                                entry.insert(ShownMove);
+                            }
+                        }
+                    });
+            });
+        }
+    }
+}
+
+/// Scrolls the move list just far enough to show the shown move, once
+/// layout has placed the buttons `render_review` spawned for it, so
+/// stepping with the keys never leaves it out of sight.
+pub fn follow_shown_move(
+    shown: Query<(&ComputedNode, &GlobalTransform), Added<ShownMove>>,
+    mut panels: Query<(&mut ScrollPosition, &ComputedNode, &GlobalTransform), With<MoveListPanel>>,
+) {
+    let Some((button, button_at)) = shown.iter().next() else {
+        return;
+    };
+    for (mut scroll, panel, panel_at) in panels.iter_mut() {
+        let scale = panel.inverse_scale_factor();
+        let top = (button_at.translation().y - button.size().y / 2.0) * scale;
+        let bottom = (button_at.translation().y + button.size().y / 2.0) * scale;
+        let panel_top = (panel_at.translation().y - panel.size().y / 2.0) * scale;
+        let panel_bottom = (panel_at.translation().y + panel.size().y / 2.0) * scale;
+        if top < panel_top {
+            scroll.offset_y = (scroll.offset_y - (panel_top - top)).max(0.0);
+        } else if bottom > panel_bottom {
+            scroll.offset_y += bottom - panel_bottom;
+        }
+    }
+}
+
+fn variation_row(variation: &Variation) -> Vec<(String, ReviewMoveButton)> {
+    variation
+        .moves
+        .iter()
+        .enumerate()
+        .map(|(ply, san)| {
+            (
+                move_name(variation.from + ply, san),
+                ReviewMoveButton {
+                    ply,
+                    variation: true,
+                },
+            )
+        })
+        .collect()
+}
+
+/// "12. Nf3" for White's moves and "12... Nf3" for Black's, by the ply
+/// counted from the first move of the game.
+fn move_name(ply: usize, san: &str) -> String {
+    let number = ply / 2 + 1;
+    let dots = if ply % 2 == 0 { "." } else { "..." };
+    format!("{number}{dots} {san}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 1. e4 e5, and the position it ends in.
+    fn open_game() -> (MoveHistory, Board, CastlingRights) {
+        let mut history = MoveHistory::default();
+        let mut board = Board::start_pos();
+        let mut castling = CastlingRights::default();
+        for (from, to) in [((1, 4), (3, 4)), ((6, 4), (4, 4))] {
+            let (from, to) = (Position::new(from.0, from.1), Position::new(to.0, to.1));
+            history.push(&board, castling, from, to, None);
+            board = rules::play_move(&board, castling, from, to, None).unwrap();
+            castling.update(from, to);
+        }
+        (history, board, castling)
+    }
+
+    #[test]
+    fn playing_the_game_move_steps_along_the_game() {
+        let (history, board, castling) = open_game();
+        let mut review = ReviewPosition::default();
+        review.jump_to(1);
+        review.play(
+            &history,
+            (&board, castling),
+            Position::new(6, 4),
+            Position::new(4, 4),
+        );
+        assert_eq!(review.ply, 2);
+        assert!(review.variation.is_none());
+    }
+
+    #[test]
+    fn another_move_branches_off_until_the_game_is_jumped_to() {
+        let (history, board, castling) = open_game();
+        let mut review = ReviewPosition::default();
+        review.jump_to(1);
+        review.play(
+            &history,
+            (&board, castling),
+            Position::new(6, 2),
+            Position::new(4, 2),
+        );
+        let variation = review.variation.as_ref().unwrap();
+        assert_eq!(
+            (variation.from, variation.moves.as_slice()),
+            (1, &["c5".to_string()][..])
+        );
+        assert!(review.in_variation());
+        let (shown, _) = review.shown(&history, (&board, castling));
+        assert!(shown.get(Position::new(4, 2)).is_some());
+
+        review.jump_to(2);
+        assert!(!review.in_variation());
+        assert!(review.variation.is_some());
+        let (shown, _) = review.shown(&history, (&board, castling));
+        assert!(shown.get(Position::new(4, 2)).is_none());
+    }
+
+    #[test]
+    fn moves_are_named_by_number_and_side() {
+        assert_eq!(move_name(0, "e4"), "1. e4");
+        assert_eq!(move_name(1, "e5"), "1... e5");
+        assert_eq!(move_name(22, "Nf3"), "12. Nf3");
+    }
+}
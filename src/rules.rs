@@ -0,0 +1,714 @@
+use hermanha_chess::{BOARD_COLS, BOARD_ROWS, Board, Color, PieceType, Position};
+
+use crate::san;
+
+pub struct EnPassant {
+    pub to: Position,
+    pub captured: Position,
+}
+
+/// En passant captures available to the side to move. A pawn moving
+/// diagonally onto an empty square can only be an en passant capture.
+pub fn en_passant_captures(board: &Board) -> Vec<EnPassant> {
+    board
+        .legal_moves()
+        .into_iter()
+        .filter(|(from, to, _)| {
+            from.col != to.col
+                && board.get(*to).is_none()
+                && board
+                    .get(*from)
+                    .is_some_and(|piece| matches!(piece.piece_type, PieceType::Pawn))
+        })
+        .map(|(from, to, _)| EnPassant {
+            to,
+            captured: Position::new(from.row, to.col),
+        })
+        .collect()
+}
+
+/// The files the king and the two rooks start on. Chess960 shuffles them,
+/// the same way for both sides.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CastlingFiles {
+    pub king: i8,
+    pub kingside_rook: i8,
+    pub queenside_rook: i8,
+}
+
+impl Default for CastlingFiles {
+    fn default() -> Self {
+        CastlingFiles {
+            king: 4,
+            kingside_rook: 7,
+            queenside_rook: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CastlingRights {
+    pub white_kingside: bool,
+    pub white_queenside: bool,
+    pub black_kingside: bool,
+    pub black_queenside: bool,
+    pub files: CastlingFiles,
+}
+
+impl Default for CastlingRights {
+    fn default() -> Self {
+        CastlingRights {
+            white_kingside: true,
+            white_queenside: true,
+            black_kingside: true,
+            black_queenside: true,
+            files: CastlingFiles::default(),
+        }
+    }
+}
+
+impl CastlingRights {
+    pub fn kingside(&self, color: Color) -> bool {
+        match color {
+            Color::White => self.white_kingside,
+            Color::Black => self.black_kingside,
+        }
+    }
+
+    pub fn queenside(&self, color: Color) -> bool {
+        match color {
+            Color::White => self.white_queenside,
+            Color::Black => self.black_queenside,
+        }
+    }
+
+    /// Drops every right whose king or rook start square is touched by the
+    /// move, either by moving away from it or by capturing on it.
+    pub fn update(&mut self, from: Position, to: Position) {
+        for pos in [from, to] {
+            let (kingside, queenside) = match pos.row {
+                0 => (&mut self.white_kingside, &mut self.white_queenside),
+                7 => (&mut self.black_kingside, &mut self.black_queenside),
+                _ => continue,
+            };
+            if pos.col == self.files.king {
+                *kingside = false;
+                *queenside = false;
+            } else if pos.col == self.files.kingside_rook {
+                *kingside = false;
+            } else if pos.col == self.files.queenside_rook {
+                *queenside = false;
+            }
+        }
+    }
+}
+
+/// The board after a move, castling onto the rook where the position
+/// calls for it. `None` if the move can't be played.
+pub fn play_move(
+    board: &Board,
+    castling: CastlingRights,
+    from: Position,
+    to: Position,
+    promotion_piece: Option<PieceType>,
+) -> Option<Board> {
+    if let Some(after) = castle_onto_rook(board, castling, from, to) {
+        return Some(after);
+    }
+    let mut after = board.clone();
+    after
+        .play((from.row, from.col), (to.row, to.col), promotion_piece)
+        .ok()?;
+    Some(after)
+}
+
+/// The board after a move already known to be legal, such as one being
+/// written down. Unlike `play_move` it needs no castling rights: a king
+/// moving onto its own rook castles.
+pub fn board_after(
+    board: &Board,
+    from: Position,
+    to: Position,
+    promotion_piece: Option<PieceType>,
+) -> Option<Board> {
+    let onto_own_rook = board
+        .get(from)
+        .zip(board.get(to))
+        .is_some_and(|(king, rook)| {
+            matches!(king.piece_type, PieceType::King) && rook.color == king.color
+        });
+    if onto_own_rook {
+        return Some(castled(board, from, to));
+    }
+    let mut after = board.clone();
+    after
+        .play((from.row, from.col), (to.row, to.col), promotion_piece)
+        .ok()?;
+    Some(after)
+}
+
+/// Whether moving `from` to `to` castles: the king moving two files, or
+/// onto its own rook as in Chess960. Such a move takes nothing.
+pub fn is_castling(board: &Board, from: Position, to: Position) -> bool {
+    let Some(king) = board.get(from) else {
+        return false;
+    };
+    matches!(king.piece_type, PieceType::King)
+        && ((to.col - from.col).abs() == 2
+            || board
+                .get(to)
+                .is_some_and(|target| target.color == king.color))
+}
+
+/// The castlings onto the rook the position allows, as the king's and the
+/// rook's squares. From the standard start squares the king castles by
+/// moving two files instead, which `Board::legal_moves` already has.
+pub fn rook_castles(board: &Board, castling: CastlingRights) -> Vec<(Position, Position)> {
+    if castling.files == CastlingFiles::default() {
+        return Vec::new();
+    }
+    let Some(king) = king_position(board, board.move_turn) else {
+        return Vec::new();
+    };
+    [castling.files.kingside_rook, castling.files.queenside_rook]
+        .into_iter()
+        .map(|col| Position::new(king.row, col))
+        .filter(|rook| castle_onto_rook(board, castling, king, *rook).is_some())
+        .map(|rook| (king, rook))
+        .collect()
+}
+
+/// Castling by moving the king onto its own rook, the way Chess960 is
+/// played. Wherever the two start, the king ends up on the g- or c-file
+/// and the rook next to it on the f- or d-file. `hermanha_chess` only
+/// castles from the standard squares, so the pieces are placed here.
+/// Returns the position after castling, or `None` if moving from `from`
+/// to `to` isn't a castling the position allows.
+pub fn castle_onto_rook(
+    board: &Board,
+    castling: CastlingRights,
+    from: Position,
+    to: Position,
+) -> Option<Board> {
+    let color = board.move_turn;
+    let back_rank = match color {
+        Color::White => 0,
+        Color::Black => BOARD_ROWS as i8 - 1,
+    };
+    let files = castling.files;
+    if from != Position::new(back_rank, files.king) || to.row != back_rank {
+        return None;
+    }
+    let (allowed, king_col, rook_col) = if to.col == files.kingside_rook {
+        (castling.kingside(color), KINGSIDE_FILES.0, KINGSIDE_FILES.1)
+    } else if to.col == files.queenside_rook {
+        (
+            castling.queenside(color),
+            QUEENSIDE_FILES.0,
+            QUEENSIDE_FILES.1,
+        )
+    } else {
+        return None;
+    };
+    if !allowed
+        || !has_piece(board, from, color, &[PieceType::King])
+        || !has_piece(board, to, color, &[PieceType::Rook])
+    {
+        return None;
+    }
+    let span =
+        move |a: i8, b: i8| (a.min(b)..=a.max(b)).map(move |col| Position::new(back_rank, col));
+    // Everything the king and rook cross or land on has to be empty,
+    // apart from the two of them.
+    if span(from.col, king_col)
+        .chain(span(to.col, rook_col))
+        .any(|pos| pos != from && pos != to && board.get(pos).is_some())
+    {
+        return None;
+    }
+    let after = castled(board, from, to);
+    // The king may not castle out of, through or into check.
+    if span(from.col, king_col).any(|pos| is_attacked(&after, pos, opponent(color))) {
+        return None;
+    }
+    Some(after)
+}
+
+/// Where the king and the rook end up castling kingside, and queenside.
+const KINGSIDE_FILES: (i8, i8) = (6, 5);
+const QUEENSIDE_FILES: (i8, i8) = (2, 3);
+
+/// The board after the king on `from` castles with its rook on `to`,
+/// without checking the castling is allowed. The kingside rook is always
+/// the one right of the king.
+fn castled(board: &Board, from: Position, to: Position) -> Board {
+    let color = board.move_turn;
+    let (king_col, rook_col) = if to.col > from.col {
+        KINGSIDE_FILES
+    } else {
+        QUEENSIDE_FILES
+    };
+    let mut after = board.clone();
+    set_square(&mut after, from, None);
+    set_square(&mut after, to, None);
+    set_square(
+        &mut after,
+        Position::new(from.row, king_col),
+        Some((color, PieceType::King)),
+    );
+    set_square(
+        &mut after,
+        Position::new(from.row, rook_col),
+        Some((color, PieceType::Rook)),
+    );
+    after.move_turn = opponent(color);
+    after
+}
+
+/// Rebuilds the board with `pos` holding `piece`, or emptied if `None`.
+/// `Board` has no way to set a single square, so this goes through the
+/// piece placement.
+pub fn set_square(board: &mut Board, pos: Position, piece: Option<(Color, PieceType)>) {
+    let mut placement = String::new();
+    for row in (0..BOARD_ROWS as i8).rev() {
+        let mut empty_count = 0;
+        for col in 0..BOARD_COLS as i8 {
+            let square = Position::new(row, col);
+            let content = if square == pos {
+                piece
+            } else {
+                board
+                    .get(square)
+                    .map(|piece| (piece.color, piece.piece_type))
+            };
+            let Some((color, piece_type)) = content else {
+                empty_count += 1;
+                continue;
+            };
+            if empty_count != 0 {
+                placement.push_str(&empty_count.to_string());
+                empty_count = 0;
+            }
+            let letter = san::piece_letter(piece_type);
+            placement.push(match color {
+                Color::White => letter,
+                Color::Black => letter.to_ascii_lowercase(),
+            });
+        }
+        if empty_count != 0 {
+            placement.push_str(&empty_count.to_string());
+        }
+        if row != 0 {
+            placement.push('/');
+        }
+    }
+    let move_turn = board.move_turn;
+    *board = Board::start_pos();
+    board.setup_fen(&placement);
+    board.move_turn = move_turn;
+}
+
+pub fn king_position(board: &Board, color: Color) -> Option<Position> {
+    (0..BOARD_ROWS as i8)
+        .flat_map(|row| (0..BOARD_COLS as i8).map(move |col| Position::new(row, col)))
+        .find(|pos| {
+            board.get(*pos).is_some_and(|piece| {
+                matches!(piece.piece_type, PieceType::King) && piece.color == color
+            })
+        })
+}
+
+pub fn piece_value(piece_type: PieceType) -> i32 {
+    match piece_type {
+        PieceType::Pawn => 1,
+        PieceType::Knight => 3,
+        PieceType::Bishop => 3,
+        PieceType::Rook => 5,
+        PieceType::Queen => 9,
+        PieceType::King => 0,
+    }
+}
+
+/// Sort key that lines pieces up by value, keeping knights and bishops
+/// in separate groups.
+pub fn tray_order(piece_type: PieceType) -> i32 {
+    let tiebreak = matches!(piece_type, PieceType::Bishop) as i32;
+    piece_value(piece_type) * 2 + tiebreak
+}
+
+fn pieces(board: &Board) -> impl Iterator<Item = (Color, PieceType)> + '_ {
+    (0..BOARD_ROWS as i8)
+        .flat_map(|row| (0..BOARD_COLS as i8).map(move |col| Position::new(row, col)))
+        .filter_map(|pos| board.get(pos))
+        .map(|piece| (piece.color, piece.piece_type))
+}
+
+/// How many more pieces of each type White has than Black, from most to
+/// least valuable. Negative counts mean Black has the extra pieces.
+pub fn material_imbalance(board: &Board) -> [(PieceType, i32); 5] {
+    let mut imbalance = [
+        (PieceType::Queen, 0),
+        (PieceType::Rook, 0),
+        (PieceType::Bishop, 0),
+        (PieceType::Knight, 0),
+        (PieceType::Pawn, 0),
+    ];
+    for (color, piece_type) in pieces(board) {
+        let Some(entry) = imbalance
+            .iter_mut()
+            .find(|(kind, _)| same_type(*kind, piece_type))
+        else {
+            continue;
+        };
+        entry.1 += match color {
+            Color::White => 1,
+            Color::Black => -1,
+        };
+    }
+    imbalance
+}
+
+/// Material of White minus material of Black, in pawns.
+pub fn material_balance(board: &Board) -> i32 {
+    material_imbalance(board)
+        .iter()
+        .map(|(piece_type, count)| piece_value(*piece_type) * count)
+        .sum()
+}
+
+/// `PieceType` doesn't implement `PartialEq`, so compare variants directly.
+pub fn same_type(a: PieceType, b: PieceType) -> bool {
+    std::mem::discriminant(&a) == std::mem::discriminant(&b)
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum GamePhase {
+    #[default]
+    Opening,
+    Middlegame,
+    Endgame,
+}
+
+impl GamePhase {
+    pub fn label(self) -> &'static str {
+        match self {
+            GamePhase::Opening => "Opening",
+            GamePhase::Middlegame => "Middlegame",
+            GamePhase::Endgame => "Endgame",
+        }
+    }
+}
+
+/// Classifies the position from the non-pawn material left on the board
+/// (62 at the start) and how many half-moves have been played.
+pub fn game_phase(board: &Board, ply_count: u32) -> GamePhase {
+    let non_pawn_material: i32 = pieces(board)
+        .filter(|(_, piece_type)| !matches!(piece_type, PieceType::Pawn))
+        .map(|(_, piece_type)| piece_value(piece_type))
+        .sum();
+    if non_pawn_material <= 26 {
+        GamePhase::Endgame
+    } else if ply_count < 20 && non_pawn_material >= 56 {
+        GamePhase::Opening
+    } else {
+        GamePhase::Middlegame
+    }
+}
+
+/// How a game on the board ended. Unlike `GameResult` this includes the
+/// draws `hermanha_chess` doesn't detect.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Checkmate(Color),
+    Stalemate,
+    Repetition,
+    FiftyMoves,
+    InsufficientMaterial,
+}
+
+impl Outcome {
+    pub fn text(self) -> &'static str {
+        match self {
+            Outcome::Checkmate(Color::White) => "White wins by checkmate",
+            Outcome::Checkmate(Color::Black) => "Black wins by checkmate",
+            Outcome::Stalemate => "Stalemate",
+            Outcome::Repetition => "Draw by threefold repetition",
+            Outcome::FiftyMoves => "Draw by the fifty-move rule",
+            Outcome::InsufficientMaterial => "Draw by insufficient material",
+        }
+    }
+}
+
+/// Neither side can possibly mate: bare kings, a single minor piece, or
+/// only bishops that all stand on squares of one color.
+pub fn insufficient_material(board: &Board) -> bool {
+    let mut minors = 0;
+    let mut bishop_squares = [false; 2];
+    for row in 0..BOARD_ROWS as i8 {
+        for col in 0..BOARD_COLS as i8 {
+            let Some(piece) = board.get(Position::new(row, col)) else {
+                continue;
+            };
+            match piece.piece_type {
+                PieceType::King => {}
+                PieceType::Knight => minors += 1,
+                PieceType::Bishop => bishop_squares[((row + col) % 2) as usize] = true,
+                _ => return false,
+            }
+        }
+    }
+    let only_bishops_on_one_color = !(bishop_squares[0] && bishop_squares[1]);
+    match minors {
+        0 => only_bishops_on_one_color,
+        1 => !bishop_squares[0] && !bishop_squares[1],
+        _ => false,
+    }
+}
+
+pub fn opponent(color: Color) -> Color {
+    match color {
+        Color::White => Color::Black,
+        Color::Black => Color::White,
+    }
+}
+
+fn on_board(pos: Position) -> bool {
+    (0..BOARD_ROWS as i8).contains(&pos.row) && (0..BOARD_COLS as i8).contains(&pos.col)
+}
+
+fn offset(pos: Position, d_row: i8, d_col: i8) -> Position {
+    Position::new(pos.row + d_row, pos.col + d_col)
+}
+
+fn pawn_direction(color: Color) -> i8 {
+    match color {
+        Color::White => 1,
+        Color::Black => -1,
+    }
+}
+
+const KNIGHT_JUMPS: [(i8, i8); 8] = [
+    (1, 2),
+    (2, 1),
+    (2, -1),
+    (1, -2),
+    (-1, -2),
+    (-2, -1),
+    (-2, 1),
+    (-1, 2),
+];
+const ORTHOGONAL: [(i8, i8); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+const DIAGONAL: [(i8, i8); 4] = [(1, 1), (1, -1), (-1, 1), (-1, -1)];
+
+fn has_piece(board: &Board, pos: Position, color: Color, types: &[PieceType]) -> bool {
+    on_board(pos)
+        && board.get(pos).is_some_and(|piece| {
+            piece.color == color && types.iter().any(|kind| same_type(*kind, piece.piece_type))
+        })
+}
+
+/// The first occupied square when walking from `from` in direction
+/// `(d_row, d_col)`, not counting `from` itself.
+fn first_piece_along(board: &Board, from: Position, d_row: i8, d_col: i8) -> Option<Position> {
+    let mut pos = offset(from, d_row, d_col);
+    while on_board(pos) {
+        if board.get(pos).is_some() {
+            return Some(pos);
+        }
+        pos = offset(pos, d_row, d_col);
+    }
+    None
+}
+
+/// Whether any piece of color `by` attacks `target`.
+pub fn is_attacked(board: &Board, target: Position, by: Color) -> bool {
+    let pawn_row = -pawn_direction(by);
+    if [-1, 1].iter().any(|d_col| {
+        has_piece(
+            board,
+            offset(target, pawn_row, *d_col),
+            by,
+            &[PieceType::Pawn],
+        )
+    }) {
+        return true;
+    }
+    if KNIGHT_JUMPS.iter().any(|(d_row, d_col)| {
+        has_piece(
+            board,
+            offset(target, *d_row, *d_col),
+            by,
+            &[PieceType::Knight],
+        )
+    }) {
+        return true;
+    }
+    if ORTHOGONAL
+        .iter()
+        .chain(DIAGONAL.iter())
+        .any(|(d_row, d_col)| {
+            has_piece(
+                board,
+                offset(target, *d_row, *d_col),
+                by,
+                &[PieceType::King],
+            )
+        })
+    {
+        return true;
+    }
+    let sliders = [
+        (ORTHOGONAL, [PieceType::Rook, PieceType::Queen]),
+        (DIAGONAL, [PieceType::Bishop, PieceType::Queen]),
+    ];
+    sliders.iter().any(|(directions, types)| {
+        directions.iter().any(|(d_row, d_col)| {
+            first_piece_along(board, target, *d_row, *d_col)
+                .is_some_and(|pos| has_piece(board, pos, by, types))
+        })
+    })
+}
+
+pub fn in_check(board: &Board, color: Color) -> bool {
+    king_position(board, color).is_some_and(|king| is_attacked(board, king, opponent(color)))
+}
+
+fn is_clear_between(board: &Board, from: Position, to: Position) -> bool {
+    let d_row = (to.row - from.row).signum();
+    let d_col = (to.col - from.col).signum();
+    let mut pos = offset(from, d_row, d_col);
+    while pos != to {
+        if board.get(pos).is_some() {
+            return false;
+        }
+        pos = offset(pos, d_row, d_col);
+    }
+    true
+}
+
+/// Checks the movement pattern of a piece, ignoring checks and pins.
+fn movement_error(
+    board: &Board,
+    from: Position,
+    to: Position,
+    color: Color,
+    piece_type: PieceType,
+) -> Option<&'static str> {
+    let d_row = to.row - from.row;
+    let d_col = to.col - from.col;
+    let straight = d_row == 0 || d_col == 0;
+    let diagonal = d_row.abs() == d_col.abs();
+    match piece_type {
+        PieceType::Pawn => {
+            let dir = pawn_direction(color);
+            let start_row = if dir == 1 { 1 } else { 6 };
+            if d_col == 0 {
+                if d_row == dir || (d_row == 2 * dir && from.row == start_row) {
+                    if board.get(to).is_some() || !is_clear_between(board, from, to) {
+                        return Some("Another piece is in the way");
+                    }
+                    return None;
+                }
+                return Some("Pawns only move straight forward");
+            }
+            if d_col.abs() == 1 && d_row == dir {
+                if board.get(to).is_none() {
+                    return Some("Pawns only move diagonally when capturing");
+                }
+                return None;
+            }
+            Some("Pawns only move straight forward")
+        }
+        PieceType::Knight => {
+            if KNIGHT_JUMPS.contains(&(d_row, d_col)) {
+                None
+            } else {
+                Some("Knights move in an L-shape")
+            }
+        }
+        PieceType::King => {
+            if d_row.abs() <= 1 && d_col.abs() <= 1 {
+                None
+            } else if d_row == 0 && d_col.abs() == 2 {
+                Some("Castling is not allowed right now")
+            } else {
+                Some("Kings move one square at a time")
+            }
+        }
+        PieceType::Rook if !straight => Some("Rooks move along ranks and files"),
+        PieceType::Bishop if !diagonal => Some("Bishops move diagonally"),
+        PieceType::Queen if !straight && !diagonal => {
+            Some("Queens move along ranks, files, and diagonals")
+        }
+        _ if !is_clear_between(board, from, to) => Some("Another piece is in the way"),
+        _ => None,
+    }
+}
+
+/// Whether the piece on `from` is pinned to its king along a line that
+/// `to` leaves.
+fn is_pinned(board: &Board, from: Position, to: Position, color: Color) -> bool {
+    let Some(king) = king_position(board, color) else {
+        return false;
+    };
+    let d_row = from.row - king.row;
+    let d_col = from.col - king.col;
+    let straight = d_row == 0 || d_col == 0;
+    let diagonal = d_row.abs() == d_col.abs();
+    if (d_row == 0 && d_col == 0) || !(straight || diagonal) {
+        return false;
+    }
+    let (d_row, d_col) = (d_row.signum(), d_col.signum());
+    if first_piece_along(board, king, d_row, d_col) != Some(from) {
+        return false;
+    }
+    let attackers: &[PieceType] = if straight {
+        &[PieceType::Rook, PieceType::Queen]
+    } else {
+        &[PieceType::Bishop, PieceType::Queen]
+    };
+    let pinned = first_piece_along(board, from, d_row, d_col)
+        .is_some_and(|pos| has_piece(board, pos, opponent(color), attackers));
+    let stays_on_line = {
+        let t_row = to.row - king.row;
+        let t_col = to.col - king.col;
+        t_row.signum() == d_row && t_col.signum() == d_col && t_row * d_col == t_col * d_row
+    };
+    pinned && !stays_on_line
+}
+
+/// A best-effort reason why moving from `from` to `to` is not legal, found
+/// by checking the constraints a move has to pass one at a time. Returns
+/// `None` if the move is legal or there is no piece to move.
+pub fn explain_illegal(board: &Board, from: Position, to: Position) -> Option<&'static str> {
+    if board
+        .legal_moves()
+        .iter()
+        .any(|(f, t, _)| *f == from && *t == to)
+    {
+        return None;
+    }
+    let piece = board.get(from)?;
+    if piece.color != board.move_turn {
+        return Some("It is not that side's turn");
+    }
+    if board
+        .get(to)
+        .is_some_and(|target| target.color == piece.color)
+    {
+        return Some("Square occupied by your own piece");
+    }
+    if let Some(reason) = movement_error(board, from, to, piece.color, piece.piece_type) {
+        return Some(reason);
+    }
+    if matches!(piece.piece_type, PieceType::King) {
+        return Some("Your king would be in check");
+    }
+    if is_pinned(board, from, to, piece.color) {
+        return Some("That piece is pinned to your king");
+    }
+    if in_check(board, piece.color) {
+        return Some("Your king is in check");
+    }
+    Some("Your king would be in check")
+}
@@ -0,0 +1,262 @@
+use hermanha_chess::{BOARD_COLS, BOARD_ROWS, Board, GameResult, PieceType, Position};
+
+use crate::input::legal_targets;
+use crate::rules::{self, CastlingRights};
+
+pub fn square_name(pos: Position) -> String {
+    let file = (b'a' + pos.col as u8) as char;
+    format!("{}{}", file, pos.row + 1)
+}
+
+pub fn piece_letter(piece_type: PieceType) -> char {
+    match piece_type {
+        PieceType::Pawn => 'P',
+        PieceType::Knight => 'N',
+        PieceType::Bishop => 'B',
+        PieceType::Rook => 'R',
+        PieceType::Queen => 'Q',
+        PieceType::King => 'K',
+    }
+}
+
+/// Standard Algebraic Notation for a move about to be played on `board`.
+/// Falls back to coordinate notation if `from` is empty.
+pub fn move_to_san(
+    board: &Board,
+    from: Position,
+    to: Position,
+    promotion_piece: Option<PieceType>,
+) -> String {
+    let Some(piece) = board.get(from) else {
+        return format!("{}{}", square_name(from), square_name(to));
+    };
+    let is_pawn = matches!(piece.piece_type, PieceType::Pawn);
+    let mut san = String::new();
+    if rules::is_castling(board, from, to) {
+        san.push_str(if to.col > from.col { "O-O" } else { "O-O-O" });
+    } else {
+        let capture = board.get(to).is_some() || (is_pawn && from.col != to.col);
+        if is_pawn {
+            if capture {
+                san.push(square_name(from).remove(0));
+            }
+        } else {
+            san.push(piece_letter(piece.piece_type));
+            san.push_str(&disambiguation(board, from, to, piece.piece_type));
+        }
+        if capture {
+            san.push('x');
+        }
+        san.push_str(&square_name(to));
+        if let Some(promotion_piece) = promotion_piece {
+            san.push('=');
+            san.push(piece_letter(promotion_piece));
+        }
+    }
+
+    if let Some(after) = rules::board_after(board, from, to, promotion_piece) {
+        if let Some(GameResult::Checkmate(_)) = after.game_over() {
+            san.push('#');
+        } else if rules::in_check(&after, after.move_turn) {
+            san.push('+');
+        }
+    }
+    san
+}
+
+/// The file, rank, or square needed to tell `from` apart from other pieces
+/// of the same kind that could also move to `to`.
+fn disambiguation(board: &Board, from: Position, to: Position, piece_type: PieceType) -> String {
+    let rivals: Vec<Position> = board
+        .legal_moves()
+        .into_iter()
+        .filter(|(other, target, _)| {
+            *target == to
+                && *other != from
+                && board
+                    .get(*other)
+                    .is_some_and(|piece| rules::same_type(piece.piece_type, piece_type))
+        })
+        .map(|(other, _, _)| other)
+        .collect();
+    if rivals.is_empty() {
+        return String::new();
+    }
+    let square = square_name(from);
+    if rivals.iter().all(|other| other.col != from.col) {
+        square[0..1].to_string()
+    } else if rivals.iter().all(|other| other.row != from.row) {
+        square[1..2].to_string()
+    } else {
+        square
+    }
+}
+
+/// A square name such as "e4", or `None` if it isn't one.
+fn parse_square(name: &[u8]) -> Option<Position> {
+    let &[file, rank] = name else {
+        return None;
+    };
+    ((b'a'..=b'h').contains(&file) && (b'1'..=b'8').contains(&rank))
+        .then(|| Position::new((rank - b'1') as i8, (file - b'a') as i8))
+}
+
+fn promotion_piece(letter: u8) -> Option<PieceType> {
+    match letter.to_ascii_uppercase() {
+        b'Q' => Some(PieceType::Queen),
+        b'R' => Some(PieceType::Rook),
+        b'B' => Some(PieceType::Bishop),
+        b'N' => Some(PieceType::Knight),
+        _ => None,
+    }
+}
+
+/// A move in UCI coordinate notation, such as "e2e4" or "e7e8q".
+pub fn parse_uci(text: &str) -> Result<(Position, Position, Option<PieceType>), String> {
+    let invalid = || format!("Invalid move: {text}");
+    let bytes = text.as_bytes();
+    if bytes.len() != 4 && bytes.len() != 5 {
+        return Err(invalid());
+    }
+    let from = parse_square(&bytes[0..2]).ok_or_else(invalid)?;
+    let to = parse_square(&bytes[2..4]).ok_or_else(invalid)?;
+    let promotion = match bytes.get(4) {
+        None => None,
+        Some(letter) => Some(promotion_piece(*letter).ok_or_else(invalid)?),
+    };
+    Ok((from, to, promotion))
+}
+
+/// The legal move on `board` that `text` names, in SAN ("Nf3", "exd5",
+/// "e8=Q", "O-O") or in UCI ("g1f3"). Check marks are optional, and a SAN
+/// move needs only as much disambiguation as the legal moves call for.
+pub fn parse_move(
+    board: &Board,
+    castling: CastlingRights,
+    text: &str,
+) -> Result<(Position, Position, Option<PieceType>), String> {
+    let text = text.trim().trim_end_matches(['+', '#', '!', '?']);
+    let mut legal: Vec<(Position, Position)> = Vec::new();
+    for row in 0..BOARD_ROWS as i8 {
+        for col in 0..BOARD_COLS as i8 {
+            let from = Position::new(row, col);
+            if board
+                .get(from)
+                .is_some_and(|piece| piece.color == board.move_turn)
+            {
+                legal.extend(
+                    legal_targets(board, castling, from)
+                        .into_iter()
+                        .map(|(to, _)| (from, to)),
+                );
+            }
+        }
+    }
+    let candidates: Vec<(Position, Position, Option<PieceType>)> = match parse_uci(text) {
+        Ok((from, to, promotion)) => legal
+            .into_iter()
+            .filter(|&(legal_from, legal_to)| legal_from == from && legal_to == to)
+            .map(|(from, to)| (from, to, promotion))
+            .collect(),
+        Err(_) => parse_san(board, &legal, text)?,
+    };
+    let &[(from, to, promotion)] = candidates.as_slice() else {
+        return Err(if candidates.is_empty() {
+            format!("No legal move {text}")
+        } else {
+            format!("{text} is ambiguous")
+        });
+    };
+    let needs_promotion = board
+        .get(from)
+        .is_some_and(|piece| matches!(piece.piece_type, PieceType::Pawn))
+        && (to.row == 0 || to.row == BOARD_ROWS as i8 - 1);
+    match (needs_promotion, promotion) {
+        (true, None) => Err(format!("{text} needs a piece to promote to, e.g. =Q")),
+        (false, Some(_)) => Err(format!("{text} is not a promotion")),
+        _ => Ok((from, to, promotion)),
+    }
+}
+
+/// The moves among `legal` that fit a SAN move: the piece letter, any
+/// file or rank of the moving piece, the target square and the promotion.
+/// Captures don't have to be marked.
+fn parse_san(
+    board: &Board,
+    legal: &[(Position, Position)],
+    text: &str,
+) -> Result<Vec<(Position, Position, Option<PieceType>)>, String> {
+    let castle = text.replace('0', "O");
+    if castle == "O-O" || castle == "O-O-O" {
+        return Ok(legal
+            .iter()
+            .filter(|(from, to)| {
+                move_to_san(board, *from, *to, None).trim_end_matches(['+', '#']) == castle
+            })
+            .map(|&(from, to)| (from, to, None))
+            .collect());
+    }
+    let invalid = || format!("Invalid move: {text}");
+    let mut bytes: Vec<u8> = text.bytes().filter(|byte| *byte != b'x').collect();
+    let piece_type = match bytes.first().copied() {
+        Some(b'N') => PieceType::Knight,
+        Some(b'B') => PieceType::Bishop,
+        Some(b'R') => PieceType::Rook,
+        Some(b'Q') => PieceType::Queen,
+        Some(b'K') => PieceType::King,
+        _ => PieceType::Pawn,
+    };
+    if !matches!(piece_type, PieceType::Pawn) {
+        bytes.remove(0);
+    }
+    let mut promotion = None;
+    if let Some(&last) = bytes.last()
+        && let Some(piece) = promotion_piece(last).filter(|_| last.is_ascii_uppercase())
+    {
+        promotion = Some(piece);
+        bytes.pop();
+        if bytes.last() == Some(&b'=') {
+            bytes.pop();
+        }
+    }
+    if bytes.len() < 2 {
+        return Err(invalid());
+    }
+    let (hints, target) = bytes.split_at(bytes.len() - 2);
+    let target = parse_square(target).ok_or_else(invalid)?;
+    let mut file = None;
+    let mut rank = None;
+    for &hint in hints {
+        match hint {
+            b'a'..=b'h' => file = Some((hint - b'a') as i8),
+            b'1'..=b'8' => rank = Some((hint - b'1') as i8),
+            _ => return Err(invalid()),
+        }
+    }
+    Ok(legal
+        .iter()
+        .filter(|(from, to)| {
+            *to == target
+                && file.is_none_or(|file| from.col == file)
+                && rank.is_none_or(|rank| from.row == rank)
+                && board
+                    .get(*from)
+                    .is_some_and(|piece| rules::same_type(piece.piece_type, piece_type))
+        })
+        .map(|&(from, to)| (from, to, promotion))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fen;
+
+    #[test]
+    fn castling_onto_the_rook_can_give_check() {
+        // The rook lands on d1, facing the king on d8.
+        let (board, _) = fen::board_from_fen("3k4/8/8/8/8/8/8/RK6 w - -").unwrap();
+        let san = move_to_san(&board, Position::new(0, 1), Position::new(0, 0), None);
+        assert_eq!(san, "O-O-O+");
+    }
+}
@@ -0,0 +1,418 @@
+use std::fs;
+use std::time::Duration;
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use hermanha_chess::{Board, Color as HermanhaColor, PieceType, Position};
+
+use crate::actions::{self, Action};
+use crate::clock::{Clocks, TimeControl};
+use crate::config::{self, quote, unquote};
+use crate::confirm::{self, ConfirmDialog, Confirmable, Confirmed};
+use crate::fen;
+use crate::game_state::{BoardState, Castling};
+use crate::history::MoveHistory;
+use crate::menu::{AppState, ResumeButton};
+use crate::net::{Connection, PlayerName};
+use crate::rules::{self, CastlingRights};
+use crate::tcp::{move_from_string, move_to_string};
+use crate::toast::Toasts;
+use crate::variant::{HANDICAP_LEVELS, Variant};
+
+const FILE_NAME: &str = "saved_game.toml";
+
+/// A local game written to disk to be finished later. It is stored next
+/// to the settings and in the same flat `key = "string"` format: the start
+/// position as a FEN and the moves played from it, so the history can be
+/// rebuilt on resume.
+pub struct SavedGame {
+    pub variant: Variant,
+    pub start: String,
+    pub moves: Vec<(Position, Position, Option<PieceType>)>,
+    /// The time control and the time White and Black had left, for games
+    /// played on the clock.
+    pub clocks: Option<(TimeControl, Duration, Duration)>,
+    pub player_name: String,
+}
+
+/// The saved game the menu offers to resume, if there is one that isn't
+/// over yet.
+#[derive(Resource, Default)]
+pub struct ResumableGame(pub Option<SavedGame>);
+
+impl SavedGame {
+    fn capture(
+        board: &Board,
+        castling: CastlingRights,
+        history: &MoveHistory,
+        variant: Variant,
+        clocks: Option<&Clocks>,
+        player_name: &PlayerName,
+    ) -> Self {
+        let (start, start_castling) = history.start().unwrap_or((board, castling));
+        SavedGame {
+            variant,
+            start: fen::position_to_fen(start, start_castling),
+            moves: history
+                .moves
+                .iter()
+                .map(|played| (played.from, played.to, played.promotion_piece))
+                .collect(),
+            clocks: clocks.map(|clocks| {
+                (
+                    clocks.time_control(),
+                    clocks.remaining(HermanhaColor::White),
+                    clocks.remaining(HermanhaColor::Black),
+                )
+            }),
+            player_name: player_name.0.clone(),
+        }
+    }
+
+    /// Whether this save is `current` as it stood at some point, so that
+    /// saving `current` over it loses nothing.
+    fn leads_to(&self, current: &SavedGame) -> bool {
+        self.variant == current.variant
+            && self.start == current.start
+            && self.moves.len() <= current.moves.len()
+            && self.moves.iter().zip(&current.moves).all(
+                |(&(from, to, promotion), &(other_from, other_to, other_promotion))| {
+                    move_to_string(from, to, promotion)
+                        == move_to_string(other_from, other_to, other_promotion)
+                },
+            )
+    }
+
+    /// Plays the moves again from the start position, giving the board,
+    /// castling rights and history they lead to.
+    pub fn restore(&self) -> Result<(Board, CastlingRights, MoveHistory), String> {
+        let (mut board, mut castling) = fen::board_from_fen(&self.start)?;
+        // A FEN doesn't say where Chess960 rooks started.
+        castling.files = self.variant.start().1.files;
+        let mut history = MoveHistory::default();
+        for &(from, to, promotion_piece) in &self.moves {
+            let before = board.clone();
+            match rules::castle_onto_rook(&board, castling, from, to) {
+                Some(after) => board = after,
+                None => {
+                    board
+                        .play((from.row, from.col), (to.row, to.col), promotion_piece)
+                        .map_err(|err| {
+                            format!(
+                                "Illegal move {} ({err:?})",
+                                move_to_string(from, to, promotion_piece)
+                            )
+                        })?;
+                }
+            }
+            history.push(&before, castling, from, to, promotion_piece);
+            castling.update(from, to);
+        }
+        Ok((board, castling, history))
+    }
+
+    /// The saved game, if there is one that can still be played on.
+    pub fn load() -> Option<Self> {
+        let path = config::config_path(FILE_NAME)?;
+        let text = fs::read_to_string(&path).ok()?;
+        SavedGame::from_toml(&text)
+            .and_then(|saved| {
+                let (board, castling, history) = saved.restore()?;
+                Ok(history.outcome(&board, castling).is_none().then_some(saved))
+            })
+            .unwrap_or_else(|err| {
+                warn!("Ignoring invalid saved game in {}: {err}", path.display());
+                None
+            })
+    }
+
+    /// Writes the game to the config directory, returning what to tell
+    /// the player.
+    fn save(&self) -> String {
+        let Some(path) = config::config_path(FILE_NAME) else {
+            warn!("No config directory to save the game to");
+            return "Could not save the game: no config directory".to_string();
+        };
+        let result = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|()| fs::write(&path, self.to_toml()));
+        match result {
+            Ok(()) => {
+                info!("Saved game to {}", path.display());
+                format!("Game saved to {}", path.display())
+            }
+            Err(err) => {
+                warn!("Could not save game to {}: {err}", path.display());
+                format!("Could not save the game: {err}")
+            }
+        }
+    }
+
+    fn remove() {
+        if let Some(path) = config::config_path(FILE_NAME)
+            && let Err(err) = fs::remove_file(&path)
+        {
+            warn!("Could not remove saved game {}: {err}", path.display());
+        }
+    }
+
+    fn from_toml(text: &str) -> Result<Self, String> {
+        let mut saved = SavedGame {
+            variant: Variant::Standard,
+            start: String::new(),
+            moves: Vec::new(),
+            clocks: None,
+            player_name: String::new(),
+        };
+        let mut time_control = None;
+        let mut white_time = None;
+        let mut black_time = None;
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("Expected key = value: {line}"))?;
+            let value = unquote(value.trim())?;
+            match key.trim() {
+                "variant" => saved.variant = parse_variant(&value)?,
+                "start" => saved.start = value,
+                "moves" => {
+                    saved.moves = value
+                        .split_whitespace()
+                        .map(|text| move_from_string(text).map_err(|err| err.to_string()))
+                        .collect::<Result<_, _>>()?;
+                }
+                "time_control" => time_control = Some(TimeControl::parse(&value)?),
+                "white_time" => white_time = Some(parse_seconds(&value)?),
+                "black_time" => black_time = Some(parse_seconds(&value)?),
+                "player_name" => saved.player_name = value,
+                other => warn!("Unknown saved game field: {other}"),
+            }
+        }
+        if saved.start.is_empty() {
+            return Err("No start position".to_string());
+        }
+        if let Some(time_control) = time_control {
+            saved.clocks = Some((
+                time_control,
+                white_time.unwrap_or(time_control.base),
+                black_time.unwrap_or(time_control.base),
+            ));
+        }
+        Ok(saved)
+    }
+
+    fn to_toml(&self) -> String {
+        let variant = match self.variant {
+            Variant::Standard => "standard".to_string(),
+            Variant::Chess960(number) => format!("chess960 {number}"),
+            Variant::Handicap(level) => format!("handicap {level}"),
+        };
+        let moves: Vec<String> = self
+            .moves
+            .iter()
+            .map(|&(from, to, promotion_piece)| move_to_string(from, to, promotion_piece))
+            .collect();
+        let mut fields = vec![
+            ("variant", variant),
+            ("start", self.start.clone()),
+            ("moves", moves.join(" ")),
+            ("player_name", self.player_name.clone()),
+        ];
+        if let Some((time_control, white, black)) = self.clocks {
+            fields.push(("time_control", time_control.to_string()));
+            fields.push(("white_time", white.as_secs_f32().to_string()));
+            fields.push(("black_time", black.as_secs_f32().to_string()));
+        }
+        fields
+            .iter()
+            .map(|(key, value)| format!("{key} = {}\n", quote(value)))
+            .collect()
+    }
+}
+
+fn parse_variant(text: &str) -> Result<Variant, String> {
+    match text.split_once(' ') {
+        None if text == "standard" => Ok(Variant::Standard),
+        Some(("chess960", number)) => number
+            .parse()
+            .map(Variant::Chess960)
+            .map_err(|_| format!("Invalid start position: {number}")),
+        Some(("handicap", level)) => level
+            .parse()
+            .ok()
+            .filter(|level| (1..=HANDICAP_LEVELS).contains(level))
+            .map(Variant::Handicap)
+            .ok_or_else(|| format!("Invalid handicap level: {level}")),
+        _ => Err(format!("Invalid variant: {text}")),
+    }
+}
+
+fn parse_seconds(text: &str) -> Result<Duration, String> {
+    text.parse()
+        .ok()
+        .and_then(|secs: f32| Duration::try_from_secs_f32(secs).ok())
+        .ok_or_else(|| format!("Invalid time: {text}"))
+}
+
+/// Asks for the game to be saved as with Ctrl+S, e.g. from the pause menu.
+#[derive(Event)]
+pub struct SaveRequested;
+
+/// The game on the board as it would be saved, next to the save it
+/// would replace.
+#[derive(SystemParam)]
+pub struct GameToSave<'w> {
+    board: Res<'w, BoardState>,
+    castling: Res<'w, Castling>,
+    history: Res<'w, MoveHistory>,
+    variant: Res<'w, Variant>,
+    clocks: Option<Res<'w, Clocks>>,
+    player_name: Res<'w, PlayerName>,
+    resumable: ResMut<'w, ResumableGame>,
+}
+
+impl GameToSave<'_> {
+    fn capture(&self) -> SavedGame {
+        SavedGame::capture(
+            &self.board.0,
+            self.castling.0,
+            &self.history,
+            *self.variant,
+            self.clocks.as_deref(),
+            &self.player_name,
+        )
+    }
+
+    /// Whether leaving now would lose moves, because the game has some
+    /// that aren't in the save.
+    pub fn unsaved(&self) -> bool {
+        let current = self.capture();
+        !current.moves.is_empty()
+            && !self.resumable.0.as_ref().is_some_and(|saved| {
+                saved.leads_to(&current) && saved.moves.len() == current.moves.len()
+            })
+    }
+
+    /// Whether saving would replace the save of a different game.
+    pub fn overwrites(&self) -> bool {
+        let current = self.capture();
+        self.resumable
+            .0
+            .as_ref()
+            .is_some_and(|saved| !saved.leads_to(&current))
+    }
+
+    /// Writes the game to disk, returning what to tell the player.
+    fn save(&mut self) -> String {
+        let saved = self.capture();
+        let message = saved.save();
+        self.resumable.0 = Some(saved);
+        message
+    }
+}
+
+/// Ctrl+S writes the game to disk. An earlier save of the same game is
+/// replaced without asking, but the save of another game only once the
+/// player has confirmed it. Only available in local play.
+#[allow(clippy::too_many_arguments)]
+pub fn save_game(
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    mut requested: EventReader<SaveRequested>,
+    mut confirmed: EventReader<Confirmed>,
+    dialogs: Query<(), With<ConfirmDialog>>,
+    connection: Option<Res<Connection>>,
+    mut game: GameToSave,
+    mut toasts: ResMut<Toasts>,
+) {
+    let asked = !requested.is_empty();
+    requested.clear();
+    let overwrite = confirmed
+        .read()
+        .any(|confirmed| confirmed.0 == Confirmable::OverwriteSave);
+    if connection.is_some() {
+        return;
+    }
+    if !overwrite {
+        if !asked && !actions::just_pressed(&keys, Action::SaveGame) {
+            return;
+        }
+        if game.overwrites() {
+            confirm::ask(&mut commands, &dialogs, Confirmable::OverwriteSave);
+            return;
+        }
+    }
+    toasts.push(game.save());
+}
+
+/// The menu's resume button picks the saved game up where it was left.
+/// The save is used up by this; saving again keeps it for another time.
+#[allow(clippy::too_many_arguments)]
+pub fn resume_game(
+    mut commands: Commands,
+    interactions: Query<&Interaction, (Changed<Interaction>, With<ResumeButton>)>,
+    mut resumable: ResMut<ResumableGame>,
+    mut board: ResMut<BoardState>,
+    mut castling: ResMut<Castling>,
+    mut history: ResMut<MoveHistory>,
+    mut variant: ResMut<Variant>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    if !interactions
+        .iter()
+        .any(|interaction| *interaction == Interaction::Pressed)
+    {
+        return;
+    }
+    let Some(saved) = resumable.0.take() else {
+        return;
+    };
+    let (restored_board, restored_castling, restored_history) = match saved.restore() {
+        Ok(restored) => restored,
+        Err(err) => {
+            warn!("Could not resume the saved game: {err}");
+            return;
+        }
+    };
+    board.0 = restored_board;
+    castling.0 = restored_castling;
+    *history = restored_history;
+    *variant = saved.variant;
+    match saved.clocks {
+        Some((time_control, white, black)) => commands.insert_resource(Clocks::resumed(
+            time_control,
+            white,
+            black,
+            history.ply_count(),
+        )),
+        None => commands.remove_resource::<Clocks>(),
+    }
+    SavedGame::remove();
+    next_state.set(AppState::Playing);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_seconds_left_on_a_clock() {
+        assert_eq!(parse_seconds("90.5"), Ok(Duration::from_secs_f32(90.5)));
+        assert_eq!(parse_seconds("0"), Ok(Duration::ZERO));
+        for text in ["-1", "NaN", "inf", "1e30", ""] {
+            assert!(parse_seconds(text).is_err(), "{text} was accepted");
+        }
+    }
+
+    #[test]
+    fn handicap_levels_are_saved_by_number() {
+        assert_eq!(parse_variant("handicap 4"), Ok(Variant::Handicap(4)));
+        for text in ["handicap 0", "handicap 6", "handicap x"] {
+            assert!(parse_variant(text).is_err(), "{text} was accepted");
+        }
+    }
+}
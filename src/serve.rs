@@ -0,0 +1,373 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use hermanha_chess::Color as HermanhaColor;
+
+use crate::clock;
+use crate::tcp::{
+    HelloMessage, Message, PROTOCOL_VERSION, PongMessage, QuitMessage, TcpConnection, TcpError,
+};
+use crate::variant::Variant;
+
+/// Where `--serve` listens unless told otherwise, the port players join
+/// on by default.
+pub const DEFAULT_SERVE_ADDRESS: &str = "0.0.0.0:8080";
+
+/// How long the relay sleeps between passes over its connections.
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// The status is printed whenever a game starts or ends, and this often
+/// otherwise while games go on.
+const STATUS_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Someone connected to the relay, known by name once their hello is in.
+struct Player {
+    connection: TcpConnection,
+    address: SocketAddr,
+    name: Option<String>,
+}
+
+impl Player {
+    fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or("?")
+    }
+
+    fn send(&mut self, message: Message) -> bool {
+        self.connection.write(message).is_ok()
+    }
+
+    fn hang_up(&mut self, reason: &str) {
+        self.send(Message::Quit(QuitMessage {
+            message: Some(reason.to_string()),
+        }));
+    }
+}
+
+/// Two players paired by the relay, each one's messages passed on to the
+/// other.
+struct RelayedGame {
+    white: Player,
+    black: Player,
+    started: Instant,
+    /// Moves relayed so far, counting both sides'.
+    plies: u32,
+}
+
+/// Every connection the relay holds: players waiting for an opponent, in
+/// the order they came, and the games under way by id.
+#[derive(Default)]
+struct Registry {
+    waiting: Vec<Player>,
+    games: BTreeMap<u32, RelayedGame>,
+    next_id: u32,
+}
+
+impl Registry {
+    /// Takes every connection waiting on the listener. Returns whether
+    /// there were any.
+    fn accept(&mut self, listener: &TcpListener) -> bool {
+        let mut accepted = false;
+        while let Ok((stream, address)) = listener.accept() {
+            match TcpConnection::accept_stream(stream) {
+                Ok(connection) => {
+                    println!("{address} connected");
+                    self.waiting.push(Player {
+                        connection,
+                        address,
+                        name: None,
+                    });
+                    accepted = true;
+                }
+                Err(err) => eprintln!("Could not set up the connection from {address}: {err}"),
+            }
+        }
+        accepted
+    }
+
+    /// Reads what waiting players send: their hello, and pings to answer.
+    /// A player who hangs up is dropped, and so is one who comes back
+    /// to a game already under way, which the relay can't pick up again.
+    /// Returns whether anyone was dropped.
+    fn poll_waiting(&mut self) -> bool {
+        let before = self.waiting.len();
+        self.waiting.retain_mut(|player| {
+            loop {
+                match player.connection.read() {
+                    Ok(Message::Hello(hello)) => {
+                        if hello.version != PROTOCOL_VERSION {
+                            let version = hello.version;
+                            println!("{} speaks protocol version {version}", player.address);
+                        }
+                        player.name = Some(hello.name);
+                    }
+                    Ok(Message::Ping(ping)) => {
+                        player.send(Message::Pong(PongMessage { id: ping.id }));
+                    }
+                    Ok(Message::Sync(_)) => {
+                        println!("{} tried to resume a game", player.address);
+                        player.hang_up("The server can't resume games \u{2014} start a new one");
+                        return false;
+                    }
+                    Ok(Message::Quit(_)) => {
+                        println!("{} left before being paired", player.address);
+                        return false;
+                    }
+                    Ok(_) => {}
+                    Err(TcpError::WouldBlock) => return true,
+                    Err(TcpError::InvalidMessage(err)) => {
+                        println!("Ignoring a message from {}: {err}", player.address);
+                    }
+                    Err(TcpError::Io(err)) => {
+                        println!("{} disconnected: {err}", player.address);
+                        return false;
+                    }
+                }
+            }
+        });
+        self.waiting.len() != before
+    }
+
+    /// Pairs the players who have said hello, first come first served,
+    /// the earlier one playing White. The relay does the host's part of
+    /// the handshake, telling each their color and the other's name.
+    /// Returns whether a game started.
+    fn pair(&mut self) -> bool {
+        let mut paired = false;
+        loop {
+            let ready: Vec<usize> = self
+                .waiting
+                .iter()
+                .enumerate()
+                .filter(|(_, player)| player.name.is_some())
+                .map(|(index, _)| index)
+                .take(2)
+                .collect();
+            let [first, second] = ready[..] else {
+                return paired;
+            };
+            let mut black = self.waiting.remove(second);
+            let mut white = self.waiting.remove(first);
+            let (white_name, black_name) = (white.name().to_string(), black.name().to_string());
+            for (player, color, opponent) in [
+                (&mut white, HermanhaColor::White, black_name),
+                (&mut black, HermanhaColor::Black, white_name),
+            ] {
+                let hello = HelloMessage {
+                    version: PROTOCOL_VERSION,
+                    name: opponent,
+                    client_color: Some(color),
+                    variant: Variant::Standard,
+                    move_timeout: None,
+                    session: None,
+                };
+                player.send(Message::Hello(hello));
+            }
+            let id = self.next_id;
+            self.next_id += 1;
+            println!("Game {id}: {} vs {}", white.name(), black.name());
+            self.games.insert(
+                id,
+                RelayedGame {
+                    white,
+                    black,
+                    started: Instant::now(),
+                    plies: 0,
+                },
+            );
+            paired = true;
+        }
+    }
+
+    /// Passes every game's messages on. A game ends when either player
+    /// leaves or drops out; the other is told, as if their opponent had
+    /// quit. Returns whether a game ended.
+    fn relay_games(&mut self) -> bool {
+        let mut ended = Vec::new();
+        for (id, game) in self.games.iter_mut() {
+            let RelayedGame {
+                white,
+                black,
+                plies,
+                ..
+            } = game;
+            if relay(white, black, plies) || relay(black, white, plies) {
+                println!("Game {id} ended");
+                ended.push(*id);
+            }
+        }
+        for id in &ended {
+            // Dropping the connections hangs up once what's queued is
+            // sent.
+            self.games.remove(id);
+        }
+        !ended.is_empty()
+    }
+
+    fn print_status(&self) {
+        println!(
+            "{} active games, {} waiting",
+            self.games.len(),
+            self.waiting.len()
+        );
+        for (id, game) in &self.games {
+            println!(
+                "  #{id:<4} {} (White) vs {} (Black), move {}, {}",
+                game.white.name(),
+                game.black.name(),
+                game.plies / 2 + 1,
+                clock::format_duration(game.started.elapsed())
+            );
+        }
+    }
+}
+
+/// Passes on what `from` sent to `to`. The hellos were the relay's to
+/// answer, so they go no further. Returns whether the game is over.
+fn relay(from: &mut Player, to: &mut Player, plies: &mut u32) -> bool {
+    loop {
+        let message = match from.connection.read() {
+            Ok(message) => message,
+            Err(TcpError::WouldBlock) => return false,
+            Err(TcpError::InvalidMessage(err)) => {
+                println!("Ignoring a message from {}: {err}", from.address);
+                continue;
+            }
+            Err(TcpError::Io(err)) => {
+                println!("{} disconnected: {err}", from.address);
+                to.hang_up("Opponent disconnected");
+                return true;
+            }
+        };
+        let quit = match message {
+            Message::Hello(_) => continue,
+            Message::Move(_) => {
+                *plies += 1;
+                false
+            }
+            Message::Quit(_) => true,
+            _ => false,
+        };
+        if !to.send(message) {
+            from.hang_up("Opponent disconnected");
+            return true;
+        }
+        if quit {
+            return true;
+        }
+    }
+}
+
+/// Runs as a dedicated server on `address`, without a window: players
+/// join it as they would join a host, are paired two by two as they come,
+/// and play through it. Only plain TCP is spoken. Lists the games under
+/// way on the console. Returns only if the address can't be listened on.
+pub fn run(address: &str) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    listener.set_nonblocking(true)?;
+    println!("Serving games on {address}");
+    let mut registry = Registry::default();
+    let mut last_status = Instant::now();
+    loop {
+        let mut changed = registry.accept(&listener);
+        changed |= registry.poll_waiting();
+        changed |= registry.pair();
+        changed |= registry.relay_games();
+        if changed || (!registry.games.is_empty() && last_status.elapsed() >= STATUS_INTERVAL) {
+            registry.print_status();
+            last_status = Instant::now();
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A player on the relay's end of a loopback connection, and the
+    /// connection at their end.
+    fn connected(listener: &TcpListener, name: Option<&str>) -> (Player, TcpConnection) {
+        let address = listener.local_addr().unwrap().to_string();
+        let client = TcpConnection::connect_to_server(&address, None).unwrap();
+        let (stream, address) = listener.accept().unwrap();
+        let player = Player {
+            connection: TcpConnection::accept_stream(stream).unwrap(),
+            address,
+            name: name.map(str::to_string),
+        };
+        (player, client)
+    }
+
+    /// The first thing reading gives other than `WouldBlock`.
+    fn read_soon(connection: &mut TcpConnection) -> Message {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        loop {
+            match connection.read() {
+                Err(TcpError::WouldBlock) if Instant::now() < deadline => {}
+                result => return result.unwrap_or_else(|err| panic!("nothing to read: {err}")),
+            }
+        }
+    }
+
+    #[test]
+    fn the_first_two_named_players_are_paired_the_earlier_as_white() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (ann, mut ann_client) = connected(&listener, Some("Ann"));
+        let (unnamed, _unnamed_client) = connected(&listener, None);
+        let (cid, mut cid_client) = connected(&listener, Some("Cid"));
+        let mut registry = Registry {
+            waiting: vec![ann, unnamed, cid],
+            ..Default::default()
+        };
+        assert!(registry.pair());
+        assert_eq!(registry.games.len(), 1);
+        let game = &registry.games[&0];
+        assert_eq!((game.white.name(), game.black.name()), ("Ann", "Cid"));
+        assert_eq!(registry.waiting.len(), 1);
+        assert_eq!(registry.waiting[0].name, None);
+        let Message::Hello(hello) = read_soon(&mut ann_client) else {
+            panic!("Ann was not told who they play");
+        };
+        assert_eq!(hello.name, "Cid");
+        assert!(matches!(hello.client_color, Some(HermanhaColor::White)));
+        let Message::Hello(hello) = read_soon(&mut cid_client) else {
+            panic!("Cid was not told who they play");
+        };
+        assert_eq!(hello.name, "Ann");
+        assert!(matches!(hello.client_color, Some(HermanhaColor::Black)));
+    }
+
+    #[test]
+    fn a_lone_named_player_keeps_waiting() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (ann, _ann_client) = connected(&listener, Some("Ann"));
+        let mut registry = Registry {
+            waiting: vec![ann],
+            ..Default::default()
+        };
+        assert!(!registry.pair());
+        assert!(registry.games.is_empty());
+        assert_eq!(registry.waiting.len(), 1);
+    }
+
+    #[test]
+    fn a_quit_is_passed_on_and_ends_the_game() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (mut white, mut white_client) = connected(&listener, Some("Ann"));
+        let (mut black, mut black_client) = connected(&listener, Some("Cid"));
+        white_client
+            .write(Message::Quit(QuitMessage { message: None }))
+            .unwrap();
+        let mut plies = 0;
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while !relay(&mut white, &mut black, &mut plies) {
+            assert!(Instant::now() < deadline, "the quit never arrived");
+            thread::sleep(POLL_INTERVAL);
+        }
+        assert_eq!(plies, 0);
+        assert!(matches!(read_soon(&mut black_client), Message::Quit(_)));
+    }
+}
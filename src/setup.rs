@@ -0,0 +1,520 @@
+use arboard::Clipboard;
+use bevy::input::keyboard::KeyboardInput;
+use bevy::prelude::*;
+use bevy::window::PrimaryWindow;
+use hermanha_chess::{Board, Color as HermanhaColor, PieceType, Position};
+
+use crate::board_render::{BoardCamera, Piece};
+use crate::game_state::{BoardState, Castling, SelectedSquare};
+use crate::history::MoveHistory;
+use crate::menu::AppState;
+use crate::rules::{self, CastlingFiles, CastlingRights};
+use crate::tcp::board_to_fen;
+use crate::text_input::{self, TextEdit, TextInput};
+use crate::{PIECE_Z, cursor_to_board_position, fen, pos_to_vec3, san, widgets};
+
+const PIECE_TYPES: [PieceType; 6] = [
+    PieceType::King,
+    PieceType::Queen,
+    PieceType::Rook,
+    PieceType::Bishop,
+    PieceType::Knight,
+    PieceType::Pawn,
+];
+
+/// What a left click on an empty square does.
+#[derive(Clone, Copy)]
+pub enum Brush {
+    Piece(HermanhaColor, PieceType),
+    Erase,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Wing {
+    Kingside,
+    Queenside,
+}
+
+/// The position being set up. The pieces live in `BoardState` so they are
+/// drawn like any other position; the side to move and castling rights
+/// are only applied when the game starts.
+#[derive(Resource)]
+pub struct SetupEditor {
+    brush: Brush,
+    side_to_move: HermanhaColor,
+    castling: CastlingRights,
+    /// The square whose piece is being dragged.
+    dragging: Option<Position>,
+    /// Why the position can't be played, or where the FEN went.
+    message: String,
+    /// The FEN being typed in to replace the position, while one is.
+    fen_input: Option<TextInput>,
+}
+
+impl SetupEditor {
+    fn fen(&self, board: &Board) -> String {
+        let side = match self.side_to_move {
+            HermanhaColor::White => "w",
+            HermanhaColor::Black => "b",
+        };
+        format!(
+            "{} {side} {}",
+            board_to_fen(board),
+            fen::castling_to_fen(self.castling)
+        )
+    }
+}
+
+/// Most FENs fit, with room for long move counters.
+const FEN_LEN: usize = 100;
+
+fn validate_fen(text: &str) -> Result<(), String> {
+    fen::board_from_fen(text).map(|_| ())
+}
+
+#[derive(Component, Clone, Copy)]
+pub enum SetupButton {
+    Brush(Brush),
+    SideToMove(HermanhaColor),
+    Castling(HermanhaColor, Wing),
+    Clear,
+    StartPosition,
+    CopyFen,
+    TypeFen,
+    Play,
+}
+
+impl SetupButton {
+    fn label(self) -> String {
+        match self {
+            SetupButton::Brush(Brush::Piece(color, piece_type)) => {
+                let letter = san::piece_letter(piece_type);
+                match color {
+                    HermanhaColor::White => letter.to_string(),
+                    HermanhaColor::Black => letter.to_ascii_lowercase().to_string(),
+                }
+            }
+            SetupButton::Brush(Brush::Erase) => "Erase".to_string(),
+            SetupButton::SideToMove(HermanhaColor::White) => "White to move".to_string(),
+            SetupButton::SideToMove(HermanhaColor::Black) => "Black to move".to_string(),
+            SetupButton::Castling(color, wing) => {
+                let side = match color {
+                    HermanhaColor::White => "White",
+                    HermanhaColor::Black => "Black",
+                };
+                match wing {
+                    Wing::Kingside => format!("{side} O-O"),
+                    Wing::Queenside => format!("{side} O-O-O"),
+                }
+            }
+            SetupButton::Clear => "Clear board".to_string(),
+            SetupButton::StartPosition => "Start position".to_string(),
+            SetupButton::CopyFen => "Copy FEN".to_string(),
+            SetupButton::TypeFen => "Type FEN".to_string(),
+            SetupButton::Play => "Play from here".to_string(),
+        }
+    }
+
+    fn active(self, editor: &SetupEditor) -> bool {
+        match self {
+            SetupButton::Brush(Brush::Piece(color, piece_type)) => matches!(
+                editor.brush,
+                Brush::Piece(brush_color, brush_type)
+                    if brush_color == color && rules::same_type(brush_type, piece_type)
+            ),
+            SetupButton::Brush(Brush::Erase) => matches!(editor.brush, Brush::Erase),
+            SetupButton::SideToMove(color) => editor.side_to_move == color,
+            SetupButton::Castling(color, Wing::Kingside) => editor.castling.kingside(color),
+            SetupButton::Castling(color, Wing::Queenside) => editor.castling.queenside(color),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct SetupPanel;
+
+#[derive(Component)]
+pub struct SetupStatus;
+
+fn button(kind: SetupButton, width: f32) -> impl Bundle {
+    (
+        kind,
+        widgets::button(
+            widgets::button_node(width, 28.0),
+            widgets::small_label(kind.label()),
+        ),
+    )
+}
+
+fn row() -> Node {
+    Node {
+        column_gap: Val::Px(4.0),
+        ..default()
+    }
+}
+
+/// Starts editing from whatever position is on the board.
+pub fn enter_setup(mut commands: Commands, board: Res<BoardState>, castling: Res<Castling>) {
+    commands.insert_resource(SetupEditor {
+        brush: Brush::Piece(HermanhaColor::White, PieceType::Pawn),
+        side_to_move: board.0.move_turn,
+        castling: castling.0,
+        dragging: None,
+        message: String::new(),
+        fen_input: None,
+    });
+    commands
+        .spawn((
+            SetupPanel,
+            Node {
+                position_type: PositionType::Absolute,
+                left: Val::Px(0.0),
+                top: Val::Px(0.0),
+                width: Val::Px(220.0),
+                height: Val::Percent(100.0),
+                padding: UiRect::all(Val::Px(10.0)),
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(6.0),
+                ..default()
+            },
+            BackgroundColor(widgets::PANEL_COLOR),
+        ))
+        .with_children(|parent| {
+            parent.spawn(Text::new("Set up position"));
+            for color in [HermanhaColor::White, HermanhaColor::Black] {
+                parent.spawn(row()).with_children(|parent| {
+                    for piece_type in PIECE_TYPES {
+                        parent.spawn(button(
+                            SetupButton::Brush(Brush::Piece(color, piece_type)),
+                            28.0,
+                        ));
+                    }
+                });
+            }
+            parent.spawn(button(SetupButton::Brush(Brush::Erase), 200.0));
+            parent.spawn(row()).with_children(|parent| {
+                parent.spawn(button(SetupButton::SideToMove(HermanhaColor::White), 98.0));
+                parent.spawn(button(SetupButton::SideToMove(HermanhaColor::Black), 98.0));
+            });
+            for color in [HermanhaColor::White, HermanhaColor::Black] {
+                parent.spawn(row()).with_children(|parent| {
+                    parent.spawn(button(SetupButton::Castling(color, Wing::Kingside), 98.0));
+                    parent.spawn(button(SetupButton::Castling(color, Wing::Queenside), 98.0));
+                });
+            }
+            parent.spawn(row()).with_children(|parent| {
+                parent.spawn(button(SetupButton::Clear, 98.0));
+                parent.spawn(button(SetupButton::StartPosition, 98.0));
+            });
+            parent.spawn(row()).with_children(|parent| {
+                parent.spawn(button(SetupButton::CopyFen, 98.0));
+                parent.spawn(button(SetupButton::TypeFen, 98.0));
+            });
+            parent.spawn(button(SetupButton::Play, 200.0));
+            parent.spawn((
+                SetupStatus,
+                Text::new(""),
+                TextFont {
+                    font_size: 13.0,
+                    ..default()
+                },
+            ));
+        });
+}
+
+pub fn exit_setup(mut commands: Commands, panels: Query<Entity, With<SetupPanel>>) {
+    for entity in panels.iter() {
+        commands.entity(entity).despawn();
+    }
+    commands.remove_resource::<SetupEditor>();
+}
+
+/// Castling rights only make sense with the king and rook still on their
+/// starting squares.
+fn check_castling(board: &Board, castling: CastlingRights) -> Result<(), String> {
+    for (color, row) in [(HermanhaColor::White, 0), (HermanhaColor::Black, 7)] {
+        let holds = |col: i8, piece_type: PieceType| {
+            board.get(Position::new(row, col)).is_some_and(|piece| {
+                piece.color == color && rules::same_type(piece.piece_type, piece_type)
+            })
+        };
+        let king_home = holds(4, PieceType::King);
+        if castling.kingside(color) && !(king_home && holds(7, PieceType::Rook))
+            || castling.queenside(color) && !(king_home && holds(0, PieceType::Rook))
+        {
+            return Err("Castling needs the king and rook on their starting squares".to_string());
+        }
+    }
+    Ok(())
+}
+
+pub fn handle_setup_buttons(
+    interactions: Query<(&Interaction, &SetupButton), Changed<Interaction>>,
+    mut editor: ResMut<SetupEditor>,
+    mut board_state: ResMut<BoardState>,
+    mut castling: ResMut<Castling>,
+    mut history: ResMut<MoveHistory>,
+    mut selected: ResMut<SelectedSquare>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    for (interaction, button) in interactions.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        editor.message.clear();
+        match *button {
+            SetupButton::Brush(brush) => editor.brush = brush,
+            SetupButton::SideToMove(color) => editor.side_to_move = color,
+            SetupButton::Castling(color, wing) => {
+                let rights = &mut editor.castling;
+                let right = match (color, wing) {
+                    (HermanhaColor::White, Wing::Kingside) => &mut rights.white_kingside,
+                    (HermanhaColor::White, Wing::Queenside) => &mut rights.white_queenside,
+                    (HermanhaColor::Black, Wing::Kingside) => &mut rights.black_kingside,
+                    (HermanhaColor::Black, Wing::Queenside) => &mut rights.black_queenside,
+                };
+                *right = !*right;
+            }
+            SetupButton::Clear => {
+                board_state.0 = Board::start_pos();
+                board_state.0.setup_fen("8/8/8/8/8/8/8/8");
+                editor.castling = CastlingRights {
+                    white_kingside: false,
+                    white_queenside: false,
+                    black_kingside: false,
+                    black_queenside: false,
+                    files: CastlingFiles::default(),
+                };
+            }
+            SetupButton::StartPosition => {
+                board_state.0 = Board::start_pos();
+                editor.side_to_move = HermanhaColor::White;
+                editor.castling = CastlingRights::default();
+            }
+            SetupButton::CopyFen => {
+                let fen = editor.fen(&board_state.0);
+                editor.message =
+                    match Clipboard::new().and_then(|mut clipboard| clipboard.set_text(&fen)) {
+                        Ok(()) => "FEN copied to the clipboard".to_string(),
+                        Err(err) => format!("Could not copy FEN: {err}"),
+                    };
+            }
+            SetupButton::TypeFen => {
+                let mut input = TextInput::new(FEN_LEN, text_input::any_char, validate_fen);
+                input.set_text(editor.fen(&board_state.0));
+                editor.fen_input = Some(input);
+            }
+            SetupButton::Play => {
+                let fen = editor.fen(&board_state.0);
+                let result = check_castling(&board_state.0, editor.castling)
+                    .and_then(|()| fen::board_from_fen(&fen));
+                match result {
+                    Ok((board, castling_rights)) => {
+                        board_state.0 = board;
+                        castling.0 = castling_rights;
+                        *history = MoveHistory::default();
+                        selected.0 = None;
+                        next_state.set(AppState::Playing);
+                    }
+                    Err(err) => editor.message = err,
+                }
+            }
+        }
+    }
+}
+
+/// Left click on an empty square places the current brush piece, left
+/// drag moves a piece (dropping it off the board removes it) and right
+/// click empties a square.
+pub fn edit_setup_board(
+    buttons: Res<ButtonInput<MouseButton>>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    camera_q: Query<(&Camera, &GlobalTransform), With<BoardCamera>>,
+    mut editor: ResMut<SetupEditor>,
+    mut board_state: ResMut<BoardState>,
+    mut pieces: Query<(&Piece, &mut Transform)>,
+) {
+    let Some(window) = windows.iter().next() else {
+        return;
+    };
+    let Some(cursor_position) = window.cursor_position() else {
+        return;
+    };
+    let Some((camera, camera_transform)) = camera_q.iter().next() else {
+        return;
+    };
+    let square = cursor_to_board_position(cursor_position, camera, camera_transform, &[])
+        .filter(|pos| board_state.0.pos_on_board(*pos));
+
+    if buttons.just_pressed(MouseButton::Right)
+        && let Some(pos) = square
+    {
+        rules::set_square(&mut board_state.0, pos, None);
+    }
+    if buttons.just_pressed(MouseButton::Left)
+        && let Some(pos) = square
+    {
+        let occupied = board_state.0.get(pos).is_some();
+        match editor.brush {
+            Brush::Erase => rules::set_square(&mut board_state.0, pos, None),
+            _ if occupied => editor.dragging = Some(pos),
+            Brush::Piece(color, piece_type) => {
+                rules::set_square(&mut board_state.0, pos, Some((color, piece_type)))
+            }
+        }
+    }
+
+    let Some(from) = editor.dragging else {
+        return;
+    };
+    if buttons.pressed(MouseButton::Left) {
+        if let Ok(world_position) = camera.viewport_to_world_2d(camera_transform, cursor_position) {
+            for (piece, mut transform) in pieces.iter_mut() {
+                if piece.pos == from {
+                    transform.translation = world_position.extend(PIECE_Z + 1.0);
+                }
+            }
+        }
+        return;
+    }
+    editor.dragging = None;
+    let moving = board_state
+        .0
+        .get(from)
+        .map(|piece| (piece.color, piece.piece_type));
+    match square {
+        Some(to) if to == from => {
+            // The board doesn't change, so put the sprite back by hand.
+            for (piece, mut transform) in pieces.iter_mut() {
+                if piece.pos == from {
+                    transform.translation = pos_to_vec3(from, PIECE_Z);
+                }
+            }
+        }
+        Some(to) => {
+            rules::set_square(&mut board_state.0, from, None);
+            rules::set_square(&mut board_state.0, to, moving);
+        }
+        None => rules::set_square(&mut board_state.0, from, None),
+    }
+}
+
+/// Typing into the FEN field opened with "Type FEN", which starts out as
+/// the current position. Enter replaces the position with the typed one if
+/// it parses, and Escape leaves the position alone. Like chat, the keys are
+/// kept from the rest of the app meanwhile. Runs in `PreUpdate`.
+pub fn type_fen(
+    mut keyboard: EventReader<KeyboardInput>,
+    mut keys: ResMut<ButtonInput<KeyCode>>,
+    mut editor: ResMut<SetupEditor>,
+    mut board_state: ResMut<BoardState>,
+) {
+    if editor.fen_input.is_none() {
+        keyboard.clear();
+        return;
+    }
+    let ctrl = text_input::ctrl_held(&keys);
+    for event in keyboard.read() {
+        if !event.state.is_pressed() {
+            continue;
+        }
+        let editor = &mut *editor;
+        let Some(input) = editor.fen_input.as_mut() else {
+            break;
+        };
+        match input.edit(&event.logical_key, ctrl) {
+            TextEdit::Unchanged => {}
+            TextEdit::Changed => editor.message.clear(),
+            TextEdit::Submitted => {
+                if let Ok((board, castling)) = fen::board_from_fen(&input.text) {
+                    editor.side_to_move = board.move_turn;
+                    editor.castling = castling;
+                    board_state.0 = board;
+                }
+                editor.fen_input = None;
+                editor.message.clear();
+            }
+            TextEdit::Invalid(err) => editor.message = err,
+            TextEdit::Cancelled => {
+                editor.fen_input = None;
+                editor.message.clear();
+            }
+        }
+    }
+    keys.reset_all();
+}
+
+pub fn render_setup_panel(
+    editor: Res<SetupEditor>,
+    board: Res<BoardState>,
+    mut buttons: Query<(&SetupButton, &mut BackgroundColor)>,
+    mut statuses: Query<&mut Text, With<SetupStatus>>,
+) {
+    if !editor.is_changed() && !board.is_changed() {
+        return;
+    }
+    for (button, mut background) in buttons.iter_mut() {
+        background.0 = if button.active(&editor) {
+            widgets::SELECTED_BUTTON_COLOR
+        } else {
+            widgets::BUTTON_COLOR
+        };
+    }
+    let mut status = match &editor.fen_input {
+        Some(input) => format!("FEN: {}", input.with_cursor()),
+        None => editor.fen(&board.0),
+    };
+    if !editor.message.is_empty() {
+        status.push_str(&format!("\n\n{}", editor.message));
+    }
+    for mut text in statuses.iter_mut() {
+        text.0 = status.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn castling_needs_the_king_and_rook_at_home() {
+        let (board, castling) = fen::board_from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq -").unwrap();
+        assert!(check_castling(&board, castling).is_ok());
+        let (moved, _) = fen::board_from_fen("r3k2r/8/8/8/8/8/8/R4K1R w - -").unwrap();
+        assert!(check_castling(&moved, castling).is_err());
+        let (_, none) = fen::board_from_fen("r3k2r/8/8/8/8/8/8/R4K1R w - -").unwrap();
+        assert!(check_castling(&moved, none).is_ok());
+    }
+
+    #[test]
+    fn the_fen_has_the_side_to_move_and_rights_chosen() {
+        let (board, castling) = fen::board_from_fen("4k3/8/8/8/8/8/8/4K2R w K -").unwrap();
+        let editor = SetupEditor {
+            brush: Brush::Erase,
+            side_to_move: HermanhaColor::Black,
+            castling,
+            dragging: None,
+            message: String::new(),
+            fen_input: None,
+        };
+        assert_eq!(editor.fen(&board), "4k3/8/8/8/8/8/8/4K2R b K");
+        assert!(SetupButton::SideToMove(HermanhaColor::Black).active(&editor));
+        assert!(SetupButton::Castling(HermanhaColor::White, Wing::Kingside).active(&editor));
+        assert!(!SetupButton::Castling(HermanhaColor::White, Wing::Queenside).active(&editor));
+        assert!(validate_fen(&editor.fen(&board)).is_ok());
+        assert!(validate_fen("").is_err());
+    }
+
+    #[test]
+    fn piece_buttons_are_labelled_like_a_fen() {
+        let white_knight = Brush::Piece(HermanhaColor::White, PieceType::Knight);
+        let black_queen = Brush::Piece(HermanhaColor::Black, PieceType::Queen);
+        assert_eq!(SetupButton::Brush(white_knight).label(), "N");
+        assert_eq!(SetupButton::Brush(black_queen).label(), "q");
+        assert_eq!(
+            SetupButton::Castling(HermanhaColor::Black, Wing::Queenside).label(),
+            "Black O-O-O"
+        );
+    }
+}
@@ -1,8 +1,19 @@
 use std::fmt;
 use std::io::{self, Read, Write};
 use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
-use hermanha_chess::{Board, Color, GameResult, PieceType, Position};
+use crossbeam_channel::{Receiver, Sender, TryRecvError};
+use hermanha_chess::{Board, Color, PieceType, Position};
+
+use crate::fen;
+use crate::offers::Conclusion;
+use crate::rules::Outcome;
+use crate::tls::{self, Fingerprint};
+use crate::transport::{IoThread, POLL_INTERVAL, Transport};
+use crate::variant::{CHESS960_POSITIONS, HANDICAP_LEVELS, Variant};
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ConnectionType {
@@ -10,12 +21,28 @@ pub enum ConnectionType {
     Client,
 }
 
+impl ConnectionType {
+    /// By default the client plays White and the server Black. The server
+    /// can pick otherwise with `--color=`, and tells the client in the
+    /// handshake.
+    pub fn player_color(self) -> Color {
+        match self {
+            ConnectionType::Client => Color::White,
+            ConnectionType::Server => Color::Black,
+        }
+    }
+}
+
 pub struct MoveMessage {
     pub from: Position,
     pub to: Position,
     pub promotion_piece: Option<PieceType>,
-    pub result: Option<GameResult>,
+    pub result: Option<Outcome>,
     pub new_board: Board,
+    /// `zobrist::hash` of the position after the move, which unlike
+    /// `new_board` covers the side to move and castling rights. Peers
+    /// before protocol version 5 don't send it.
+    pub position_hash: Option<u64>,
 }
 
 impl MoveMessage {
@@ -27,26 +54,27 @@ impl MoveMessage {
         ret.push(':');
         ret.push_str(&board_to_fen(&self.new_board));
         ret.push(':');
-        add_padding(&mut ret);
+        if let Some(hash) = self.position_hash {
+            ret.push_str(&format!("{hash:016X}:"));
+        }
         ret
     }
 
-    fn from_string(msg_str: String) -> Result<Self, String> {
-        if msg_str.len() != 128 {
-            return Err("Message must be 128 characters".to_string());
-        }
+    fn from_string(msg_str: String) -> Result<Self, ProtocolError> {
         let parts: Vec<&str> = msg_str.split(':').collect();
-        if parts.len() != 5 {
-            return Err("Invalid message format".to_string());
+        if parts.len() != 5 && parts.len() != 6 {
+            return Err(ProtocolError::BadFormat("move"));
         }
-        let Ok((from, to, promotion_piece)) = move_from_string(parts[1]) else {
-            return Err("Invalid move format".to_string());
-        };
-        let Ok(result) = game_result_from_string(parts[2]) else {
-            return Err("Invalid result format".to_string());
+        let (from, to, promotion_piece) = move_from_string(parts[1])?;
+        let result = game_result_from_string(parts[2])?;
+        let board = board_from_fen(parts[3])?;
+        let position_hash = match parts.len() {
+            6 => Some(
+                u64::from_str_radix(parts[4], 16)
+                    .map_err(|_| ProtocolError::BadField("position hash"))?,
+            ),
+            _ => None,
         };
-        let mut board = Board::start_pos();
-        board.setup_fen(parts[3]);
 
         Ok(Self {
             from,
@@ -54,6 +82,7 @@ impl MoveMessage {
             promotion_piece,
             result,
             new_board: board,
+            position_hash,
         })
     }
 }
@@ -74,10 +103,15 @@ fn pos_to_string(pos: Position) -> String {
     format!("{}{}", file, rank)
 }
 
-fn pos_from_string(pos_str: &str) -> Result<Position, String> {
-    let file = pos_str.chars().nth(0).ok_or("Invalid position string")?;
-    let rank = pos_str.chars().nth(1).ok_or("Invalid position string")?;
-    let row = rank.to_digit(10).ok_or("Invalid rank")? as i8 - 1;
+fn pos_from_string(pos_str: &str) -> Result<Position, ProtocolError> {
+    let bad_square = || ProtocolError::BadMove(pos_str.to_string());
+    let file = pos_str.chars().nth(0).ok_or_else(bad_square)?;
+    let rank = pos_str.chars().nth(1).ok_or_else(bad_square)?;
+    let row = rank
+        .to_digit(10)
+        .filter(|rank| (1..=8).contains(rank))
+        .ok_or_else(bad_square)? as i8
+        - 1;
     let col = match file {
         'A' => 0,
         'B' => 1,
@@ -87,7 +121,7 @@ fn pos_from_string(pos_str: &str) -> Result<Position, String> {
         'F' => 5,
         'G' => 6,
         'H' => 7,
-        _ => return Err("Invalid file".to_string()),
+        _ => return Err(bad_square()),
     };
     Ok(Position::new(row, col))
 }
@@ -103,7 +137,7 @@ fn piece_type_to_char(piece_type: PieceType) -> char {
     }
 }
 
-fn char_to_piece_type(c: char) -> Result<PieceType, String> {
+fn char_to_piece_type(c: char) -> Result<PieceType, ProtocolError> {
     match c {
         'P' => Ok(PieceType::Pawn),
         'N' => Ok(PieceType::Knight),
@@ -111,11 +145,11 @@ fn char_to_piece_type(c: char) -> Result<PieceType, String> {
         'R' => Ok(PieceType::Rook),
         'Q' => Ok(PieceType::Queen),
         'K' => Ok(PieceType::King),
-        _ => Err("Invalid piece type".to_string()),
+        _ => Err(ProtocolError::BadMove(c.to_string())),
     }
 }
 
-fn move_to_string(from: Position, to: Position, promotion_piece: Option<PieceType>) -> String {
+pub fn move_to_string(from: Position, to: Position, promotion_piece: Option<PieceType>) -> String {
     let from_str = pos_to_string(from);
     let to_str = pos_to_string(to);
     let promotion_str = if let Some(piece_type) = promotion_piece {
@@ -126,9 +160,11 @@ fn move_to_string(from: Position, to: Position, promotion_piece: Option<PieceTyp
     format!("{}{}{}", from_str, to_str, promotion_str)
 }
 
-fn move_from_string(move_str: &str) -> Result<(Position, Position, Option<PieceType>), String> {
-    if move_str.len() != 5 {
-        return Err("Invalid move string".to_string());
+pub fn move_from_string(
+    move_str: &str,
+) -> Result<(Position, Position, Option<PieceType>), ProtocolError> {
+    if move_str.len() != 5 || !move_str.is_ascii() {
+        return Err(ProtocolError::BadMove(move_str.to_string()));
     }
     let from_str = &move_str[0..2];
     let from = pos_from_string(from_str)?;
@@ -143,23 +179,25 @@ fn move_from_string(move_str: &str) -> Result<(Position, Position, Option<PieceT
     Ok((from, to, promotion_piece))
 }
 
-fn game_result_to_string(result: Option<GameResult>) -> String {
+/// The result field only tells wins from draws, so every draw is sent as
+/// "1-1" and read back as a stalemate.
+fn game_result_to_string(result: Option<Outcome>) -> String {
     match result {
-        Some(GameResult::Checkmate(Color::White)) => "1-0",
-        Some(GameResult::Checkmate(Color::Black)) => "0-1",
-        Some(GameResult::Stalemate) => "1-1",
+        Some(Outcome::Checkmate(Color::White)) => "1-0",
+        Some(Outcome::Checkmate(Color::Black)) => "0-1",
+        Some(_) => "1-1",
         None => "0-0",
     }
     .to_string()
 }
 
-fn game_result_from_string(s: &str) -> Result<Option<GameResult>, String> {
+fn game_result_from_string(s: &str) -> Result<Option<Outcome>, ProtocolError> {
     match s {
-        "1-0" => Ok(Some(GameResult::Checkmate(Color::White))),
-        "0-1" => Ok(Some(GameResult::Checkmate(Color::Black))),
-        "1-1" => Ok(Some(GameResult::Stalemate)),
+        "1-0" => Ok(Some(Outcome::Checkmate(Color::White))),
+        "0-1" => Ok(Some(Outcome::Checkmate(Color::Black))),
+        "1-1" => Ok(Some(Outcome::Stalemate)),
         "0-0" => Ok(None),
-        _ => Err("Invalid game result".to_string()),
+        _ => Err(ProtocolError::BadField("game result")),
     }
 }
 
@@ -193,6 +231,15 @@ pub fn board_to_fen(board: &Board) -> String {
     ret
 }
 
+/// Reads back a piece placement written by `board_to_fen`, checking it
+/// as a FEN's placement is checked.
+fn board_from_fen(fen: &str) -> Result<Board, ProtocolError> {
+    fen::validate_placement(fen).map_err(|_| ProtocolError::BadFen(fen.to_string()))?;
+    let mut board = Board::start_pos();
+    board.setup_fen(fen);
+    Ok(board)
+}
+
 pub struct QuitMessage {
     pub message: Option<String>,
 }
@@ -203,15 +250,10 @@ impl QuitMessage {
             Some(msg) => msg.clone(),
             None => String::new(),
         };
-        let mut ret = format!("ChessQUIT:{}:", msg);
-        add_padding(&mut ret);
-        ret
+        format!("ChessQUIT:{}:", msg)
     }
 
-    fn from_string(msg_str: String) -> Result<Self, String> {
-        if msg_str.len() != 128 {
-            return Err("Message must be 128 characters".to_string());
-        }
+    fn from_string(msg_str: String) -> Result<Self, ProtocolError> {
         let parts: Vec<&str> = msg_str.split(':').collect();
         let msg = if parts.len() == 3 {
             Some(parts[1].to_string())
@@ -222,20 +264,725 @@ impl QuitMessage {
     }
 }
 
-fn add_padding(str: &mut String) {
-    let padding = "0".repeat(128 - str.len());
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DrawAction {
+    Offer,
+    Accept,
+    Decline,
+}
+
+pub struct DrawMessage {
+    pub action: DrawAction,
+}
+
+impl DrawMessage {
+    fn to_string(&self) -> String {
+        let action = match self.action {
+            DrawAction::Offer => "OFFER",
+            DrawAction::Accept => "ACCEPT",
+            DrawAction::Decline => "DECLINE",
+        };
+        format!("ChessDRAW:{action}:")
+    }
+
+    fn from_string(msg_str: String) -> Result<Self, ProtocolError> {
+        let parts: Vec<&str> = msg_str.split(':').collect();
+        if parts.len() != 3 {
+            return Err(ProtocolError::BadFormat("draw"));
+        }
+        let action = match parts[1] {
+            "OFFER" => DrawAction::Offer,
+            "ACCEPT" => DrawAction::Accept,
+            "DECLINE" => DrawAction::Decline,
+            _ => return Err(ProtocolError::BadField("draw action")),
+        };
+        Ok(DrawMessage { action })
+    }
+}
+
+/// Sent by both sides right after connecting. The server fills in
+/// `client_color` to tell the client which side it plays, `variant` with
+/// the game it starts, and `move_timeout` with the seconds each move may
+/// take if it set a limit; the client leaves them all empty.
+pub struct HelloMessage {
+    pub version: u16,
+    pub name: String,
+    pub client_color: Option<Color>,
+    pub variant: Variant,
+    pub move_timeout: Option<u16>,
+    /// The host's token for the game, which a client coming back after
+    /// losing the connection repeats so the host knows it's them.
+    pub session: Option<u32>,
+}
+
+impl HelloMessage {
+    /// Longest name that still fits in a frame.
+    pub const MAX_NAME_LEN: usize = 32;
+
+    fn to_string(&self) -> String {
+        let name: String = self
+            .name
+            .chars()
+            .filter(|c| ChatMessage::is_valid_char(*c))
+            .take(HelloMessage::MAX_NAME_LEN)
+            .collect();
+        let color = match self.client_color {
+            Some(Color::White) => "W",
+            Some(Color::Black) => "B",
+            None => "-",
+        };
+        // Chess960 sends the number of its start position, a handicap game
+        // its level after an "H".
+        let start_position = match self.variant {
+            Variant::Standard => "-".to_string(),
+            Variant::Chess960(number) => format!("{number:03}"),
+            Variant::Handicap(level) => format!("H{level}"),
+        };
+        let mut ret = format!(
+            "ChessHELLO:{:04X}:{name}:{color}:{start_position}:",
+            self.version
+        );
+        // Left out when there's no limit or session, so version 5 peers
+        // can still read the hello.
+        match (self.move_timeout, self.session) {
+            (timeout, Some(session)) => {
+                let timeout = timeout.map_or("-".to_string(), |secs| format!("{secs:04X}"));
+                ret.push_str(&format!("{timeout}:{session:08X}:"));
+            }
+            (Some(secs), None) => ret.push_str(&format!("{secs:04X}:")),
+            (None, None) => {}
+        }
+        ret
+    }
+
+    fn from_string(msg_str: String) -> Result<Self, ProtocolError> {
+        let parts: Vec<&str> = msg_str.split(':').collect();
+        // Version 2 peers don't send a start position.
+        if !(5..=8).contains(&parts.len()) {
+            return Err(ProtocolError::BadFormat("hello"));
+        }
+        let version = u16::from_str_radix(parts[1], 16)
+            .map_err(|_| ProtocolError::BadField("protocol version"))?;
+        let client_color = match parts[3] {
+            "W" => Some(Color::White),
+            "B" => Some(Color::Black),
+            "-" => None,
+            _ => return Err(ProtocolError::BadField("client color")),
+        };
+        let variant = if parts.len() == 5 || parts[4] == "-" {
+            Variant::Standard
+        } else if let Some(level) = parts[4].strip_prefix('H') {
+            level
+                .parse()
+                .ok()
+                .filter(|level| (1..=HANDICAP_LEVELS).contains(level))
+                .map(Variant::Handicap)
+                .ok_or(ProtocolError::BadField("start position"))?
+        } else {
+            parts[4]
+                .parse()
+                .ok()
+                .filter(|number| *number < CHESS960_POSITIONS)
+                .map(Variant::Chess960)
+                .ok_or(ProtocolError::BadField("start position"))?
+        };
+        let move_timeout = if parts.len() >= 7 && parts[5] != "-" {
+            let secs = u16::from_str_radix(parts[5], 16)
+                .ok()
+                .filter(|secs| *secs > 0)
+                .ok_or(ProtocolError::BadField("move timeout"))?;
+            Some(secs)
+        } else {
+            None
+        };
+        let session = if parts.len() == 8 {
+            let session = u32::from_str_radix(parts[6], 16)
+                .map_err(|_| ProtocolError::BadField("session"))?;
+            Some(session)
+        } else {
+            None
+        };
+        Ok(HelloMessage {
+            version,
+            name: parts[2].to_string(),
+            client_color,
+            variant,
+            move_timeout,
+            session,
+        })
+    }
+}
+
+/// Sent by both sides after reconnecting so they can check they still
+/// agree on the game.
+pub struct SyncMessage {
+    pub ply_count: u32,
+    pub board: Board,
+    /// White's and Black's remaining time, which the host sends in games
+    /// with a time control so a client coming back gets its clock back.
+    pub clocks: Option<(Duration, Duration)>,
+}
+
+impl SyncMessage {
+    fn to_string(&self) -> String {
+        let mut ret = format!(
+            "ChessSYNC:{:04X}:{}:",
+            self.ply_count,
+            board_to_fen(&self.board)
+        );
+        // Left out without clocks, so version 6 peers can still read it.
+        if let Some((white, black)) = self.clocks {
+            ret.push_str(&format!(
+                "{:08X}:{:08X}:",
+                white.as_millis(),
+                black.as_millis()
+            ));
+        }
+        ret
+    }
+
+    fn from_string(msg_str: String) -> Result<Self, ProtocolError> {
+        let parts: Vec<&str> = msg_str.split(':').collect();
+        if parts.len() != 4 && parts.len() != 6 {
+            return Err(ProtocolError::BadFormat("sync"));
+        }
+        let ply_count =
+            u32::from_str_radix(parts[1], 16).map_err(|_| ProtocolError::BadField("ply count"))?;
+        let board = board_from_fen(parts[2])?;
+        let millis = |text: &str| {
+            u64::from_str_radix(text, 16)
+                .map(Duration::from_millis)
+                .map_err(|_| ProtocolError::BadField("clock"))
+        };
+        let clocks = if parts.len() == 6 {
+            Some((millis(parts[3])?, millis(parts[4])?))
+        } else {
+            None
+        };
+        Ok(SyncMessage {
+            ply_count,
+            board,
+            clocks,
+        })
+    }
+}
+
+pub struct ChatMessage {
+    pub text: String,
+}
+
+impl ChatMessage {
+    pub const MAX_LEN: usize = 100;
+
+    /// Printable ASCII except the field separator.
+    pub fn is_valid_char(c: char) -> bool {
+        (c.is_ascii_graphic() && c != ':') || c == ' '
+    }
+
+    fn to_string(&self) -> String {
+        let text: String = self
+            .text
+            .chars()
+            .filter(|c| ChatMessage::is_valid_char(*c))
+            .take(ChatMessage::MAX_LEN)
+            .collect();
+        format!("ChessCHAT:{text}:")
+    }
+
+    fn from_string(msg_str: String) -> Result<Self, ProtocolError> {
+        let parts: Vec<&str> = msg_str.split(':').collect();
+        if parts.len() != 3 {
+            return Err(ProtocolError::BadFormat("chat"));
+        }
+        let text = parts[1];
+        if text.len() > ChatMessage::MAX_LEN || !text.chars().all(ChatMessage::is_valid_char) {
+            return Err(ProtocolError::BadField("chat text"));
+        }
+        Ok(ChatMessage {
+            text: text.to_string(),
+        })
+    }
+}
+
+/// Sent by the server to connections beyond the first, which only get to
+/// watch.
+pub struct SpectateMessage;
+
+impl SpectateMessage {
+    fn to_string(&self) -> String {
+        "ChessSPEC:".to_string()
+    }
+}
+
+/// Asks the other side for a `SyncMessage` after a move didn't check out.
+pub struct ResyncMessage;
+
+impl ResyncMessage {
+    fn to_string(&self) -> String {
+        "ChessRESYNC:".to_string()
+    }
+}
+
+/// Asks for another game once this one is over.
+pub struct RematchMessage;
+
+impl RematchMessage {
+    fn to_string(&self) -> String {
+        "ChessREMATCH:".to_string()
+    }
+}
+
+/// Sent every few seconds to measure the round trip. The other side
+/// answers with a `PongMessage` carrying the same id.
+pub struct PingMessage {
+    pub id: u16,
+}
+
+impl PingMessage {
+    fn to_string(&self) -> String {
+        format!("ChessPING:{:04X}:", self.id)
+    }
+
+    fn from_string(msg_str: String) -> Result<Self, ProtocolError> {
+        parse_ping_id(&msg_str).map(|id| PingMessage { id })
+    }
+}
+
+pub struct PongMessage {
+    pub id: u16,
+}
+
+impl PongMessage {
+    fn to_string(&self) -> String {
+        format!("ChessPONG:{:04X}:", self.id)
+    }
+
+    fn from_string(msg_str: String) -> Result<Self, ProtocolError> {
+        parse_ping_id(&msg_str).map(|id| PongMessage { id })
+    }
+}
+
+fn parse_ping_id(msg_str: &str) -> Result<u16, ProtocolError> {
+    let parts: Vec<&str> = msg_str.split(':').collect();
+    if parts.len() != 3 {
+        return Err(ProtocolError::BadFormat("ping"));
+    }
+    u16::from_str_radix(parts[1], 16).map_err(|_| ProtocolError::BadField("ping id"))
+}
+
+/// What a host tells its spectators beyond the moves: who plays which
+/// side, the clocks and how the game ended if it wasn't on the board. Only
+/// spectators get these; the opponent never does.
+pub struct RelayMessage {
+    pub white: String,
+    pub black: String,
+    /// White's and Black's remaining time, in games with a time control.
+    pub clocks: Option<(Duration, Duration)>,
+    pub conclusion: Option<Conclusion>,
+}
+
+impl RelayMessage {
+    fn to_string(&self) -> String {
+        let name = |name: &str| -> String {
+            name.chars()
+                .filter(|c| ChatMessage::is_valid_char(*c))
+                .take(HelloMessage::MAX_NAME_LEN)
+                .collect()
+        };
+        let clocks = match self.clocks {
+            Some((white, black)) => {
+                format!("{:08X}:{:08X}", white.as_millis(), black.as_millis())
+            }
+            None => "-:-".to_string(),
+        };
+        let conclusion = match self.conclusion {
+            None => "-",
+            Some(Conclusion::DrawAgreed) => "D",
+            Some(Conclusion::Resigned(Color::White)) => "RW",
+            Some(Conclusion::Resigned(Color::Black)) => "RB",
+            Some(Conclusion::Abandoned(Color::White)) => "AW",
+            Some(Conclusion::Abandoned(Color::Black)) => "AB",
+            Some(Conclusion::MoveTimedOut(Color::White)) => "TW",
+            Some(Conclusion::MoveTimedOut(Color::Black)) => "TB",
+            Some(Conclusion::Aborted) => "X",
+        };
+        format!(
+            "ChessRELAY:{}:{}:{clocks}:{conclusion}:",
+            name(&self.white),
+            name(&self.black)
+        )
+    }
+
+    fn from_string(msg_str: String) -> Result<Self, ProtocolError> {
+        let parts: Vec<&str> = msg_str.split(':').collect();
+        if parts.len() != 7 {
+            return Err(ProtocolError::BadFormat("relay"));
+        }
+        let millis = |text: &str| {
+            u64::from_str_radix(text, 16)
+                .map(Duration::from_millis)
+                .map_err(|_| ProtocolError::BadField("clock"))
+        };
+        let clocks = match (parts[3], parts[4]) {
+            ("-", "-") => None,
+            (white, black) => Some((millis(white)?, millis(black)?)),
+        };
+        let conclusion = match parts[5] {
+            "-" => None,
+            "D" => Some(Conclusion::DrawAgreed),
+            "RW" => Some(Conclusion::Resigned(Color::White)),
+            "RB" => Some(Conclusion::Resigned(Color::Black)),
+            "AW" => Some(Conclusion::Abandoned(Color::White)),
+            "AB" => Some(Conclusion::Abandoned(Color::Black)),
+            "TW" => Some(Conclusion::MoveTimedOut(Color::White)),
+            "TB" => Some(Conclusion::MoveTimedOut(Color::Black)),
+            "X" => Some(Conclusion::Aborted),
+            _ => return Err(ProtocolError::BadField("conclusion")),
+        };
+        Ok(RelayMessage {
+            white: parts[1].to_string(),
+            black: parts[2].to_string(),
+            clocks,
+            conclusion,
+        })
+    }
+}
+
+pub struct ResignMessage;
+
+impl ResignMessage {
+    fn to_string(&self) -> String {
+        "ChessRESIGN:".to_string()
+    }
+}
+
+const CHUNK_PAYLOAD_BYTES: usize = 40;
+
+pub struct ChunkMessage {
+    pub transfer_id: u16,
+    pub seq: u16,
+    pub total: u16,
+    pub checksum: u32,
+    pub payload: Vec<u8>,
+}
+
+impl ChunkMessage {
+    fn new(transfer_id: u16, seq: u16, total: u16, payload: &[u8]) -> Self {
+        ChunkMessage {
+            transfer_id,
+            seq,
+            total,
+            checksum: checksum(payload),
+            payload: payload.to_vec(),
+        }
+    }
+
+    fn to_string(&self) -> String {
+        format!(
+            "ChessCHNK:{:04X}:{:04X}:{:04X}:{:08X}:{}:",
+            self.transfer_id,
+            self.seq,
+            self.total,
+            self.checksum,
+            bytes_to_hex(&self.payload)
+        )
+    }
+
+    fn from_string(msg_str: String) -> Result<Self, ProtocolError> {
+        let parts: Vec<&str> = msg_str.split(':').collect();
+        if parts.len() != 7 {
+            return Err(ProtocolError::BadFormat("chunk"));
+        }
+        let transfer_id = u16::from_str_radix(parts[1], 16)
+            .map_err(|_| ProtocolError::BadField("transfer id"))?;
+        let seq = u16::from_str_radix(parts[2], 16)
+            .map_err(|_| ProtocolError::BadField("chunk sequence"))?;
+        // Every transfer has a chunk, even an empty one.
+        let total = u16::from_str_radix(parts[3], 16)
+            .ok()
+            .filter(|total| *total > 0)
+            .ok_or(ProtocolError::BadField("chunk total"))?;
+        let checksum = u32::from_str_radix(parts[4], 16)
+            .map_err(|_| ProtocolError::BadField("chunk checksum"))?;
+        let payload = hex_to_bytes(parts[5])?;
+        Ok(ChunkMessage {
+            transfer_id,
+            seq,
+            total,
+            checksum,
+            payload,
+        })
+    }
+
+    fn is_intact(&self) -> bool {
+        checksum(&self.payload) == self.checksum
+    }
+}
+
+/// Asks the sender to resend a transfer starting at `from_seq`.
+pub struct ResendMessage {
+    pub transfer_id: u16,
+    pub from_seq: u16,
+}
+
+impl ResendMessage {
+    fn to_string(&self) -> String {
+        format!("ChessRSND:{:04X}:{:04X}:", self.transfer_id, self.from_seq)
+    }
+
+    fn from_string(msg_str: String) -> Result<Self, ProtocolError> {
+        let parts: Vec<&str> = msg_str.split(':').collect();
+        if parts.len() != 4 {
+            return Err(ProtocolError::BadFormat("resend"));
+        }
+        let transfer_id = u16::from_str_radix(parts[1], 16)
+            .map_err(|_| ProtocolError::BadField("transfer id"))?;
+        let from_seq = u16::from_str_radix(parts[2], 16)
+            .map_err(|_| ProtocolError::BadField("chunk sequence"))?;
+        Ok(ResendMessage {
+            transfer_id,
+            from_seq,
+        })
+    }
+}
+
+/// The sending half of a bulk transfer. Keeps every chunk around so a
+/// resend request can be answered without the caller re-splitting the data.
+pub struct OutgoingTransfer {
+    chunks: Vec<ChunkMessage>,
+    last_sent: Instant,
+}
+
+impl OutgoingTransfer {
+    pub fn new(transfer_id: u16, data: &[u8]) -> Result<Self, ProtocolError> {
+        let total = data.len().div_ceil(CHUNK_PAYLOAD_BYTES).max(1);
+        let total =
+            u16::try_from(total).map_err(|_| ProtocolError::TransferTooLarge(data.len()))?;
+        let chunks = if data.is_empty() {
+            vec![ChunkMessage::new(transfer_id, 0, total, &[])]
+        } else {
+            data.chunks(CHUNK_PAYLOAD_BYTES)
+                .enumerate()
+                .map(|(seq, payload)| ChunkMessage::new(transfer_id, seq as u16, total, payload))
+                .collect()
+        };
+        Ok(OutgoingTransfer {
+            chunks,
+            last_sent: Instant::now(),
+        })
+    }
+
+    /// The chunks from `from_seq` on, as they go out.
+    fn chunks_from(&self, from_seq: u16) -> impl Iterator<Item = ChunkMessage> + '_ {
+        self.chunks
+            .iter()
+            .skip(from_seq as usize)
+            .map(|chunk| ChunkMessage {
+                transfer_id: chunk.transfer_id,
+                seq: chunk.seq,
+                total: chunk.total,
+                checksum: chunk.checksum,
+                payload: chunk.payload.clone(),
+            })
+    }
+
+    pub fn send_from(
+        &mut self,
+        connection: &mut dyn Transport,
+        from_seq: u16,
+    ) -> Result<(), TcpError> {
+        self.last_sent = Instant::now();
+        for chunk in self.chunks_from(from_seq) {
+            connection.write(Message::Chunk(chunk))?;
+        }
+        Ok(())
+    }
+
+    /// How long since the chunks were last sent, which is how long the
+    /// receiver has gone without asking for any again.
+    pub fn idle_for(&self) -> Duration {
+        self.last_sent.elapsed()
+    }
+}
+
+pub enum ChunkStatus {
+    Accepted,
+    /// A chunk past a gap already asked about, dropped while the resent
+    /// ones are on their way.
+    Dropped,
+    Complete(Vec<u8>),
+    Resend(ResendMessage),
+}
+
+/// The receiving half of a bulk transfer. Chunks must arrive in order; a
+/// gap or a corrupted chunk produces a resend request for the first
+/// missing sequence number, and later chunks are dropped until it arrives.
+/// A gap is asked about once, as the chunks already sent past it keep
+/// coming until the sender gets the request.
+pub struct IncomingTransfer {
+    pub transfer_id: u16,
+    total: u16,
+    data: Vec<u8>,
+    next_seq: u16,
+    resend_requested: bool,
+    last_chunk: Instant,
+}
+
+impl IncomingTransfer {
+    pub fn new(transfer_id: u16, total: u16) -> Self {
+        IncomingTransfer {
+            transfer_id,
+            total,
+            data: Vec::new(),
+            next_seq: 0,
+            resend_requested: false,
+            last_chunk: Instant::now(),
+        }
+    }
+
+    pub fn accept(&mut self, chunk: ChunkMessage) -> ChunkStatus {
+        self.last_chunk = Instant::now();
+        if chunk.seq < self.next_seq {
+            return ChunkStatus::Accepted;
+        }
+        // The chunk asked for, arriving damaged, is asked for again.
+        let resent = chunk.seq == self.next_seq;
+        if !resent || chunk.total != self.total || !chunk.is_intact() {
+            if self.resend_requested && !resent {
+                return ChunkStatus::Dropped;
+            }
+            self.resend_requested = true;
+            return ChunkStatus::Resend(ResendMessage {
+                transfer_id: self.transfer_id,
+                from_seq: self.next_seq,
+            });
+        }
+        self.resend_requested = false;
+        self.data.extend_from_slice(&chunk.payload);
+        self.next_seq += 1;
+        if self.next_seq == self.total {
+            ChunkStatus::Complete(std::mem::take(&mut self.data))
+        } else {
+            ChunkStatus::Accepted
+        }
+    }
+
+    /// How many chunks the transfer is made of.
+    pub fn total(&self) -> u16 {
+        self.total
+    }
+
+    /// How long since a chunk last came in.
+    pub fn idle_for(&self) -> Duration {
+        self.last_chunk.elapsed()
+    }
+}
+
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c9dc5, |hash, byte| {
+        (hash ^ *byte as u32).wrapping_mul(0x01000193)
+    })
+}
+
+fn bytes_to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02X}")).collect()
+}
+
+fn hex_to_bytes(hex: &str) -> Result<Vec<u8>, ProtocolError> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return Err(ProtocolError::BadField("payload"));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| ProtocolError::BadField("payload"))
+        })
+        .collect()
+}
+
+/// The longest frame either side sends. Fixed frames are padded to exactly
+/// this length, and length-prefixed ones may not be any longer.
+const MAX_FRAME_LEN: usize = 128;
+
+fn add_padding(str: &mut String) -> Result<(), ProtocolError> {
+    if str.len() > MAX_FRAME_LEN {
+        return Err(ProtocolError::FrameTooLong(str.len()));
+    }
+    let padding = "0".repeat(MAX_FRAME_LEN - str.len());
     str.push_str(&padding);
+    Ok(())
 }
 
 pub enum Message {
     Move(MoveMessage),
     Quit(QuitMessage),
+    Chunk(ChunkMessage),
+    Resend(ResendMessage),
+    Draw(DrawMessage),
+    Resign(ResignMessage),
+    Hello(HelloMessage),
+    Sync(SyncMessage),
+    Chat(ChatMessage),
+    Spectate(SpectateMessage),
+    Resync(ResyncMessage),
+    Rematch(RematchMessage),
+    Ping(PingMessage),
+    Pong(PongMessage),
+    Relay(RelayMessage),
+}
+
+/// Why a frame from the other side couldn't be understood.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProtocolError {
+    /// Fixed frames are 128 bytes; this one had the given length.
+    BadLength(usize),
+    /// The frame starts with an identifier we don't know.
+    BadIdentifier(String),
+    /// A move, square or promotion piece that doesn't parse.
+    BadMove(String),
+    /// A board that isn't a valid FEN piece placement.
+    BadFen(String),
+    /// A known message with the wrong number of fields.
+    BadFormat(&'static str),
+    /// A field of a known message that doesn't parse.
+    BadField(&'static str),
+    /// A well-formed message that makes no sense at this point, e.g. a
+    /// relay sent to a player rather than a spectator.
+    UnexpectedMessage(&'static str),
+    /// Data of this many bytes doesn't fit in one transfer.
+    TransferTooLarge(usize),
+    /// A frame of this many bytes, longer than any frame may be.
+    FrameTooLong(usize),
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::BadLength(len) => write!(f, "frame is {len} bytes instead of 128"),
+            ProtocolError::BadIdentifier(identifier) => {
+                write!(f, "unknown message identifier {identifier:?}")
+            }
+            ProtocolError::BadMove(text) => write!(f, "invalid move {text:?}"),
+            ProtocolError::BadFen(fen) => write!(f, "invalid board {fen:?}"),
+            ProtocolError::BadFormat(message) => write!(f, "malformed {message} message"),
+            ProtocolError::BadField(field) => write!(f, "invalid {field}"),
+            ProtocolError::UnexpectedMessage(message) => write!(f, "unexpected {message} message"),
+            ProtocolError::TransferTooLarge(len) => {
+                write!(f, "{len} bytes are too many for one transfer")
+            }
+            ProtocolError::FrameTooLong(len) => {
+                write!(f, "frame is {len} bytes, more than {MAX_FRAME_LEN}")
+            }
+        }
+    }
 }
 
+impl std::error::Error for ProtocolError {}
+
 #[derive(Debug)]
 pub enum TcpError {
     WouldBlock,
-    InvalidMessage(String),
+    InvalidMessage(ProtocolError),
     Io(io::Error),
 }
 
@@ -249,19 +996,63 @@ impl fmt::Display for TcpError {
     }
 }
 
+impl std::error::Error for TcpError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TcpError::WouldBlock => None,
+            TcpError::InvalidMessage(err) => Some(err),
+            TcpError::Io(err) => Some(err),
+        }
+    }
+}
+
 impl Message {
     fn to_string(&self) -> String {
         match self {
             Message::Move(move_msg) => move_msg.to_string(),
             Message::Quit(quit_msg) => quit_msg.to_string(),
+            Message::Chunk(chunk_msg) => chunk_msg.to_string(),
+            Message::Resend(resend_msg) => resend_msg.to_string(),
+            Message::Draw(draw_msg) => draw_msg.to_string(),
+            Message::Resign(resign_msg) => resign_msg.to_string(),
+            Message::Hello(hello_msg) => hello_msg.to_string(),
+            Message::Sync(sync_msg) => sync_msg.to_string(),
+            Message::Chat(chat_msg) => chat_msg.to_string(),
+            Message::Spectate(spectate_msg) => spectate_msg.to_string(),
+            Message::Resync(resync_msg) => resync_msg.to_string(),
+            Message::Rematch(rematch_msg) => rematch_msg.to_string(),
+            Message::Ping(ping_msg) => ping_msg.to_string(),
+            Message::Pong(pong_msg) => pong_msg.to_string(),
+            Message::Relay(relay_msg) => relay_msg.to_string(),
         }
     }
 
-    fn from_string(msg_str: String) -> Result<Self, String> {
-        if msg_str.len() != 128 {
-            return Err("Message must be 128 characters".to_string());
+    /// The message padded to a fixed 128-byte frame, as spectators and
+    /// peers from before length prefixes read it.
+    pub fn encode(&self) -> Result<String, ProtocolError> {
+        let mut frame = self.to_string();
+        add_padding(&mut frame)?;
+        Ok(frame)
+    }
+
+    /// Parses one fixed 128-byte frame as it appears on the wire.
+    pub fn decode(frame: &[u8]) -> Result<Self, ProtocolError> {
+        if frame.len() != MAX_FRAME_LEN {
+            return Err(ProtocolError::BadLength(frame.len()));
         }
-        let identifier = &msg_str[0..9];
+        Message::decode_body(frame)
+    }
+
+    /// Parses the body of a length-prefixed frame, which is padded like a
+    /// fixed frame when it comes from a peer before version 8.
+    fn decode_body(body: &[u8]) -> Result<Self, ProtocolError> {
+        Message::from_string(String::from_utf8_lossy(body).to_string())
+    }
+
+    fn from_string(msg_str: String) -> Result<Self, ProtocolError> {
+        // Identifiers were all 9 characters before ChessRESIGN, so read up
+        // to the first separator rather than a fixed width.
+        let identifier = msg_str.split(':').next().unwrap_or_default();
         match identifier {
             "ChessMOVE" => {
                 MoveMessage::from_string(msg_str).map(|move_msg| Message::Move(move_msg))
@@ -269,54 +1060,1022 @@ impl Message {
             "ChessQUIT" => {
                 QuitMessage::from_string(msg_str).map(|quit_msg| Message::Quit(quit_msg))
             }
-            _ => Err(format!("Invalid message identifier")),
+            "ChessCHNK" => ChunkMessage::from_string(msg_str).map(Message::Chunk),
+            "ChessRSND" => ResendMessage::from_string(msg_str).map(Message::Resend),
+            "ChessDRAW" => DrawMessage::from_string(msg_str).map(Message::Draw),
+            "ChessRESIGN" => Ok(Message::Resign(ResignMessage)),
+            "ChessHELLO" => HelloMessage::from_string(msg_str).map(Message::Hello),
+            "ChessSYNC" => SyncMessage::from_string(msg_str).map(Message::Sync),
+            "ChessCHAT" => ChatMessage::from_string(msg_str).map(Message::Chat),
+            "ChessSPEC" => Ok(Message::Spectate(SpectateMessage)),
+            "ChessRESYNC" => Ok(Message::Resync(ResyncMessage)),
+            "ChessREMATCH" => Ok(Message::Rematch(RematchMessage)),
+            "ChessPING" => PingMessage::from_string(msg_str).map(Message::Ping),
+            "ChessPONG" => PongMessage::from_string(msg_str).map(Message::Pong),
+            "ChessRELAY" => RelayMessage::from_string(msg_str).map(Message::Relay),
+            _ => Err(ProtocolError::BadIdentifier(identifier.to_string())),
         }
     }
 }
 
-pub struct TcpConnection {
+/// Protocol version announced when connecting. Version 1 peers only speak
+/// fixed 128-byte frames and can't be played against; from version 2 on
+/// every frame after the version announcement is prefixed with its length
+/// as a big-endian u32. Version 3 adds the Chess960 start position to
+/// the hello. Version 4 peers answer `PingMessage`s, which older ones would
+/// reject. Version 5 adds a hash of the position to every move. Version 6
+/// adds the move timeout to the hello, and version 7 the session token a
+/// reconnecting client has to repeat and the clocks to the sync. Version 8
+/// leaves the padding to 128 bytes off length-prefixed frames, and version
+/// 9 adds handicap games to the hello's start position.
+pub const PROTOCOL_VERSION: u16 = 9;
+
+/// The first version whose length-prefixed frames aren't padded.
+const UNPADDED_VERSION: u16 = 8;
+
+const VERSION_PREFIX: &[u8] = b"ChessVERS:";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Framing {
+    Fixed,
+    LengthPrefixed,
+}
+
+fn version_frame() -> Result<String, ProtocolError> {
+    let mut ret = format!("ChessVERS:{PROTOCOL_VERSION:04X}:");
+    add_padding(&mut ret)?;
+    Ok(ret)
+}
+
+fn parse_version_frame(frame: &[u8]) -> Result<u16, ProtocolError> {
+    let text = String::from_utf8_lossy(frame);
+    let parts: Vec<&str> = text.split(':').collect();
+    if parts.len() != 3 {
+        return Err(ProtocolError::BadFormat("version"));
+    }
+    u16::from_str_radix(parts[1], 16).map_err(|_| ProtocolError::BadField("protocol version"))
+}
+
+/// What frames travel over: the socket itself, or a TLS session on top of
+/// it. Either way reads time out after `POLL_INTERVAL`.
+trait Stream: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Stream for T {}
+
+/// The socket end of a connection, owned by the connection's I/O thread.
+struct FrameStream {
+    stream: Box<dyn Stream>,
+    /// Bytes received but not yet returned as a complete frame. A read can
+    /// stop anywhere, so frames are assembled here.
+    buffer: Vec<u8>,
+    /// How the peer's frames are read. We write length-prefixed frames
+    /// after our announcement unless the peer turns out to predate it.
+    read_framing: Framing,
+    /// The version the peer announced, or 1 if it sent a message without
+    /// announcing one. Until it's known our frames are padded, which every
+    /// version reads.
+    peer_version: Option<u16>,
+}
+
+impl FrameStream {
+    /// Announces our version in a fixed frame, which is always the first
+    /// frame either side sends. Everything we write after it is
+    /// length-prefixed, and the peer's frames are read that way once their
+    /// announcement is in, so each side can write before it has heard from
+    /// the other.
+    fn new(mut stream: Box<dyn Stream>) -> Result<Self, std::io::Error> {
+        let frame =
+            version_frame().map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        stream.write_all(frame.as_bytes())?;
+        stream.flush()?;
+        Ok(FrameStream {
+            stream,
+            buffer: Vec::new(),
+            read_framing: Framing::Fixed,
+            peer_version: None,
+        })
+    }
+
+    /// Takes the next complete frame off the read buffer, if there is one.
+    /// A length prefix over `MAX_FRAME_LEN` is an error rather than a
+    /// reason to wait for that many bytes.
+    fn next_frame(&mut self) -> Result<Option<Vec<u8>>, ProtocolError> {
+        match self.read_framing {
+            Framing::Fixed => {
+                if self.buffer.len() < MAX_FRAME_LEN {
+                    return Ok(None);
+                }
+                Ok(Some(self.buffer.drain(..MAX_FRAME_LEN).collect()))
+            }
+            Framing::LengthPrefixed => {
+                let Some(header) = self.buffer.first_chunk::<4>() else {
+                    return Ok(None);
+                };
+                let len = u32::from_be_bytes(*header) as usize;
+                if len > MAX_FRAME_LEN {
+                    return Err(ProtocolError::FrameTooLong(len));
+                }
+                if self.buffer.len() < 4 + len {
+                    return Ok(None);
+                }
+                self.buffer.drain(..4);
+                Ok(Some(self.buffer.drain(..len).collect()))
+            }
+        }
+    }
+
+    fn fill_buffer(&mut self) -> Result<(), TcpError> {
+        let mut chunk = [0; 1024];
+        match self.stream.read(&mut chunk) {
+            Ok(0) => Err(TcpError::Io(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "connection closed",
+            ))),
+            Ok(len) => {
+                self.buffer.extend_from_slice(&chunk[..len]);
+                Ok(())
+            }
+            Err(ref err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                Err(TcpError::WouldBlock)
+            }
+            Err(err) => Err(TcpError::Io(err)),
+        }
+    }
+
+    fn read(&mut self) -> Result<Message, TcpError> {
+        loop {
+            let Some(frame) = self.next_frame().map_err(TcpError::InvalidMessage)? else {
+                self.fill_buffer()?;
+                continue;
+            };
+            if frame.starts_with(VERSION_PREFIX) {
+                let version = parse_version_frame(&frame).map_err(TcpError::InvalidMessage)?;
+                if version >= 2 {
+                    self.read_framing = Framing::LengthPrefixed;
+                }
+                self.peer_version = Some(version);
+                continue;
+            }
+            self.peer_version.get_or_insert(1);
+            return Message::decode_body(&frame).map_err(TcpError::InvalidMessage);
+        }
+    }
+
+    fn write_body(&mut self, body: &str) -> Result<(), TcpError> {
+        let mut body = body.to_string();
+        match self.peer_version {
+            Some(version) if version >= UNPADDED_VERSION => {
+                if body.len() > MAX_FRAME_LEN {
+                    return Err(TcpError::InvalidMessage(ProtocolError::FrameTooLong(
+                        body.len(),
+                    )));
+                }
+            }
+            _ => add_padding(&mut body).map_err(TcpError::InvalidMessage)?,
+        }
+        let mut frame = match self.peer_version {
+            // Peers from before the announcement only read fixed frames.
+            Some(version) if version < 2 => Vec::new(),
+            _ => (body.len() as u32).to_be_bytes().to_vec(),
+        };
+        frame.extend_from_slice(body.as_bytes());
+        self.stream
+            .write_all(&frame)
+            .and_then(|()| self.stream.flush())
+            .map_err(TcpError::Io)
+    }
+}
+
+/// A spectator's socket, which never blocks, and the frames it hasn't
+/// taken yet. A slow spectator only falls behind instead of holding up
+/// the game.
+struct Spectator {
     stream: TcpStream,
+    pending: Vec<u8>,
+}
+
+impl Spectator {
+    fn new(stream: TcpStream) -> Result<Self, io::Error> {
+        stream.set_nonblocking(true)?;
+        Ok(Spectator {
+            stream,
+            pending: Vec::new(),
+        })
+    }
+
+    fn queue(&mut self, frame: &str) {
+        self.pending.extend_from_slice(frame.as_bytes());
+    }
+
+    /// Writes as much of the pending frames as the socket takes. False
+    /// once the stream has failed or closed.
+    fn flush(&mut self) -> bool {
+        while !self.pending.is_empty() {
+            match self.stream.write(&self.pending) {
+                Ok(0) => return false,
+                Ok(len) => {
+                    self.pending.drain(..len);
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return true,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => return false,
+            }
+        }
+        true
+    }
+
+    /// Spectators can't affect the game, so anything they send is thrown
+    /// away. False once the stream has failed or closed.
+    fn drain(&mut self) -> bool {
+        let mut chunk = [0; 1024];
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return false,
+                Ok(_) => {}
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return err.kind() == io::ErrorKind::WouldBlock,
+            }
+        }
+    }
+}
+
+/// The background side of a `TcpConnection`. Besides the opponent's
+/// stream, a server keeps its listener open and takes every later
+/// connection as a read-only spectator.
+struct ConnectionThread {
+    peer: FrameStream,
+    listener: Option<TcpListener>,
+    spectators: Vec<Spectator>,
+    /// Every move frame so far, replayed to spectators who join late.
+    moves: Vec<String>,
+    /// The latest relay frame, also sent to spectators who join late.
+    relay: Option<String>,
+    spectator_count: Arc<AtomicUsize>,
+}
+
+impl ConnectionThread {
+    /// Shuttles messages between the socket and the channels until either
+    /// side goes away or the socket fails. Errors are passed on to the
+    /// reader before the thread exits.
+    fn run(mut self, outgoing: Receiver<Message>, incoming: Sender<Result<Message, TcpError>>) {
+        loop {
+            self.accept_spectators();
+            self.serve_spectators();
+            loop {
+                match outgoing.try_recv() {
+                    Ok(message) => {
+                        let sent = match message {
+                            Message::Relay(_) => {
+                                self.relay(&message).map_err(TcpError::InvalidMessage)
+                            }
+                            _ => {
+                                if let Message::Move(_) = message {
+                                    self.broadcast(&message);
+                                }
+                                self.peer.write_body(&message.to_string())
+                            }
+                        };
+                        match sent {
+                            Ok(()) => {}
+                            // A message too long for a frame is left unsent.
+                            Err(err @ TcpError::InvalidMessage(_)) => {
+                                if incoming.send(Err(err)).is_err() {
+                                    return;
+                                }
+                            }
+                            Err(err) => {
+                                let _ = incoming.send(Err(err));
+                                return;
+                            }
+                        }
+                    }
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => return,
+                }
+            }
+            // The socket read times out after `POLL_INTERVAL`, which paces
+            // this loop.
+            match self.peer.read() {
+                Ok(message) => {
+                    if let Message::Move(_) = message {
+                        self.broadcast(&message);
+                    }
+                    if incoming.send(Ok(message)).is_err() {
+                        return;
+                    }
+                }
+                Err(TcpError::WouldBlock) => {}
+                Err(err) => {
+                    let _ = incoming.send(Err(err));
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Greets each new spectator and queues the game so far for it.
+    /// Spectators always get fixed frames, which every version understands.
+    fn accept_spectators(&mut self) {
+        let Some(listener) = &self.listener else {
+            return;
+        };
+        let Ok(greeting) = Message::Spectate(SpectateMessage).encode() else {
+            return;
+        };
+        while let Ok((stream, _)) = listener.accept() {
+            let Ok(mut spectator) = Spectator::new(stream) else {
+                continue;
+            };
+            spectator.queue(&greeting);
+            for frame in self.moves.iter().chain(&self.relay) {
+                spectator.queue(frame);
+            }
+            self.spectators.push(spectator);
+        }
+    }
+
+    /// Reads and throws away what spectators sent and writes them what the
+    /// socket takes of their pending frames. A closed or failed stream
+    /// drops the spectator.
+    fn serve_spectators(&mut self) {
+        self.spectators
+            .retain_mut(|spectator| spectator.drain() && spectator.flush());
+        self.spectator_count
+            .store(self.spectators.len(), Ordering::Relaxed);
+    }
+
+    /// Passes a move on to the spectators. A move too long for a fixed
+    /// frame never reaches the opponent either, whose write reports it.
+    fn broadcast(&mut self, message: &Message) {
+        let Ok(frame) = message.encode() else {
+            return;
+        };
+        for spectator in &mut self.spectators {
+            spectator.queue(&frame);
+        }
+        self.moves.push(frame);
+    }
+
+    fn relay(&mut self, message: &Message) -> Result<(), ProtocolError> {
+        let frame = message.encode()?;
+        for spectator in &mut self.spectators {
+            spectator.queue(&frame);
+        }
+        self.relay = Some(frame);
+        Ok(())
+    }
+}
+
+/// A connection to the opponent. The socket lives on a background thread,
+/// so `read` and `write` never block; they only move messages through
+/// channels. It can be encrypted with TLS, in which case the client checks
+/// the host's certificate against the fingerprint the host shared.
+pub struct TcpConnection {
+    connection_type: ConnectionType,
+    io: IoThread,
+    spectator_count: Arc<AtomicUsize>,
 }
 
 impl TcpConnection {
-    pub fn start_server(address: &str) -> Result<Self, std::io::Error> {
+    /// Blocks until an opponent connects, so call it off the main thread.
+    /// An encrypted host takes no spectators, who would watch in
+    /// plaintext.
+    pub fn start_server(address: &str, encrypted: bool) -> Result<Self, std::io::Error> {
         let listener = TcpListener::bind(address)?;
         let (stream, _) = listener.accept()?;
-        stream
-            .set_nonblocking(true)
-            .expect("set_nonblocking call failed");
-        Ok(TcpConnection { stream: stream })
+        if encrypted {
+            // The handshake is done blocking; only then does the read
+            // timeout that paces the I/O thread go on.
+            let stream = tls::accept(stream)?;
+            stream.sock.set_read_timeout(Some(POLL_INTERVAL))?;
+            return TcpConnection::spawn(Box::new(stream), None, ConnectionType::Server);
+        }
+        stream.set_read_timeout(Some(POLL_INTERVAL))?;
+        listener.set_nonblocking(true)?;
+        TcpConnection::spawn(Box::new(stream), Some(listener), ConnectionType::Server)
     }
 
-    pub fn connect_to_server(address: &str) -> Result<Self, std::io::Error> {
+    /// The server's side of a connection a listener elsewhere accepted,
+    /// for a relay serving many games from one listener. No spectators
+    /// are taken.
+    pub fn accept_stream(stream: TcpStream) -> Result<Self, std::io::Error> {
+        // Some platforms hand out sockets from a non-blocking listener
+        // non-blocking too.
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(POLL_INTERVAL))?;
+        TcpConnection::spawn(Box::new(stream), None, ConnectionType::Server)
+    }
+
+    /// Encrypted when given the fingerprint of the host's certificate.
+    pub fn connect_to_server(
+        address: &str,
+        fingerprint: Option<Fingerprint>,
+    ) -> Result<Self, std::io::Error> {
         let stream = TcpStream::connect(address)?;
-        stream
-            .set_nonblocking(true)
-            .expect("set_nonblocking call failed");
+        let Some(fingerprint) = fingerprint else {
+            stream.set_read_timeout(Some(POLL_INTERVAL))?;
+            return TcpConnection::spawn(Box::new(stream), None, ConnectionType::Client);
+        };
+        let stream = tls::connect(stream, fingerprint)?;
+        stream.sock.set_read_timeout(Some(POLL_INTERVAL))?;
+        TcpConnection::spawn(Box::new(stream), None, ConnectionType::Client)
+    }
+
+    fn spawn(
+        stream: Box<dyn Stream>,
+        listener: Option<TcpListener>,
+        connection_type: ConnectionType,
+    ) -> Result<Self, std::io::Error> {
+        let spectator_count = Arc::new(AtomicUsize::new(0));
+        let connection_thread = ConnectionThread {
+            peer: FrameStream::new(stream)?,
+            listener,
+            spectators: Vec::new(),
+            moves: Vec::new(),
+            relay: None,
+            spectator_count: spectator_count.clone(),
+        };
         Ok(TcpConnection {
-            #[rustfmt::skip]
-            stream: stream,
+            connection_type,
+            io: IoThread::spawn(move |outgoing, incoming| {
+                connection_thread.run(outgoing, incoming)
+            }),
+            spectator_count,
         })
     }
 
+    /// How many spectators are watching. Always 0 for a client.
+    pub fn spectator_count(&self) -> usize {
+        self.spectator_count.load(Ordering::Relaxed)
+    }
+
+    pub fn connection_type(&self) -> ConnectionType {
+        self.connection_type
+    }
+
     pub fn read(&mut self) -> Result<Message, TcpError> {
-        let mut buffer = [0; 128];
-        match self.stream.read_exact(&mut buffer) {
-            Ok(_) => {}
-            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
-                return Err(TcpError::WouldBlock);
+        self.io.read()
+    }
+
+    pub fn write(&mut self, message: Message) -> Result<(), TcpError> {
+        self.io.write(message)
+    }
+
+    /// Lets the I/O thread send everything queued, then hangs up.
+    pub fn close(&mut self, timeout: Duration) {
+        self.io.close(timeout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes `message` both as a fixed frame and unpadded, checks that
+    /// each reads back as the same message, and returns what was read.
+    fn round_trip(message: Message) -> Message {
+        let body = message.to_string();
+        let frame = message.encode().unwrap();
+        assert_eq!(frame.len(), 128, "{frame}");
+        let decoded = Message::decode(frame.as_bytes())
+            .unwrap_or_else(|err| panic!("{frame} didn't read back: {err}"));
+        assert_eq!(decoded.to_string(), body);
+        let unpadded = Message::decode_body(body.as_bytes())
+            .unwrap_or_else(|err| panic!("{body} didn't read back unpadded: {err}"));
+        assert_eq!(unpadded.to_string(), body);
+        decoded
+    }
+
+    fn board(fen: &str) -> Board {
+        fen::board_from_fen(fen).unwrap().0
+    }
+
+    #[test]
+    fn every_message_reads_back_as_sent() {
+        let messages = [
+            Message::Move(MoveMessage {
+                from: Position::new(1, 4),
+                to: Position::new(3, 4),
+                promotion_piece: None,
+                result: None,
+                new_board: board("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq -"),
+                position_hash: None,
+            }),
+            Message::Move(MoveMessage {
+                from: Position::new(6, 0),
+                to: Position::new(7, 0),
+                promotion_piece: Some(PieceType::Queen),
+                result: Some(Outcome::Checkmate(Color::White)),
+                new_board: board("Q3k3/8/4K3/8/8/8/8/8 b - -"),
+                position_hash: Some(u64::MAX),
+            }),
+            Message::Quit(QuitMessage {
+                message: Some("Bye for now".to_string()),
+            }),
+            Message::Chunk(ChunkMessage::new(3, 1, 2, b"1. e4 e5")),
+            Message::Resend(ResendMessage {
+                transfer_id: 3,
+                from_seq: 1,
+            }),
+            Message::Draw(DrawMessage {
+                action: DrawAction::Offer,
+            }),
+            Message::Draw(DrawMessage {
+                action: DrawAction::Accept,
+            }),
+            Message::Draw(DrawMessage {
+                action: DrawAction::Decline,
+            }),
+            Message::Resign(ResignMessage),
+            Message::Hello(HelloMessage {
+                version: PROTOCOL_VERSION,
+                name: "Magnus".to_string(),
+                client_color: Some(Color::Black),
+                variant: Variant::Chess960(518),
+                move_timeout: Some(30),
+                session: Some(0xDEAD_BEEF),
+            }),
+            Message::Sync(SyncMessage {
+                ply_count: 41,
+                board: board("r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq -"),
+                clocks: None,
+            }),
+            Message::Chat(ChatMessage {
+                text: "good luck, have fun".to_string(),
+            }),
+            Message::Spectate(SpectateMessage),
+            Message::Resync(ResyncMessage),
+            Message::Rematch(RematchMessage),
+            Message::Ping(PingMessage { id: 0xBEEF }),
+            Message::Pong(PongMessage { id: 7 }),
+            Message::Relay(RelayMessage {
+                white: "Anand".to_string(),
+                black: "Carlsen".to_string(),
+                clocks: Some((Duration::from_secs(300), Duration::from_millis(61_250))),
+                conclusion: Some(Conclusion::Resigned(Color::Black)),
+            }),
+        ];
+        for message in messages {
+            round_trip(message);
+        }
+    }
+
+    #[test]
+    fn messages_keep_their_fields() {
+        let Message::Move(move_msg) = round_trip(Message::Move(MoveMessage {
+            from: Position::new(6, 0),
+            to: Position::new(7, 0),
+            promotion_piece: Some(PieceType::Knight),
+            result: None,
+            new_board: board("N3k3/8/4K3/8/8/8/8/8 b - -"),
+            position_hash: Some(0x0123_4567_89AB_CDEF),
+        })) else {
+            panic!("not a move");
+        };
+        assert_eq!(
+            move_to_string(move_msg.from, move_msg.to, move_msg.promotion_piece),
+            "A7A8N"
+        );
+        assert_eq!(board_to_fen(&move_msg.new_board), "N3k3/8/4K3/8/8/8/8/8");
+        assert_eq!(move_msg.position_hash, Some(0x0123_4567_89AB_CDEF));
+
+        // The result field only tells wins from draws.
+        let Message::Move(move_msg) = round_trip(Message::Move(MoveMessage {
+            from: Position::new(0, 4),
+            to: Position::new(1, 4),
+            promotion_piece: None,
+            result: Some(Outcome::Repetition),
+            new_board: board("4k3/8/8/8/8/8/4K3/8 b - -"),
+            position_hash: None,
+        })) else {
+            panic!("not a move");
+        };
+        assert_eq!(move_msg.result, Some(Outcome::Stalemate));
+        // Peers before version 5 send no hash.
+        assert_eq!(move_msg.position_hash, None);
+
+        let Message::Hello(hello) = round_trip(Message::Hello(HelloMessage {
+            version: 1,
+            name: "Hou Yifan".to_string(),
+            client_color: None,
+            variant: Variant::Standard,
+            move_timeout: None,
+            session: Some(0x1234_5678),
+        })) else {
+            panic!("not a hello");
+        };
+        assert_eq!((hello.version, hello.name.as_str()), (1, "Hou Yifan"));
+        assert!(hello.client_color.is_none());
+        assert_eq!(hello.variant, Variant::Standard);
+        assert_eq!(hello.move_timeout, None);
+        assert_eq!(hello.session, Some(0x1234_5678));
+
+        // Version 2 peers end the hello after the color.
+        let mut frame = "ChessHELLO:0002:Hou Yifan:W:".to_string();
+        add_padding(&mut frame).unwrap();
+        let Ok(Message::Hello(hello)) = Message::decode(frame.as_bytes()) else {
+            panic!("a version 2 hello didn't read back");
+        };
+        assert!(matches!(hello.client_color, Some(Color::White)));
+        assert_eq!(hello.variant, Variant::Standard);
+        assert_eq!(hello.session, None);
+
+        let Message::Hello(hello) = round_trip(Message::Hello(HelloMessage {
+            version: PROTOCOL_VERSION,
+            name: "Coach".to_string(),
+            client_color: Some(Color::Black),
+            variant: Variant::Handicap(3),
+            move_timeout: None,
+            session: None,
+        })) else {
+            panic!("not a hello");
+        };
+        assert_eq!(hello.variant, Variant::Handicap(3));
+
+        let Message::Sync(sync) = round_trip(Message::Sync(SyncMessage {
+            ply_count: 300,
+            board: Board::start_pos(),
+            clocks: Some((Duration::from_millis(1_500), Duration::from_secs(600))),
+        })) else {
+            panic!("not a sync");
+        };
+        assert_eq!(sync.ply_count, 300);
+        assert_eq!(
+            board_to_fen(&sync.board),
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR"
+        );
+        assert_eq!(
+            sync.clocks,
+            Some((Duration::from_millis(1_500), Duration::from_secs(600)))
+        );
+
+        let Message::Chat(chat) = round_trip(Message::Chat(ChatMessage {
+            text: "gg: well played\n".to_string(),
+        })) else {
+            panic!("not a chat");
+        };
+        assert_eq!(chat.text, "gg well played");
+
+        let Message::Pong(pong) = round_trip(Message::Pong(PongMessage { id: 0xBEEF })) else {
+            panic!("not a pong");
+        };
+        assert_eq!(pong.id, 0xBEEF);
+
+        let Message::Relay(relay) = round_trip(Message::Relay(RelayMessage {
+            white: "Anand".to_string(),
+            black: "Carl:sen".to_string(),
+            clocks: Some((Duration::from_secs(300), Duration::from_millis(61_250))),
+            conclusion: Some(Conclusion::DrawAgreed),
+        })) else {
+            panic!("not a relay");
+        };
+        assert_eq!(
+            (relay.white.as_str(), relay.black.as_str()),
+            ("Anand", "Carlsen")
+        );
+        assert_eq!(
+            relay.clocks,
+            Some((Duration::from_secs(300), Duration::from_millis(61_250)))
+        );
+        assert!(relay.conclusion == Some(Conclusion::DrawAgreed));
+        for conclusion in [
+            Conclusion::Resigned(Color::White),
+            Conclusion::Abandoned(Color::Black),
+            Conclusion::Aborted,
+            Conclusion::MoveTimedOut(Color::White),
+        ] {
+            let Message::Relay(relay) = round_trip(Message::Relay(RelayMessage {
+                white: "Anand".to_string(),
+                black: "Carlsen".to_string(),
+                clocks: None,
+                conclusion: Some(conclusion),
+            })) else {
+                panic!("not a relay");
+            };
+            assert!(relay.conclusion == Some(conclusion));
+        }
+        let Message::Relay(relay) = round_trip(Message::Relay(RelayMessage {
+            white: String::new(),
+            black: String::new(),
+            clocks: None,
+            conclusion: None,
+        })) else {
+            panic!("not a relay");
+        };
+        assert!(relay.clocks.is_none() && relay.conclusion.is_none());
+
+        let Message::Chunk(chunk) = round_trip(Message::Chunk(ChunkMessage::new(3, 1, 2, b"e4")))
+        else {
+            panic!("not a chunk");
+        };
+        assert_eq!(chunk.payload, b"e4");
+        assert!(chunk.is_intact());
+    }
+
+    /// The error decoding `text`, padded to a full frame, gives.
+    fn decode_error(text: &str) -> Option<ProtocolError> {
+        let mut frame = text.to_string();
+        add_padding(&mut frame).unwrap();
+        Message::decode(frame.as_bytes()).err()
+    }
+
+    #[test]
+    fn malformed_frames_say_what_is_wrong() {
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR";
+        assert_eq!(
+            Message::decode(b"ChessPING:0001:").err(),
+            Some(ProtocolError::BadLength(15))
+        );
+        let cases = [
+            (
+                "ChessNOPE:",
+                ProtocolError::BadIdentifier("ChessNOPE".to_string()),
+            ),
+            (
+                "ChessMOVE:E2E9Q:0-0:{start}:",
+                ProtocolError::BadMove("E9".to_string()),
+            ),
+            (
+                "ChessMOVE:E2E4:0-0:{start}:",
+                ProtocolError::BadMove("E2E4".to_string()),
+            ),
+            (
+                "ChessMOVE:E2E40:2-0:{start}:",
+                ProtocolError::BadField("game result"),
+            ),
+            (
+                "ChessMOVE:E2E40:0-0:{start}:XYZ:",
+                ProtocolError::BadField("position hash"),
+            ),
+            (
+                "ChessSYNC:0001:8/8/8:",
+                ProtocolError::BadFen("8/8/8".to_string()),
+            ),
+            (
+                "ChessSYNC:0001:8/8/8/8/8/8/8/8:",
+                ProtocolError::BadFen("8/8/8/8/8/8/8/8".to_string()),
+            ),
+            ("ChessSYNC:0001:", ProtocolError::BadFormat("sync")),
+            (
+                "ChessSYNC:0001:{start}:-:0001:",
+                ProtocolError::BadField("clock"),
+            ),
+            ("ChessDRAW:OFFER:AGAIN:", ProtocolError::BadFormat("draw")),
+            ("ChessDRAW:MAYBE:", ProtocolError::BadField("draw action")),
+            ("ChessHELLO:0004:", ProtocolError::BadFormat("hello")),
+            (
+                "ChessHELLO:0004:Magnus:X:-:",
+                ProtocolError::BadField("client color"),
+            ),
+            (
+                "ChessHELLO:0004:Magnus:W:960:",
+                ProtocolError::BadField("start position"),
+            ),
+            (
+                "ChessHELLO:0009:Magnus:W:H6:",
+                ProtocolError::BadField("start position"),
+            ),
+            (
+                "ChessHELLO:0006:Magnus:W:-:0000:",
+                ProtocolError::BadField("move timeout"),
+            ),
+            (
+                "ChessHELLO:0007:Magnus:W:-:-:SESSION:",
+                ProtocolError::BadField("session"),
+            ),
+            ("ChessPING:XYZ:", ProtocolError::BadField("ping id")),
+            (
+                "ChessCHNK:0001:0000:0001:00000000:ABC:",
+                ProtocolError::BadField("payload"),
+            ),
+            (
+                "ChessCHNK:0001:0000:0000:00000000::",
+                ProtocolError::BadField("chunk total"),
+            ),
+            (
+                "ChessRELAY:A:B:-:-:Q:",
+                ProtocolError::BadField("conclusion"),
+            ),
+        ];
+        for (text, error) in cases {
+            let text = text.replace("{start}", start);
+            assert_eq!(decode_error(&text), Some(error), "{text}");
+        }
+        let mut quit = format!("ChessQUIT:{}:", "-".repeat(128));
+        assert_eq!(
+            add_padding(&mut quit).err(),
+            Some(ProtocolError::FrameTooLong(139))
+        );
+        let data = vec![0; CHUNK_PAYLOAD_BYTES * (u16::MAX as usize + 1)];
+        assert_eq!(
+            OutgoingTransfer::new(0, &data).err(),
+            Some(ProtocolError::TransferTooLarge(data.len()))
+        );
+    }
+
+    /// Two ends of a loopback connection, reading as the I/O thread does.
+    fn socket_pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        for stream in [&client, &server] {
+            stream.set_read_timeout(Some(POLL_INTERVAL)).unwrap();
+        }
+        (client, server)
+    }
+
+    /// The first thing reading gives other than `WouldBlock`.
+    fn read_result(stream: &mut FrameStream) -> Result<Message, TcpError> {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        loop {
+            match stream.read() {
+                Err(TcpError::WouldBlock) if Instant::now() < deadline => {}
+                result => return result,
             }
-            Err(err) => return Err(TcpError::Io(err)),
         }
-        let msg_str = String::from_utf8_lossy(&buffer).to_string();
-        Message::from_string(msg_str).map_err(|e| TcpError::InvalidMessage(e))
     }
 
-    pub fn write(&mut self, message: Message) -> Result<(), TcpError> {
-        match self.stream.write_all(message.to_string().as_bytes()) {
-            Ok(_) => Ok(()),
-            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => Err(TcpError::WouldBlock),
-            Err(err) => Err(TcpError::Io(err)),
+    fn read_soon(stream: &mut FrameStream) -> Message {
+        read_result(stream).unwrap_or_else(|err| panic!("nothing to read: {err}"))
+    }
+
+    #[test]
+    fn frames_written_before_the_peers_announcement_arrive() {
+        let (client, server) = socket_pair();
+        let mut client = FrameStream::new(Box::new(client)).unwrap();
+        // The client writes before it has read anything from the server.
+        client
+            .write_body(&Message::Resign(ResignMessage).to_string())
+            .unwrap();
+        let mut server = FrameStream::new(Box::new(server)).unwrap();
+        assert!(matches!(read_soon(&mut server), Message::Resign(_)));
+        server
+            .write_body(&Message::Rematch(RematchMessage).to_string())
+            .unwrap();
+        assert!(matches!(read_soon(&mut client), Message::Rematch(_)));
+    }
+
+    fn announcement(version: u16) -> Vec<u8> {
+        let mut frame = format!("ChessVERS:{version:04X}:");
+        add_padding(&mut frame).unwrap();
+        frame.into_bytes()
+    }
+
+    fn length_prefixed(body: &str) -> Vec<u8> {
+        [&(body.len() as u32).to_be_bytes()[..], body.as_bytes()].concat()
+    }
+
+    /// Everything after its announcement that a host writes in answer to a
+    /// peer opening with `opening`, which must end in a resignation.
+    fn answer_to(opening: &[u8]) -> Vec<u8> {
+        let (mut client, server) = socket_pair();
+        client.write_all(opening).unwrap();
+        let mut server = FrameStream::new(Box::new(server)).unwrap();
+        assert!(matches!(read_soon(&mut server), Message::Resign(_)));
+        server
+            .write_body(&Message::Rematch(RematchMessage).to_string())
+            .unwrap();
+        drop(server);
+        client.set_read_timeout(None).unwrap();
+        let mut answer = Vec::new();
+        client.read_to_end(&mut answer).unwrap();
+        answer.split_off(MAX_FRAME_LEN)
+    }
+
+    #[test]
+    fn frames_are_written_the_way_the_peer_reads_them() {
+        let resign = Message::Resign(ResignMessage);
+        let rematch = Message::Rematch(RematchMessage);
+        // Peers from before the announcement only know fixed frames.
+        assert_eq!(
+            answer_to(resign.encode().unwrap().as_bytes()),
+            rematch.encode().unwrap().as_bytes()
+        );
+        let opening = [
+            announcement(UNPADDED_VERSION - 1),
+            length_prefixed(&resign.encode().unwrap()),
+        ]
+        .concat();
+        assert_eq!(
+            answer_to(&opening),
+            length_prefixed(&rematch.encode().unwrap())
+        );
+        let opening = [
+            announcement(PROTOCOL_VERSION),
+            length_prefixed(&resign.to_string()),
+        ]
+        .concat();
+        assert_eq!(answer_to(&opening), length_prefixed(&rematch.to_string()));
+    }
+
+    #[test]
+    fn an_overlong_frame_is_refused_before_it_arrives() {
+        let (mut client, server) = socket_pair();
+        let mut server = FrameStream::new(Box::new(server)).unwrap();
+        client.write_all(&announcement(PROTOCOL_VERSION)).unwrap();
+        client.write_all(&u32::MAX.to_be_bytes()).unwrap();
+        assert!(matches!(
+            read_result(&mut server),
+            Err(TcpError::InvalidMessage(ProtocolError::FrameTooLong(len))) if len == u32::MAX as usize
+        ));
+    }
+
+    #[test]
+    fn a_spectator_that_reads_nothing_only_falls_behind() {
+        let (watcher, host) = socket_pair();
+        let mut spectator = Spectator::new(host).unwrap();
+        // Far more than the socket buffers hold.
+        let frame = Message::Resign(ResignMessage).encode().unwrap();
+        for _ in 0..200_000 {
+            spectator.queue(&frame);
+        }
+        assert!(spectator.flush());
+        assert!(!spectator.pending.is_empty());
+        drop(watcher);
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while spectator.drain() && spectator.flush() {
+            assert!(Instant::now() < deadline, "a closed spectator was kept");
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// Four chunks' worth, the last one short.
+    fn transfer_data() -> Vec<u8> {
+        (0..=255)
+            .cycle()
+            .take(CHUNK_PAYLOAD_BYTES * 3 + 7)
+            .collect()
+    }
+
+    #[test]
+    fn transfers_are_put_back_together() {
+        let data = transfer_data();
+        let outgoing = OutgoingTransfer::new(7, &data).unwrap();
+        let mut incoming = IncomingTransfer::new(7, 4);
+        let mut statuses: Vec<ChunkStatus> = outgoing
+            .chunks_from(0)
+            .map(|chunk| incoming.accept(chunk))
+            .collect();
+        assert!(
+            matches!(statuses.pop(), Some(ChunkStatus::Complete(received)) if received == data)
+        );
+        assert!(
+            statuses
+                .iter()
+                .all(|status| matches!(status, ChunkStatus::Accepted))
+        );
+    }
+
+    #[test]
+    fn a_lost_chunk_is_asked_for_once_and_resent() {
+        let data = transfer_data();
+        let outgoing = OutgoingTransfer::new(7, &data).unwrap();
+        let mut incoming = IncomingTransfer::new(7, 4);
+        let mut chunks = outgoing.chunks_from(0);
+        assert!(matches!(
+            incoming.accept(chunks.next().unwrap()),
+            ChunkStatus::Accepted
+        ));
+        // The second chunk never arrives.
+        chunks.next();
+        let ChunkStatus::Resend(resend) = incoming.accept(chunks.next().unwrap()) else {
+            panic!("the gap went unnoticed");
+        };
+        assert_eq!((resend.transfer_id, resend.from_seq), (7, 1));
+        assert!(matches!(
+            incoming.accept(chunks.next().unwrap()),
+            ChunkStatus::Dropped
+        ));
+        let mut statuses: Vec<ChunkStatus> = outgoing
+            .chunks_from(resend.from_seq)
+            .map(|chunk| incoming.accept(chunk))
+            .collect();
+        assert!(
+            matches!(statuses.pop(), Some(ChunkStatus::Complete(received)) if received == data)
+        );
+    }
+
+    #[test]
+    fn a_damaged_chunk_is_asked_for_every_time() {
+        let data = transfer_data();
+        let outgoing = OutgoingTransfer::new(7, &data).unwrap();
+        let mut incoming = IncomingTransfer::new(7, 4);
+        for _ in 0..2 {
+            let mut chunk = outgoing.chunks_from(0).next().unwrap();
+            chunk.payload[0] ^= 1;
+            assert!(matches!(
+                incoming.accept(chunk),
+                ChunkStatus::Resend(ResendMessage { from_seq: 0, .. })
+            ));
         }
+        assert!(matches!(
+            incoming.accept(outgoing.chunks_from(0).next().unwrap()),
+            ChunkStatus::Accepted
+        ));
     }
 }
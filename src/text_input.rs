@@ -0,0 +1,266 @@
+use bevy::input::ButtonInput;
+use bevy::input::keyboard::Key;
+use bevy::prelude::*;
+
+use crate::clipboard;
+
+/// What a key press did to a `TextInput`.
+#[derive(Debug, PartialEq)]
+pub enum TextEdit {
+    /// The key doesn't edit text, or had nothing to do.
+    Unchanged,
+    Changed,
+    /// Enter, with text that passed the validation.
+    Submitted,
+    /// Enter, with text that didn't; the error says why.
+    Invalid(String),
+    /// Escape.
+    Cancelled,
+}
+
+/// One line of editable text, since `bevy_ui` has none: a cursor moved
+/// with the arrow keys, Home and End, Backspace and Delete on either side
+/// of it, and Ctrl+V to paste. Only the characters `accept` lets through
+/// are typed, up to `max_len` of them, and Enter only submits text that
+/// `validate` passes.
+pub struct TextInput {
+    pub text: String,
+    /// Where typing goes, as a byte offset into `text` on a character
+    /// boundary.
+    cursor: usize,
+    max_len: usize,
+    accept: fn(char) -> bool,
+    validate: fn(&str) -> Result<(), String>,
+}
+
+/// Lets everything but control characters through.
+pub fn any_char(c: char) -> bool {
+    !c.is_control()
+}
+
+/// Passes any text.
+pub fn always_valid(_text: &str) -> Result<(), String> {
+    Ok(())
+}
+
+/// Whether Ctrl is held, for the shortcuts a `TextInput` takes.
+pub fn ctrl_held(keys: &ButtonInput<KeyCode>) -> bool {
+    keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight])
+}
+
+impl TextInput {
+    pub fn new(
+        max_len: usize,
+        accept: fn(char) -> bool,
+        validate: fn(&str) -> Result<(), String>,
+    ) -> Self {
+        TextInput {
+            text: String::new(),
+            cursor: 0,
+            max_len,
+            accept,
+            validate,
+        }
+    }
+
+    /// Replaces the text, with the cursor at its end.
+    pub fn set_text(&mut self, text: impl Into<String>) {
+        self.text = text.into();
+        self.cursor = self.text.len();
+    }
+
+    pub fn clear(&mut self) {
+        self.set_text(String::new());
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        (self.validate)(&self.text)
+    }
+
+    /// The text with a bar where the cursor is.
+    pub fn with_cursor(&self) -> String {
+        let mut shown = self.text.clone();
+        shown.insert(self.clamped_cursor(), '|');
+        shown
+    }
+
+    /// The cursor kept inside the text, which may have been replaced
+    /// from outside.
+    fn clamped_cursor(&self) -> usize {
+        let mut cursor = self.cursor.min(self.text.len());
+        while !self.text.is_char_boundary(cursor) {
+            cursor -= 1;
+        }
+        cursor
+    }
+
+    /// Types `text` at the cursor, skipping characters that aren't
+    /// accepted and stopping once the input is full. Returns whether
+    /// anything was typed.
+    pub fn insert(&mut self, text: &str) -> bool {
+        let mut len = self.text.chars().count();
+        let mut changed = false;
+        for c in text.chars().filter(|c| (self.accept)(*c)) {
+            if len >= self.max_len {
+                break;
+            }
+            self.text.insert(self.cursor, c);
+            self.cursor += c.len_utf8();
+            len += 1;
+            changed = true;
+        }
+        changed
+    }
+
+    fn previous_boundary(&self) -> Option<usize> {
+        self.text[..self.cursor]
+            .char_indices()
+            .next_back()
+            .map(|(index, _)| index)
+    }
+
+    fn next_boundary(&self) -> Option<usize> {
+        self.text[self.cursor..]
+            .chars()
+            .next()
+            .map(|c| self.cursor + c.len_utf8())
+    }
+
+    /// Applies one pressed key. `ctrl` is whether Ctrl was held, which
+    /// turns V into paste and keeps other shortcuts from being typed.
+    pub fn edit(&mut self, key: &Key, ctrl: bool) -> TextEdit {
+        self.cursor = self.clamped_cursor();
+        let changed = match key {
+            Key::Enter => {
+                return match self.validate() {
+                    Ok(()) => TextEdit::Submitted,
+                    Err(err) => TextEdit::Invalid(err),
+                };
+            }
+            Key::Escape => return TextEdit::Cancelled,
+            Key::Backspace => match self.previous_boundary() {
+                Some(index) => {
+                    self.text.remove(index);
+                    self.cursor = index;
+                    true
+                }
+                None => false,
+            },
+            Key::Delete if self.cursor < self.text.len() => {
+                self.text.remove(self.cursor);
+                true
+            }
+            Key::ArrowLeft => {
+                if let Some(index) = self.previous_boundary() {
+                    self.cursor = index;
+                }
+                true
+            }
+            Key::ArrowRight => {
+                if let Some(index) = self.next_boundary() {
+                    self.cursor = index;
+                }
+                true
+            }
+            Key::Home => {
+                self.cursor = 0;
+                true
+            }
+            Key::End => {
+                self.cursor = self.text.len();
+                true
+            }
+            Key::Character(chars) if ctrl => {
+                if !chars.eq_ignore_ascii_case("v") {
+                    return TextEdit::Unchanged;
+                }
+                match clipboard::read_text() {
+                    // Pasting stops at the first line.
+                    Ok(pasted) => self.insert(pasted.lines().next().unwrap_or_default()),
+                    Err(err) => {
+                        warn!("Could not paste: {err}");
+                        false
+                    }
+                }
+            }
+            Key::Space => self.insert(" "),
+            Key::Character(chars) => self.insert(chars),
+            _ => false,
+        };
+        if changed {
+            TextEdit::Changed
+        } else {
+            TextEdit::Unchanged
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digits(c: char) -> bool {
+        c.is_ascii_digit()
+    }
+
+    fn not_empty(text: &str) -> Result<(), String> {
+        if text.is_empty() {
+            Err("Empty".to_string())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn type_text(input: &mut TextInput, text: &str) {
+        input.edit(&Key::Character(text.into()), false);
+    }
+
+    #[test]
+    fn typing_goes_where_the_cursor_is() {
+        let mut input = TextInput::new(10, any_char, always_valid);
+        type_text(&mut input, "ac");
+        input.edit(&Key::ArrowLeft, false);
+        type_text(&mut input, "b");
+        assert_eq!(input.text, "abc");
+        assert_eq!(input.with_cursor(), "ab|c");
+        input.edit(&Key::Home, false);
+        input.edit(&Key::Delete, false);
+        input.edit(&Key::End, false);
+        input.edit(&Key::Backspace, false);
+        assert_eq!(input.text, "b");
+    }
+
+    #[test]
+    fn only_accepted_characters_fit() {
+        let mut input = TextInput::new(3, digits, always_valid);
+        type_text(&mut input, "1a2b3c4");
+        assert_eq!(input.text, "123");
+        assert_eq!(
+            input.edit(&Key::Character("5".into()), false),
+            TextEdit::Unchanged
+        );
+    }
+
+    #[test]
+    fn enter_submits_only_valid_text() {
+        let mut input = TextInput::new(10, any_char, not_empty);
+        assert_eq!(
+            input.edit(&Key::Enter, false),
+            TextEdit::Invalid("Empty".to_string())
+        );
+        type_text(&mut input, "x");
+        assert_eq!(input.edit(&Key::Enter, false), TextEdit::Submitted);
+        assert_eq!(input.edit(&Key::Escape, false), TextEdit::Cancelled);
+    }
+
+    #[test]
+    fn the_cursor_follows_text_set_from_outside() {
+        let mut input = TextInput::new(10, any_char, always_valid);
+        type_text(&mut input, "long text");
+        input.text = "ab".to_string();
+        type_text(&mut input, "c");
+        assert_eq!(input.text, "abc");
+        input.edit(&Key::Character("s".into()), true);
+        assert_eq!(input.text, "abc");
+    }
+}
@@ -0,0 +1,250 @@
+use std::fs;
+
+use bevy::asset::io::file::FileAssetReader;
+use bevy::prelude::*;
+use hermanha_chess::{Color as HermanhaColor, PieceType, Position};
+
+use crate::san;
+
+/// Lists the board color schemes and piece sets, relative to the asset
+/// directory.
+const MANIFEST_PATH: &str = "themes/themes.txt";
+
+#[derive(Clone)]
+pub struct BoardTheme {
+    pub name: String,
+    pub light: Color,
+    pub dark: Color,
+}
+
+#[derive(Clone)]
+pub struct PieceSet {
+    pub name: String,
+    /// Directory under `assets/` holding the piece SVGs.
+    pub dir: String,
+}
+
+/// The colors of the markers drawn over the board. Besides the standard
+/// colors there is a palette that stays apart for red-green and
+/// blue-yellow colorblindness, taken from Okabe and Ito's, and a starker
+/// one for low vision.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum Palette {
+    #[default]
+    Standard,
+    Colorblind,
+    HighContrast,
+}
+
+impl Palette {
+    const ALL: [Palette; 3] = [
+        Palette::Standard,
+        Palette::Colorblind,
+        Palette::HighContrast,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Palette::Standard => "Standard",
+            Palette::Colorblind => "Colorblind",
+            Palette::HighContrast => "High contrast",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Palette::ALL
+            .into_iter()
+            .find(|palette| palette.name() == name)
+    }
+
+    fn next(self) -> Self {
+        let index = Palette::ALL.iter().position(|p| *p == self).unwrap_or(0);
+        Palette::ALL[(index + 1) % Palette::ALL.len()]
+    }
+
+    /// The dots and rings on the squares the selected piece can move to.
+    pub fn legal_move(self) -> Color {
+        match self {
+            Palette::Standard => Color::srgb(0.2, 0.3, 0.1),
+            Palette::Colorblind => Color::srgb_u8(0, 114, 178),
+            Palette::HighContrast => Color::BLACK,
+        }
+    }
+
+    /// The arrow over the opponent's last move.
+    pub fn last_move(self) -> Color {
+        match self {
+            Palette::Standard => Color::srgb(0.85, 0.45, 0.1),
+            Palette::Colorblind => Color::srgb_u8(230, 159, 0),
+            Palette::HighContrast => Color::srgb(1.0, 1.0, 0.0),
+        }
+    }
+
+    /// The tint on the square of a king in check.
+    pub fn check(self) -> Color {
+        match self {
+            Palette::Standard => Color::srgb(0.9, 0.15, 0.15),
+            Palette::Colorblind => Color::srgb_u8(204, 121, 167),
+            Palette::HighContrast => Color::srgb(1.0, 0.0, 1.0),
+        }
+    }
+}
+
+/// Every available board color scheme and piece set, and which of each
+/// is in use, along with the marker palette.
+#[derive(Resource)]
+pub struct Theme {
+    boards: Vec<BoardTheme>,
+    piece_sets: Vec<PieceSet>,
+    board: usize,
+    pieces: usize,
+    pub palette: Palette,
+    /// Adds shapes to the markers that are otherwise told apart by color
+    /// alone: a cross over a king in check, and an arrow over the last
+    /// move that stays until the next one.
+    pub shape_markers: bool,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            boards: vec![BoardTheme {
+                name: "Brown".to_string(),
+                light: Color::srgb(0.87, 0.81, 0.74),
+                dark: Color::srgb(0.62, 0.42, 0.32),
+            }],
+            piece_sets: vec![PieceSet {
+                name: "Classic".to_string(),
+                dir: "pieces".to_string(),
+            }],
+            board: 0,
+            pieces: 0,
+            palette: Palette::default(),
+            shape_markers: false,
+        }
+    }
+}
+
+impl Theme {
+    /// Reads the theme manifest from the asset directory, falling back to
+    /// the built-in brown board and classic pieces if it can't be used.
+    pub fn load() -> Self {
+        let path = FileAssetReader::new("assets")
+            .root_path()
+            .join(MANIFEST_PATH);
+        let result = fs::read_to_string(&path)
+            .map_err(|err| err.to_string())
+            .and_then(|text| Theme::parse(&text));
+        match result {
+            Ok(theme) => theme,
+            Err(err) => {
+                warn!("Could not load themes from {}: {err}", path.display());
+                Theme::default()
+            }
+        }
+    }
+
+    fn parse(text: &str) -> Result<Self, String> {
+        let mut boards = Vec::new();
+        let mut piece_sets = Vec::new();
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                ["board", light, dark, name @ ..] if !name.is_empty() => {
+                    let hex = |value: &str| {
+                        Srgba::hex(value)
+                            .map(Color::from)
+                            .map_err(|_| format!("Invalid color: {value}"))
+                    };
+                    boards.push(BoardTheme {
+                        name: name.join(" "),
+                        light: hex(light)?,
+                        dark: hex(dark)?,
+                    });
+                }
+                ["pieces", dir, name @ ..] if !name.is_empty() => piece_sets.push(PieceSet {
+                    name: name.join(" "),
+                    dir: dir.to_string(),
+                }),
+                _ => return Err(format!("Invalid line: {line}")),
+            }
+        }
+        if boards.is_empty() || piece_sets.is_empty() {
+            return Err("Need at least one board and one piece set".to_string());
+        }
+        Ok(Theme {
+            boards,
+            piece_sets,
+            ..Theme::default()
+        })
+    }
+
+    pub fn board(&self) -> &BoardTheme {
+        &self.boards[self.board]
+    }
+
+    pub fn piece_set(&self) -> &PieceSet {
+        &self.piece_sets[self.pieces]
+    }
+
+    pub fn square_color(&self, pos: Position) -> Color {
+        if (pos.row + pos.col) % 2 == 0 {
+            self.board().dark
+        } else {
+            self.board().light
+        }
+    }
+
+    pub fn piece_path(&self, color: HermanhaColor, piece_type: PieceType) -> String {
+        let letter = san::piece_letter(piece_type).to_ascii_lowercase();
+        let shade = match color {
+            HermanhaColor::White => 'l',
+            HermanhaColor::Black => 'd',
+        };
+        format!("{}/Chess_{letter}{shade}t45.svg", self.piece_set().dir)
+    }
+
+    pub fn next_board(&mut self) {
+        self.board = (self.board + 1) % self.boards.len();
+    }
+
+    pub fn next_piece_set(&mut self) {
+        self.pieces = (self.pieces + 1) % self.piece_sets.len();
+    }
+
+    pub fn next_palette(&mut self) {
+        self.palette = self.palette.next();
+    }
+
+    /// Selects a board by name, keeping the current one if there's no
+    /// such board.
+    pub fn select_board(&mut self, name: &str) {
+        if let Some(index) = self.boards.iter().position(|board| board.name == name) {
+            self.board = index;
+        }
+    }
+
+    pub fn select_piece_set(&mut self, name: &str) {
+        if let Some(index) = self.piece_sets.iter().position(|set| set.name == name) {
+            self.pieces = index;
+        }
+    }
+}
+
+/// A board square; recolored when the board theme changes.
+#[derive(Component)]
+pub struct Square(pub Position);
+
+/// Recolors the squares. The pieces follow the piece set through
+/// `rasterize_pieces`.
+pub fn apply_theme(theme: Res<Theme>, mut squares: Query<(&Square, &mut Sprite)>) {
+    if !theme.is_changed() {
+        return;
+    }
+    for (square, mut sprite) in squares.iter_mut() {
+        sprite.color = theme.square_color(square.0);
+    }
+}
@@ -0,0 +1,207 @@
+use std::io;
+use std::net::TcpStream;
+use std::sync::{Arc, OnceLock};
+use std::time::Instant;
+
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{self, CryptoProvider};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName, UnixTime};
+use rustls::{
+    CertificateError, ClientConfig, ClientConnection, DigitallySignedStruct, ServerConfig,
+    ServerConnection, SignatureScheme, StreamOwned,
+};
+use sha2::{Digest, Sha256};
+
+use crate::transport::{HANDSHAKE_TIMEOUT, clear_timeouts, time_out_at};
+
+/// The name in the host's certificate. Clients trust the certificate by
+/// its fingerprint, so the name is never checked.
+const SERVER_NAME: &str = "chess";
+
+/// The SHA-256 hash of a host's certificate.
+pub type Fingerprint = [u8; 32];
+
+/// The self-signed certificate a host presents, made once per run so
+/// that reconnecting clients see the same fingerprint.
+struct Identity {
+    cert: CertificateDer<'static>,
+    key: PrivatePkcs8KeyDer<'static>,
+}
+
+fn identity() -> &'static Identity {
+    static IDENTITY: OnceLock<Identity> = OnceLock::new();
+    IDENTITY.get_or_init(|| {
+        let certified = rcgen::generate_simple_self_signed(vec![SERVER_NAME.to_string()])
+            .expect("generating a self-signed certificate");
+        Identity {
+            cert: certified.cert.der().clone(),
+            key: PrivatePkcs8KeyDer::from(certified.key_pair.serialize_der()),
+        }
+    })
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(crypto::ring::default_provider())
+}
+
+fn fingerprint_of(cert: &[u8]) -> Fingerprint {
+    Sha256::digest(cert).into()
+}
+
+/// The fingerprint of our certificate as the host shows it, for the
+/// opponent to type after the address.
+pub fn host_fingerprint() -> String {
+    format_fingerprint(&fingerprint_of(&identity().cert))
+}
+
+/// Groups of four hex digits separated by dashes, which the menu's
+/// address field accepts.
+pub fn format_fingerprint(fingerprint: &Fingerprint) -> String {
+    fingerprint
+        .chunks(2)
+        .map(|pair| format!("{:02X}{:02X}", pair[0], pair[1]))
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Reads a fingerprint back, ignoring case and any dashes or colons.
+pub fn parse_fingerprint(text: &str) -> Result<Fingerprint, String> {
+    let digits: Vec<u8> = text
+        .bytes()
+        .filter(|byte| !matches!(byte, b'-' | b':'))
+        .collect();
+    if digits.len() != 64 {
+        return Err("A fingerprint has 64 hex digits".to_string());
+    }
+    let mut fingerprint = [0; 32];
+    for (byte, pair) in fingerprint.iter_mut().zip(digits.chunks(2)) {
+        let pair = std::str::from_utf8(pair).map_err(|_| "Invalid fingerprint".to_string())?;
+        *byte = u8::from_str_radix(pair, 16).map_err(|_| "Invalid fingerprint".to_string())?;
+    }
+    Ok(fingerprint)
+}
+
+/// Splits a client's `address#fingerprint` into its two halves.
+pub fn split_address(address: &str) -> io::Result<(&str, Fingerprint)> {
+    let (address, fingerprint) = address.split_once('#').ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Type the host's fingerprint after the address, as address#fingerprint",
+        )
+    })?;
+    let fingerprint = parse_fingerprint(fingerprint)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    Ok((address, fingerprint))
+}
+
+/// Runs the server side of the handshake on a freshly accepted stream.
+/// Blocks until it is done, or for at most `HANDSHAKE_TIMEOUT`.
+pub fn accept(stream: TcpStream) -> io::Result<StreamOwned<ServerConnection, TcpStream>> {
+    let identity = identity();
+    let config = ServerConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .map_err(io::Error::other)?
+        .with_no_client_auth()
+        .with_single_cert(
+            vec![identity.cert.clone()],
+            PrivateKeyDer::Pkcs8(identity.key.clone_key()),
+        )
+        .map_err(io::Error::other)?;
+    let connection = ServerConnection::new(Arc::new(config)).map_err(io::Error::other)?;
+    let mut stream = StreamOwned::new(connection, stream);
+    let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+    while stream.conn.is_handshaking() {
+        time_out_at(&stream.sock, deadline)?;
+        stream.conn.complete_io(&mut stream.sock)?;
+    }
+    clear_timeouts(&stream.sock)?;
+    Ok(stream)
+}
+
+/// Runs the client side of the handshake, failing unless the host's
+/// certificate has the `expected` fingerprint. Blocks until it is done,
+/// or for at most `HANDSHAKE_TIMEOUT`.
+pub fn connect(
+    stream: TcpStream,
+    expected: Fingerprint,
+) -> io::Result<StreamOwned<ClientConnection, TcpStream>> {
+    let provider = provider();
+    let config = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(io::Error::other)?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(FingerprintVerifier { expected, provider }))
+        .with_no_client_auth();
+    let server_name = ServerName::try_from(SERVER_NAME).expect("valid server name");
+    let connection =
+        ClientConnection::new(Arc::new(config), server_name).map_err(io::Error::other)?;
+    let mut stream = StreamOwned::new(connection, stream);
+    let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+    while stream.conn.is_handshaking() {
+        time_out_at(&stream.sock, deadline)?;
+        stream.conn.complete_io(&mut stream.sock)?;
+    }
+    clear_timeouts(&stream.sock)?;
+    Ok(stream)
+}
+
+/// Trusts exactly the certificate with the fingerprint the host shared.
+/// Signatures are still checked, so the host has to hold its key.
+#[derive(Debug)]
+struct FingerprintVerifier {
+    expected: Fingerprint,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for FingerprintVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if fingerprint_of(end_entity) == self.expected {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
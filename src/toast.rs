@@ -0,0 +1,168 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use bevy::prelude::*;
+
+/// How long a toast stays up unless it is dismissed.
+const TOAST_DURATION: Duration = Duration::from_secs(5);
+
+/// The last part of `TOAST_DURATION`, over which a toast fades out.
+const FADE_DURATION: Duration = Duration::from_secs(1);
+
+/// Toasts beyond this many waiting to be shown push out the oldest, so a
+/// flood of them stays bounded, as do headless runs where nothing shows
+/// them.
+const MAX_QUEUED: usize = 8;
+
+/// At most this many toasts are on screen; the oldest make way.
+const MAX_SHOWN: usize = 4;
+
+const TOAST_ALPHA: f32 = 0.9;
+const DISMISS_ALPHA: f32 = 0.15;
+
+/// Short notices waiting to be shown in the corner of the window, such as
+/// the opponent connecting or the game being saved. Anything can push one;
+/// the UI takes them off the queue.
+#[derive(Resource, Default)]
+pub struct Toasts {
+    queue: VecDeque<String>,
+}
+
+impl Toasts {
+    pub fn push(&mut self, text: impl Into<String>) {
+        if self.queue.len() == MAX_QUEUED {
+            self.queue.pop_front();
+        }
+        self.queue.push_back(text.into());
+    }
+}
+
+/// The column in the bottom right corner the toasts stack up in.
+#[derive(Component)]
+pub struct ToastStack;
+
+#[derive(Component)]
+pub struct Toast(Timer);
+
+#[derive(Component)]
+pub struct ToastDismissButton;
+
+pub fn spawn_toast_stack(mut commands: Commands) {
+    commands.spawn((
+        ToastStack,
+        Node {
+            position_type: PositionType::Absolute,
+            right: Val::Px(12.0),
+            bottom: Val::Px(12.0),
+            flex_direction: FlexDirection::Column,
+            align_items: AlignItems::End,
+            row_gap: Val::Px(6.0),
+            ..default()
+        },
+        GlobalZIndex(11),
+    ));
+}
+
+pub fn show_toasts(
+    mut commands: Commands,
+    mut toasts: ResMut<Toasts>,
+    stacks: Query<Entity, With<ToastStack>>,
+    shown: Query<(Entity, &Toast)>,
+) {
+    if toasts.queue.is_empty() {
+        return;
+    }
+    let texts: Vec<String> = toasts.queue.drain(..).collect();
+    let mut oldest_first: Vec<(Entity, &Toast)> = shown.iter().collect();
+    oldest_first.sort_by_key(|(_, toast)| std::cmp::Reverse(toast.0.elapsed()));
+    let excess = (oldest_first.len() + texts.len()).saturating_sub(MAX_SHOWN);
+    for (entity, _) in oldest_first.into_iter().take(excess) {
+        commands.entity(entity).despawn();
+    }
+    let skipped = texts.len().saturating_sub(MAX_SHOWN);
+    for stack in stacks.iter() {
+        for text in &texts[skipped..] {
+            commands.entity(stack).with_child(toast(text.clone()));
+        }
+    }
+}
+
+fn toast(text: String) -> impl Bundle {
+    (
+        Toast(Timer::new(TOAST_DURATION, TimerMode::Once)),
+        Node {
+            max_width: Val::Px(360.0),
+            padding: UiRect::axes(Val::Px(10.0), Val::Px(6.0)),
+            column_gap: Val::Px(10.0),
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        BackgroundColor(Color::srgba(0.18, 0.18, 0.22, TOAST_ALPHA)),
+        children![
+            (
+                Text::new(text),
+                TextFont {
+                    font_size: 14.0,
+                    ..default()
+                },
+            ),
+            (
+                ToastDismissButton,
+                Button,
+                Node {
+                    width: Val::Px(22.0),
+                    height: Val::Px(22.0),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                BackgroundColor(Color::srgba(1.0, 1.0, 1.0, DISMISS_ALPHA)),
+                children![Text::new("x")],
+            ),
+        ],
+    )
+}
+
+pub fn dismiss_toasts(
+    mut commands: Commands,
+    buttons: Query<(&Interaction, &ChildOf), (Changed<Interaction>, With<ToastDismissButton>)>,
+) {
+    for (interaction, child_of) in buttons.iter() {
+        if *interaction == Interaction::Pressed {
+            commands.entity(child_of.parent()).despawn();
+        }
+    }
+}
+
+/// Fades toasts out over their last `FADE_DURATION` and removes them once
+/// they are gone. Toasts time out in real time, so they go away even while
+/// a game is paused.
+pub fn fade_toasts(
+    mut commands: Commands,
+    time: Res<Time<Real>>,
+    mut toasts: Query<(Entity, &mut Toast, &mut BackgroundColor), Without<ToastDismissButton>>,
+    children: Query<&Children>,
+    mut texts: Query<&mut TextColor>,
+    mut buttons: Query<&mut BackgroundColor, With<ToastDismissButton>>,
+) {
+    for (entity, mut toast, mut background) in toasts.iter_mut() {
+        if toast.0.tick(time.delta()).finished() {
+            commands.entity(entity).despawn();
+            continue;
+        }
+        let remaining = toast.0.remaining();
+        if remaining >= FADE_DURATION {
+            continue;
+        }
+        let opacity = remaining.as_secs_f32() / FADE_DURATION.as_secs_f32();
+        background.0.set_alpha(TOAST_ALPHA * opacity);
+        for descendant in children.iter_descendants(entity) {
+            if let Ok(mut color) = texts.get_mut(descendant) {
+                color.0.set_alpha(opacity);
+            }
+            if let Ok(mut button) = buttons.get_mut(descendant) {
+                button.0.set_alpha(DISMISS_ALPHA * opacity);
+            }
+        }
+    }
+}
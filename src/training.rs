@@ -0,0 +1,121 @@
+use bevy::prelude::*;
+use hermanha_chess::{Color as HermanhaColor, PieceType};
+
+use crate::widgets;
+
+/// Which pieces are drawn, to practise seeing the board without them.
+/// Moves are played the same way whatever is hidden.
+#[derive(Resource, Default, Clone, Copy, PartialEq)]
+pub enum Training {
+    #[default]
+    Off,
+    /// No pieces at all.
+    Blindfold,
+    /// Only our own pieces.
+    HideOpponent,
+    /// Only the pawns of both sides.
+    PawnStructure,
+}
+
+impl Training {
+    const ALL: [Training; 4] = [
+        Training::Off,
+        Training::Blindfold,
+        Training::HideOpponent,
+        Training::PawnStructure,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Training::Off => "Off",
+            Training::Blindfold => "Blindfold",
+            Training::HideOpponent => "Hide opponent pieces",
+            Training::PawnStructure => "Pawn structure only",
+        }
+    }
+
+    /// Whether a piece is drawn when `own` is the color we play.
+    pub fn shows(self, color: HermanhaColor, piece_type: PieceType, own: HermanhaColor) -> bool {
+        match self {
+            Training::Off => true,
+            Training::Blindfold => false,
+            Training::HideOpponent => color == own,
+            Training::PawnStructure => matches!(piece_type, PieceType::Pawn),
+        }
+    }
+}
+
+/// The training submenu, opened from the settings panel.
+#[derive(Component)]
+pub struct TrainingPanel;
+
+#[derive(Component, Clone, Copy)]
+pub enum TrainingButton {
+    Mode(Training),
+    Back,
+}
+
+pub fn spawn_training_panel(commands: &mut Commands) {
+    commands
+        .spawn((TrainingPanel, widgets::modal(10.0, 10)))
+        .with_children(|parent| {
+            parent.spawn(widgets::label("Training"));
+            let buttons = Training::ALL
+                .map(TrainingButton::Mode)
+                .into_iter()
+                .chain([TrainingButton::Back]);
+            for button in buttons {
+                let label = match button {
+                    TrainingButton::Mode(training) => training.label(),
+                    TrainingButton::Back => "Back",
+                };
+                parent.spawn((
+                    button,
+                    widgets::button(widgets::button_node(260.0, 36.0), widgets::label(label)),
+                ));
+            }
+        });
+}
+
+pub fn despawn_training_panel(
+    commands: &mut Commands,
+    panels: &Query<Entity, With<TrainingPanel>>,
+) {
+    for entity in panels.iter() {
+        commands.entity(entity).despawn();
+    }
+}
+
+pub fn handle_training_buttons(
+    mut commands: Commands,
+    interactions: Query<(&Interaction, &TrainingButton), Changed<Interaction>>,
+    panels: Query<Entity, With<TrainingPanel>>,
+    mut training: ResMut<Training>,
+) {
+    for (interaction, button) in interactions.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        match button {
+            TrainingButton::Mode(mode) => *training = *mode,
+            TrainingButton::Back => despawn_training_panel(&mut commands, &panels),
+        }
+    }
+}
+
+/// Marks the chosen mode among the submenu's buttons.
+pub fn render_training_panel(
+    training: Res<Training>,
+    added: Query<(), Added<TrainingPanel>>,
+    mut buttons: Query<(&TrainingButton, &mut BackgroundColor)>,
+) {
+    if !training.is_changed() && added.is_empty() {
+        return;
+    }
+    for (button, mut background) in buttons.iter_mut() {
+        background.0 = match button {
+            TrainingButton::Mode(mode) if *mode == *training => widgets::SELECTED_BUTTON_COLOR,
+            _ => widgets::BUTTON_COLOR,
+        };
+    }
+}
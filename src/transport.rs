@@ -0,0 +1,215 @@
+use std::io;
+use std::net::TcpStream;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use crossbeam_channel::{Receiver, Sender, TryRecvError, unbounded};
+
+use crate::tcp::{ConnectionType, Message, TcpConnection, TcpError};
+use crate::tls;
+use crate::ws::WsConnection;
+
+/// A way of exchanging `Message`s with the opponent. The I/O happens on a
+/// background thread, so `read` and `write` never block.
+pub trait Transport: Send + Sync {
+    fn connection_type(&self) -> ConnectionType;
+
+    /// How many spectators are watching. Only hosts over TCP take
+    /// spectators.
+    fn spectator_count(&self) -> usize {
+        0
+    }
+
+    fn read(&mut self) -> Result<Message, TcpError>;
+
+    fn write(&mut self, message: Message) -> Result<(), TcpError>;
+
+    /// Sends what was written so far and closes the connection, giving up
+    /// after `timeout`. Used as the app exits, which would otherwise cut
+    /// the I/O thread off with messages still queued. Transports without
+    /// one have nothing to wait for.
+    fn close(&mut self, _timeout: Duration) {}
+}
+
+/// How long an I/O thread waits on its socket before checking for
+/// messages to send.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// How long a handshake may take in all, so a peer that connects and then
+/// says nothing can't hold up the side waiting on it.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Makes the socket's reads and writes give up at `deadline`.
+pub fn time_out_at(sock: &TcpStream, deadline: Instant) -> io::Result<()> {
+    let left = deadline.saturating_duration_since(Instant::now());
+    if left.is_zero() {
+        return Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "The handshake took too long",
+        ));
+    }
+    sock.set_read_timeout(Some(left))?;
+    sock.set_write_timeout(Some(left))
+}
+
+/// Takes the handshake's timeouts off the socket again, leaving the
+/// caller to set its own.
+pub fn clear_timeouts(sock: &TcpStream) -> io::Result<()> {
+    sock.set_read_timeout(None)?;
+    sock.set_write_timeout(None)
+}
+
+/// The game's end of a connection's I/O thread. Messages pass through
+/// channels, so neither `read` nor `write` waits on the socket.
+pub struct IoThread {
+    outgoing: Sender<Message>,
+    incoming: Receiver<Result<Message, TcpError>>,
+    thread: JoinHandle<()>,
+}
+
+impl IoThread {
+    /// Runs `io` on its own thread with the messages to send and where to
+    /// put the ones received. It should pass errors on before it returns.
+    pub fn spawn(
+        io: impl FnOnce(Receiver<Message>, Sender<Result<Message, TcpError>>) + Send + 'static,
+    ) -> Self {
+        let (outgoing_tx, outgoing_rx) = unbounded();
+        let (incoming_tx, incoming_rx) = unbounded();
+        let thread = thread::spawn(move || io(outgoing_rx, incoming_tx));
+        IoThread {
+            outgoing: outgoing_tx,
+            incoming: incoming_rx,
+            thread,
+        }
+    }
+
+    pub fn read(&mut self) -> Result<Message, TcpError> {
+        match self.incoming.try_recv() {
+            Ok(result) => result,
+            Err(TryRecvError::Empty) => Err(TcpError::WouldBlock),
+            Err(TryRecvError::Disconnected) => Err(stopped()),
+        }
+    }
+
+    pub fn write(&mut self, message: Message) -> Result<(), TcpError> {
+        self.outgoing.send(message).map_err(|_| stopped())
+    }
+
+    /// Dropping the only sender tells the thread to stop once it has sent
+    /// everything queued, which hangs up the socket. Waits up to `timeout`
+    /// for it to do so.
+    pub fn close(&mut self, timeout: Duration) {
+        self.outgoing = unbounded().0;
+        let deadline = Instant::now() + timeout;
+        while !self.thread.is_finished() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+fn stopped() -> TcpError {
+    TcpError::Io(io::Error::new(
+        io::ErrorKind::BrokenPipe,
+        "connection thread stopped",
+    ))
+}
+
+impl Transport for TcpConnection {
+    fn connection_type(&self) -> ConnectionType {
+        TcpConnection::connection_type(self)
+    }
+
+    fn spectator_count(&self) -> usize {
+        TcpConnection::spectator_count(self)
+    }
+
+    fn read(&mut self) -> Result<Message, TcpError> {
+        TcpConnection::read(self)
+    }
+
+    fn write(&mut self, message: Message) -> Result<(), TcpError> {
+        TcpConnection::write(self, message)
+    }
+
+    fn close(&mut self, timeout: Duration) {
+        TcpConnection::close(self, timeout)
+    }
+}
+
+/// Which transport online games use, picked in the menu or with `--tls` or
+/// `--websocket`. Both sides have to pick the same one.
+#[derive(Resource, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransportKind {
+    /// Length-prefixed frames straight over TCP.
+    #[default]
+    Tcp,
+    /// The same frames encrypted with TLS. The host shows the fingerprint
+    /// of its self-signed certificate, which the client types after the
+    /// address as `address#fingerprint`.
+    Tls,
+    /// One text message per frame over a WebSocket, which gets through
+    /// HTTP proxies and can be bridged to a browser.
+    WebSocket,
+}
+
+impl TransportKind {
+    pub fn label(self) -> &'static str {
+        match self {
+            TransportKind::Tcp => "Over TCP",
+            TransportKind::Tls => "Over TCP, encrypted",
+            TransportKind::WebSocket => "Over WebSocket",
+        }
+    }
+
+    /// The short name beacons and the lobby pass the transport around by.
+    pub fn tag(self) -> &'static str {
+        match self {
+            TransportKind::Tcp => "tcp",
+            TransportKind::Tls => "tls",
+            TransportKind::WebSocket => "ws",
+        }
+    }
+
+    pub fn from_tag(tag: &str) -> Result<Self, String> {
+        match tag {
+            "tcp" => Ok(TransportKind::Tcp),
+            "tls" => Ok(TransportKind::Tls),
+            "ws" => Ok(TransportKind::WebSocket),
+            _ => Err(format!("Invalid transport: {tag}")),
+        }
+    }
+
+    /// Connects, or for the server waits for the client. Blocks, so call
+    /// it off the main thread.
+    pub fn connect(
+        self,
+        connection_type: ConnectionType,
+        address: &str,
+    ) -> io::Result<Box<dyn Transport>> {
+        Ok(match (self, connection_type) {
+            (TransportKind::Tcp, ConnectionType::Server) => {
+                Box::new(TcpConnection::start_server(address, false)?)
+            }
+            (TransportKind::Tcp, ConnectionType::Client) => {
+                Box::new(TcpConnection::connect_to_server(address, None)?)
+            }
+            (TransportKind::Tls, ConnectionType::Server) => {
+                Box::new(TcpConnection::start_server(address, true)?)
+            }
+            (TransportKind::Tls, ConnectionType::Client) => {
+                let (address, fingerprint) = tls::split_address(address)?;
+                Box::new(TcpConnection::connect_to_server(
+                    address,
+                    Some(fingerprint),
+                )?)
+            }
+            (TransportKind::WebSocket, ConnectionType::Server) => {
+                Box::new(WsConnection::start_server(address)?)
+            }
+            (TransportKind::WebSocket, ConnectionType::Client) => {
+                Box::new(WsConnection::connect_to_server(address)?)
+            }
+        })
+    }
+}
@@ -0,0 +1,425 @@
+use bevy::input::{ButtonInput, InputSystem};
+use bevy::prelude::*;
+use bevy::ui::UiSystem;
+
+use crate::actions::{self, Action};
+use crate::board_render::Upright;
+use crate::chat::{self, ChatInput};
+use crate::clock::{self, Clocks};
+use crate::config::{self, NameInput};
+use crate::confirm::{self, Confirmed};
+use crate::event_log::{self, ShowEventLog};
+use crate::game_state::{Phase, PlayerColor};
+use crate::input::IllegalMove;
+use crate::menu::{self, AppState, PlayState};
+use crate::move_input::{self, MoveInput};
+use crate::net::Connection;
+use crate::puzzle::{self, PuzzleSession};
+use crate::rules::GamePhase;
+use crate::save::{self, ResumableGame, SaveRequested};
+use crate::setup::{self, SetupEditor};
+use crate::widgets::{self, UiFocus};
+use crate::window::{self, MiniMode};
+use crate::{
+    GameSet, SpawnGameUi, TILE_SIZE, clipboard, coordinates, cursor, discovery, eval_graph,
+    game_over, games, hint, history, lobby, name_plates, offers, pause, pos_to_vec3, promotion,
+    review, theme, toast, training,
+};
+
+/// Menus, panels, overlays and labels around the board, and the window
+/// and settings controls.
+pub struct UiPlugin;
+
+impl Plugin for UiPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<MiniMode>()
+            .init_resource::<ChatInput>()
+            .init_resource::<MoveInput>()
+            .init_resource::<ShowExplanations>()
+            .init_resource::<NameInput>()
+            .init_resource::<ResumableGame>()
+            .init_resource::<ShowEventLog>()
+            .init_resource::<UiFocus>()
+            .add_event::<SaveRequested>()
+            .add_event::<Confirmed>()
+            .add_systems(Startup, (setup_phase_label, toast::spawn_toast_stack))
+            .add_systems(OnEnter(AppState::Menu), menu::spawn_menu)
+            .add_systems(OnExit(AppState::Menu), menu::despawn_menu)
+            .add_systems(OnEnter(AppState::Connecting), menu::spawn_waiting_screen)
+            .add_systems(OnExit(AppState::Connecting), menu::despawn_waiting_screen)
+            .add_systems(
+                Update,
+                menu::handle_cancel_button.run_if(in_state(AppState::Connecting)),
+            )
+            .add_systems(
+                SpawnGameUi,
+                (
+                    games::spawn_tab_bar,
+                    history::spawn_move_list,
+                    event_log::spawn_event_log,
+                    move_input::spawn_move_input,
+                    hint::spawn_hint_button.run_if(not(resource_exists::<PlayerColor>)),
+                    clock::spawn_clock_display.run_if(resource_exists::<Clocks>),
+                    name_plates::spawn_name_plates,
+                    puzzle::spawn_puzzle_panel.run_if(resource_exists::<PuzzleSession>),
+                    (offers::spawn_offer_buttons, chat::spawn_chat_panel)
+                        .run_if(resource_exists::<Connection>),
+                ),
+            )
+            .add_systems(
+                OnExit(AppState::Playing),
+                (
+                    despawn_game_ui,
+                    move_input::close_move_input,
+                    clipboard::clear_pending_paste,
+                ),
+            )
+            .add_systems(
+                PreUpdate,
+                move_input::type_move
+                    .after(InputSystem)
+                    .after(chat::type_chat)
+                    .after(config::type_name)
+                    .run_if(in_state(AppState::Playing))
+                    .run_if(not(in_state(PlayState::Review))),
+            )
+            .add_systems(
+                Update,
+                (
+                    offers::show_draw_offer,
+                    offers::render_offer_status,
+                    chat::render_chat,
+                )
+                    .in_set(GameSet::Render)
+                    .run_if(resource_exists::<Connection>)
+                    .run_if(in_state(AppState::Playing)),
+            )
+            .add_systems(
+                PreUpdate,
+                actions::update_scopes
+                    .after(InputSystem)
+                    .before(chat::type_chat)
+                    .before(widgets::move_focus),
+            )
+            .add_systems(
+                PreUpdate,
+                chat::type_chat
+                    .after(InputSystem)
+                    .run_if(resource_exists::<Connection>)
+                    .run_if(in_state(AppState::Playing)),
+            )
+            .add_systems(
+                Update,
+                (
+                    puzzle::handle_puzzle_buttons.in_set(GameSet::Input),
+                    puzzle::render_puzzle_panel.in_set(GameSet::Render),
+                )
+                    .run_if(resource_exists::<PuzzleSession>)
+                    .run_if(in_state(AppState::Playing)),
+            )
+            .add_systems(
+                Update,
+                clock::render_clocks
+                    .in_set(GameSet::Render)
+                    .run_if(resource_exists::<Clocks>)
+                    .run_if(in_state(AppState::Playing)),
+            )
+            .add_systems(
+                Update,
+                name_plates::render_name_plates
+                    .in_set(GameSet::Render)
+                    .run_if(in_state(AppState::Playing)),
+            )
+            .add_systems(
+                Update,
+                (
+                    pause::toggle_pause.before(cursor::move_cursor),
+                    pause::handle_pause_buttons,
+                )
+                    .in_set(GameSet::Input)
+                    .run_if(in_state(PlayState::Ongoing)),
+            )
+            .add_systems(OnExit(PlayState::Ongoing), pause::close_pause_menu)
+            .add_systems(PreUpdate, config::type_name.after(InputSystem))
+            .add_systems(
+                PreUpdate,
+                setup::type_fen
+                    .after(InputSystem)
+                    .run_if(resource_exists::<SetupEditor>),
+            )
+            .add_systems(
+                PreUpdate,
+                widgets::move_focus
+                    .after(InputSystem)
+                    .after(UiSystem::Focus)
+                    .after(config::type_name)
+                    .after(chat::type_chat)
+                    .after(move_input::type_move)
+                    .after(setup::type_fen),
+            )
+            .add_systems(
+                Update,
+                (widgets::style_buttons, confirm::handle_confirm_buttons),
+            )
+            .add_systems(
+                Update,
+                (
+                    config::apply_default_time_control,
+                    menu::handle_menu_buttons,
+                    menu::edit_address,
+                    menu::render_address,
+                    menu::render_menu_message,
+                    menu::render_variant,
+                    menu::render_transport,
+                    save::resume_game,
+                )
+                    .run_if(in_state(AppState::Menu)),
+            )
+            .add_systems(OnEnter(AppState::FindingGames), discovery::enter_lan_screen)
+            .add_systems(OnExit(AppState::FindingGames), discovery::exit_lan_screen)
+            .add_systems(
+                Update,
+                (
+                    discovery::receive_beacons,
+                    discovery::render_lan_games,
+                    discovery::handle_lan_buttons,
+                )
+                    .chain()
+                    .run_if(in_state(AppState::FindingGames)),
+            )
+            .init_resource::<lobby::LobbyAddress>()
+            .add_systems(OnEnter(AppState::Lobby), lobby::enter_lobby)
+            .add_systems(OnExit(AppState::Lobby), lobby::exit_lobby)
+            .add_systems(
+                Update,
+                (
+                    lobby::handle_lobby_buttons,
+                    lobby::poll_lobby,
+                    lobby::render_lobby,
+                )
+                    .chain()
+                    .run_if(in_state(AppState::Lobby)),
+            )
+            .add_systems(
+                Update,
+                lobby::keep_listing
+                    .run_if(resource_exists::<lobby::LobbyListing>)
+                    .run_if(in_state(AppState::Connecting)),
+            )
+            .add_systems(OnExit(AppState::Connecting), lobby::stop_listing)
+            .add_systems(
+                OnEnter(PlayState::Review),
+                (review::enter_review, eval_graph::start_evaluations),
+            )
+            .add_systems(
+                OnExit(PlayState::Review),
+                (review::exit_review, eval_graph::stop_evaluations),
+            )
+            .add_systems(
+                Update,
+                (
+                    (
+                        review::step_review,
+                        eval_graph::handle_graph_clicks,
+                        review::play_review_moves,
+                        review::follow_shown_move,
+                    )
+                        .chain()
+                        .in_set(GameSet::Input),
+                    (
+                        eval_graph::collect_evaluations,
+                        (review::render_review, eval_graph::render_eval_graph),
+                    )
+                        .chain()
+                        .in_set(GameSet::Render),
+                )
+                    .run_if(in_state(PlayState::Review)),
+            )
+            .add_systems(OnEnter(AppState::Coordinates), coordinates::enter_drill)
+            .add_systems(OnExit(AppState::Coordinates), coordinates::exit_drill)
+            .add_systems(
+                Update,
+                (
+                    coordinates::handle_drill_buttons,
+                    coordinates::answer_drill,
+                    coordinates::render_drill,
+                )
+                    .chain()
+                    .run_if(in_state(AppState::Coordinates)),
+            )
+            .add_systems(OnEnter(AppState::Setup), setup::enter_setup)
+            .add_systems(OnExit(AppState::Setup), setup::exit_setup)
+            .add_systems(
+                Update,
+                (
+                    setup::handle_setup_buttons,
+                    setup::edit_setup_board,
+                    setup::render_setup_panel,
+                )
+                    .chain()
+                    .run_if(in_state(AppState::Setup)),
+            )
+            .add_systems(
+                Update,
+                (
+                    actions::toggle_help_overlay,
+                    (
+                        toast::show_toasts,
+                        toast::dismiss_toasts,
+                        toast::fade_toasts,
+                    ),
+                    window::apply_window_flags,
+                    window::fit_board_to_window,
+                    (
+                        config::toggle_settings,
+                        config::handle_settings_buttons,
+                        config::render_settings,
+                        training::handle_training_buttons,
+                        training::render_training_panel,
+                        theme::apply_theme,
+                        config::save_config,
+                    )
+                        .chain(),
+                ),
+            )
+            .add_systems(
+                Update,
+                (
+                    (
+                        window::toggle_mini_mode,
+                        window::toggle_window_flags,
+                        toggle_explanations,
+                        game_over::handle_game_over_buttons,
+                        offers::handle_offer_buttons,
+                        save::save_game,
+                        clipboard::copy_to_clipboard,
+                        clipboard::handle_paste_dialog,
+                        games::handle_tab_keys,
+                        games::handle_tab_buttons,
+                        event_log::toggle_event_log,
+                    )
+                        .in_set(GameSet::Input),
+                    (
+                        render_game_phase,
+                        games::render_tab_labels,
+                        promotion::render_promotion_dialog,
+                        (
+                            game_over::show_game_over,
+                            game_over::render_rematch_status,
+                            game_over::clear_game_over,
+                        )
+                            .chain(),
+                        (hint::request_hint, hint::expire_hint).chain(),
+                        (history::render_move_list, history::scroll_move_list).chain(),
+                        move_input::render_move_input,
+                        event_log::render_event_log,
+                        clipboard::show_paste_dialog,
+                        (show_illegal_move_tooltip, expire_tooltips).chain(),
+                    )
+                        .in_set(GameSet::Render),
+                )
+                    .run_if(in_state(AppState::Playing)),
+            );
+    }
+}
+
+/// UI that only exists while a game is on screen, removed when leaving
+/// `AppState::Playing`.
+#[derive(Component)]
+pub struct GameUi;
+
+#[derive(Resource)]
+struct ShowExplanations(bool);
+
+impl Default for ShowExplanations {
+    fn default() -> Self {
+        ShowExplanations(true)
+    }
+}
+
+#[derive(Component)]
+struct Tooltip(Timer);
+
+#[derive(Component)]
+struct PhaseLabel;
+
+fn despawn_game_ui(mut commands: Commands, ui: Query<Entity, With<GameUi>>) {
+    for entity in ui.iter() {
+        commands.entity(entity).despawn();
+    }
+}
+
+fn setup_phase_label(mut commands: Commands) {
+    commands.spawn((
+        PhaseLabel,
+        Text::new(GamePhase::default().label()),
+        TextFont {
+            font_size: 14.0,
+            ..default()
+        },
+        TextColor(Color::srgba(1.0, 1.0, 1.0, 0.5)),
+        Node {
+            position_type: PositionType::Absolute,
+            left: Val::Px(8.0),
+            top: Val::Px(8.0),
+            ..default()
+        },
+    ));
+}
+
+fn render_game_phase(phase: Res<Phase>, mut labels: Query<&mut Text, With<PhaseLabel>>) {
+    if !phase.is_changed() {
+        return;
+    }
+    for mut text in labels.iter_mut() {
+        text.0 = phase.label().to_string();
+    }
+}
+
+fn toggle_explanations(keys: Res<ButtonInput<KeyCode>>, mut show: ResMut<ShowExplanations>) {
+    if actions::just_pressed(&keys, Action::ExplainIllegal) {
+        show.0 = !show.0;
+    }
+}
+
+fn show_illegal_move_tooltip(
+    mut commands: Commands,
+    mut illegal_moves: EventReader<IllegalMove>,
+    show: Res<ShowExplanations>,
+    tooltips: Query<Entity, With<Tooltip>>,
+) {
+    let Some(illegal) = illegal_moves.read().last() else {
+        return;
+    };
+    if !show.0 {
+        return;
+    }
+    for entity in tooltips.iter() {
+        commands.entity(entity).despawn();
+    }
+    commands.spawn((
+        Tooltip(Timer::from_seconds(2.5, TimerMode::Once)),
+        Upright,
+        Text2d::new(illegal.reason),
+        TextFont {
+            font_size: 16.0,
+            ..default()
+        },
+        TextColor(Color::srgb(1.0, 0.85, 0.55)),
+        Transform::from_translation(
+            pos_to_vec3(illegal.at, 3.0) + Vec3::new(0.0, TILE_SIZE * 0.6, 0.0),
+        ),
+    ));
+}
+
+fn expire_tooltips(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut tooltips: Query<(Entity, &mut Tooltip)>,
+) {
+    for (entity, mut tooltip) in tooltips.iter_mut() {
+        if tooltip.0.tick(time.delta()).finished() {
+            commands.entity(entity).despawn();
+        }
+    }
+}
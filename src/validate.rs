@@ -0,0 +1,153 @@
+use std::fs;
+
+use hermanha_chess::{Board, MoveOk};
+
+use crate::tcp::{Message, board_to_fen};
+
+const FRAME_LEN: usize = 128;
+
+/// Replays a recorded session through the protocol parser and the rules
+/// engine without starting the GUI. The log is the raw byte stream of
+/// 128-byte frames in the order the moves were played; line breaks between
+/// frames are ignored so hand-edited logs work too.
+///
+/// Returns the number of frames checked, or a description of the first
+/// frame that failed to parse or diverged from the locally replayed game.
+pub fn validate_log(path: &str) -> Result<usize, String> {
+    let bytes = fs::read(path).map_err(|err| format!("could not read {path}: {err}"))?;
+    let bytes: Vec<u8> = bytes
+        .into_iter()
+        .filter(|byte| *byte != b'\n' && *byte != b'\r')
+        .collect();
+    if !bytes.len().is_multiple_of(FRAME_LEN) {
+        return Err(format!(
+            "log is {} bytes, which is not a whole number of {FRAME_LEN}-byte frames",
+            bytes.len()
+        ));
+    }
+
+    let mut board = Board::start_pos();
+    for (index, frame) in bytes.chunks(FRAME_LEN).enumerate() {
+        let message =
+            Message::decode(frame).map_err(|err| format!("frame {index}: parse error: {err}"))?;
+        let move_msg = match message {
+            Message::Move(move_msg) => move_msg,
+            Message::Quit(_) => return Ok(index + 1),
+            Message::Chunk(_)
+            | Message::Resend(_)
+            | Message::Draw(_)
+            | Message::Resign(_)
+            | Message::Hello(_)
+            | Message::Sync(_)
+            | Message::Chat(_)
+            | Message::Spectate(_)
+            | Message::Relay(_)
+            | Message::Resync(_)
+            | Message::Rematch(_)
+            | Message::Ping(_)
+            | Message::Pong(_) => {
+                continue;
+            }
+        };
+        let from = (move_msg.from.row, move_msg.from.col);
+        let to = (move_msg.to.row, move_msg.to.col);
+        match board.play(from, to, move_msg.promotion_piece) {
+            Ok(MoveOk::NeedsPromotion) => {
+                return Err(format!(
+                    "frame {index}: promotion move without a promotion piece"
+                ));
+            }
+            Ok(_) => {}
+            Err(err) => return Err(format!("frame {index}: illegal move: {err:?}")),
+        }
+        let local_fen = board_to_fen(&board);
+        let remote_fen = board_to_fen(&move_msg.new_board);
+        if local_fen != remote_fen {
+            return Err(format!(
+                "frame {index}: position diverged\n  replayed: {local_fen}\n  in log:   {remote_fen}"
+            ));
+        }
+    }
+    Ok(bytes.len() / FRAME_LEN)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use hermanha_chess::Position;
+
+    use super::*;
+    use crate::tcp::{MoveMessage, QuitMessage};
+
+    /// Plays the move on `board` and gives its frame as a peer would send
+    /// it.
+    fn move_frame(board: &mut Board, from: Position, to: Position) -> String {
+        board
+            .play((from.row, from.col), (to.row, to.col), None)
+            .unwrap();
+        frame(from, to, board.clone())
+    }
+
+    /// A move frame claiming `new_board` came of the move.
+    fn frame(from: Position, to: Position, new_board: Board) -> String {
+        Message::Move(MoveMessage {
+            from,
+            to,
+            promotion_piece: None,
+            result: None,
+            new_board,
+            position_hash: None,
+        })
+        .encode()
+        .unwrap()
+    }
+
+    /// Validates the frames written one per line, as a hand-edited log
+    /// would have them.
+    fn validate(name: &str, frames: &[String]) -> Result<usize, String> {
+        let path = env::temp_dir().join(format!("validate-{}-{name}.log", std::process::id()));
+        fs::write(&path, frames.join("\n")).unwrap();
+        let result = validate_log(path.to_str().unwrap());
+        let _ = fs::remove_file(&path);
+        result
+    }
+
+    #[test]
+    fn a_played_game_checks_out_up_to_the_quit() {
+        let mut board = Board::start_pos();
+        let frames = [
+            move_frame(&mut board, Position::new(1, 4), Position::new(3, 4)),
+            move_frame(&mut board, Position::new(6, 4), Position::new(4, 4)),
+            Message::Quit(QuitMessage { message: None })
+                .encode()
+                .unwrap(),
+        ];
+        assert_eq!(validate("game", &frames), Ok(3));
+    }
+
+    #[test]
+    fn the_first_bad_frame_is_reported() {
+        let mut board = Board::start_pos();
+        let opening = move_frame(&mut board, Position::new(1, 4), Position::new(3, 4));
+
+        // Says d4 was played but sends the board after e4.
+        let diverged = frame(Position::new(1, 3), Position::new(3, 3), board.clone());
+        let error = validate("diverged", &[diverged]).unwrap_err();
+        assert!(error.starts_with("frame 0: position diverged"), "{error}");
+
+        // Black can't move a pawn three squares.
+        let illegal = frame(Position::new(6, 4), Position::new(3, 4), board);
+        let error = validate("illegal", &[opening, illegal]).unwrap_err();
+        assert!(error.starts_with("frame 1: illegal move"), "{error}");
+    }
+
+    #[test]
+    fn a_partial_frame_is_turned_away() {
+        let error = validate("partial", &["ChessQUIT:".to_string()]).unwrap_err();
+        assert!(
+            error.contains("not a whole number of 128-byte frames"),
+            "{error}"
+        );
+    }
+}
@@ -0,0 +1,243 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+use bevy::prelude::*;
+use hermanha_chess::{Board, PieceType};
+
+use crate::clock::TimeControl;
+use crate::game_state::NewGame;
+use crate::rules::{self, CastlingFiles, CastlingRights};
+use crate::{fen, san};
+
+/// How many start positions Chess960 has, numbered from 0.
+pub const CHESS960_POSITIONS: u16 = 960;
+
+/// How many "Coach vs student" handicap levels there are, numbered from 1.
+pub const HANDICAP_LEVELS: u8 = 5;
+
+/// Clocks for a handicap game when no default time control is set, so
+/// the time odds have something to take from.
+pub const HANDICAP_TIME_CONTROL: TimeControl = TimeControl {
+    base: Duration::from_secs(600),
+    increment: Duration::from_secs(5),
+};
+
+/// The handicap levels, the smallest odds first: the position the game
+/// starts from, with White, the coach, short of the material it gives,
+/// what the odds are, and White's share of the base time, one part in so
+/// many.
+const HANDICAPS: [(&str, &str, u32); HANDICAP_LEVELS as usize] = [
+    (
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPP1PP/RNBQKBNR w KQkq -",
+        "pawn odds",
+        1,
+    ),
+    (
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/R1BQKBNR w KQkq -",
+        "knight odds",
+        1,
+    ),
+    (
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/R1BQKBNR w KQkq -",
+        "knight odds, half the time",
+        2,
+    ),
+    (
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/1NBQKBNR w Kkq -",
+        "rook odds, half the time",
+        2,
+    ),
+    (
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNB1KBNR w KQkq -",
+        "queen odds, a third of the time",
+        3,
+    ),
+];
+
+/// Knight squares among the five left once the bishops and queen are
+/// placed, indexed by what is left of the position number.
+const KNIGHT_SQUARES: [(usize, usize); 10] = [
+    (0, 1),
+    (0, 2),
+    (0, 3),
+    (0, 4),
+    (1, 2),
+    (1, 3),
+    (1, 4),
+    (2, 3),
+    (2, 4),
+    (3, 4),
+];
+
+/// The game being played and, for Chess960, which start position it uses,
+/// or the level of a handicap game. Rematches start from the same
+/// position.
+#[derive(Resource, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variant {
+    #[default]
+    Standard,
+    Chess960(u16),
+    /// A "Coach vs student" preset from `HANDICAPS`, combining material
+    /// and time odds.
+    Handicap(u8),
+}
+
+impl Variant {
+    /// Chess960 from a randomly picked start position.
+    pub fn random_chess960() -> Self {
+        // `RandomState` is seeded randomly for every instance, which is
+        // all the randomness picking a position needs.
+        let seed = RandomState::new().build_hasher().finish();
+        Variant::Chess960((seed % CHESS960_POSITIONS as u64) as u16)
+    }
+
+    /// The handicap level after this one, from the first for a game
+    /// without one, and back to standard chess after the last.
+    pub fn next_handicap(self) -> Self {
+        match self {
+            Variant::Handicap(level) if level >= HANDICAP_LEVELS => Variant::Standard,
+            Variant::Handicap(level) => Variant::Handicap(level + 1),
+            _ => Variant::Handicap(1),
+        }
+    }
+
+    pub fn label(self) -> String {
+        match self {
+            Variant::Standard => "Standard chess".to_string(),
+            Variant::Chess960(number) => format!("Chess960 #{number}"),
+            Variant::Handicap(level) => {
+                format!("Coach vs student {level}: {}", handicap(level).1)
+            }
+        }
+    }
+
+    /// The part of the base time White gets, one in this many; 1 without
+    /// time odds.
+    pub fn time_odds(self) -> u32 {
+        match self {
+            Variant::Handicap(level) => handicap(level).2,
+            _ => 1,
+        }
+    }
+
+    /// The position the game starts from and its castling rights, which
+    /// in Chess960 remember where the king and rooks started.
+    pub fn start(self) -> (Board, CastlingRights) {
+        let number = match self {
+            Variant::Standard => return (Board::start_pos(), CastlingRights::default()),
+            Variant::Handicap(level) => {
+                return fen::board_from_fen(handicap(level).0)
+                    .expect("handicap positions are valid FENs");
+            }
+            Variant::Chess960(number) => number,
+        };
+        let back_rank = back_rank(number);
+        let white: String = back_rank
+            .iter()
+            .map(|piece_type| san::piece_letter(*piece_type))
+            .collect();
+        let mut board = Board::start_pos();
+        board.setup_fen(&format!(
+            "{}/pppppppp/8/8/8/8/PPPPPPPP/{white}",
+            white.to_ascii_lowercase()
+        ));
+        let files_of = |kind: PieceType| {
+            back_rank
+                .iter()
+                .enumerate()
+                .filter(move |(_, piece_type)| rules::same_type(**piece_type, kind))
+                .map(|(col, _)| col as i8)
+        };
+        let castling = CastlingRights {
+            files: CastlingFiles {
+                king: files_of(PieceType::King).next().unwrap(),
+                kingside_rook: files_of(PieceType::Rook).last().unwrap(),
+                queenside_rook: files_of(PieceType::Rook).next().unwrap(),
+            },
+            ..CastlingRights::default()
+        };
+        (board, castling)
+    }
+}
+
+/// The preset for handicap `level`, or the nearest one there is.
+fn handicap(level: u8) -> (&'static str, &'static str, u32) {
+    HANDICAPS[(level.clamp(1, HANDICAP_LEVELS) - 1) as usize]
+}
+
+/// White's first rank in Chess960 start position `number`, from the
+/// a-file to the h-file, in Scharnagl's numbering: the bishops, the queen
+/// and the knights are placed by successive remainders of the number, and
+/// the king goes between the rooks on the three squares left. Position 518
+/// is the standard one.
+pub fn back_rank(number: u16) -> [PieceType; 8] {
+    let mut rank: [Option<PieceType>; 8] = [None; 8];
+    let mut n = (number % CHESS960_POSITIONS) as usize;
+    rank[n % 4 * 2 + 1] = Some(PieceType::Bishop);
+    n /= 4;
+    rank[n % 4 * 2] = Some(PieceType::Bishop);
+    n /= 4;
+    place(&mut rank, n % 6, PieceType::Queen);
+    n /= 6;
+    let (first, second) = KNIGHT_SQUARES[n];
+    // The second knight first, so placing it doesn't shift the first.
+    place(&mut rank, second, PieceType::Knight);
+    place(&mut rank, first, PieceType::Knight);
+    for piece_type in [PieceType::Rook, PieceType::King, PieceType::Rook] {
+        place(&mut rank, 0, piece_type);
+    }
+    rank.map(|piece_type| piece_type.expect("every square is filled"))
+}
+
+/// Puts `piece_type` on the `nth_empty` square that is still empty.
+fn place(rank: &mut [Option<PieceType>; 8], nth_empty: usize, piece_type: PieceType) {
+    let index = (0..rank.len())
+        .filter(|index| rank[*index].is_none())
+        .nth(nth_empty)
+        .expect("enough empty squares");
+    rank[index] = Some(piece_type);
+}
+
+/// Switches to another variant or start position and starts a new game
+/// from it: from the menu, or when the server's handshake names the
+/// position an online game starts from.
+#[derive(Event)]
+pub struct VariantChosen(pub Variant);
+
+pub fn choose_variant(mut chosen: EventReader<VariantChosen>, mut new_game: NewGame) {
+    if let Some(VariantChosen(variant)) = chosen.read().last() {
+        new_game.start_variant(*variant);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fen;
+
+    #[test]
+    fn handicaps_take_from_white_alone() {
+        let (board, castling) = Variant::Standard.start();
+        let standard = fen::position_to_fen(&board, castling);
+        for level in 1..=HANDICAP_LEVELS {
+            let (board, castling) = Variant::Handicap(level).start();
+            let handicap = fen::position_to_fen(&board, castling);
+            let (black, white) = handicap.split_at(handicap.find("/8/").unwrap());
+            assert!(standard.starts_with(black), "level {level}");
+            assert!(!standard.ends_with(white), "level {level}");
+        }
+    }
+
+    #[test]
+    fn handicap_levels_run_out_into_standard_chess() {
+        let mut variant = Variant::Chess960(518);
+        for level in 1..=HANDICAP_LEVELS {
+            variant = variant.next_handicap();
+            assert_eq!(variant, Variant::Handicap(level));
+        }
+        assert_eq!(variant.next_handicap(), Variant::Standard);
+        assert_eq!(Variant::Standard.time_odds(), 1);
+        assert_eq!(Variant::Handicap(HANDICAP_LEVELS).time_odds(), 3);
+    }
+}
@@ -0,0 +1,223 @@
+use bevy::input::ButtonInput;
+use bevy::prelude::*;
+
+use crate::actions::{self, Action, ActiveScopes};
+
+/// The background of every plain button.
+pub const BUTTON_COLOR: Color = Color::srgb(0.25, 0.25, 0.3);
+/// The background of the button a screen leads with, like resuming a
+/// saved game.
+pub const ACCENT_BUTTON_COLOR: Color = Color::srgb(0.25, 0.35, 0.25);
+/// The background of the chosen one of a set of buttons.
+pub const SELECTED_BUTTON_COLOR: Color = Color::srgb(0.3, 0.45, 0.3);
+/// The background of full-window screens such as the main menu.
+pub const SCREEN_COLOR: Color = Color::srgb(0.12, 0.12, 0.14);
+/// The background of the panels beside the board.
+pub const PANEL_COLOR: Color = Color::srgba(0.1, 0.1, 0.1, 0.8);
+/// The dimmed backdrop behind modal dialogs.
+pub const BACKDROP_COLOR: Color = Color::srgba(0.0, 0.0, 0.0, 0.6);
+
+const HOVER_BORDER: Color = Color::srgb(0.55, 0.55, 0.6);
+const FOCUS_BORDER: Color = Color::srgb(1.0, 0.85, 0.55);
+const BORDER_WIDTH: f32 = 2.0;
+
+/// A button made with `button`. Hovering it and focusing it with the
+/// keyboard light up its border, leaving the background to the screen
+/// it's on.
+#[derive(Component)]
+pub struct WidgetButton;
+
+/// A dialog that takes the keyboard focus: while one is open, Tab only
+/// moves between its own buttons.
+#[derive(Component)]
+pub struct Modal;
+
+/// The button Tab has moved to, which Enter presses. Set back to none by
+/// any mouse click, so the mouse and keyboard don't fight over it.
+#[derive(Resource, Default)]
+pub struct UiFocus {
+    pub focused: Option<Entity>,
+    /// The button pressed with Enter last frame, let go this frame.
+    pressed: Option<Entity>,
+}
+
+/// A node of the given size with its content centered, the usual shape of
+/// a button. Override its fields for buttons placed on their own.
+pub fn button_node(width: f32, height: f32) -> Node {
+    Node {
+        width: Val::Px(width),
+        height: Val::Px(height),
+        justify_content: JustifyContent::Center,
+        align_items: AlignItems::Center,
+        border: UiRect::all(Val::Px(BORDER_WIDTH)),
+        ..default()
+    }
+}
+
+/// A plain button laid out by `node`, showing `content`, usually a
+/// `label`. Presses show up as `Interaction::Pressed` as for any other
+/// button, whether they come from the mouse or from Enter.
+pub fn button(node: Node, content: impl Bundle) -> impl Bundle {
+    colored_button(node, BUTTON_COLOR, content)
+}
+
+pub fn colored_button(node: Node, color: Color, content: impl Bundle) -> impl Bundle {
+    (
+        WidgetButton,
+        Button,
+        node,
+        BackgroundColor(color),
+        BorderColor(Color::NONE),
+        children![content],
+    )
+}
+
+/// Text at the default size.
+pub fn label(text: impl Into<String>) -> impl Bundle {
+    Text::new(text)
+}
+
+/// Text for hints and secondary buttons.
+pub fn small_label(text: impl Into<String>) -> impl Bundle {
+    (
+        Text::new(text),
+        TextFont {
+            font_size: 14.0,
+            ..default()
+        },
+    )
+}
+
+/// The heading of a screen or dialog.
+pub fn title(text: impl Into<String>, font_size: f32) -> impl Bundle {
+    (
+        Text::new(text),
+        TextFont {
+            font_size,
+            ..default()
+        },
+    )
+}
+
+/// A full-window column with its content centered, the root of every menu
+/// screen. `z_index` keeps screens opened on top of others above them.
+pub fn screen(color: Color, row_gap: f32, z_index: i32) -> impl Bundle {
+    (
+        Node {
+            position_type: PositionType::Absolute,
+            width: Val::Percent(100.0),
+            height: Val::Percent(100.0),
+            flex_direction: FlexDirection::Column,
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            row_gap: Val::Px(row_gap),
+            ..default()
+        },
+        BackgroundColor(color),
+        GlobalZIndex(z_index),
+    )
+}
+
+/// A `screen` over a dimmed view of the game, which keeps the keyboard
+/// focus to itself.
+pub fn modal(row_gap: f32, z_index: i32) -> impl Bundle {
+    (Modal, screen(BACKDROP_COLOR, row_gap, z_index))
+}
+
+/// A row of buttons under a dialog's text.
+pub fn button_row() -> Node {
+    Node {
+        column_gap: Val::Px(10.0),
+        ..default()
+    }
+}
+
+/// Tab and Shift+Tab move the focus through the visible buttons from top
+/// to bottom and left to right, within the topmost modal if one is open.
+/// Enter presses the focused button, and is then taken away so it doesn't
+/// also select a square or open the chat.
+#[allow(clippy::too_many_arguments)]
+pub fn move_focus(
+    mut keys: ResMut<ButtonInput<KeyCode>>,
+    scopes: Res<ActiveScopes>,
+    mouse: Res<ButtonInput<MouseButton>>,
+    mut focus: ResMut<UiFocus>,
+    buttons: Query<(Entity, &GlobalTransform, &InheritedVisibility), With<WidgetButton>>,
+    modals: Query<(Entity, &GlobalZIndex), With<Modal>>,
+    children: Query<&Children>,
+    mut interactions: Query<&mut Interaction>,
+) {
+    if let Some(pressed) = focus.pressed.take()
+        && let Ok(mut interaction) = interactions.get_mut(pressed)
+    {
+        *interaction = Interaction::None;
+    }
+    if focus
+        .focused
+        .is_some_and(|focused| !buttons.contains(focused))
+    {
+        focus.focused = None;
+    }
+    if mouse.get_just_pressed().next().is_some() {
+        focus.focused = None;
+        return;
+    }
+    if actions::just_pressed_in(&keys, &scopes, Action::PressFocused)
+        && let Some(focused) = focus.focused
+        && let Ok(mut interaction) = interactions.get_mut(focused)
+    {
+        *interaction = Interaction::Pressed;
+        focus.pressed = Some(focused);
+        keys.clear_just_pressed(KeyCode::Enter);
+        return;
+    }
+    let backwards = actions::just_pressed(&keys, Action::FocusPrevious);
+    if !backwards && !actions::just_pressed(&keys, Action::FocusNext) {
+        return;
+    }
+    let within: Option<Vec<Entity>> = modals
+        .iter()
+        .max_by_key(|(_, z_index)| z_index.0)
+        .map(|(modal, _)| children.iter_descendants(modal).collect());
+    let mut candidates: Vec<(Entity, Vec3)> = buttons
+        .iter()
+        .filter(|(entity, _, visibility)| {
+            visibility.get() && within.as_ref().is_none_or(|within| within.contains(entity))
+        })
+        .map(|(entity, transform, _)| (entity, transform.translation()))
+        .collect();
+    if candidates.is_empty() {
+        return;
+    }
+    // UI nodes are placed with y growing downwards.
+    candidates.sort_by(|(_, a), (_, b)| a.y.total_cmp(&b.y).then(a.x.total_cmp(&b.x)));
+    let current = focus
+        .focused
+        .and_then(|focused| candidates.iter().position(|(entity, _)| *entity == focused));
+    let next = match (current, backwards) {
+        (None, false) => 0,
+        (None, true) => candidates.len() - 1,
+        (Some(index), false) => (index + 1) % candidates.len(),
+        (Some(index), true) => (index + candidates.len() - 1) % candidates.len(),
+    };
+    focus.focused = Some(candidates[next].0);
+}
+
+/// Borders the focused button, and any button under the mouse.
+pub fn style_buttons(
+    focus: Res<UiFocus>,
+    mut buttons: Query<(Entity, &Interaction, &mut BorderColor), With<WidgetButton>>,
+) {
+    for (entity, interaction, mut border) in buttons.iter_mut() {
+        let color = if focus.focused == Some(entity) {
+            FOCUS_BORDER
+        } else if *interaction != Interaction::None {
+            HOVER_BORDER
+        } else {
+            Color::NONE
+        };
+        if border.0 != color {
+            border.0 = color;
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use bevy::input::ButtonInput;
+use bevy::prelude::*;
+use bevy::window::{PrimaryWindow, WindowLevel, WindowResized};
+use hermanha_chess::{BOARD_COLS, BOARD_ROWS};
+
+use crate::TILE_SIZE;
+use crate::actions::{self, Action};
+use crate::board_render::BoardCamera;
+
+/// Window behaviour for overlay use. Saved in the settings file and
+/// turned on with `--always-on-top`, `--borderless` and `--click-through`
+/// on the command line; the first two can also be toggled while running.
+#[derive(Resource, Default, Clone, Copy)]
+pub struct WindowFlags {
+    pub always_on_top: bool,
+    pub borderless: bool,
+    pub click_through: bool,
+}
+
+impl WindowFlags {
+    /// `saved` with whichever options are given in `args` turned on.
+    pub fn from_args(args: &[String], saved: WindowFlags) -> Self {
+        let given = |flag: &str| args.iter().any(|arg| arg == flag);
+        WindowFlags {
+            always_on_top: saved.always_on_top || given("--always-on-top"),
+            borderless: saved.borderless || given("--borderless"),
+            click_through: saved.click_through || given("--click-through"),
+        }
+    }
+
+    fn window_level(&self) -> WindowLevel {
+        if self.always_on_top {
+            WindowLevel::AlwaysOnTop
+        } else {
+            WindowLevel::Normal
+        }
+    }
+}
+
+/// Window size to restore when leaving mini mode; `None` while the window
+/// is at its normal size.
+#[derive(Resource, Default)]
+pub struct MiniMode(Option<Vec2>);
+
+pub fn apply_window_flags(
+    flags: Res<WindowFlags>,
+    mini_mode: Res<MiniMode>,
+    mut windows: Query<&mut Window, With<PrimaryWindow>>,
+) {
+    if !flags.is_changed() {
+        return;
+    }
+    let Some(mut window) = windows.iter_mut().next() else {
+        return;
+    };
+    if mini_mode.0.is_none() {
+        window.window_level = flags.window_level();
+    }
+    window.decorations = !flags.borderless;
+    window.cursor_options.hit_test = !flags.click_through;
+}
+
+pub fn toggle_window_flags(keys: Res<ButtonInput<KeyCode>>, mut flags: ResMut<WindowFlags>) {
+    if actions::just_pressed(&keys, Action::AlwaysOnTop) {
+        flags.always_on_top = !flags.always_on_top;
+    }
+    if actions::just_pressed(&keys, Action::Borderless) {
+        flags.borderless = !flags.borderless;
+    }
+}
+
+pub fn toggle_mini_mode(
+    keys: Res<ButtonInput<KeyCode>>,
+    flags: Res<WindowFlags>,
+    mut mini_mode: ResMut<MiniMode>,
+    mut windows: Query<&mut Window, With<PrimaryWindow>>,
+) {
+    let on_top = actions::just_pressed(&keys, Action::MiniModeOnTop);
+    if !on_top && !actions::just_pressed(&keys, Action::MiniMode) {
+        return;
+    }
+    let Some(mut window) = windows.iter_mut().next() else {
+        return;
+    };
+    if let Some(size) = mini_mode.0.take() {
+        window.resolution.set(size.x, size.y);
+        window.window_level = flags.window_level();
+        return;
+    }
+    mini_mode.0 = Some(Vec2::new(
+        window.resolution.width(),
+        window.resolution.height(),
+    ));
+    window
+        .resolution
+        .set(BOARD_COLS as f32 * TILE_SIZE, BOARD_ROWS as f32 * TILE_SIZE);
+    if on_top {
+        window.window_level = WindowLevel::AlwaysOnTop;
+    }
+}
+
+/// Space left around the board when the window isn't in mini mode, room
+/// enough for the name plates.
+const BOARD_MARGIN: f32 = TILE_SIZE * 0.75;
+
+/// Zooms the camera so the whole board fits the main window after every
+/// resize.
+/// The board stays centered on the world origin, and UI overlays are laid
+/// out in window space so they follow the window on their own.
+pub fn fit_board_to_window(
+    mut resized: EventReader<WindowResized>,
+    primary: Query<(), With<PrimaryWindow>>,
+    mini_mode: Res<MiniMode>,
+    mut projections: Query<&mut Projection, With<BoardCamera>>,
+) {
+    let Some(event) = resized
+        .read()
+        .filter(|event| primary.contains(event.window))
+        .last()
+    else {
+        return;
+    };
+    if event.width <= 0.0 || event.height <= 0.0 {
+        return;
+    }
+    let margin = if mini_mode.0.is_some() {
+        0.0
+    } else {
+        BOARD_MARGIN
+    };
+    let board_width = BOARD_COLS as f32 * TILE_SIZE + 2.0 * margin;
+    let board_height = BOARD_ROWS as f32 * TILE_SIZE + 2.0 * margin;
+    let scale = (board_width / event.width).max(board_height / event.height);
+    for mut projection in projections.iter_mut() {
+        if let Projection::Orthographic(orthographic) = &mut *projection {
+            orthographic.scale = scale;
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use std::io;
+use std::net::{TcpListener, TcpStream};
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{Receiver, Sender, TryRecvError};
+use tungstenite::handshake::{HandshakeError, HandshakeRole};
+use tungstenite::{Message as WsMessage, WebSocket};
+
+use crate::tcp::{ConnectionType, Message, TcpError};
+use crate::transport::{self, HANDSHAKE_TIMEOUT, IoThread, POLL_INTERVAL, Transport};
+
+/// A connection to the opponent over a WebSocket, carrying each frame as
+/// one text message. Like `TcpConnection` the socket lives on a
+/// background thread. A host over WebSocket takes no spectators.
+pub struct WsConnection {
+    connection_type: ConnectionType,
+    io: IoThread,
+}
+
+impl WsConnection {
+    /// Blocks until an opponent connects and completes the WebSocket
+    /// handshake, or for at most `HANDSHAKE_TIMEOUT` after it connects,
+    /// so call it off the main thread.
+    pub fn start_server(address: &str) -> Result<Self, io::Error> {
+        let listener = TcpListener::bind(address)?;
+        let (stream, _) = listener.accept()?;
+        let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+        transport::time_out_at(&stream, deadline)?;
+        let socket = finish_handshake(tungstenite::accept(stream), deadline)?;
+        WsConnection::spawn(socket, ConnectionType::Server)
+    }
+
+    /// Gives up if the handshake takes longer than `HANDSHAKE_TIMEOUT`.
+    pub fn connect_to_server(address: &str) -> Result<Self, io::Error> {
+        let stream = TcpStream::connect(address)?;
+        let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+        transport::time_out_at(&stream, deadline)?;
+        let (socket, _) = finish_handshake(
+            tungstenite::client(format!("ws://{address}/"), stream),
+            deadline,
+        )?;
+        WsConnection::spawn(socket, ConnectionType::Client)
+    }
+
+    /// The handshake is done blocking; only then does the read timeout
+    /// that paces the I/O thread go on.
+    fn spawn(socket: WebSocket<TcpStream>, connection_type: ConnectionType) -> io::Result<Self> {
+        transport::clear_timeouts(socket.get_ref())?;
+        socket.get_ref().set_read_timeout(Some(POLL_INTERVAL))?;
+        Ok(WsConnection {
+            connection_type,
+            io: IoThread::spawn(move |outgoing, incoming| run(socket, outgoing, incoming)),
+        })
+    }
+}
+
+/// Finishes a handshake, failing once `deadline` has passed. The socket's
+/// timeouts end at the deadline, so a read that times out interrupts the
+/// handshake and fails it here.
+fn finish_handshake<Role: HandshakeRole<InternalStream = TcpStream>>(
+    mut handshake: Result<Role::FinalResult, HandshakeError<Role>>,
+    deadline: Instant,
+) -> io::Result<Role::FinalResult> {
+    loop {
+        match handshake {
+            Ok(result) => return Ok(result),
+            Err(HandshakeError::Interrupted(mid)) => {
+                transport::time_out_at(mid.get_ref().get_ref(), deadline)?;
+                handshake = mid.handshake();
+            }
+            Err(HandshakeError::Failure(err)) => return Err(io::Error::other(err.to_string())),
+        }
+    }
+}
+
+impl Transport for WsConnection {
+    fn connection_type(&self) -> ConnectionType {
+        self.connection_type
+    }
+
+    fn read(&mut self) -> Result<Message, TcpError> {
+        self.io.read()
+    }
+
+    fn write(&mut self, message: Message) -> Result<(), TcpError> {
+        self.io.write(message)
+    }
+
+    /// Like `TcpConnection::close`; the thread ends with a close frame.
+    fn close(&mut self, timeout: Duration) {
+        self.io.close(timeout);
+    }
+}
+
+/// Shuttles messages between the socket and the channels until either
+/// side goes away or the socket fails. WebSocket pings are answered by
+/// tungstenite while reading.
+fn run(
+    mut socket: WebSocket<TcpStream>,
+    outgoing: Receiver<Message>,
+    incoming: Sender<Result<Message, TcpError>>,
+) {
+    loop {
+        loop {
+            match outgoing.try_recv() {
+                Ok(message) => {
+                    let frame = match message.encode() {
+                        Ok(frame) => frame,
+                        // A message too long for a frame is left unsent.
+                        Err(err) => {
+                            if incoming.send(Err(TcpError::InvalidMessage(err))).is_err() {
+                                return;
+                            }
+                            continue;
+                        }
+                    };
+                    if let Err(err) = socket.send(WsMessage::text(frame)) {
+                        let _ = incoming.send(Err(TcpError::Io(io::Error::other(err))));
+                        return;
+                    }
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    let _ = socket.close(None);
+                    return;
+                }
+            }
+        }
+        let result = match socket.read() {
+            Ok(WsMessage::Text(text)) => {
+                Message::decode(text.as_bytes()).map_err(TcpError::InvalidMessage)
+            }
+            Ok(WsMessage::Close(_)) => Err(TcpError::Io(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "connection closed",
+            ))),
+            Ok(_) => continue,
+            Err(tungstenite::Error::Io(err))
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                continue;
+            }
+            Err(err) => Err(TcpError::Io(io::Error::other(err))),
+        };
+        let failed = result.is_err();
+        if incoming.send(result).is_err() || failed {
+            return;
+        }
+    }
+}
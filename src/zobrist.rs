@@ -0,0 +1,305 @@
+use hermanha_chess::{BOARD_COLS, BOARD_ROWS, Board, Color as HermanhaColor, PieceType, Position};
+
+use crate::rules::{self, CastlingRights};
+
+const SQUARES: usize = BOARD_ROWS * BOARD_COLS;
+
+/// Seed of the keys. Both players of a network game compare hashes, so the
+/// keys must come out the same in every build.
+const SEED: u64 = 0x5EED_C4E5_5B0A_4D00;
+
+/// One random key per piece on each square, and one for each of: Black to
+/// move, every castling right and the file of an en passant capture. A
+/// position hashes to the XOR of the keys of everything in it.
+struct Keys {
+    pieces: [[u64; SQUARES]; 12],
+    black_to_move: u64,
+    castling: [u64; 4],
+    en_passant: [u64; BOARD_COLS],
+}
+
+/// SplitMix64, which is enough to spread the keys and simple enough to
+/// run at compile time.
+pub const fn next_key(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+const fn generate_keys() -> Keys {
+    let mut state = SEED;
+    let mut pieces = [[0; SQUARES]; 12];
+    let mut piece = 0;
+    while piece < 12 {
+        let mut square = 0;
+        while square < SQUARES {
+            pieces[piece][square] = next_key(&mut state);
+            square += 1;
+        }
+        piece += 1;
+    }
+    let black_to_move = next_key(&mut state);
+    let mut castling = [0; 4];
+    let mut right = 0;
+    while right < 4 {
+        castling[right] = next_key(&mut state);
+        right += 1;
+    }
+    let mut en_passant = [0; BOARD_COLS];
+    let mut file = 0;
+    while file < BOARD_COLS {
+        en_passant[file] = next_key(&mut state);
+        file += 1;
+    }
+    Keys {
+        pieces,
+        black_to_move,
+        castling,
+        en_passant,
+    }
+}
+
+static KEYS: Keys = generate_keys();
+
+fn piece_index(color: HermanhaColor, piece_type: PieceType) -> usize {
+    let kind = match piece_type {
+        PieceType::Pawn => 0,
+        PieceType::Knight => 1,
+        PieceType::Bishop => 2,
+        PieceType::Rook => 3,
+        PieceType::Queen => 4,
+        PieceType::King => 5,
+    };
+    match color {
+        HermanhaColor::White => kind,
+        HermanhaColor::Black => 6 + kind,
+    }
+}
+
+fn square_key(board: &Board, pos: Position) -> u64 {
+    board.get(pos).map_or(0, |piece| {
+        let square = pos.row as usize * BOARD_COLS + pos.col as usize;
+        KEYS.pieces[piece_index(piece.color, piece.piece_type)][square]
+    })
+}
+
+fn castling_keys(castling: CastlingRights) -> u64 {
+    let rights = [
+        castling.white_kingside,
+        castling.white_queenside,
+        castling.black_kingside,
+        castling.black_queenside,
+    ];
+    KEYS.castling
+        .iter()
+        .zip(rights)
+        .filter(|(_, allowed)| *allowed)
+        .fold(0, |hash, (key, _)| hash ^ key)
+}
+
+/// The file an en passant capture can be made on. Both pawns beside a
+/// double-stepped one capture onto the same square, so the first capture
+/// names it.
+fn en_passant_file(board: &Board) -> Option<usize> {
+    rules::en_passant_captures(board)
+        .first()
+        .map(|capture| capture.to.col as usize)
+}
+
+/// A position's hash along with its en passant file, which is what
+/// `after_move` needs to hash the next position without starting over.
+#[derive(Clone, Copy)]
+pub struct Key {
+    pub hash: u64,
+    en_passant: Option<usize>,
+}
+
+impl Key {
+    pub fn new(board: &Board, castling: CastlingRights) -> Self {
+        let mut hash = 0;
+        for row in 0..BOARD_ROWS as i8 {
+            for col in 0..BOARD_COLS as i8 {
+                hash ^= square_key(board, Position::new(row, col));
+            }
+        }
+        if board.move_turn == HermanhaColor::Black {
+            hash ^= KEYS.black_to_move;
+        }
+        hash ^= castling_keys(castling);
+        let en_passant = en_passant_file(board);
+        if let Some(file) = en_passant {
+            hash ^= KEYS.en_passant[file];
+        }
+        Key { hash, en_passant }
+    }
+
+    /// The key of `next`, reached from this key's `board` by moving from
+    /// `from` to `to`. Only what the move changed is hashed again: the
+    /// squares of its rank, where a castling rook or a pawn taken en
+    /// passant stands, and the square it lands on.
+    pub fn after_move(
+        self,
+        board: &Board,
+        castling: CastlingRights,
+        next: &Board,
+        next_castling: CastlingRights,
+        from: Position,
+        to: Position,
+    ) -> Self {
+        let mut hash = self.hash ^ KEYS.black_to_move;
+        for col in 0..BOARD_COLS as i8 {
+            let pos = Position::new(from.row, col);
+            hash ^= square_key(board, pos) ^ square_key(next, pos);
+        }
+        if to.row != from.row {
+            hash ^= square_key(board, to) ^ square_key(next, to);
+        }
+        hash ^= castling_keys(castling) ^ castling_keys(next_castling);
+        if let Some(file) = self.en_passant {
+            hash ^= KEYS.en_passant[file];
+        }
+        // Only a pawn's double step lets the next move take en passant.
+        let double_step = (to.row - from.row).abs() == 2
+            && next
+                .get(to)
+                .is_some_and(|piece| matches!(piece.piece_type, PieceType::Pawn));
+        let en_passant = if double_step {
+            en_passant_file(next)
+        } else {
+            None
+        };
+        if let Some(file) = en_passant {
+            hash ^= KEYS.en_passant[file];
+        }
+        Key { hash, en_passant }
+    }
+}
+
+/// The Zobrist hash of a position: its pieces, the side to move, the
+/// castling rights and the file an en passant capture can be made on.
+/// Positions that are the same for the rules of repetition hash the same.
+pub fn hash(board: &Board, castling: CastlingRights) -> u64 {
+    Key::new(board, castling).hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fen;
+
+    fn hash_fen(text: &str) -> u64 {
+        let (board, castling) = fen::board_from_fen(text).unwrap();
+        hash(&board, castling)
+    }
+
+    #[test]
+    fn same_position_hashes_the_same_however_it_was_reached() {
+        let (mut board, mut castling) =
+            fen::board_from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq -").unwrap();
+        for (from, to) in [
+            ((0, 6), (2, 5)),
+            ((7, 6), (5, 5)),
+            ((2, 5), (0, 6)),
+            ((5, 5), (7, 6)),
+        ] {
+            board.play(from, to, None).unwrap();
+            castling.update(Position::new(from.0, from.1), Position::new(to.0, to.1));
+        }
+        assert_eq!(
+            hash(&board, castling),
+            hash_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq -")
+        );
+    }
+
+    #[test]
+    fn side_to_move_and_castling_rights_change_the_hash() {
+        let start = hash_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq -");
+        assert_ne!(start, hash_fen("r3k2r/8/8/8/8/8/8/R3K2R b KQkq -"));
+        assert_ne!(start, hash_fen("r3k2r/8/8/8/8/8/8/R3K2R w Qkq -"));
+        assert_ne!(start, hash_fen("r3k2r/8/8/8/8/8/8/R3K2R w - -"));
+    }
+
+    #[test]
+    fn en_passant_rights_change_the_hash() {
+        let (mut board, castling) = fen::board_from_fen("4k3/8/8/8/5p2/8/4P3/4K3 w - -").unwrap();
+        let mut quiet = board.clone();
+        board.play((1, 4), (3, 4), None).unwrap();
+        // The same pieces reached with single steps, Black's king walking
+        // round a triangle to hand White the last move.
+        for (from, to) in [
+            ((1, 4), (2, 4)),
+            ((7, 4), (7, 3)),
+            ((0, 4), (0, 3)),
+            ((7, 3), (6, 3)),
+            ((0, 3), (0, 4)),
+            ((6, 3), (7, 4)),
+            ((2, 4), (3, 4)),
+        ] {
+            quiet.play(from, to, None).unwrap();
+        }
+        // Only `board` lets f4 take on e3.
+        assert_eq!(
+            fen::position_to_fen(&board, castling),
+            fen::position_to_fen(&quiet, castling)
+        );
+        assert_ne!(hash(&board, castling), hash(&quiet, castling));
+    }
+
+    /// Plays `moves` from `text`, checking after each that the key
+    /// carried along matches one computed from scratch.
+    fn assert_keys_follow(text: &str, moves: &[((i8, i8), (i8, i8), Option<PieceType>)]) {
+        let (mut board, mut castling) = fen::board_from_fen(text).unwrap();
+        let mut key = Key::new(&board, castling);
+        for &(from, to, promotion_piece) in moves {
+            let (from, to) = (Position::new(from.0, from.1), Position::new(to.0, to.1));
+            let next = rules::play_move(&board, castling, from, to, promotion_piece).unwrap();
+            let mut next_castling = castling;
+            next_castling.update(from, to);
+            key = key.after_move(&board, castling, &next, next_castling, from, to);
+            assert_eq!(key.hash, hash(&next, next_castling));
+            (board, castling) = (next, next_castling);
+        }
+    }
+
+    #[test]
+    fn moves_update_the_hash_as_hashing_from_scratch_would() {
+        // A rook taken on its square and a king move, losing castling
+        // rights both ways.
+        assert_keys_follow(
+            "r3k2r/8/8/8/8/8/8/R3K2R w KQkq -",
+            &[((0, 7), (7, 7), None), ((7, 4), (6, 4), None)],
+        );
+        // Castling.
+        assert_keys_follow(
+            "r3k2r/8/8/8/8/8/8/R3K2R w KQkq -",
+            &[((0, 4), (0, 6), None), ((7, 4), (7, 2), None)],
+        );
+        // A double step allowing en passant, the capture, and a double
+        // step nothing can take.
+        assert_keys_follow(
+            "4k3/8/8/8/5p2/8/4P1P1/4K3 w - -",
+            &[
+                ((1, 4), (3, 4), None),
+                ((3, 5), (2, 4), None),
+                ((1, 6), (3, 6), None),
+            ],
+        );
+        // A double step whose en passant right is dropped by the reply,
+        // then promotions with and without a capture.
+        assert_keys_follow(
+            "1n2k3/P7/8/8/5p2/8/4P3/4K3 w - -",
+            &[
+                ((1, 4), (3, 4), None),
+                ((7, 4), (7, 5), None),
+                ((6, 0), (7, 1), Some(PieceType::Queen)),
+                ((7, 5), (6, 5), None),
+            ],
+        );
+        assert_keys_follow(
+            "4k3/P7/8/8/8/8/8/4K3 w - -",
+            &[((6, 0), (7, 0), Some(PieceType::Knight))],
+        );
+    }
+}
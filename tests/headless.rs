@@ -0,0 +1,357 @@
+use std::net::TcpListener;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bevy::input::InputPlugin;
+use bevy::prelude::*;
+use bevy::state::app::StatesPlugin;
+use chess_app::ChessPlugin;
+use chess_app::cursor::SquareChosen;
+use chess_app::event_log::EventLog;
+use chess_app::game_over::GameEnded;
+use chess_app::game_state::{BoardState, Castling, GameOutcome, MoveOrigin, MoveRequested};
+use chess_app::games::{GameId, GameTabs, TabCommand};
+use chess_app::history::MoveHistory;
+use chess_app::menu::AppState;
+use chess_app::rules::Outcome;
+use chess_app::tcp::{
+    HelloMessage, Message, MoveMessage, PROTOCOL_VERSION, TcpConnection, TcpError,
+};
+use chess_app::variant::{self, Variant, VariantChosen};
+use chess_app::{fen, pgn, san, serve, zobrist};
+use hermanha_chess::{Color as HermanhaColor, PieceType, Position};
+
+fn headless_app() -> App {
+    let mut app = App::new();
+    app.add_plugins((MinimalPlugins, StatesPlugin, InputPlugin, ChessPlugin))
+        .insert_state(AppState::Playing);
+    app.update();
+    app
+}
+
+/// Algebraic square name to board position, e.g. "e4".
+fn square(name: &str) -> Position {
+    let bytes = name.as_bytes();
+    Position::new((bytes[1] - b'1') as i8, (bytes[0] - b'a') as i8)
+}
+
+/// Plays a move the way a player would: choosing the piece, then the
+/// target square, one frame each.
+fn play(app: &mut App, from: &str, to: &str) {
+    for at in [square(from), square(to)] {
+        app.world_mut().send_event(SquareChosen { at });
+        app.update();
+    }
+}
+
+#[test]
+fn scripted_game_ends_in_checkmate() {
+    let mut app = headless_app();
+    for (from, to) in [("f2", "f3"), ("e7", "e5"), ("g2", "g4"), ("d8", "h4")] {
+        play(&mut app, from, to);
+    }
+
+    let board = app.world().resource::<BoardState>();
+    let queen = board.get(square("h4")).expect("queen on h4");
+    assert_eq!(queen.color, HermanhaColor::Black);
+    assert!(matches!(queen.piece_type, PieceType::Queen));
+    assert_eq!(app.world().resource::<MoveHistory>().ply_count(), 4);
+    assert_eq!(
+        app.world().resource::<GameOutcome>().0,
+        Some(Outcome::Checkmate(HermanhaColor::Black))
+    );
+    assert!(!app.world().resource::<Events<GameEnded>>().is_empty());
+}
+
+#[test]
+fn event_log_describes_the_game_in_words() {
+    let mut app = headless_app();
+    for (from, to) in [("f2", "f3"), ("e7", "e5"), ("g2", "g4"), ("d8", "h4")] {
+        play(&mut app, from, to);
+    }
+
+    assert_eq!(
+        app.world().resource::<EventLog>().lines,
+        [
+            "White plays pawn f3",
+            "Black plays pawn e5",
+            "White plays pawn g4",
+            "Black plays queen h4",
+            "White is in check",
+            "Black wins by checkmate",
+        ]
+    );
+}
+
+#[test]
+fn typed_moves_are_parsed_against_the_legal_moves() {
+    let mut app = headless_app();
+    for text in ["e4", "e7e5", "Bc4", "Nc6", "Qh5", "Nf6", "Qxf7#"] {
+        let board = &app.world().resource::<BoardState>().0;
+        let castling = app.world().resource::<Castling>().0;
+        let (from, to, promotion_piece) =
+            san::parse_move(board, castling, text).expect("legal move");
+        app.world_mut().send_event(MoveRequested {
+            from,
+            to,
+            promotion_piece,
+            origin: MoveOrigin::Local,
+        });
+        app.update();
+    }
+
+    assert_eq!(
+        app.world().resource::<GameOutcome>().0,
+        Some(Outcome::Checkmate(HermanhaColor::White))
+    );
+    let board = &app.world().resource::<BoardState>().0;
+    let castling = app.world().resource::<Castling>().0;
+    assert!(san::parse_move(board, castling, "Ke7").is_err());
+}
+
+#[test]
+fn moves_are_refused_once_the_game_is_over() {
+    let mut app = headless_app();
+    for (from, to) in [("f2", "f3"), ("e7", "e5"), ("g2", "g4"), ("d8", "h4")] {
+        play(&mut app, from, to);
+    }
+    play(&mut app, "e2", "e4");
+
+    let board = app.world().resource::<BoardState>();
+    assert!(board.get(square("e4")).is_none());
+    assert_eq!(app.world().resource::<MoveHistory>().ply_count(), 4);
+}
+
+#[test]
+fn chess960_king_castles_onto_its_rook() {
+    let mut app = headless_app();
+    // RKNNBBQR: once the knights are out, b1 can castle with a1.
+    assert_eq!(
+        variant::back_rank(746).map(chess_app::san::piece_letter),
+        ['R', 'K', 'N', 'N', 'B', 'B', 'Q', 'R']
+    );
+    app.world_mut()
+        .send_event(VariantChosen(Variant::Chess960(746)));
+    app.update();
+    for (from, to) in [("c1", "b3"), ("c8", "b6"), ("d1", "c3"), ("d8", "c6")] {
+        play(&mut app, from, to);
+    }
+    play(&mut app, "b1", "a1");
+
+    let board = app.world().resource::<BoardState>();
+    let king = board.get(square("c1")).expect("king on c1");
+    assert!(matches!(king.piece_type, PieceType::King));
+    let rook = board.get(square("d1")).expect("rook on d1");
+    assert!(matches!(rook.piece_type, PieceType::Rook));
+    assert!(board.get(square("a1")).is_none() && board.get(square("b1")).is_none());
+    assert_eq!(board.move_turn, HermanhaColor::Black);
+    let castling = app.world().resource::<Castling>().0;
+    assert!(!castling.kingside(HermanhaColor::White) && !castling.queenside(HermanhaColor::White));
+    assert_eq!(app.world().resource::<MoveHistory>().ply_count(), 5);
+}
+
+#[test]
+fn tabs_keep_their_own_games() {
+    let mut app = headless_app();
+    play(&mut app, "e2", "e4");
+    app.world_mut().send_event(TabCommand::New);
+    app.update();
+    assert_eq!(app.world().resource::<MoveHistory>().ply_count(), 0);
+    play(&mut app, "d2", "d4");
+
+    app.world_mut().send_event(TabCommand::Show(GameId(0)));
+    app.update();
+    assert_eq!(app.world().resource::<GameTabs>().active(), GameId(0));
+    let board = app.world().resource::<BoardState>();
+    assert!(board.get(square("e4")).is_some());
+    assert!(board.get(square("d4")).is_none());
+    assert_eq!(board.move_turn, HermanhaColor::Black);
+
+    app.world_mut().send_event(TabCommand::Next);
+    app.update();
+    let board = app.world().resource::<BoardState>();
+    assert!(board.get(square("d4")).is_some());
+    assert!(board.get(square("e4")).is_none());
+    assert_eq!(app.world().resource::<MoveHistory>().ply_count(), 1);
+}
+
+#[test]
+fn pgn_of_a_game_loads_back_to_the_same_position() {
+    let mut app = headless_app();
+    for (from, to) in [("e2", "e4"), ("e7", "e5"), ("g1", "f3"), ("b8", "c6")] {
+        play(&mut app, from, to);
+    }
+    let history = app.world().resource::<MoveHistory>();
+    let start = history.start().expect("moves were played");
+    let text = pgn::to_pgn(history, start, "Alice", "Bob", "*", None);
+    assert!(text.contains("[White \"Alice\"]"));
+    assert!(text.contains("1. e4 e5 2. Nf3 Nc6 *"));
+
+    let (board, castling, loaded) = pgn::parse_pgn(&text).expect("valid PGN");
+    assert_eq!(loaded.ply_count(), 4);
+    let board_state = app.world().resource::<BoardState>();
+    let castling_state = app.world().resource::<Castling>();
+    assert_eq!(
+        fen::position_to_fen(&board, castling),
+        fen::position_to_fen(&board_state.0, castling_state.0)
+    );
+
+    let seeded = pgn::to_pgn(history, start, "Alice", "Computer", "*", Some(42));
+    assert!(seeded.contains("[Seed \"42\"]"));
+    assert_eq!(pgn::parse_pgn(&seeded).expect("valid PGN").2.ply_count(), 4);
+
+    let annotated = "1. e4 {best by test} e5 (1... c5) 2. Nf3! $1 Nc6 *";
+    assert_eq!(pgn::parse_fen_or_pgn(annotated).unwrap().2.ply_count(), 4);
+    assert_eq!(
+        pgn::parse_fen_or_pgn("8/8/8/8/8/8/8/K6k w - -")
+            .unwrap()
+            .2
+            .ply_count(),
+        0
+    );
+    assert!(pgn::parse_pgn("1. e4 e4").is_err());
+}
+
+#[test]
+fn move_messages_carry_a_hash_of_the_whole_position() {
+    let (board, castling) =
+        fen::board_from_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq -").unwrap();
+    let hash = zobrist::hash(&board, castling);
+    let frame = Message::Move(MoveMessage {
+        from: square("e2"),
+        to: square("e4"),
+        promotion_piece: None,
+        result: None,
+        new_board: board.clone(),
+        position_hash: Some(hash),
+    })
+    .encode()
+    .unwrap();
+    let Ok(Message::Move(decoded)) = Message::decode(frame.as_bytes()) else {
+        panic!("move message did not decode");
+    };
+    assert_eq!(decoded.position_hash, Some(hash));
+
+    // Same pieces, but White has lost the right to castle.
+    let mut moved_rook = castling;
+    moved_rook.white_kingside = false;
+    assert_ne!(zobrist::hash(&board, moved_rook), hash);
+}
+
+#[test]
+fn the_move_timeout_and_session_are_only_in_the_hello_when_there_are_some() {
+    let hello = |move_timeout, session| {
+        Message::Hello(HelloMessage {
+            version: PROTOCOL_VERSION,
+            name: "Host".to_string(),
+            client_color: Some(HermanhaColor::Black),
+            variant: Variant::Standard,
+            move_timeout,
+            session,
+        })
+        .encode()
+        .unwrap()
+    };
+    for (move_timeout, session) in [
+        (Some(90), None),
+        (None, Some(0xC0FF_EE00)),
+        (Some(90), Some(7)),
+    ] {
+        let Ok(Message::Hello(decoded)) = Message::decode(hello(move_timeout, session).as_bytes())
+        else {
+            panic!("hello did not decode");
+        };
+        assert_eq!(decoded.move_timeout, move_timeout);
+        assert_eq!(decoded.session, session);
+        assert_eq!(decoded.client_color, Some(HermanhaColor::Black));
+    }
+
+    // Without either the hello is the one version 5 peers know.
+    let frame = hello(None, None);
+    assert!(frame.contains("ChessHELLO:") && frame.split(':').count() == 6);
+    let Ok(Message::Hello(decoded)) = Message::decode(frame.as_bytes()) else {
+        panic!("hello did not decode");
+    };
+    assert_eq!((decoded.move_timeout, decoded.session), (None, None));
+}
+
+/// The next message from the relay, waiting for it a while.
+fn next_message(connection: &mut TcpConnection) -> Message {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        match connection.read() {
+            Ok(message) => return message,
+            Err(TcpError::WouldBlock) if Instant::now() < deadline => {
+                thread::sleep(Duration::from_millis(5));
+            }
+            Err(err) => panic!("no message from the relay: {err}"),
+        }
+    }
+}
+
+#[test]
+fn the_server_pairs_players_and_relays_their_moves() {
+    let address = {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().to_string()
+    };
+    let serving = address.clone();
+    thread::spawn(move || serve::run(&serving));
+    let connect = |name: &str| {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut connection = loop {
+            match TcpConnection::connect_to_server(&address, None) {
+                Ok(connection) => break connection,
+                Err(_) if Instant::now() < deadline => thread::sleep(Duration::from_millis(10)),
+                Err(err) => panic!("could not reach the server: {err}"),
+            }
+        };
+        connection
+            .write(Message::Hello(HelloMessage {
+                version: PROTOCOL_VERSION,
+                name: name.to_string(),
+                client_color: None,
+                variant: Variant::Standard,
+                move_timeout: None,
+                session: None,
+            }))
+            .unwrap();
+        connection
+    };
+    let mut alice = connect("Alice");
+    // Alice came first, so she plays White.
+    thread::sleep(Duration::from_millis(100));
+    let mut bob = connect("Bob");
+    let Message::Hello(hello) = next_message(&mut alice) else {
+        panic!("Alice got no hello");
+    };
+    assert_eq!(
+        (hello.name.as_str(), hello.client_color),
+        ("Bob", Some(HermanhaColor::White))
+    );
+    let Message::Hello(hello) = next_message(&mut bob) else {
+        panic!("Bob got no hello");
+    };
+    assert_eq!(
+        (hello.name.as_str(), hello.client_color),
+        ("Alice", Some(HermanhaColor::Black))
+    );
+
+    let (board, castling) =
+        fen::board_from_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq -").unwrap();
+    let hash = zobrist::hash(&board, castling);
+    alice
+        .write(Message::Move(MoveMessage {
+            from: square("e2"),
+            to: square("e4"),
+            promotion_piece: None,
+            result: None,
+            new_board: board,
+            position_hash: Some(hash),
+        }))
+        .unwrap();
+    let Message::Move(relayed) = next_message(&mut bob) else {
+        panic!("Bob got no move");
+    };
+    assert!(relayed.to == square("e4"));
+}